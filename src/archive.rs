@@ -0,0 +1,340 @@
+//! Zip archives distributed as model files, like textual inversion bundles, wildcards or poses.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use indicatif::ProgressBar;
+use zip::ZipArchive;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+/// Most bytes all entries of an archive may extract to, larger archives are refused before
+/// anything is written.
+const MAX_EXTRACTED_SIZE: u64 = 32 * 1024 * 1024 * 1024;
+
+/// An entry of the archive as listed in its central directory.
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    /// Path inside the archive, always with `/` separators.
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+/// Whether the file is a zip archive, by its extension or its leading bytes.
+pub fn is_zip_file(path: &Path) -> bool {
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+    {
+        return true;
+    }
+    let mut signature = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|_| u32::from_le_bytes(signature) == LOCAL_HEADER_SIGNATURE)
+}
+
+/// Directory an archive is extracted into, named after the file without its extension.
+pub fn extraction_dir(archive_path: &Path) -> PathBuf {
+    let stem = archive_path.file_stem().unwrap_or_default();
+    archive_path.with_file_name(stem)
+}
+
+fn open_archive(archive_path: &Path) -> Result<ZipArchive<BufReader<File>>> {
+    let file = File::open(archive_path)
+        .with_context(|| format!("Failed to open {}", archive_path.display()))?;
+    ZipArchive::new(BufReader::new(file))
+        .with_context(|| format!("{} is not a readable zip archive", archive_path.display()))
+}
+
+/// Entries of the archive read from its central directory, without reading their content.
+pub fn list_entries(archive_path: &Path) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = open_archive(archive_path)?;
+    (0..archive.len())
+        .map(|index| {
+            let entry = archive.by_index_raw(index)?;
+            Ok(ArchiveEntry {
+                name: entry.name().to_string(),
+                size: entry.size(),
+                is_dir: entry.is_dir(),
+            })
+        })
+        .collect()
+}
+
+/// Extracts every entry of the archive under the destination directory, returning the paths of
+/// the extracted files. Entries whose path would leave the destination are refused, and the
+/// bar advances by one for every entry.
+pub fn extract(
+    archive_path: &Path,
+    destination: &Path,
+    bar: Option<&ProgressBar>,
+) -> Result<Vec<PathBuf>> {
+    let entries = list_entries(archive_path)?;
+    // 先检查所有路径和总大小，有问题的压缩包不解压任何文件
+    let relative_paths = entries
+        .iter()
+        .map(|entry| safe_entry_path(&entry.name))
+        .collect::<Result<Vec<_>>>()?;
+    check_total_size(&entries, MAX_EXTRACTED_SIZE)?;
+    if let Some(bar) = bar {
+        bar.set_length(entries.len() as u64);
+    }
+    std::fs::create_dir_all(destination)
+        .with_context(|| format!("Failed to create directory {}", destination.display()))?;
+    let destination = destination.canonicalize()?;
+    let mut archive = open_archive(archive_path)?;
+    let mut extracted = Vec::new();
+    for (index, (entry, relative_path)) in entries.iter().zip(relative_paths).enumerate() {
+        if let Some(bar) = bar {
+            bar.set_message(entry.name.clone());
+        }
+        let target_path = destination.join(&relative_path);
+        if entry.is_dir {
+            std::fs::create_dir_all(&target_path)?;
+        } else if !relative_path.as_os_str().is_empty() {
+            if let Some(parent) = target_path.parent() {
+                std::fs::create_dir_all(parent)?;
+                // 已存在的目录可能是指向别处的符号链接
+                if !parent.canonicalize()?.starts_with(&destination) {
+                    bail!("Entry {} leads out of the extraction directory", entry.name);
+                }
+            }
+            extract_entry(&mut archive, index, entry, &target_path)
+                .with_context(|| format!("Failed to extract {}", entry.name))?;
+            extracted.push(target_path);
+        }
+        if let Some(bar) = bar {
+            bar.inc(1);
+        }
+    }
+    Ok(extracted)
+}
+
+fn check_total_size(entries: &[ArchiveEntry], limit: u64) -> Result<()> {
+    let total_size = entries
+        .iter()
+        .fold(0u64, |total, entry| total.saturating_add(entry.size));
+    if total_size > limit {
+        bail!("Archive extracts to {total_size} bytes, more than the allowed {limit} bytes");
+    }
+    Ok(())
+}
+
+/// Relative path of the entry, refusing absolute paths, drive letters and `..` that would
+/// escape the destination. Backslashes written by some Windows tools are taken as separators.
+fn safe_entry_path(name: &str) -> Result<PathBuf> {
+    let normalized = name.replace('\\', "/");
+    if normalized.starts_with('/') {
+        bail!("Entry {name} has an absolute path");
+    }
+    let mut path = PathBuf::new();
+    for part in normalized.split('/') {
+        match Path::new(part).components().next() {
+            None | Some(Component::CurDir) => {}
+            Some(Component::Normal(_)) if !part.contains(':') => path.push(part),
+            _ => bail!("Entry {name} has a path leading out of the extraction directory"),
+        }
+    }
+    Ok(path)
+}
+
+/// Creates the file of an entry, replacing a regular file left by an earlier extraction but
+/// never writing through a symbolic link.
+fn create_entry_file(target_path: &Path) -> Result<File> {
+    match std::fs::symlink_metadata(target_path) {
+        Ok(metadata) if metadata.is_file() => std::fs::remove_file(target_path)?,
+        Ok(_) => bail!("{} exists and is not a regular file", target_path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target_path)?)
+}
+
+fn extract_entry<R: Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    entry: &ArchiveEntry,
+    target_path: &Path,
+) -> Result<()> {
+    // 加密和不支持的压缩方式在这里报错，校验和在读到末尾时检查
+    let mut content = archive.by_index(index)?;
+    let mut output = BufWriter::new(create_entry_file(target_path)?);
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut written = 0u64;
+    loop {
+        let read_size = content.read(&mut buffer)?;
+        if read_size == 0 {
+            break;
+        }
+        written += read_size as u64;
+        // 解压后的大小不能超过目录中声明的大小
+        if written > entry.size {
+            bail!("content is larger than the declared {} bytes", entry.size);
+        }
+        output.write_all(&buffer[..read_size])?;
+    }
+    output.flush()?;
+    if written != entry.size {
+        bail!("content is corrupted");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    const CONTENT: &[u8] = b"a textual inversion embedding";
+
+    fn stored() -> SimpleFileOptions {
+        SimpleFileOptions::default().compression_method(CompressionMethod::Stored)
+    }
+
+    /// Writes an archive of the entries into the directory, returning its path.
+    fn write_archive(dir: &Path, entries: &[(&str, SimpleFileOptions)]) -> PathBuf {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, options) in entries {
+            writer.start_file(*name, *options).unwrap();
+            writer.write_all(CONTENT).unwrap();
+        }
+        let archive_path = dir.join("bundle.zip");
+        std::fs::write(&archive_path, writer.finish().unwrap().into_inner()).unwrap();
+        archive_path
+    }
+
+    #[test]
+    fn stored_deflated_and_zip64_entries_are_extracted() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = write_archive(
+            dir.path(),
+            &[
+                ("stored.pt", stored()),
+                ("nested/deflated.pt", SimpleFileOptions::default()),
+                ("nested\\windows.pt", stored()),
+                ("large.pt", stored().large_file(true)),
+            ],
+        );
+
+        let entries = list_entries(&archive_path).unwrap();
+        assert!(
+            entries
+                .iter()
+                .all(|entry| entry.size == CONTENT.len() as u64)
+        );
+        let destination = extraction_dir(&archive_path);
+        let extracted = extract(&archive_path, &destination, None).unwrap();
+
+        assert_eq!(extracted.len(), 4);
+        let destination = destination.canonicalize().unwrap();
+        for name in [
+            "stored.pt",
+            "nested/deflated.pt",
+            "nested/windows.pt",
+            "large.pt",
+        ] {
+            assert_eq!(std::fs::read(destination.join(name)).unwrap(), CONTENT);
+        }
+    }
+
+    #[test]
+    fn escaping_paths_are_refused_before_extraction() {
+        for name in [
+            "../escape.pt",
+            "nested/../../escape.pt",
+            "..\\escape.pt",
+            "/absolute.pt",
+            "\\absolute.pt",
+            "C:/drive.pt",
+            "C:drive.pt",
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let archive_path =
+                write_archive(dir.path(), &[("first.pt", stored()), (name, stored())]);
+            let destination = dir.path().join("out");
+
+            assert!(
+                extract(&archive_path, &destination, None).is_err(),
+                "{name}"
+            );
+            assert!(!destination.join("first.pt").exists(), "{name}");
+        }
+        assert_eq!(
+            safe_entry_path("./a/./b.pt").unwrap(),
+            Path::new("a").join("b.pt")
+        );
+    }
+
+    #[test]
+    fn crc_mismatch_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = write_archive(dir.path(), &[("stored.pt", stored())]);
+        let mut bytes = std::fs::read(&archive_path).unwrap();
+        let start = bytes
+            .windows(CONTENT.len())
+            .position(|window| window == CONTENT)
+            .unwrap();
+        bytes[start] ^= 0xff;
+        std::fs::write(&archive_path, bytes).unwrap();
+
+        let error = extract(&archive_path, &dir.path().join("out"), None).unwrap_err();
+        assert!(format!("{error:#}").contains("checksum"), "{error:#}");
+    }
+
+    #[test]
+    fn total_size_is_capped() {
+        let entry = |size| ArchiveEntry {
+            name: "a.pt".to_string(),
+            size,
+            is_dir: false,
+        };
+        assert!(check_total_size(&[entry(60), entry(40)], 100).is_ok());
+        assert!(check_total_size(&[entry(60), entry(41)], 100).is_err());
+        assert!(check_total_size(&[entry(u64::MAX), entry(1)], u64::MAX - 1).is_err());
+    }
+
+    #[test]
+    fn existing_files_are_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = write_archive(dir.path(), &[("stored.pt", stored())]);
+        let destination = dir.path().join("out");
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(
+            destination.join("stored.pt"),
+            b"an older and longer content",
+        )
+        .unwrap();
+
+        extract(&archive_path, &destination, None).unwrap();
+        assert_eq!(
+            std::fs::read(destination.join("stored.pt")).unwrap(),
+            CONTENT
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_not_written_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = write_archive(dir.path(), &[("stored.pt", stored())]);
+        let outside = dir.path().join("outside.txt");
+        std::fs::write(&outside, b"untouched").unwrap();
+        let destination = dir.path().join("out");
+        std::fs::create_dir_all(&destination).unwrap();
+        std::os::unix::fs::symlink(&outside, destination.join("stored.pt")).unwrap();
+
+        assert!(extract(&archive_path, &destination, None).is_err());
+        assert_eq!(std::fs::read(&outside).unwrap(), b"untouched");
+    }
+}
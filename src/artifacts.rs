@@ -0,0 +1,179 @@
+//! Files produced for a downloaded model, the model file itself and the metadata beside it.
+
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::InvalidInputError;
+
+/// One kind of file produced for a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Artifact {
+    /// The model file.
+    Model,
+    /// `<stem>.blake3` holding the hash of the model file.
+    Hash,
+    /// `<stem>.md` describing the model.
+    Readme,
+    /// `<stem>.cover.png`, or the video cover.
+    Cover,
+    /// `<stem>.civitai.json` with the Civitai metadata.
+    Json,
+    /// `<stem>.txt` with the trained words.
+    Triggers,
+    /// Prompts of the community images, listed in the readme.
+    Samples,
+}
+
+impl Artifact {
+    pub const ALL: [Artifact; 7] = [
+        Self::Model,
+        Self::Hash,
+        Self::Readme,
+        Self::Cover,
+        Self::Json,
+        Self::Triggers,
+        Self::Samples,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Model => "model",
+            Self::Hash => "hash",
+            Self::Readme => "readme",
+            Self::Cover => "cover",
+            Self::Json => "json",
+            Self::Triggers => "triggers",
+            Self::Samples => "samples",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl Display for Artifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The artifacts requested for a download, or produced by it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ArtifactSet(u8);
+
+impl ArtifactSet {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Everything produced before artifacts could be chosen, the trained words file is opt-in.
+    pub fn standard() -> Self {
+        Artifact::ALL
+            .into_iter()
+            .filter(|artifact| *artifact != Artifact::Triggers)
+            .collect()
+    }
+
+    pub fn contains(&self, artifact: Artifact) -> bool {
+        self.0 & artifact.bit() != 0
+    }
+
+    pub fn insert(&mut self, artifact: Artifact) {
+        self.0 |= artifact.bit();
+    }
+
+    pub fn without(self, artifact: Artifact) -> Self {
+        Self(self.0 & !artifact.bit())
+    }
+
+    /// Leaves out the community image prompts when `--skip-community` is given.
+    pub fn skipping_community(self, skip_community: bool) -> Self {
+        if skip_community {
+            self.without(Artifact::Samples)
+        } else {
+            self
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Artifact> + '_ {
+        Artifact::ALL
+            .into_iter()
+            .filter(|artifact| self.contains(*artifact))
+    }
+}
+
+impl FromIterator<Artifact> for ArtifactSet {
+    fn from_iter<I: IntoIterator<Item = Artifact>>(iter: I) -> Self {
+        let mut set = Self::empty();
+        for artifact in iter {
+            set.insert(artifact);
+        }
+        set
+    }
+}
+
+/// Comma separated names, `all` takes every artifact.
+impl FromStr for ArtifactSet {
+    type Err = InvalidInputError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut set = Self::empty();
+        for name in value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if name.eq_ignore_ascii_case("all") {
+                set = Artifact::ALL.into_iter().collect();
+                continue;
+            }
+            let artifact = Artifact::ALL
+                .into_iter()
+                .find(|artifact| artifact.name().eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    InvalidInputError(format!(
+                        "Unknown artifact \"{name}\", expected a comma list of {} or all",
+                        Artifact::ALL.map(|artifact| artifact.name()).join(", ")
+                    ))
+                })?;
+            set.insert(artifact);
+        }
+        if set.is_empty() {
+            return Err(InvalidInputError(
+                "No artifact given, expected a comma list like model,hash,readme".to_string(),
+            ));
+        }
+        Ok(set)
+    }
+}
+
+impl Display for ArtifactSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = self
+            .iter()
+            .map(|artifact| artifact.name())
+            .collect::<Vec<_>>();
+        f.write_str(&names.join(","))
+    }
+}
+
+impl TryFrom<String> for ArtifactSet {
+    type Error = InvalidInputError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ArtifactSet> for String {
+    fn from(set: ArtifactSet) -> Self {
+        set.to_string()
+    }
+}
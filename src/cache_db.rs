@@ -7,25 +7,70 @@ use std::{
 use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use time::UtcDateTime;
 
 use crate::civitai;
 
-static CACHE_DB: LazyLock<Arc<Mutex<sled::Db>>> = LazyLock::new(|| {
-    let cache_dir = directories::UserDirs::new()
+/// Directory of the cache database, also holding other files kept for diagnostics.
+pub fn cache_dir() -> Option<PathBuf> {
+    directories::UserDirs::new()
         .map(|dirs| dirs.home_dir().to_path_buf())
-        .map(|home_dir| home_dir.join(".config").join("imd").join("cache"));
+        .map(|home_dir| home_dir.join(".config").join("imd").join("cache"))
+}
+
+static CACHE_DB: LazyLock<Arc<Mutex<sled::Db>>> = LazyLock::new(|| {
+    // 单元测试不读写用户的缓存
+    if cfg!(test) {
+        let db = sled::Config::new()
+            .temporary(true)
+            .open()
+            .expect("Failed to open temporary cache database");
+        return Arc::new(Mutex::new(db));
+    }
+    let cache_dir = cache_dir();
     if cache_dir.is_none() {
         panic!("Failed to get cache directory.");
     }
     let cache_dir = cache_dir.unwrap();
-    if !cache_dir.exists() {
-        std::fs::create_dir_all(&cache_dir).expect("Failed to create cache directory");
+    let db = match open_cache_db(&cache_dir) {
+        Ok(db) => db,
+        Err(path) => {
+            // 没有权限时使用临时数据库，下载仍可进行，只是不保留缓存
+            crate::events::message(format!(
+                "WARNING: Permission denied on the cache at {}.\n{}\nUntil then the cache is kept only for this run.",
+                path.display(),
+                crate::utils::permission_advice(&path)
+            ));
+            sled::Config::new()
+                .temporary(true)
+                .open()
+                .expect("Failed to open temporary cache database")
+        }
+    };
+    Arc::new(Mutex::new(db))
+});
+
+/// Opens the cache database in the directory, the path denying access on permission errors.
+fn open_cache_db(cache_dir: &Path) -> Result<sled::Db, PathBuf> {
+    if !cache_dir.exists()
+        && let Err(e) = std::fs::create_dir_all(cache_dir)
+    {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            return Err(cache_dir.to_path_buf());
+        }
+        panic!(
+            "Failed to create cache directory {}: {e}",
+            cache_dir.display()
+        );
     }
 
     let db_path = cache_dir.join("cache.db");
-    let db = sled::open(&db_path).expect("Failed to open cache database");
-    Arc::new(Mutex::new(db))
-});
+    match sled::open(&db_path) {
+        Ok(db) => Ok(db),
+        Err(sled::Error::Io(e)) if e.kind() == std::io::ErrorKind::PermissionDenied => Err(db_path),
+        Err(e) => panic!("Failed to open cache database {}: {e}", db_path.display()),
+    }
+}
 
 pub fn store_civitai_model(model_meta: &civitai::Model) -> Result<()> {
     let model_id = model_meta.id();
@@ -38,7 +83,6 @@ pub fn store_civitai_model(model_meta: &civitai::Model) -> Result<()> {
     Ok(())
 }
 
-#[allow(dead_code)]
 pub fn retreive_civitai_model(model_id: u64) -> Result<Option<civitai::Model>> {
     let model_key = format!("civitai:model:{}", model_id);
     let db = CACHE_DB
@@ -79,7 +123,6 @@ pub fn store_civitai_model_version(model_version_meta: &civitai::ModelVersion) -
     Ok(())
 }
 
-#[allow(dead_code)]
 pub fn retreive_civitai_model_version(
     model_id: u64,
     model_version_id: u64,
@@ -109,6 +152,35 @@ pub fn is_civitai_model_version_exists(model_id: u64, model_version_id: u64) ->
     Ok(exists)
 }
 
+/// Finds a cached model version without knowing its model, by scanning every cached version.
+pub fn find_civitai_model_version<F>(matches: F) -> Result<Option<civitai::ModelVersion>>
+where
+    F: Fn(&civitai::ModelVersion) -> bool,
+{
+    const MODEL_PREFIX: &str = "civitai:model:";
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    for entry in db.scan_prefix(MODEL_PREFIX) {
+        let (key, raw_value) = entry?;
+        // 只有 civitai:model:<模型ID>:<版本ID> 形式的键保存的是版本元数据
+        let key = String::from_utf8_lossy(&key);
+        let ids = key[MODEL_PREFIX.len()..].split(':').collect::<Vec<_>>();
+        if ids.len() != 2 || ids.iter().any(|id| id.parse::<u64>().is_err()) {
+            continue;
+        }
+        let version_value: Value = serde_json::from_slice(&raw_value)?;
+        if let Ok(model_version) = civitai::ModelVersion::try_from(&version_value)
+            && matches(&model_version)
+        {
+            return Ok(Some(model_version));
+        }
+    }
+    Ok(None)
+}
+
+const FILE_LOCATION_PREFIX: &str = "civitai:model:file:blake3:";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct CivitaiFileLocationRecord {
@@ -116,6 +188,17 @@ struct CivitaiFileLocationRecord {
     pub version_id: u64,
     pub file_id: u64,
     pub locations: Vec<String>,
+    /// Page of the version on Civitai website, missing in records of older versions.
+    #[serde(default)]
+    pub page_url: Option<String>,
+}
+
+impl CivitaiFileLocationRecord {
+    fn page_url(&self) -> String {
+        self.page_url
+            .clone()
+            .unwrap_or_else(|| civitai::model_page_url(self.model_id, self.version_id))
+    }
 }
 
 pub fn store_civitai_model_file_location<P: AsRef<Path>>(
@@ -128,14 +211,17 @@ pub fn store_civitai_model_file_location<P: AsRef<Path>>(
     let location = file_location.as_ref().canonicalize()?;
     let location_str = location.to_string_lossy().into_owned();
 
-    let file_blake3_key = format!("civitai:model:file:blake3:{blake3_hash}");
+    let file_blake3_key = format!("{FILE_LOCATION_PREFIX}{blake3_hash}");
 
     let db = CACHE_DB
         .lock()
         .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
     if let Ok(Some(record)) = db.get(&file_blake3_key) {
         let mut record: CivitaiFileLocationRecord = serde_json::from_slice(&record)?;
-        record.locations.push(location_str);
+        if !record.locations.contains(&location_str) {
+            record.locations.push(location_str);
+        }
+        record.page_url = Some(civitai::model_page_url(model_id, version_id));
         db.insert(&file_blake3_key, serde_json::to_vec(&record)?)?;
     } else {
         let new_record = CivitaiFileLocationRecord {
@@ -143,6 +229,7 @@ pub fn store_civitai_model_file_location<P: AsRef<Path>>(
             version_id,
             file_id,
             locations: vec![location_str],
+            page_url: Some(civitai::model_page_url(model_id, version_id)),
         };
         db.insert(&file_blake3_key, serde_json::to_vec(&new_record)?)?;
     }
@@ -151,9 +238,73 @@ pub fn store_civitai_model_file_location<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Removes the location from every file location record, records left without any location are
+/// deleted. Returns the number of records changed.
+pub fn remove_civitai_model_file_location<P: AsRef<Path>>(file_location: P) -> Result<usize> {
+    let location_str = file_location.as_ref().to_string_lossy().into_owned();
+
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    let mut changed_records = 0;
+    for entry in db.scan_prefix(FILE_LOCATION_PREFIX) {
+        let (key, raw_value) = entry?;
+        let mut record: CivitaiFileLocationRecord = serde_json::from_slice(&raw_value)?;
+        if !record.locations.contains(&location_str) {
+            continue;
+        }
+        record
+            .locations
+            .retain(|location| *location != location_str);
+        if record.locations.is_empty() {
+            db.remove(key)?;
+        } else {
+            db.insert(key, serde_json::to_vec(&record)?)?;
+        }
+        changed_records += 1;
+    }
+    db.flush()?;
+
+    Ok(changed_records)
+}
+
+/// Civitai page of the model file at the location, `None` when the location is not recorded.
+pub fn retreive_civitai_model_page_url_by_location<P: AsRef<Path>>(
+    file_location: P,
+) -> Result<Option<String>> {
+    let location_str = file_location.as_ref().to_string_lossy().into_owned();
+
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    for entry in db.scan_prefix(FILE_LOCATION_PREFIX) {
+        let (_, raw_value) = entry?;
+        let record: CivitaiFileLocationRecord = serde_json::from_slice(&raw_value)?;
+        if record.locations.contains(&location_str) {
+            return Ok(Some(record.page_url()));
+        }
+    }
+    Ok(None)
+}
+
+/// Civitai page of the model file with the hash, `None` when no file with the hash is recorded.
+pub fn retreive_civitai_model_page_url_by_blake3(hash: &str) -> Result<Option<String>> {
+    let location_key = format!("{FILE_LOCATION_PREFIX}{hash}");
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    match db.get(&location_key)? {
+        Some(raw_value) => {
+            let record: CivitaiFileLocationRecord = serde_json::from_slice(&raw_value)?;
+            Ok(Some(record.page_url()))
+        }
+        None => Ok(None),
+    }
+}
+
 #[allow(dead_code)]
 pub fn retreive_civitai_model_locations_by_blake3(hash: &str) -> Result<Option<Vec<PathBuf>>> {
-    let location_key = format!("civitai:model:file:blake3:{}", hash);
+    let location_key = format!("{FILE_LOCATION_PREFIX}{hash}");
     let db = CACHE_DB
         .lock()
         .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
@@ -164,7 +315,7 @@ pub fn retreive_civitai_model_locations_by_blake3(hash: &str) -> Result<Option<V
             let converted_locations: Vec<PathBuf> = location_record
                 .locations
                 .iter()
-                .map(|l| PathBuf::from(l))
+                .map(PathBuf::from)
                 .collect();
             Ok(Some(converted_locations))
         }
@@ -172,6 +323,279 @@ pub fn retreive_civitai_model_locations_by_blake3(hash: &str) -> Result<Option<V
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CivitaiCommunityImagesRecord {
+    pub model_id: u64,
+    /// Unix timestamp in seconds.
+    pub fetched_at: i64,
+    pub images: Vec<Value>,
+}
+
+pub fn store_civitai_community_images(
+    model_id: u64,
+    images: &[civitai::ModelCommunityImage],
+) -> Result<()> {
+    let images_key = format!("civitai:model:{model_id}:images");
+    let record = CivitaiCommunityImagesRecord {
+        model_id,
+        fetched_at: UtcDateTime::now().unix_timestamp(),
+        images: images
+            .iter()
+            .map(|image| image.as_value().clone())
+            .collect(),
+    };
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    db.insert(images_key, serde_json::to_vec(&record)?)?;
+    db.flush()?;
+    Ok(())
+}
+
+/// Retreives cached community images of the model, `None` when not cached or fetched earlier
+/// than the given time to live.
+pub fn retreive_civitai_community_images(
+    model_id: u64,
+    ttl: Duration,
+) -> Result<Option<Vec<civitai::ModelCommunityImage>>> {
+    let images_key = format!("civitai:model:{model_id}:images");
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    let Some(raw_value) = db.get(&images_key)? else {
+        return Ok(None);
+    };
+    let record: CivitaiCommunityImagesRecord = serde_json::from_slice(&raw_value)?;
+    let age = UtcDateTime::now().unix_timestamp() - record.fetched_at;
+    if age < 0 || age as u64 >= ttl.as_secs() {
+        return Ok(None);
+    }
+    let images = record
+        .images
+        .iter()
+        .map(civitai::ModelCommunityImage::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(images))
+}
+
+const DOWNLOAD_SESSION_PREFIX: &str = "civitai:session:";
+
+/// Sessions are keyed by `<model id>:<version ids>`, so downloads of different versions of a
+/// model are kept apart. Sessions recorded before were keyed by the model id alone.
+pub fn store_download_session(session: &civitai::DownloadSession) -> Result<()> {
+    let session_key = format!("{DOWNLOAD_SESSION_PREFIX}{}", session.key());
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    db.insert(session_key, serde_json::to_vec(session)?)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub fn remove_download_session(session: &civitai::DownloadSession) -> Result<()> {
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    db.remove(format!("{DOWNLOAD_SESSION_PREFIX}{}", session.key()))?;
+    // 旧格式的记录只以模型 ID 为键，可能属于其他版本
+    let legacy_key = format!("{DOWNLOAD_SESSION_PREFIX}{}", session.model_id);
+    if let Some(raw_value) = db.get(&legacy_key)?
+        && serde_json::from_slice::<civitai::DownloadSession>(&raw_value)
+            .is_ok_and(|legacy| legacy.key() == session.key())
+    {
+        db.remove(legacy_key)?;
+    }
+    db.flush()?;
+    Ok(())
+}
+
+pub fn list_download_sessions() -> Result<Vec<civitai::DownloadSession>> {
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    let mut sessions = Vec::new();
+    for entry in db.scan_prefix(DOWNLOAD_SESSION_PREFIX) {
+        let (_, raw_value) = entry?;
+        sessions.push(serde_json::from_slice(&raw_value)?);
+    }
+    Ok(sessions)
+}
+
+/// Removes every download session, returns how many were removed.
+pub fn remove_download_sessions() -> Result<usize> {
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    let mut removed = 0;
+    for entry in db.scan_prefix(DOWNLOAD_SESSION_PREFIX) {
+        let (key, _) = entry?;
+        db.remove(key)?;
+        removed += 1;
+    }
+    db.flush()?;
+    Ok(removed)
+}
+
+const MANUAL_MATCH_PREFIX: &str = "civitai:manual-match:blake3:";
+
+pub fn store_manual_match(hash: &str, manual_match: &civitai::ManualMatch) -> Result<()> {
+    let match_key = format!("{MANUAL_MATCH_PREFIX}{}", hash.to_ascii_uppercase());
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    db.insert(match_key, serde_json::to_vec(manual_match)?)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub fn retreive_manual_match(hash: &str) -> Result<Option<civitai::ManualMatch>> {
+    let match_key = format!("{MANUAL_MATCH_PREFIX}{}", hash.to_ascii_uppercase());
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    match db.get(&match_key)? {
+        Some(raw_value) => Ok(Some(serde_json::from_slice(&raw_value)?)),
+        None => Ok(None),
+    }
+}
+
+const EXISTING_FILE_DECISION_PREFIX: &str = "civitai:decision:existing:blake3:";
+
+pub fn store_existing_file_decision(
+    hash: &str,
+    decision: &civitai::ExistingFileDecision,
+) -> Result<()> {
+    let decision_key = format!(
+        "{EXISTING_FILE_DECISION_PREFIX}{}",
+        hash.to_ascii_uppercase()
+    );
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    db.insert(decision_key, serde_json::to_vec(decision)?)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub fn retreive_existing_file_decision(
+    hash: &str,
+) -> Result<Option<civitai::ExistingFileDecision>> {
+    let decision_key = format!(
+        "{EXISTING_FILE_DECISION_PREFIX}{}",
+        hash.to_ascii_uppercase()
+    );
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    match db.get(&decision_key)? {
+        Some(raw_value) => Ok(Some(serde_json::from_slice(&raw_value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Removes every remembered answer to the existing file prompt, returns how many were removed.
+pub fn remove_existing_file_decisions() -> Result<usize> {
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    let mut removed = 0;
+    for entry in db.scan_prefix(EXISTING_FILE_DECISION_PREFIX) {
+        let (key, _) = entry?;
+        db.remove(key)?;
+        removed += 1;
+    }
+    db.flush()?;
+    Ok(removed)
+}
+
+const VERSION_UPSTREAM_PREFIX: &str = "civitai:upstream:version:";
+
+pub fn store_version_upstream(version_id: u64, state: &civitai::UpstreamState) -> Result<()> {
+    let upstream_key = format!("{VERSION_UPSTREAM_PREFIX}{version_id}");
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    db.insert(upstream_key, serde_json::to_vec(state)?)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub fn retreive_version_upstream(version_id: u64) -> Result<Option<civitai::UpstreamState>> {
+    let upstream_key = format!("{VERSION_UPSTREAM_PREFIX}{version_id}");
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    match db.get(&upstream_key)? {
+        Some(raw_value) => Ok(Some(serde_json::from_slice(&raw_value)?)),
+        None => Ok(None),
+    }
+}
+
+const DOWNLOAD_HISTORY_PREFIX: &str = "civitai:history:";
+
+pub fn store_download_record(record: &civitai::DownloadRecord) -> Result<()> {
+    // 键按下载时间排序，便于按时间倒序列出
+    let record_key = format!(
+        "{DOWNLOAD_HISTORY_PREFIX}{:020}:{}",
+        record.downloaded_at, record.file_id
+    );
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    db.insert(record_key, serde_json::to_vec(record)?)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub fn list_download_records(limit: usize) -> Result<Vec<civitai::DownloadRecord>> {
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    let mut records = Vec::new();
+    for entry in db.scan_prefix(DOWNLOAD_HISTORY_PREFIX).rev().take(limit) {
+        let (_, raw_value) = entry?;
+        records.push(serde_json::from_slice(&raw_value)?);
+    }
+    Ok(records)
+}
+
+const TRANSFER_STATS_PREFIX: &str = "civitai:transfer:";
+
+/// Reads, updates and writes back the transfer statistics of a model file in one go.
+pub fn update_file_transfer_stats<F>(file_id: u64, update: F) -> Result<()>
+where
+    F: FnOnce(&mut civitai::FileTransferStats),
+{
+    let stats_key = format!("{TRANSFER_STATS_PREFIX}{file_id}");
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    let mut stats = match db.get(&stats_key)? {
+        Some(raw_value) => serde_json::from_slice(&raw_value).unwrap_or_default(),
+        None => civitai::FileTransferStats::default(),
+    };
+    update(&mut stats);
+    db.insert(stats_key, serde_json::to_vec(&stats)?)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub fn list_file_transfer_stats() -> Result<Vec<civitai::FileTransferStats>> {
+    let db = CACHE_DB
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock database, {}", e))?;
+    let mut stats = Vec::new();
+    for entry in db.scan_prefix(TRANSFER_STATS_PREFIX) {
+        let (_, raw_value) = entry?;
+        // 无法解析的旧记录直接跳过
+        if let Ok(file_stats) = serde_json::from_slice(&raw_value) {
+            stats.push(file_stats);
+        }
+    }
+    Ok(stats)
+}
+
 /// Gracefully shutdown the cache database to prevent background thread panics
 ///
 /// This function is critical for proper shutdown because:
@@ -0,0 +1,129 @@
+//! Checking whether the access key may download the planned files, before anything is
+//! transferred.
+
+use std::fmt::Display;
+
+use reqwest::Client;
+use time::UtcDateTime;
+
+use crate::{
+    configuration::Configuration, events, progress::SkipReason, utils::datetime_to_date_string,
+};
+
+use super::{download_task, plan::DownloadPlan};
+
+/// What Civitai answered to a request for the first byte of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAccess {
+    Accessible,
+    /// Only accounts that purchased early access may download it before the date.
+    EarlyAccess {
+        until: Option<UtcDateTime>,
+    },
+    /// Refused outside early access, the file needs a membership or a purchase.
+    RequiresMembership,
+    /// The access key is missing or invalid.
+    KeyRejected,
+    NotFound,
+}
+
+impl FileAccess {
+    pub fn is_accessible(&self) -> bool {
+        matches!(self, FileAccess::Accessible)
+    }
+
+    pub fn skip_reason(&self) -> Option<SkipReason> {
+        match self {
+            FileAccess::Accessible => None,
+            FileAccess::EarlyAccess { .. } => Some(SkipReason::EarlyAccess),
+            FileAccess::RequiresMembership | FileAccess::KeyRejected => Some(SkipReason::NoAccess),
+            FileAccess::NotFound => Some(SkipReason::NotFoundOnCivitai),
+        }
+    }
+}
+
+impl Display for FileAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileAccess::Accessible => write!(f, "accessible"),
+            FileAccess::EarlyAccess { until: Some(until) } => {
+                write!(f, "early access until {}", datetime_to_date_string(until))
+            }
+            FileAccess::EarlyAccess { until: None } => write!(f, "early access"),
+            FileAccess::RequiresMembership => write!(f, "requires membership"),
+            FileAccess::KeyRejected => write!(f, "access key rejected"),
+            FileAccess::NotFound => write!(f, "not found"),
+        }
+    }
+}
+
+/// Checks every file the plan would transfer, marking the ones the access key may not download.
+/// A failed check leaves the file planned, the download reports the error itself.
+pub async fn preflight_plan(
+    client: &Client,
+    config: &Configuration,
+    download_plan: &mut DownloadPlan,
+) {
+    for version_plan in download_plan.versions.iter_mut() {
+        if version_plan.already_downloaded {
+            continue;
+        }
+        for file_plan in version_plan.files.iter_mut() {
+            if !file_plan.will_transfer() {
+                continue;
+            }
+            match download_task::probe_file_access(
+                client,
+                config,
+                &version_plan.version,
+                &file_plan.file,
+            )
+            .await
+            {
+                Ok(access) if !access.is_accessible() => file_plan.inaccessible = Some(access),
+                Ok(_) => {}
+                Err(e) => events::message(format!(
+                    "WARNING: Unable to check access to {}: {e:#}",
+                    file_plan.file.name()
+                )),
+            }
+        }
+    }
+}
+
+/// Checks every planned file and prints what the access key may download, returns the number
+/// of files it may not.
+pub async fn report_access(
+    client: &Client,
+    config: &Configuration,
+    download_plan: &DownloadPlan,
+) -> usize {
+    events::message(format!(
+        "\nModel: {} ({})",
+        download_plan.model.name(),
+        download_plan.model.id()
+    ));
+    let mut inaccessible = 0;
+    for version_plan in download_plan.versions.iter() {
+        let version = &version_plan.version;
+        events::message(format!("Version: {} ({})", version.name(), version.id()));
+        for file_plan in version_plan.files.iter() {
+            let file = &file_plan.file;
+            let status = match download_task::probe_file_access(client, config, version, file).await
+            {
+                Ok(access) => {
+                    if !access.is_accessible() {
+                        inaccessible += 1;
+                    }
+                    access.to_string()
+                }
+                Err(e) => {
+                    inaccessible += 1;
+                    format!("check failed: {e:#}")
+                }
+            };
+            events::message(format!("  File: {} -> {status}", file.name()));
+        }
+    }
+    inaccessible
+}
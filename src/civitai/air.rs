@@ -0,0 +1,217 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::errors::InvalidInputError;
+
+const AIR_PREFIX: &str = "urn:air:";
+
+/// Shape of an AIR, shown when a given one is malformed.
+pub const AIR_SHAPE: &str = "urn:air:<ecosystem>:<type>:<source>:<id>[@<version>][.<format>]";
+
+/// AI Resource Name identifying a model version, like
+/// `urn:air:sd1:lora:civitai:123@456`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Air {
+    pub ecosystem: String,
+    pub model_type: String,
+    pub source: String,
+    pub id: String,
+    pub version: Option<String>,
+    pub format: Option<String>,
+}
+
+impl Air {
+    /// Model id and version id of an AIR from Civitai.
+    pub fn civitai_ids(&self) -> Result<(u64, Option<u64>), InvalidInputError> {
+        if !self.source.eq_ignore_ascii_case("civitai") {
+            return Err(InvalidInputError(format!(
+                "\"{self}\" refers to a resource on {}, only civitai is supported",
+                self.source
+            )));
+        }
+        let model_id = self.id.parse::<u64>().map_err(|_| {
+            InvalidInputError(format!(
+                "\"{}\" in \"{self}\" is not a valid model id",
+                self.id
+            ))
+        })?;
+        let version_id = self
+            .version
+            .as_ref()
+            .map(|version| {
+                version.parse::<u64>().map_err(|_| {
+                    InvalidInputError(format!(
+                        "\"{version}\" in \"{self}\" is not a valid model version id"
+                    ))
+                })
+            })
+            .transpose()?;
+        Ok((model_id, version_id))
+    }
+}
+
+pub fn is_air(value: &str) -> bool {
+    value
+        .get(..AIR_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(AIR_PREFIX))
+}
+
+impl FromStr for Air {
+    type Err = InvalidInputError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let malformed = || {
+            InvalidInputError(format!(
+                "\"{value}\" is not a valid AIR, expected {AIR_SHAPE}"
+            ))
+        };
+        if !is_air(value) {
+            return Err(malformed());
+        }
+        let parts = value[AIR_PREFIX.len()..].split(':').collect::<Vec<_>>();
+        let [ecosystem, model_type, source, resource] = parts[..] else {
+            return Err(malformed());
+        };
+        // 格式后缀只出现在最后，版本号中不包含点
+        let (resource, format) = match resource.rsplit_once('.') {
+            Some((resource, format)) => (resource, Some(format)),
+            None => (resource, None),
+        };
+        let (id, version) = match resource.split_once('@') {
+            Some((id, version)) => (id, Some(version)),
+            None => (resource, None),
+        };
+        let is_segment = |segment: &str| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        };
+        if ![ecosystem, model_type, source, id]
+            .into_iter()
+            .all(is_segment)
+            || !version.into_iter().chain(format).all(is_segment)
+        {
+            return Err(malformed());
+        }
+        Ok(Self {
+            ecosystem: ecosystem.to_string(),
+            model_type: model_type.to_string(),
+            source: source.to_string(),
+            id: id.to_string(),
+            version: version.map(String::from),
+            format: format.map(String::from),
+        })
+    }
+}
+
+impl Display for Air {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{AIR_PREFIX}{}:{}:{}:{}",
+            self.ecosystem, self.model_type, self.source, self.id
+        )?;
+        if let Some(version) = self.version.as_ref() {
+            write!(f, "@{version}")?;
+        }
+        if let Some(format) = self.format.as_ref() {
+            write!(f, ".{format}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn airs_are_parsed_and_formatted_back() {
+        for (value, expected) in [
+            (
+                "urn:air:sd1:lora:civitai:123@456",
+                ("sd1", "lora", "civitai", "123", Some("456"), None),
+            ),
+            (
+                "urn:air:sdxl:checkpoint:civitai:101055",
+                ("sdxl", "checkpoint", "civitai", "101055", None, None),
+            ),
+            (
+                "urn:air:flux1:vae:civitai:9@10.safetensors",
+                (
+                    "flux1",
+                    "vae",
+                    "civitai",
+                    "9",
+                    Some("10"),
+                    Some("safetensors"),
+                ),
+            ),
+            (
+                "urn:air:sd1:embedding:huggingface:some_repo-name.pt",
+                (
+                    "sd1",
+                    "embedding",
+                    "huggingface",
+                    "some_repo-name",
+                    None,
+                    Some("pt"),
+                ),
+            ),
+        ] {
+            let air = value.parse::<Air>().unwrap();
+            let (ecosystem, model_type, source, id, version, format) = expected;
+            assert_eq!(air.ecosystem, ecosystem);
+            assert_eq!(air.model_type, model_type);
+            assert_eq!(air.source, source);
+            assert_eq!(air.id, id);
+            assert_eq!(air.version.as_deref(), version);
+            assert_eq!(air.format.as_deref(), format);
+            assert_eq!(air.to_string(), value);
+        }
+    }
+
+    #[test]
+    fn air_prefix_is_case_insensitive() {
+        assert!(is_air("URN:AIR:sd1:lora:civitai:1"));
+        assert!(!is_air("urn:ai"));
+        assert!(!is_air("https://civitai.com/models/1"));
+        let air = "URN:AIR:sd1:lora:civitai:1@2".parse::<Air>().unwrap();
+        assert_eq!(air.to_string(), "urn:air:sd1:lora:civitai:1@2");
+    }
+
+    #[test]
+    fn malformed_airs_are_refused() {
+        for value in [
+            "",
+            "urn:air:",
+            "air:sd1:lora:civitai:123",
+            "urn:air:sd1:lora:civitai",
+            "urn:air:sd1:lora:civitai:123:extra",
+            "urn:air:sd1::civitai:123",
+            "urn:air:sd1:lora:civitai:@456",
+            "urn:air:sd1:lora:civitai:123@",
+            "urn:air:sd1:lora:civitai:123@456.",
+            "urn:air:sd1:lora:civitai:123@4@5",
+            "urn:air:sd1:lora:civitai:1 23",
+            "urn:air:sd1:lo/ra:civitai:123",
+            "urn:air:模型:lora:civitai:123",
+        ] {
+            let error = value.parse::<Air>().unwrap_err();
+            assert!(error.to_string().contains(AIR_SHAPE), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn civitai_ids_are_resolved() {
+        let ids = |value: &str| value.parse::<Air>().unwrap().civitai_ids();
+        assert_eq!(
+            ids("urn:air:sd1:lora:civitai:123@456").unwrap(),
+            (123, Some(456))
+        );
+        assert_eq!(ids("urn:air:sd1:lora:Civitai:123").unwrap(), (123, None));
+        assert!(ids("urn:air:sd1:lora:huggingface:123@456").is_err());
+        assert!(ids("urn:air:sd1:lora:civitai:abc@456").is_err());
+        assert!(ids("urn:air:sd1:lora:civitai:123@v2").is_err());
+    }
+}
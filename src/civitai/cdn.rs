@@ -0,0 +1,108 @@
+use reqwest::Url;
+
+/// Transform requesting a still frame of a video.
+const POSTER_TRANSFORM: &str = "anim=false,transcode=true,original=true";
+
+/// Rewrites the transform segment of a Civitai image CDN URL, like the `width=450` in
+/// `https://image.civitai.com/<key>/<uuid>/width=450/<name>.jpeg`. URLs without a transform
+/// segment get one inserted before the file name. Returns `None` for URLs not on the CDN.
+pub fn with_transform(url: &str, transform: &str) -> Option<String> {
+    let mut parsed = Url::parse(url).ok()?;
+    if !parsed
+        .host_str()
+        .is_some_and(|host| host.starts_with("image.civitai."))
+    {
+        return None;
+    }
+    let mut segments = parsed
+        .path_segments()?
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    match segments.len() {
+        4 if segments[2].contains('=') => segments[2] = transform.to_string(),
+        3 => segments.insert(2, transform.to_string()),
+        _ => return None,
+    }
+    parsed.set_path(&segments.join("/"));
+    Some(parsed.to_string())
+}
+
+/// URL of an image resized to the given width by the CDN, `None` when the URL is not on the CDN
+/// or no resizing is requested.
+pub fn resized_image_url(url: &str, width: u32) -> Option<String> {
+    if width == 0 {
+        return None;
+    }
+    with_transform(url, &format!("width={width}"))
+}
+
+/// URL of a still frame of a video on the CDN, served as JPEG.
+pub fn video_poster_url(url: &str) -> Option<String> {
+    let transformed = with_transform(url, POSTER_TRANSFORM)?;
+    let mut parsed = Url::parse(&transformed).ok()?;
+    let (dir, file_name) = parsed.path().rsplit_once('/')?;
+    let stem = file_name
+        .rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .unwrap_or(file_name);
+    let poster_path = format!("{dir}/{stem}.jpeg");
+    parsed.set_path(&poster_path);
+    Some(parsed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://image.civitai.com/xG1nkqKTMzGDvpLrqFT7WA/3f6c1b2a-0d4e";
+
+    #[test]
+    fn transforms_are_rewritten_or_inserted() {
+        let cases = [
+            (
+                format!("{BASE}/width=450/cover.jpeg"),
+                Some(format!("{BASE}/width=1024/cover.jpeg")),
+            ),
+            (
+                format!("{BASE}/cover.jpeg"),
+                Some(format!("{BASE}/width=1024/cover.jpeg")),
+            ),
+            (
+                format!("{BASE}/original=true,quality=90/cover.jpeg"),
+                Some(format!("{BASE}/width=1024/cover.jpeg")),
+            ),
+            // 第三段不是变换参数
+            (format!("{BASE}/extra/cover.jpeg"), None),
+            (format!("{BASE}/a/b/cover.jpeg"), None),
+            ("https://civitai.com/images/123".to_string(), None),
+            ("https://example.com/a/b/cover.jpeg".to_string(), None),
+            ("not a url".to_string(), None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(resized_image_url(&url, 1024), expected, "{url}");
+        }
+        assert_eq!(resized_image_url(&format!("{BASE}/cover.jpeg"), 0), None);
+    }
+
+    #[test]
+    fn video_posters_are_jpeg_still_frames() {
+        let cases = [
+            (
+                format!("{BASE}/width=450/clip.mp4"),
+                Some(format!("{BASE}/{POSTER_TRANSFORM}/clip.jpeg")),
+            ),
+            (
+                format!("{BASE}/clip.webm"),
+                Some(format!("{BASE}/{POSTER_TRANSFORM}/clip.jpeg")),
+            ),
+            (
+                format!("{BASE}/clip"),
+                Some(format!("{BASE}/{POSTER_TRANSFORM}/clip.jpeg")),
+            ),
+            ("https://example.com/a/b/clip.mp4".to_string(), None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(video_poster_url(&url), expected, "{url}");
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt::Display;
+
+use crate::{configuration::Configuration, errors::InvalidInputError, events};
+use anyhow::{Context, Result, bail};
+use reqwest::{Client, Url};
+
+use super::{meta, model::Model, pagination::Paginator, schema};
+
+/// Page size requested when enumerating models.
+const MODELS_PAGE_SIZE: u64 = 100;
+
+/// URL shapes accepted by sync command.
+pub const CIVITAI_SYNC_URL_SHAPES: &str =
+    "https://civitai.com/collections/<id> or https://civitai.com/user/<username>";
+
+/// A set of models on Civitai that can be mirrored locally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncSource {
+    Collection(u64),
+    User(String),
+}
+
+impl Display for SyncSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncSource::Collection(id) => write!(f, "collection {id}"),
+            SyncSource::User(username) => write!(f, "models of user {username}"),
+        }
+    }
+}
+
+pub fn try_parse_civitai_sync_url(url: &Url) -> Result<SyncSource> {
+    let segments = url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    match segments.as_slice() {
+        [kind, id, ..] if kind.eq_ignore_ascii_case("collections") => {
+            let collection_id = id.parse::<u64>().map_err(|_| {
+                InvalidInputError(format!(
+                    "\"{id}\" in \"{url}\" is not a valid collection id, expected {CIVITAI_SYNC_URL_SHAPES}"
+                ))
+            })?;
+            Ok(SyncSource::Collection(collection_id))
+        }
+        [kind, username, ..] if kind.eq_ignore_ascii_case("user") => {
+            Ok(SyncSource::User(username.to_string()))
+        }
+        _ => Err(InvalidInputError(format!(
+            "\"{url}\" is not a Civitai collection or user profile, expected {CIVITAI_SYNC_URL_SHAPES}"
+        ))
+        .into()),
+    }
+}
+
+/// Fetches every model of the source, following the pages returned by Civitai API.
+pub async fn fetch_source_models(
+    client: &Client,
+    config: &Configuration,
+    source: &SyncSource,
+) -> Result<Vec<Model>> {
+    fetch_models(client, config, source, None).await
+}
+
+/// Fetches at most `cap` models published by the user, also tells whether more are left out.
+pub async fn fetch_user_models(
+    client: &Client,
+    config: &Configuration,
+    username: &str,
+    cap: usize,
+) -> Result<(Vec<Model>, bool)> {
+    let source = SyncSource::User(username.to_string());
+    // 多取一个模型，以确认是否还有更多
+    let mut models = fetch_models(client, config, &source, Some(cap + 1)).await?;
+    let truncated = models.len() > cap;
+    models.truncate(cap);
+    Ok((models, truncated))
+}
+
+async fn fetch_models(
+    client: &Client,
+    config: &Configuration,
+    source: &SyncSource,
+    max_items: Option<usize>,
+) -> Result<Vec<Model>> {
+    let mut query = vec![("limit", MODELS_PAGE_SIZE.to_string())];
+    match source {
+        SyncSource::Collection(id) => query.push(("collectionId", id.to_string())),
+        SyncSource::User(username) => query.push(("username", username.clone())),
+    }
+    let mut pages = Paginator::new(
+        client,
+        config,
+        meta::civitai_api_url(config, "models"),
+        query,
+        source.to_string(),
+    );
+    if let Some(max_items) = max_items {
+        pages = pages.max_items(max_items);
+    }
+
+    let mut models = Vec::new();
+    while let Some(items) = pages.next_page().await? {
+        for item in items.iter() {
+            schema::guard(item, "models", schema::check_model)?;
+            models.push(Model::try_from(item).context("Parse model")?);
+        }
+        if let SyncSource::Collection(collection_id) = source
+            && models.len() == items.len()
+        {
+            ensure_collection_filtered(client, config, *collection_id, &models).await?;
+        }
+        events::message(format!("Fetched {} models of {source}...", models.len()));
+    }
+    Ok(models)
+}
+
+/// `collectionId` is not a documented filter of the models endpoint. Were it ignored, every
+/// model on Civitai would be listed as the collection, so the first page is compared with the
+/// unfiltered listing before going on.
+async fn ensure_collection_filtered(
+    client: &Client,
+    config: &Configuration,
+    collection_id: u64,
+    first_page: &[Model],
+) -> Result<()> {
+    if first_page.is_empty() {
+        return Ok(());
+    }
+    let mut unfiltered = Paginator::new(
+        client,
+        config,
+        meta::civitai_api_url(config, "models"),
+        vec![("limit", MODELS_PAGE_SIZE.to_string())],
+        "models",
+    );
+    let items = unfiltered.next_page().await?.unwrap_or_default();
+    let unfiltered_ids = items.iter().filter_map(|item| item["id"].as_u64());
+    if unfiltered_ids.eq(first_page.iter().map(Model::id)) {
+        bail!(
+            "Civitai ignored the filter of collection {collection_id} and listed all models, the collection can not be synced."
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path, query_param, query_param_is_missing},
+    };
+
+    use super::*;
+    use crate::configuration::CivitaiConfig;
+
+    fn page(ids: &[u64]) -> Value {
+        let items = ids
+            .iter()
+            .map(|id| json!({ "id": id, "name": format!("model {id}"), "description": "", "modelVersions": [] }))
+            .collect::<Vec<_>>();
+        json!({ "items": items, "metadata": {} })
+    }
+
+    /// Serves `collection` for the collection 7 and `all` for the unfiltered listing.
+    async fn models_server(collection: &[u64], all: &[u64]) -> (MockServer, Configuration) {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/models"))
+            .and(query_param("collectionId", "7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page(collection)))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/models"))
+            .and(query_param_is_missing("collectionId"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page(all)))
+            .mount(&server)
+            .await;
+        let mut config = Configuration::default();
+        config.civitai = CivitaiConfig {
+            api_base_url: Some(format!("{}/api/v1", server.uri())),
+            ..Default::default()
+        };
+        (server, config)
+    }
+
+    #[tokio::test]
+    async fn filtered_collection_is_fetched() {
+        let (_server, config) = models_server(&[3, 5], &[1, 2, 3, 4, 5]).await;
+        let models = fetch_source_models(&Client::new(), &config, &SyncSource::Collection(7))
+            .await
+            .unwrap();
+        assert_eq!(models.iter().map(Model::id).collect::<Vec<_>>(), [3, 5]);
+    }
+
+    #[tokio::test]
+    async fn ignored_collection_filter_is_refused() {
+        let (_server, config) = models_server(&[1, 2, 3], &[1, 2, 3]).await;
+        let Err(error) =
+            fetch_source_models(&Client::new(), &config, &SyncSource::Collection(7)).await
+        else {
+            panic!("an ignored collection filter should fail");
+        };
+        assert!(
+            error
+                .to_string()
+                .contains("ignored the filter of collection 7")
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_collection_needs_no_check() {
+        let (server, config) = models_server(&[], &[1, 2, 3]).await;
+        let models = fetch_source_models(&Client::new(), &config, &SyncSource::Collection(7))
+            .await
+            .unwrap();
+        assert!(models.is_empty());
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,575 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use indicatif::MultiProgress;
+use reqwest::Client;
+
+use crate::{
+    artifacts::{Artifact, ArtifactSet},
+    configuration::Configuration,
+    errors::InvalidInputError,
+    hashing::AUTOV2_LENGTH,
+    progress::{ArtifactKind, OperationSummary, ReporterKind, SkipReason, StepProgress},
+    safetensors,
+};
+
+use super::{
+    download_task, is_not_found_error,
+    manual_match::{ManualMatch, ManualTarget},
+    meta,
+    model::{Model, ModelVersion},
+    selections, sidecar,
+};
+
+/// How metadata of a local model file is completed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompletionBehavior {
+    /// Files to produce beside the model file, the model itself is never touched.
+    pub artifacts: ArtifactSet,
+    /// Fetch community images metadata again instead of using the cached one.
+    pub refresh_images: bool,
+    /// Download the cover again when its source changed.
+    pub refresh_cover: bool,
+    /// Number of other versions listed in the readme with their descriptions, `None` lists none.
+    pub version_history: Option<usize>,
+    /// Model or version given by user for the file, instead of looking it up by hash.
+    pub manual_target: Option<ManualTarget>,
+    /// Use the given version even when none of its files matches the local file.
+    pub force_match: bool,
+    /// How many community image prompts the readme lists and how long they are.
+    pub prompt_limits: meta::PromptLimits,
+    /// Where the cover transfer is reported to.
+    pub reporter: ReporterKind,
+}
+
+/// Model file path with its directory, relative paths are resolved against current directory.
+fn resolve_model_file(source_file: &Path) -> Result<(PathBuf, PathBuf)> {
+    let source_file_path = if let Some(parent) = source_file.parent()
+        && parent.to_string_lossy().is_empty()
+    {
+        let parent_dir = env::current_dir().context("Unable to get current working directory")?;
+        parent_dir.join(source_file)
+    } else {
+        source_file.to_path_buf()
+    };
+    let working_dir = source_file_path.parent().map(Path::to_path_buf).unwrap();
+    crate::downloader::validate_output_dir(&working_dir, false)?;
+    Ok((source_file_path, working_dir))
+}
+
+pub async fn complete_file_meta<P>(
+    client: &Client,
+    config: &Configuration,
+    source_file: P,
+    behavior: &CompletionBehavior,
+) -> Result<OperationSummary>
+where
+    P: AsRef<Path>,
+{
+    let (source_file_path, working_dir) = resolve_model_file(source_file.as_ref())?;
+    let mut progress = StepProgress::new(7);
+
+    progress.begin("Calculating file hash...");
+    let source_file_hash = progress
+        .track(meta::blake3_hash(&source_file_path, None).await)
+        .context("Calculate file hash")?;
+    progress.println(format!(
+        "File hash: {}",
+        source_file_hash.to_ascii_uppercase()
+    ));
+
+    let summary = complete_hashed_file_meta(
+        client,
+        config,
+        &source_file_path,
+        &working_dir,
+        &source_file_hash,
+        behavior,
+        &mut progress,
+    )
+    .await?;
+    summary.print(progress.elapsed());
+    Ok(summary)
+}
+
+/// Completes metadata of a model file hashed before, its steps are reported to the shared
+/// progress display without drawing them, so several files can be completed at once.
+pub async fn complete_file_meta_with_hash(
+    client: &Client,
+    config: &Configuration,
+    source_file: &Path,
+    source_file_hash: &str,
+    behavior: &CompletionBehavior,
+    multi: &MultiProgress,
+) -> Result<OperationSummary> {
+    let (source_file_path, working_dir) = resolve_model_file(source_file)?;
+    let mut progress = StepProgress::attached(multi, 6);
+    complete_hashed_file_meta(
+        client,
+        config,
+        &source_file_path,
+        &working_dir,
+        source_file_hash,
+        behavior,
+        &mut progress,
+    )
+    .await
+}
+
+async fn complete_hashed_file_meta(
+    client: &Client,
+    config: &Configuration,
+    source_file_path: &Path,
+    working_dir: &PathBuf,
+    source_file_hash: &str,
+    behavior: &CompletionBehavior,
+    progress: &mut StepProgress,
+) -> Result<OperationSummary> {
+    let source_file_path = source_file_path.to_path_buf();
+    let source_file_hash = source_file_hash.to_string();
+    let mut summary = OperationSummary::default();
+
+    progress.begin("Saving file hash...");
+    if behavior.artifacts.contains(Artifact::Hash) {
+        progress
+            .track(meta::save_version_file_hash(&source_file_path, &source_file_hash).await)
+            .context("Save file hash")?;
+        summary.produce(Artifact::Hash);
+    } else {
+        progress.skip();
+    }
+
+    progress.begin("Requesting model version metadata...");
+    // 手动指定的版本优先于按哈希查找的结果
+    let lookup = match behavior.manual_target {
+        Some(target) => fetch_manual_version(
+            client,
+            config,
+            &source_file_path,
+            &source_file_hash,
+            target,
+            behavior.force_match,
+            progress,
+        )
+        .await
+        .map(|(version, hash)| (version, hash, true)),
+        None => match fetch_remembered_version(client, config, &source_file_hash, progress).await {
+            Ok(Some((version, hash))) => Ok((version, hash, true)),
+            Ok(None) => fetch_version_by_file_hashes(
+                client,
+                config,
+                &source_file_path,
+                &source_file_hash,
+                progress,
+            )
+            .await
+            .map(|(version, hash)| (version, Some(hash), false)),
+            Err(e) => Err(e),
+        },
+    };
+    let (model_version_meta, matched_hash, is_manual) = match progress.track(lookup) {
+        Ok(found) => found,
+        Err(e) if behavior.manual_target.is_some() => return Err(e),
+        Err(e)
+            if is_not_found_error(&e)
+                && behavior.artifacts.contains(Artifact::Readme)
+                && safetensors::is_safetensors_file(&source_file_path) =>
+        {
+            let header = safetensors::read_header(&source_file_path).context(
+                "Model is not found on Civitai, and its embedded metadata is unreadable",
+            )?;
+            progress.set_total_steps(4);
+            progress.begin("Saving readme from embedded metadata...");
+            let readme_path = progress
+                .track(meta::save_local_model_readme(&source_file_path, &header).await)
+                .context("Failed to save model readme file")?;
+            summary.readme_files.push(readme_path);
+            summary.produce(Artifact::Readme);
+            return Ok(summary);
+        }
+        Err(e) if is_not_found_error(&e) => {
+            progress.println("No model on Civitai has a file of this hash.");
+            summary.skip(
+                source_file_path.display().to_string(),
+                SkipReason::NotFoundOnCivitai,
+            );
+            return Ok(summary);
+        }
+        Err(e) => return Err(e),
+    };
+
+    progress.begin("Collecting related model metadata...");
+    let model_meta = progress
+        .track(meta::fetch_model_metadata(client, config, model_version_meta.model_id()).await)
+        .context("Request for model metadata")?;
+    let model_version_meta = match matched_hash.as_deref() {
+        Some(hash)
+            if !is_manual
+                && !model_version_meta
+                    .files()?
+                    .iter()
+                    .any(|f| f.match_by_hash(hash)) =>
+        {
+            resolve_version_by_hash(
+                client,
+                config,
+                &model_meta,
+                model_version_meta,
+                hash,
+                progress,
+            )
+            .await?
+        }
+        _ => model_version_meta,
+    };
+    let source_file_name = source_file_path
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+    let source_version_file = match matched_hash.as_deref() {
+        Some(hash) => model_version_meta
+            .files()?
+            .into_iter()
+            .find(|f| f.match_by_hash(hash)),
+        None => None,
+    };
+    if behavior.artifacts.contains(Artifact::Json) {
+        sidecar::save_sidecar(
+            &source_file_path,
+            &model_meta,
+            &model_version_meta,
+            source_version_file.as_ref(),
+        )
+        .await
+        .context("Failed to save model metadata sidecar")?;
+        summary.produce(Artifact::Json);
+    }
+    if behavior.artifacts.contains(Artifact::Triggers) {
+        match meta::save_trained_words(&source_file_path, &model_version_meta).await {
+            Ok(Some(_)) => summary.produce(Artifact::Triggers),
+            Ok(None) => {}
+            Err(e) => summary.fail(source_file_name.clone(), ArtifactKind::TrainedWords, e),
+        }
+    }
+
+    progress.begin("Downloading cover image...");
+    let cover_image_file_name = if behavior.artifacts.contains(Artifact::Cover) {
+        let mut reporter = behavior
+            .reporter
+            .create(&format!("{source_file_name} cover"), progress);
+        match progress.track(
+            download_task::download_model_version_cover_image(
+                client,
+                config,
+                &model_version_meta,
+                download_task::ModelVersionFileNamePresent::FileName(source_file_name.clone()),
+                Some(working_dir),
+                behavior.refresh_cover,
+                None,
+                reporter.as_mut(),
+            )
+            .await,
+        ) {
+            Ok(file_name) => {
+                summary.produce(Artifact::Cover);
+                file_name
+            }
+            Err(e) => {
+                summary.fail(source_file_name.clone(), ArtifactKind::Cover, e);
+                None
+            }
+        }
+    } else {
+        progress.skip();
+        None
+    };
+
+    progress.begin("Collecting related community images metadata...");
+    let related_community_images = if behavior.artifacts.contains(Artifact::Samples)
+        && behavior.artifacts.contains(Artifact::Readme)
+    {
+        match progress.track(
+            meta::fetch_model_community_images(
+                client,
+                config,
+                model_meta.id(),
+                behavior.refresh_images,
+            )
+            .await,
+        ) {
+            Ok(images) => images,
+            Err(e) => {
+                summary.fail(source_file_name.clone(), ArtifactKind::CommunityImages, e);
+                Vec::new()
+            }
+        }
+    } else {
+        progress.skip();
+        Vec::new()
+    };
+
+    progress.begin("Saving model version readme file...");
+    if !behavior.artifacts.contains(Artifact::Readme) {
+        progress.skip();
+        return Ok(summary);
+    }
+    let version_history = match behavior.version_history {
+        Some(limit) => {
+            meta::collect_version_history(
+                client,
+                config,
+                &model_meta,
+                model_version_meta.id(),
+                limit,
+            )
+            .await
+        }
+        None => Vec::new(),
+    };
+    let readme_path = progress
+        .track(
+            meta::save_model_version_readme(
+                config,
+                &model_meta,
+                &model_version_meta,
+                &related_community_images,
+                &version_history,
+                cover_image_file_name,
+                Some(working_dir),
+                source_file_name,
+                behavior.prompt_limits,
+            )
+            .await,
+        )
+        .context("Failed to save model version readme file")?;
+    summary.readme_files.push(readme_path);
+    summary.produce(Artifact::Readme);
+    if !related_community_images.is_empty() {
+        summary.produce(Artifact::Samples);
+    }
+
+    Ok(summary)
+}
+
+/// Looks the file up by its blake3 hash, then by its sha256 and AutoV2 hashes, which Civitai
+/// indexed older files by. Returns the version with the hash that found it.
+async fn fetch_version_by_file_hashes(
+    client: &Client,
+    config: &Configuration,
+    source_file_path: &Path,
+    blake3: &str,
+    progress: &StepProgress,
+) -> Result<(ModelVersion, String)> {
+    let mut error = match meta::fetch_model_version_meta_by_hash(client, config, blake3).await {
+        Ok(version) => return Ok((version, blake3.to_string())),
+        Err(e) if is_not_found_error(&e) => e,
+        Err(e) => return Err(e),
+    };
+    let sha256 = local_sha256(source_file_path, progress).await?;
+    for hash in [sha256.as_str(), &sha256[..AUTOV2_LENGTH]] {
+        match meta::fetch_model_version_meta_by_hash(client, config, hash).await {
+            Ok(version) => return Ok((version, hash.to_string())),
+            Err(e) if is_not_found_error(&e) => error = e,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(error)
+}
+
+/// SHA256 hash of the local file, calculated and recorded beside it when not recorded yet.
+async fn local_sha256(source_file_path: &Path, progress: &StepProgress) -> Result<String> {
+    // 记录在文件旁的SHA256可以省去再次计算
+    if let Some(hash) = meta::load_version_file_sha256(source_file_path) {
+        return Ok(hash);
+    }
+    progress.println("Not found by BLAKE3 hash, try SHA256 hash.");
+    let hash = meta::sha256_hash(source_file_path, Some(progress.multi()))
+        .await
+        .context("Calculate file SHA256 hash")?;
+    meta::save_version_file_sha256(source_file_path, &hash)
+        .await
+        .context("Save file SHA256 hash")?;
+    Ok(hash)
+}
+
+/// Version given by user for the file, bypassing the lookup by hash. A model without a version
+/// uses its version having a file equal to the local one. The version must have such a file
+/// unless forced, the association is then remembered for later renews and scans.
+/// Returns the version with the hash of its file that matched.
+async fn fetch_manual_version(
+    client: &Client,
+    config: &Configuration,
+    source_file_path: &Path,
+    blake3: &str,
+    target: ManualTarget,
+    force: bool,
+    progress: &StepProgress,
+) -> Result<(ModelVersion, Option<String>)> {
+    let candidates = match (target.model_id, target.version_id) {
+        (model_id, Some(version_id)) => {
+            let version = meta::fetch_model_version_meta(client, config, version_id).await?;
+            if let Some(model_id) = model_id
+                && version.model_id() != model_id
+            {
+                return Err(InvalidInputError(format!(
+                    "Model version {version_id} belongs to model {}, not model {model_id}",
+                    version.model_id()
+                ))
+                .into());
+            }
+            vec![version]
+        }
+        (Some(model_id), None) => {
+            let model_meta = meta::fetch_model_metadata(client, config, model_id)
+                .await
+                .context("Request for model metadata")?;
+            let mut versions = Vec::new();
+            for brief in model_meta.versions()? {
+                versions.push(meta::fetch_model_version_meta(client, config, brief.id()).await?);
+            }
+            versions
+        }
+        (None, None) => {
+            return Err(InvalidInputError("No model or model version is given".to_string()).into());
+        }
+    };
+    let has_hash = |version: &ModelVersion, hash: &str| {
+        version
+            .files()
+            .is_ok_and(|files| files.iter().any(|f| f.match_by_hash(hash)))
+    };
+    let mut matched = candidates
+        .iter()
+        .position(|version| has_hash(version, blake3))
+        .map(|index| (index, blake3.to_string()));
+    if matched.is_none() {
+        // 较早的文件在Civitai上可能只有SHA256
+        let sha256 = local_sha256(source_file_path, progress).await?;
+        matched = candidates
+            .iter()
+            .position(|version| has_hash(version, &sha256))
+            .map(|index| (index, sha256));
+    }
+
+    let (index, matched_hash) = match matched {
+        Some((index, hash)) => (index, Some(hash)),
+        None => {
+            let given = match target.version_id {
+                Some(version_id) => format!("model version {version_id}"),
+                None => format!(
+                    "any version of model {}",
+                    target.model_id.unwrap_or_default()
+                ),
+            };
+            progress.println(format!(
+                "WARNING: None of the files of {given} matches \"{}\".",
+                source_file_path.display()
+            ));
+            if !force {
+                return Err(InvalidInputError(format!(
+                    "None of the files of {given} matches the local file, give --force to associate them anyway"
+                ))
+                .into());
+            }
+            // 模型的版本从新到旧排列
+            (0, None)
+        }
+    };
+    let Some(version) = candidates.into_iter().nth(index) else {
+        return Err(InvalidInputError(format!(
+            "Model {} has no version",
+            target.model_id.unwrap_or_default()
+        ))
+        .into());
+    };
+    ManualMatch::new(version.model_id(), version.id(), matched_hash.clone())
+        .save(blake3)
+        .context("Save association of the file with the model version")?;
+    progress.println(format!(
+        "Associated the file with version {} of model {}.",
+        version.name(),
+        version.model_id()
+    ));
+    Ok((version, matched_hash))
+}
+
+/// Version associated with the file by hand before, `None` when there is none or it is no
+/// longer on Civitai.
+async fn fetch_remembered_version(
+    client: &Client,
+    config: &Configuration,
+    blake3: &str,
+    progress: &StepProgress,
+) -> Result<Option<(ModelVersion, Option<String>)>> {
+    let Some(manual_match) = ManualMatch::load(blake3)? else {
+        return Ok(None);
+    };
+    match meta::fetch_model_version_meta(client, config, manual_match.version_id).await {
+        Ok(version) => {
+            progress.println(format!(
+                "Use version {} associated with the file by hand.",
+                version.name()
+            ));
+            Ok(Some((version, manual_match.matched_hash)))
+        }
+        Err(e) if is_not_found_error(&e) => {
+            progress.println(format!(
+                "WARNING: Model version {} associated with the file by hand is not found, look it up by hash.",
+                manual_match.version_id
+            ));
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Looks for the versions of the model having a file of the hash, when the version Civitai
+/// returned for the hash does not have it, like a file uploaded again to another version.
+/// Several matching versions are chosen by user, the newest one when nobody can answer.
+async fn resolve_version_by_hash(
+    client: &Client,
+    config: &Configuration,
+    model_meta: &Model,
+    returned_version: ModelVersion,
+    hash: &str,
+    progress: &StepProgress,
+) -> Result<ModelVersion> {
+    progress.println(format!(
+        "WARNING: Version {} returned by Civitai has no file of hash {}, looking for it in other versions...",
+        returned_version.name(),
+        hash.to_ascii_uppercase()
+    ));
+    let mut matched_briefs = Vec::new();
+    let mut matched_versions = Vec::new();
+    for brief in model_meta.versions()? {
+        if brief.id() == returned_version.id() {
+            continue;
+        }
+        match meta::fetch_model_version_meta(client, config, brief.id()).await {
+            Ok(version) => {
+                if version.files()?.iter().any(|f| f.match_by_hash(hash)) {
+                    matched_briefs.push(brief);
+                    matched_versions.push(version);
+                }
+            }
+            Err(e) => progress.println(format!("Skip model version {}: {e}", brief.id())),
+        }
+    }
+    let index = match matched_versions.len() {
+        0 => {
+            progress.println("No other version has the file, use the returned version.");
+            return Ok(returned_version);
+        }
+        1 => 0,
+        _ => progress
+            .multi()
+            .suspend(|| selections::select_hash_version(&matched_briefs)),
+    };
+    let version = matched_versions.swap_remove(index);
+    progress.println(format!("Use the metadata of version {}.", version.name()));
+    Ok(version)
+}
@@ -0,0 +1,289 @@
+//! Choosing the cover among the images of a version, previewing the image under the cursor on
+//! terminals that can show images inline.
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io::{IsTerminal, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use dialoguer::console::{Key, Term, style};
+use reqwest::Client;
+
+use crate::{downloader::read_body_limited, events, prompt};
+
+use super::{ImageMeta, cdn, model::ModelImage, selections};
+
+/// Width the CDN resizes thumbnails to.
+const THUMBNAIL_WIDTH: u32 = 256;
+/// Longest wait for a thumbnail, the list is shown without preview after it.
+const THUMBNAIL_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Terminal rows taken by the preview.
+const PREVIEW_ROWS: usize = 12;
+/// Images listed around the cursor at once.
+const VISIBLE_ITEMS: usize = 7;
+/// Largest thumbnail response read, resized thumbnails are far smaller.
+const MAX_THUMBNAIL_BODY_SIZE: usize = 4 * 1024 * 1024;
+/// Base64 payload size of one kitty graphics escape sequence.
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Inline image protocols of terminals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GraphicsProtocol {
+    Kitty,
+    Iterm2,
+}
+
+impl GraphicsProtocol {
+    /// Protocol of the terminal the prompts are shown in, by the variables the terminals set.
+    /// Terminal multiplexers pass neither through, so nothing is detected inside them.
+    fn detect() -> Option<Self> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let env = |name: &str| std::env::var(name).unwrap_or_default();
+        if env("TERM_PROGRAM") == "tmux" || env("TERM").starts_with("screen") {
+            return None;
+        }
+        if !env("KITTY_WINDOW_ID").is_empty()
+            || env("TERM") == "xterm-kitty"
+            || env("TERM_PROGRAM") == "ghostty"
+        {
+            return Some(Self::Kitty);
+        }
+        match env("TERM_PROGRAM").as_str() {
+            "iTerm.app" | "WezTerm" => Some(Self::Iterm2),
+            _ => None,
+        }
+    }
+
+    /// Escape sequences drawing the PNG image over the given number of rows.
+    fn draw(self, png: &[u8], rows: usize) -> String {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(png);
+        match self {
+            Self::Kitty => {
+                let chunks = encoded
+                    .as_bytes()
+                    .chunks(KITTY_CHUNK_SIZE)
+                    .collect::<Vec<_>>();
+                let mut sequence = String::new();
+                for (index, chunk) in chunks.iter().enumerate() {
+                    let more = u8::from(index + 1 < chunks.len());
+                    let chunk = String::from_utf8_lossy(chunk);
+                    if index == 0 {
+                        sequence.push_str(&format!(
+                            "\x1b_Gf=100,a=T,q=2,r={rows},m={more};{chunk}\x1b\\"
+                        ));
+                    } else {
+                        sequence.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\"));
+                    }
+                }
+                sequence
+            }
+            Self::Iterm2 => format!(
+                "\x1b]1337;File=inline=1;size={};height={rows};preserveAspectRatio=1:{encoded}\x07",
+                png.len()
+            ),
+        }
+    }
+
+    /// Escape sequence removing the images drawn before, kitty keeps them apart from the text.
+    fn clear(self) -> &'static str {
+        match self {
+            Self::Kitty => "\x1b_Ga=d,q=2\x1b\\",
+            Self::Iterm2 => "",
+        }
+    }
+}
+
+/// Prompts for the cover among the images of a version, returning the index of the chosen one.
+/// Where the terminal can show images, the image under the cursor is previewed, otherwise a plain
+/// list is shown. `None` when there is nothing to choose or no one to ask.
+pub async fn pick_cover(client: &Client, images: &[ModelImage]) -> Result<Option<usize>> {
+    if images.len() < 2 || events::enabled() || !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let labels = selections::cover_choice_labels(images);
+    // 有超时的提示框需要在无人应答时取默认值，只有普通列表支持
+    let protocol = GraphicsProtocol::detect().filter(|_| !prompt::has_timeout());
+    let Some(protocol) = protocol else {
+        let selected = tokio::task::spawn_blocking(move || selections::select_cover(&labels))
+            .await
+            .context("Cover selection is interrupted")??;
+        return Ok(Some(selected));
+    };
+
+    let term = Term::stderr();
+    let mut previews: HashMap<usize, Option<Vec<u8>>> = HashMap::new();
+    let mut cursor = 0;
+    let mut drawn_lines = 0;
+    term.hide_cursor()?;
+    let selected = loop {
+        if let Entry::Vacant(entry) = previews.entry(cursor) {
+            let preview =
+                tokio::time::timeout(THUMBNAIL_TIMEOUT, load_thumbnail(client, &images[cursor]))
+                    .await
+                    .ok()
+                    .and_then(Result::ok);
+            entry.insert(preview);
+        }
+        let mut screen = String::new();
+        screen.push_str(&format!(
+            "{} {}\n",
+            style("Select the cover image").bold(),
+            style("(↑/↓ to move, Enter to pick, Esc to keep the default)").dim()
+        ));
+        let first_visible = cursor
+            .saturating_sub(VISIBLE_ITEMS / 2)
+            .min(labels.len().saturating_sub(VISIBLE_ITEMS));
+        for (index, label) in labels
+            .iter()
+            .enumerate()
+            .skip(first_visible)
+            .take(VISIBLE_ITEMS)
+        {
+            if index == cursor {
+                screen.push_str(&format!("{} {}\n", style(">").cyan(), style(label).cyan()));
+            } else {
+                screen.push_str(&format!("  {label}\n"));
+            }
+        }
+        let mut lines = labels.len().min(VISIBLE_ITEMS) + 1;
+        match previews.get(&cursor).and_then(Option::as_ref) {
+            Some(png) => {
+                screen.push_str(&protocol.draw(png, PREVIEW_ROWS));
+                screen.push('\n');
+                lines += PREVIEW_ROWS;
+            }
+            None => {
+                screen.push_str(&format!("{}\n", style("  (no preview)").dim()));
+                lines += 1;
+            }
+        }
+        term.clear_last_lines(drawn_lines)?;
+        let mut output = &term;
+        output.write_all(protocol.clear().as_bytes())?;
+        output.write_all(screen.as_bytes())?;
+        output.flush()?;
+        drawn_lines = lines;
+
+        let key_term = term.clone();
+        let key = tokio::task::spawn_blocking(move || key_term.read_key())
+            .await
+            .context("Cover selection is interrupted")??;
+        match key {
+            Key::ArrowUp | Key::Char('k') => {
+                cursor = cursor.checked_sub(1).unwrap_or(labels.len() - 1);
+            }
+            Key::ArrowDown | Key::Char('j') | Key::Tab => cursor = (cursor + 1) % labels.len(),
+            Key::Enter => break Some(cursor),
+            Key::Escape | Key::Char('q') => break None,
+            _ => {}
+        }
+    };
+    term.clear_last_lines(drawn_lines)?;
+    let mut output = &term;
+    output.write_all(protocol.clear().as_bytes())?;
+    term.show_cursor()?;
+    if let Some(index) = selected {
+        events::message(format!("Cover image: {}", labels[index].trim_end()));
+    }
+    Ok(selected)
+}
+
+/// Thumbnail of the image as PNG, from the cache or resized by the CDN. Videos are previewed by
+/// a still frame. Images off the CDN are not previewed, their full size originals are not
+/// downloaded for it.
+async fn load_thumbnail(client: &Client, image: &ModelImage) -> Result<Vec<u8>> {
+    let cache_path = thumbnail_cache_path(image);
+    if let Some(path) = cache_path.as_ref()
+        && let Ok(png) = tokio::fs::read(path).await
+    {
+        return Ok(png);
+    }
+    let thumbnail_url = thumbnail_url(image).context("Image is not on the image CDN")?;
+    let response = client
+        .get(&thumbnail_url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let bytes = read_body_limited(response, MAX_THUMBNAIL_BODY_SIZE).await?;
+    let png = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+        let thumbnail =
+            image::load_from_memory(&bytes)?.thumbnail(THUMBNAIL_WIDTH, THUMBNAIL_WIDTH * 2);
+        let mut png = std::io::Cursor::new(Vec::new());
+        thumbnail.write_to(&mut png, image::ImageFormat::Png)?;
+        Ok(png.into_inner())
+    })
+    .await??;
+    if let Some(path) = cache_path {
+        // 缓存失败不影响预览
+        if let Some(dir) = path.parent() {
+            let _ = tokio::fs::create_dir_all(dir).await;
+        }
+        let _ = tokio::fs::write(&path, &png).await;
+    }
+    Ok(png)
+}
+
+/// URL of the thumbnail resized by the CDN, a still frame for videos.
+fn thumbnail_url(image: &ModelImage) -> Option<String> {
+    let url = image.url();
+    if image.media_type().eq_ignore_ascii_case("video") {
+        cdn::video_poster_url(&url)
+            .and_then(|poster| cdn::resized_image_url(&poster, THUMBNAIL_WIDTH))
+    } else {
+        cdn::resized_image_url(&url, THUMBNAIL_WIDTH)
+    }
+}
+
+/// Thumbnails are cached by image id, or by a hash of the URL for images without one.
+fn thumbnail_cache_path(image: &ModelImage) -> Option<PathBuf> {
+    let key = match image.id() {
+        Some(id) => id.to_string(),
+        None => blake3::hash(image.url().as_bytes()).to_hex()[..16].to_string(),
+    };
+    crate::cache_db::cache_dir().map(|dir| dir.join("thumbnails").join(format!("{key}.png")))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn image(url: &str, media_type: &str) -> ModelImage {
+        ModelImage::try_from(&json!({
+            "url": url,
+            "type": media_type,
+            "hasMeta": false,
+            "hasPositivePrompt": false
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn only_cdn_resized_images_are_previewed() {
+        let base = "https://image.civitai.com/xG1nkqKTMzGDvpLrqFT7WA/3f6c1b2a-0d4e";
+        assert_eq!(
+            thumbnail_url(&image(&format!("{base}/width=1024/cover.png"), "image")),
+            Some(format!("{base}/width=256/cover.png"))
+        );
+        assert!(
+            thumbnail_url(&image(&format!("{base}/clip.mp4"), "video"))
+                .is_some_and(|url| url.contains("/width=256/"))
+        );
+        // 不在CDN上的原图不下载预览
+        assert_eq!(
+            thumbnail_url(&image("https://example.com/full/cover.png", "image")),
+            None
+        );
+        assert_eq!(
+            thumbnail_url(&image("https://example.com/full/clip.mp4", "video")),
+            None
+        );
+    }
+}
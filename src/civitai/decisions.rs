@@ -0,0 +1,68 @@
+//! Answers to the prompt about files downloaded to another location before, remembered so that
+//! the same file is not asked about on every download touching it.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use time::UtcDateTime;
+
+use crate::cache_db;
+
+/// Set once "Redownload all" is picked, later files of the same run are downloaded again without
+/// asking.
+static REDOWNLOAD_ALL: AtomicBool = AtomicBool::new(false);
+
+/// A skip answer to the existing file prompt, kept in cache database by blake3 hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExistingFileDecision {
+    /// Unix timestamp of the answer.
+    pub decided_at: i64,
+    /// Never ask again, regardless of the configured time.
+    #[serde(default)]
+    pub forever: bool,
+}
+
+impl ExistingFileDecision {
+    /// Whether the answer still stands after the given time.
+    fn is_valid(&self, ttl: Duration) -> bool {
+        self.forever
+            || (!ttl.is_zero()
+                && UtcDateTime::now().unix_timestamp() - self.decided_at < ttl.as_secs() as i64)
+    }
+}
+
+/// The remembered skip answer of the file, when it still stands.
+pub(super) fn remembered_skip(hash: &str, ttl: Duration) -> Option<ExistingFileDecision> {
+    cache_db::retreive_existing_file_decision(hash)
+        .ok()
+        .flatten()
+        .filter(|decision| decision.is_valid(ttl))
+}
+
+pub(super) fn remember_skip(hash: &str, forever: bool) -> Result<()> {
+    cache_db::store_existing_file_decision(
+        hash,
+        &ExistingFileDecision {
+            decided_at: UtcDateTime::now().unix_timestamp(),
+            forever,
+        },
+    )
+}
+
+pub(super) fn redownload_all() -> bool {
+    REDOWNLOAD_ALL.load(Ordering::Relaxed)
+}
+
+pub(super) fn set_redownload_all() {
+    REDOWNLOAD_ALL.store(true, Ordering::Relaxed);
+}
+
+/// Clears every remembered answer, returns how many were cleared.
+pub fn forget_decisions() -> Result<usize> {
+    cache_db::remove_existing_file_decisions()
+}
@@ -1,99 +1,932 @@
-use std::{cmp::min, env, io::Cursor, path::PathBuf};
+use std::{
+    env,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, anyhow};
+use anyhow::{Context, anyhow, bail};
+use backoff::backoff::Backoff;
 use futures_util::StreamExt;
 use image::ImageReader;
 use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::Client;
-use tokio::{fs::File, io::AsyncWriteExt};
+use reqwest::{Client, Response, StatusCode, Url, header};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
 
 use crate::{
-    cache_db,
-    civitai::{
-        ImageMeta,
-        meta::{self, save_version_file_hash},
+    archive, cache_db,
+    civitai::{ImageMeta, cdn, meta},
+    configuration::{Configuration, ExistingCheck, ProxyConfig, VideoCoverMode},
+    downloader::{make_backoff_policy, read_body_prefix},
+    errors::{
+        CloudflareChallengeError, FileStillProcessingError, TransferError, UnexpectedResponseError,
     },
-    downloader::make_backoff_policy,
-    utils::duration_to_sec_string,
+    events,
+    progress::{DownloadReporter, FileSummary, StepProgress},
+    sink::{DownloadSink, HashingStream},
+    staging,
+    utils::{datetime_to_date_string, format_duration, sanitize_file_name},
 };
 
-use super::model;
+use super::{model, resolved_url, transfer_stats::TransferTracker};
+
+const SIZE_TOLERANCE_RATIO: f64 = 0.01;
+/// Bytes of an HTML page read in place of a model file, enough to detect a Cloudflare challenge.
+const ERROR_PAGE_READ_SIZE: usize = 256 * 1024;
+/// Bytes read from each end of an existing file by the sample check.
+const SAMPLE_CHECK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// A model file download URL with the access key placed as the configured mode asks. The key is
+/// only attached for the Civitai site and the configured API host.
+struct DownloadTarget {
+    file_id: u64,
+    /// URL requested, carrying the key when it is sent as query parameter.
+    url: Url,
+    /// URL shown in messages, never carrying the key.
+    display_url: String,
+    /// Key sent in the Authorization header.
+    bearer_key: Option<String>,
+    /// URL carrying the key as `token` query parameter, tried in `auto` mode when the header is
+    /// rejected.
+    query_token_url: Option<Url>,
+}
+
+impl DownloadTarget {
+    fn new(
+        file_id: u64,
+        download_url: &str,
+        civitai: &crate::configuration::CivitaiConfig,
+    ) -> anyhow::Result<Self> {
+        let mut url = Url::parse(download_url)
+            .with_context(|| format!("Invalid model file download URL: {download_url}"))?;
+        let auth_key = civitai.api_key.as_deref().unwrap_or_default();
+        let has_token = url.query_pairs().any(|(name, _)| name == "token");
+        let with_token = |url: &Url| {
+            let mut url = url.clone();
+            if !has_token {
+                url.query_pairs_mut().append_pair("token", auth_key);
+            }
+            url
+        };
+        // 镜像等第三方主机不论哪种方式都收不到密钥
+        let (bearer_key, query_token_url) =
+            if auth_key.is_empty() || !civitai.sends_key_to(download_url) {
+                (None, None)
+            } else if civitai.auth_mode.uses_query_token() {
+                url = with_token(&url);
+                (None, None)
+            } else {
+                let fallback = civitai
+                    .auth_mode
+                    .falls_back_to_query_token()
+                    .then(|| with_token(&url));
+                (Some(auth_key.to_string()), fallback)
+            };
+        Ok(Self {
+            file_id,
+            url,
+            display_url: download_url.to_string(),
+            bearer_key,
+            query_token_url,
+        })
+    }
+
+    /// Target sending the key in the query instead, when the response shows that the host the
+    /// header was sent to rejected it and the auth mode allows the fallback.
+    fn query_token_fallback(&self, response: &Response) -> Option<Self> {
+        let url = self.query_token_url.as_ref()?;
+        // 重定向到其他主机时请求头已被移除，那里的401/403与密钥无关
+        let rejected = matches!(
+            response.status(),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+        ) && response.url().host_str() == self.url.host_str();
+        rejected.then(|| Self {
+            file_id: self.file_id,
+            url: url.clone(),
+            display_url: self.display_url.clone(),
+            bearer_key: None,
+            query_token_url: None,
+        })
+    }
+
+    /// Target of the file with the configured key, download URL rewrite and auth mode.
+    fn from_config(
+        civitai: &crate::configuration::CivitaiConfig,
+        selected_file: &model::ModelVersionFile,
+    ) -> anyhow::Result<Self> {
+        let download_url = if selected_file.is_primary().unwrap_or_default() {
+            selected_file.download_url()
+        } else {
+            selected_file.variant_download_url()
+        }
+        .ok_or_else(|| FileStillProcessingError(selected_file.name()))?;
+        let download_url = civitai.rewrite_download_url(&download_url);
+        Self::new(selected_file.id(), &download_url, civitai)
+    }
+
+    /// Target of a URL the download was redirected to before, authorized by its own signature.
+    fn resolved(&self, url: Url) -> Self {
+        Self {
+            file_id: self.file_id,
+            url,
+            display_url: self.display_url.clone(),
+            bearer_key: None,
+            query_token_url: None,
+        }
+    }
 
+    /// Drops the URL from errors when it carries the key, so that the key is never printed.
+    fn redact(&self, e: reqwest::Error) -> reqwest::Error {
+        if self.bearer_key.is_none() {
+            e.without_url()
+        } else {
+            e
+        }
+    }
+}
+
+/// Extensions of the cover files, PNG for images and the formats kept for video covers.
+const COVER_EXTENSIONS: [&str; 5] = ["png", "mp4", "webm", "mov", "gif"];
+
+/// Downloads the file of the version into the target path with the given settings, verifying
+/// its size and blake3 hash and recording its location in the cache database. Progress and
+/// warnings go to the reporter.
 pub async fn download_single_model_file(
     client: &Client,
+    config: &Configuration,
     model_version_meta: &model::ModelVersion,
     file_id: u64,
-    destination_path: Option<&PathBuf>,
-) -> anyhow::Result<String> {
+    target_file_path: &Path,
+    reporter: &mut dyn DownloadReporter,
+) -> anyhow::Result<FileSummary> {
     let selected_file = model_version_meta
         .files()?
         .into_iter()
         .find(|f| f.id() == file_id)
         .ok_or(anyhow!("Request model file is not found"))?;
-    println!("Downloading file: {}", selected_file.name());
-    let target_file_path = match destination_path {
-        Some(given_path) => given_path.clone(),
-        None => env::current_dir()?,
+    reporter.on_message(&format!("Downloading file: {}", selected_file.name()));
+    warn_pickle_format(&selected_file, reporter);
+    let target_file_path = target_file_path.to_path_buf();
+    // 设置了临时目录时，先下载到临时目录，校验后再移动到目标位置
+    let staged_path = staging::staging_path(&config.download, &target_file_path).await?;
+    let mut file = File::create(&staged_path)
+        .await
+        .with_context(|| format!("Failed to create {}", staged_path.display()))?;
+    let fetched = fetch_model_file(
+        client,
+        config,
+        model_version_meta,
+        &selected_file,
+        &mut file,
+        reporter,
+    )
+    .await;
+    // 写入磁盘失败时同样丢弃未完成的文件
+    let fetched = match fetched {
+        Ok(_) => file
+            .flush()
+            .await
+            .with_context(|| format!("Failed to write {}", staged_path.display())),
+        Err(e) => Err(e),
+    };
+    drop(file);
+    if let Err(e) = fetched {
+        staging::discard(&staged_path, &target_file_path).await;
+        return Err(e);
     }
-    .join(selected_file.name());
-    let config = crate::configuration::CONFIGURATION.read().await;
-    let civitai_auth_key = config.civitai.api_key.clone().unwrap_or_default();
-    let download_request = client
-        .request(reqwest::Method::GET, selected_file.download_url())
-        .bearer_auth(civitai_auth_key);
-    let request = download_request.build()?;
+    reporter.on_finish();
 
-    let response = client.execute(request).await?;
+    // Check received size against the size declared in metadata
+    let received_size = tokio::fs::metadata(&staged_path).await?.len();
+    warn_size_mismatch(&selected_file, received_size, reporter);
 
-    let file_legnth = response
-        .content_length()
-        .ok_or(anyhow!("Incorrect model file length"))?;
+    // Run blake3 check
+    let blake3_checksum = meta::blake3_hash(&staged_path, reporter.multi_progress()).await?;
+    let hash_matched = check_blake3(&selected_file, &blake3_checksum, reporter);
+
+    if staging::is_staged(&staged_path, &target_file_path) {
+        reporter.on_message(&format!("Moving {} into place...", selected_file.name()));
+        staging::finish(&staged_path, &target_file_path).await?;
+    }
 
-    let pb = ProgressBar::new(file_legnth);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{wide_bar:.cyan/blue}] {decimal_bytes}/{decimal_total_bytes} [{elapsed}] ETA:{eta}")?
+    cache_db::store_civitai_model_file_location(
+        model_version_meta.model_id(),
+        model_version_meta.id(),
+        file_id,
+        &blake3_checksum,
+        &target_file_path,
+    )
+    .context("Store file location to cache database")?;
+
+    Ok(FileSummary {
+        name: target_file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| sanitize_file_name(&selected_file.name())),
+        path: target_file_path,
+        size: received_size,
+        blake3: Some(blake3_checksum),
+        hash_matched,
+        page_url: Some(model_version_meta.page_url()),
+    })
+}
+
+/// Extracts the downloaded zip archive into a directory beside it, named after the archive, and
+/// deletes the archive with its hash, readme and sidecar afterwards when asked. Returns the number
+/// of extracted files.
+pub async fn extract_model_archive(
+    archive_path: &Path,
+    remove_archive: bool,
+    progress: &StepProgress,
+) -> anyhow::Result<usize> {
+    let destination = archive::extraction_dir(archive_path);
+    let bar = progress.multi().add(ProgressBar::new(0));
+    bar.set_style(
+        ProgressStyle::with_template("Extracting [{wide_bar:.cyan/blue}] {pos}/{len} {msg}")
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
             .progress_chars("=>-"),
     );
-    let mut file = File::create(&target_file_path).await?;
+    let extracted = {
+        let archive_path = archive_path.to_path_buf();
+        let destination = destination.clone();
+        let bar = bar.clone();
+        tokio::task::spawn_blocking(move || {
+            archive::extract(&archive_path, &destination, Some(&bar))
+        })
+        .await
+    };
+    bar.finish_and_clear();
+    let extracted = extracted.context("Archive extraction is interrupted")??;
+    progress.println(format!(
+        "Extracted {} files into {}",
+        extracted.len(),
+        destination.display()
+    ));
+
+    if remove_archive {
+        tokio::fs::remove_file(archive_path)
+            .await
+            .with_context(|| format!("Failed to delete {}", archive_path.display()))?;
+        cache_db::remove_civitai_model_file_location(archive_path)
+            .context("Forget file location in cache database")?;
+        for companion in archive_companions(archive_path) {
+            match tokio::fs::remove_file(&companion).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to delete {}", companion.display()));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(extracted.len())
+}
+
+/// Hash, readme and sidecar saved beside the archive, named after it.
+fn archive_companions(archive_path: &Path) -> [PathBuf; 3] {
+    let stem = sanitize_file_name(
+        &archive_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default(),
+    );
+    [
+        archive_path.with_file_name(format!("{stem}.blake3")),
+        archive_path.with_file_name(format!("{stem}.md")),
+        super::sidecar::sidecar_path(archive_path),
+    ]
+}
+
+/// Streams the model file into the writer instead of a file, like stdout piped into another
+/// program. The content is hashed on the way, and nothing is written to disk or recorded.
+pub async fn stream_single_model_file<W>(
+    client: &Client,
+    config: &Configuration,
+    model_version_meta: &model::ModelVersion,
+    file_id: u64,
+    writer: W,
+    reporter: &mut dyn DownloadReporter,
+) -> anyhow::Result<FileSummary>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let selected_file = model_version_meta
+        .files()?
+        .into_iter()
+        .find(|f| f.id() == file_id)
+        .ok_or(anyhow!("Request model file is not found"))?;
+    warn_pickle_format(&selected_file, reporter);
+    let mut stream = HashingStream::new(writer, true, false);
+    fetch_model_file(
+        client,
+        config,
+        model_version_meta,
+        &selected_file,
+        &mut stream,
+        reporter,
+    )
+    .await?;
+    stream.flush().await?;
+    reporter.on_finish();
+
+    let received_size = stream.written();
+    warn_size_mismatch(&selected_file, received_size, reporter);
+    let blake3_checksum = stream.finalize().blake3.unwrap_or_default();
+    let hash_matched = check_blake3(&selected_file, &blake3_checksum, reporter);
+    Ok(FileSummary {
+        name: sanitize_file_name(&selected_file.name()),
+        path: PathBuf::from("-"),
+        size: received_size,
+        blake3: Some(blake3_checksum),
+        hash_matched,
+        page_url: Some(model_version_meta.page_url()),
+    })
+}
+
+fn warn_pickle_format(
+    selected_file: &model::ModelVersionFile,
+    reporter: &mut dyn DownloadReporter,
+) {
+    if selected_file.is_pickle_format() {
+        reporter.on_message(&format!(
+            "WARNING: {} is a pickle format file, which can run arbitrary code when loaded. Prefer .safetensors files when available.",
+            selected_file.name()
+        ));
+    }
+}
+
+fn warn_size_mismatch(
+    selected_file: &model::ModelVersionFile,
+    received_size: u64,
+    reporter: &mut dyn DownloadReporter,
+) {
+    let expected_size = selected_file.size_in_bytes();
+    if !is_size_within_tolerance(received_size, expected_size) {
+        reporter.on_message(&format!(
+            "WARNING: Received {received_size} bytes for file {}, but Civitai declares {expected_size} bytes. The downloaded file may be a different variant or incomplete.",
+            selected_file.name()
+        ));
+    }
+}
+
+/// Compares the hash of the received content with the declared one, `None` when Civitai
+/// declares no blake3 hash.
+fn check_blake3(
+    selected_file: &model::ModelVersionFile,
+    blake3_checksum: &str,
+    reporter: &mut dyn DownloadReporter,
+) -> Option<bool> {
+    let hash_matched = selected_file
+        .blake3_hash()
+        .map(|_| selected_file.match_by_blake3(blake3_checksum));
+    if hash_matched == Some(false) {
+        reporter.on_message(&format!(
+            "File {} blake3 check failed. Maybe need to redownload.",
+            selected_file.name()
+        ));
+    }
+    hash_matched
+}
+
+/// Downloads the content of the file into the sink, resuming interrupted transfers from where
+/// they stopped. Returns the number of bytes received.
+async fn fetch_model_file<S: DownloadSink>(
+    client: &Client,
+    config: &Configuration,
+    model_version_meta: &model::ModelVersion,
+    selected_file: &model::ModelVersionFile,
+    sink: &mut S,
+    reporter: &mut dyn DownloadReporter,
+) -> anyhow::Result<u64> {
+    let file_id = selected_file.id();
+    // 多个终端同时下载时，用模型和版本名称区分各自的进度条
+    reporter.set_label(&match model_version_meta.model_name() {
+        Some(model_name) => format!("{model_name} - {}", model_version_meta.name()),
+        None => model_version_meta.name(),
+    });
+    if config.download.terminal_title {
+        reporter.show_in_window_title(&selected_file.name());
+    }
+    let idle_timeout = config.network.idle_timeout();
+    let download_target = DownloadTarget::from_config(&config.civitai, selected_file)?;
+    let mut tracker = TransferTracker::new(file_id, selected_file.name(), config.proxy.is_in_use());
+
     let mut downloaded_size: u64 = 0;
-    let mut download_stream = response.bytes_stream();
+    let mut policy = make_backoff_policy(&config.backoff, idle_timeout.as_secs());
 
-    while let Some(chunk) = download_stream.next().await {
-        let chunk = chunk?;
-        file.write_all(&chunk).await?;
-        downloaded_size = min(downloaded_size + chunk.len() as u64, file_legnth);
-        pb.set_position(downloaded_size);
+    // 下载中断时，使用Range请求从已下载的位置继续下载
+    loop {
+        let attempt_started = Instant::now();
+        let size_before_attempt = downloaded_size;
+        let attempt = download_attempt(
+            client,
+            config,
+            &download_target,
+            model_version_meta,
+            sink,
+            &mut downloaded_size,
+            &mut tracker.host,
+            reporter,
+        )
+        .await;
+        let received_size = downloaded_size.saturating_sub(size_before_attempt);
+        tracker.attempt_finished(received_size, attempt_started.elapsed());
+        match attempt {
+            Ok(()) => break,
+            Err(backoff::Error::Permanent(e)) => {
+                tracker.record_failure(&e, received_size);
+                return Err(e);
+            }
+            Err(backoff::Error::Transient { err, retry_after }) => match policy.next_backoff() {
+                Some(wait) => {
+                    tracker.record_failure(&err, received_size);
+                    let wait = retry_after.map_or(wait, |delay| delay.max(wait));
+                    reporter.on_retry(wait, &err.to_string());
+                    tokio::time::sleep(wait).await;
+                }
+                None => {
+                    tracker.record_failure(&err, received_size);
+                    return Err(CloudflareChallengeError::advise(err));
+                }
+            },
+        }
     }
-    file.flush().await?;
+    tracker.record_success();
+    Ok(downloaded_size)
+}
 
-    pb.finish_with_message(format!("File {} download completed.", selected_file.name()));
+/// Places a verified copy of a previously downloaded file at the target path, hardlinked when
+/// both are on the same file system and copied otherwise.
+pub async fn link_existing_model_file(
+    model_version_meta: &model::ModelVersion,
+    file_id: u64,
+    source_file_path: &Path,
+    target_file_path: &Path,
+    reporter: &mut dyn DownloadReporter,
+) -> anyhow::Result<FileSummary> {
+    let selected_file = model_version_meta
+        .files()?
+        .into_iter()
+        .find(|f| f.id() == file_id)
+        .ok_or(anyhow!("Request model file is not found"))?;
 
-    // Run blake3 check
-    let blake3_checksum = meta::blake3_hash(&target_file_path)?;
+    // 链接之前确认已有文件没有被修改
+    let blake3_checksum = meta::blake3_hash(source_file_path, reporter.multi_progress()).await?;
+    if selected_file.blake3_hash().is_some() && !selected_file.match_by_blake3(&blake3_checksum) {
+        bail!(
+            "{} no longer matches the blake3 hash declared by Civitai",
+            source_file_path.display()
+        );
+    }
 
-    // Check crc32
-    if selected_file.match_by_blake3(&blake3_checksum) {
-        println!("File blake3 check passed.");
+    let same_file = target_file_path.exists()
+        && target_file_path.canonicalize()? == source_file_path.canonicalize()?;
+    if same_file {
+        reporter.on_message(&format!(
+            "File {} is already in place.",
+            target_file_path.display()
+        ));
     } else {
-        println!("File blake3 check failed. Maybe need to redownload.");
+        if target_file_path.exists() {
+            tokio::fs::remove_file(target_file_path)
+                .await
+                .with_context(|| format!("Failed to replace {}", target_file_path.display()))?;
+        }
+        link_or_copy(source_file_path, target_file_path, reporter).await?;
     }
 
-    // Record model blake3 hash
-    save_version_file_hash(&target_file_path, &blake3_checksum)
-        .await
-        .context("Save file blake3 hash record")?;
-
     cache_db::store_civitai_model_file_location(
         model_version_meta.model_id(),
         model_version_meta.id(),
         file_id,
         &blake3_checksum,
-        &target_file_path,
+        target_file_path,
     )
     .context("Store file location to cache database")?;
 
-    Ok(selected_file.name())
+    Ok(FileSummary {
+        name: target_file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| sanitize_file_name(&selected_file.name())),
+        path: target_file_path.to_path_buf(),
+        size: tokio::fs::metadata(target_file_path).await?.len(),
+        blake3: Some(blake3_checksum),
+        hash_matched: selected_file.blake3_hash().map(|_| true),
+        page_url: Some(model_version_meta.page_url()),
+    })
+}
+
+/// Checks whether the file already at the target path is the requested file, as far as the
+/// given check level goes. Files without a declared blake3 hash are never taken, their location
+/// could not be recorded.
+pub async fn check_file_at_target(
+    model_version_meta: &model::ModelVersion,
+    file_id: u64,
+    target_file_path: &Path,
+    check: ExistingCheck,
+    progress: &StepProgress,
+) -> anyhow::Result<bool> {
+    let selected_file = model_version_meta
+        .files()?
+        .into_iter()
+        .find(|f| f.id() == file_id)
+        .ok_or(anyhow!("Request model file is not found"))?;
+    let Some(declared_blake3) = selected_file.blake3_hash() else {
+        return Ok(false);
+    };
+    if check == ExistingCheck::Off || !target_file_path.is_file() {
+        return Ok(false);
+    }
+    // sizeKB只精确到KB以下，差距不足1KB视为相同大小
+    let size = tokio::fs::metadata(target_file_path).await?.len();
+    if size.abs_diff(selected_file.size_in_bytes()) >= 1024 {
+        return Ok(false);
+    }
+    let matched = match check {
+        ExistingCheck::Off => false,
+        ExistingCheck::Size => true,
+        ExistingCheck::Sample => {
+            let target_file_path = target_file_path.to_path_buf();
+            tokio::task::spawn_blocking(move || has_data_at_both_ends(&target_file_path))
+                .await
+                .context("Existing file check is interrupted")??
+        }
+        ExistingCheck::Full => {
+            let blake3_checksum =
+                meta::blake3_hash(target_file_path, Some(progress.multi())).await?;
+            blake3_checksum.eq_ignore_ascii_case(&declared_blake3)
+        }
+    };
+    if !matched {
+        return Ok(false);
+    }
+
+    cache_db::store_civitai_model_file_location(
+        model_version_meta.model_id(),
+        model_version_meta.id(),
+        file_id,
+        &declared_blake3,
+        target_file_path,
+    )
+    .context("Store file location to cache database")?;
+    Ok(true)
+}
+
+/// Reads the head and the tail of the file, a file preallocated but never filled has only
+/// zeros there. Safetensors files must also have a readable header.
+fn has_data_at_both_ends(file_path: &Path) -> std::io::Result<bool> {
+    use std::io::{Read, Seek};
+
+    if crate::safetensors::is_safetensors_file(file_path)
+        && crate::safetensors::read_header(file_path).is_err()
+    {
+        return Ok(false);
+    }
+    let mut file = std::fs::File::open(file_path)?;
+    let size = file.metadata()?.len();
+    let sample_size = SAMPLE_CHECK_SIZE.min(size) as usize;
+    let mut buffer = vec![0u8; sample_size];
+    for offset in [0, size - sample_size as u64] {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buffer)?;
+        if buffer.iter().all(|byte| *byte == 0) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Hardlinks the file, falls back to copying when hardlinks are not possible, e.g. across file
+/// systems.
+async fn link_or_copy(
+    source_file_path: &Path,
+    target_file_path: &Path,
+    reporter: &mut dyn DownloadReporter,
+) -> anyhow::Result<()> {
+    if std::fs::hard_link(source_file_path, target_file_path).is_ok() {
+        reporter.on_message(&format!(
+            "Linked {} to {}",
+            source_file_path.display(),
+            target_file_path.display()
+        ));
+        return Ok(());
+    }
+    copy_with_progress(source_file_path, target_file_path, reporter)
+        .await
+        .with_context(|| format!("Failed to copy {}", source_file_path.display()))?;
+    reporter.on_message(&format!(
+        "Copied {} to {}",
+        source_file_path.display(),
+        target_file_path.display()
+    ));
+    Ok(())
+}
+
+async fn copy_with_progress(
+    source_file_path: &Path,
+    target_file_path: &Path,
+    reporter: &mut dyn DownloadReporter,
+) -> anyhow::Result<()> {
+    let mut source = File::open(source_file_path).await?;
+    let mut target = File::create(target_file_path).await?;
+    reporter.on_start(source.metadata().await?.len());
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut copied_size: u64 = 0;
+    loop {
+        let read_size = source.read(&mut buffer).await?;
+        if read_size == 0 {
+            break;
+        }
+        target.write_all(&buffer[..read_size]).await?;
+        copied_size += read_size as u64;
+        reporter.on_progress(copied_size);
+    }
+    target.flush().await?;
+    reporter.on_finish();
+    Ok(())
+}
+
+/// Performs one download request, appending received content to the sink. Interrupted
+/// transfers are reported as transient errors so that they can be resumed.
+#[allow(clippy::too_many_arguments)]
+async fn download_attempt<S: DownloadSink>(
+    client: &Client,
+    config: &Configuration,
+    download_target: &DownloadTarget,
+    model_version_meta: &model::ModelVersion,
+    sink: &mut S,
+    downloaded_size: &mut u64,
+    served_host: &mut Option<String>,
+    reporter: &mut dyn DownloadReporter,
+) -> Result<(), backoff::Error<anyhow::Error>> {
+    let response = open_download(
+        client,
+        &config.proxy,
+        download_target,
+        model_version_meta,
+        *downloaded_size,
+    )
+    .await?;
+    *served_host = response.url().host_str().map(String::from);
+    transfer_body(
+        response,
+        download_target,
+        sink,
+        downloaded_size,
+        reporter,
+        config.network.idle_timeout(),
+    )
+    .await
+}
+
+/// Requests the file from the URL resolved before while it is still valid, otherwise through
+/// Civitai's download redirects, remembering the URL they lead to.
+async fn open_download(
+    client: &Client,
+    proxy: &ProxyConfig,
+    download_target: &DownloadTarget,
+    model_version_meta: &model::ModelVersion,
+    downloaded_size: u64,
+) -> Result<Response, backoff::Error<anyhow::Error>> {
+    if let Some(url) = resolved_url::lookup(download_target.file_id) {
+        let resolved_target = download_target.resolved(url);
+        let response =
+            send_download_request(client, proxy, &resolved_target, downloaded_size).await?;
+        if response.status() != StatusCode::FORBIDDEN {
+            return check_download_response(response, &resolved_target, model_version_meta).await;
+        }
+        // 预签名地址已失效，重新经过Civitai获取
+        resolved_url::forget(download_target.file_id);
+    }
+    let mut response =
+        send_download_request(client, proxy, download_target, downloaded_size).await?;
+    // auto模式下请求头中的密钥被拒绝时，改用查询参数再试一次
+    let fallback_target = download_target.query_token_fallback(&response);
+    if let Some(fallback_target) = fallback_target.as_ref() {
+        response = send_download_request(client, proxy, fallback_target, downloaded_size).await?;
+    }
+    let requested_target = fallback_target.as_ref().unwrap_or(download_target);
+    let response = check_download_response(response, requested_target, model_version_meta).await?;
+    if response.url() != &requested_target.url {
+        resolved_url::remember(download_target.file_id, response.url());
+    }
+    Ok(response)
+}
+
+/// Requests the file, from the given offset when part of it has been downloaded already.
+async fn send_download_request(
+    client: &Client,
+    proxy: &ProxyConfig,
+    download_target: &DownloadTarget,
+    downloaded_size: u64,
+) -> Result<Response, backoff::Error<anyhow::Error>> {
+    // 模型文件需要原始字节，才能续传和校验长度
+    // 跨主机的重定向（例如预签名的存储地址）由reqwest移除Authorization头
+    let mut download_request = client
+        .request(reqwest::Method::GET, download_target.url.clone())
+        .header(header::ACCEPT_ENCODING, "identity");
+    if let Some(key) = download_target.bearer_key.as_ref() {
+        download_request = download_request.bearer_auth(key);
+    }
+    if downloaded_size > 0 {
+        download_request =
+            download_request.header(header::RANGE, format!("bytes={downloaded_size}-"));
+    }
+    let request = download_request
+        .build()
+        .map_err(|e| backoff::Error::permanent(anyhow!(download_target.redact(e))))?;
+
+    crate::downloader::execute(client, proxy, request)
+        .await
+        .map_err(|e| {
+            backoff::Error::transient(anyhow!(TransferError::Request(download_target.redact(e))))
+        })
+}
+
+/// Requests the first byte of the file with the configured key, to learn whether the key may
+/// download it without transferring the content.
+pub(super) async fn probe_file_access(
+    client: &Client,
+    config: &Configuration,
+    model_version_meta: &model::ModelVersion,
+    selected_file: &model::ModelVersionFile,
+) -> anyhow::Result<super::access::FileAccess> {
+    use super::access::FileAccess;
+
+    let download_target = DownloadTarget::from_config(&config.civitai, selected_file)?;
+    let mut response = send_probe_request(client, &config.proxy, &download_target).await?;
+    let fallback_target = download_target.query_token_fallback(&response);
+    if let Some(fallback_target) = fallback_target.as_ref() {
+        response = send_probe_request(client, &config.proxy, fallback_target).await?;
+    }
+    let download_target = fallback_target.as_ref().unwrap_or(&download_target);
+    // 只判断状态码，不读取响应内容
+    let status = response.status();
+    let access = match status {
+        StatusCode::NOT_FOUND => FileAccess::NotFound,
+        StatusCode::UNAUTHORIZED => FileAccess::KeyRejected,
+        StatusCode::FORBIDDEN if model_version_meta.is_early_access() => FileAccess::EarlyAccess {
+            until: model_version_meta.early_access_ends_at(),
+        },
+        StatusCode::FORBIDDEN => FileAccess::RequiresMembership,
+        _ => {
+            check_download_response(response, download_target, model_version_meta)
+                .await
+                .map_err(|e| match e {
+                    backoff::Error::Permanent(e) | backoff::Error::Transient { err: e, .. } => e,
+                })?;
+            FileAccess::Accessible
+        }
+    };
+    Ok(access)
+}
+
+async fn send_probe_request(
+    client: &Client,
+    proxy: &ProxyConfig,
+    download_target: &DownloadTarget,
+) -> anyhow::Result<Response> {
+    let mut probe_request = client
+        .request(reqwest::Method::GET, download_target.url.clone())
+        .header(header::ACCEPT_ENCODING, "identity")
+        .header(header::RANGE, "bytes=0-0");
+    if let Some(key) = download_target.bearer_key.as_ref() {
+        probe_request = probe_request.bearer_auth(key);
+    }
+    let request = probe_request
+        .build()
+        .map_err(|e| anyhow!(download_target.redact(e)))?;
+    crate::downloader::execute(client, proxy, request)
+        .await
+        .map_err(|e| anyhow!(TransferError::Request(download_target.redact(e))))
+}
+
+/// Turns responses other than the file content into errors, transient when worth retrying.
+async fn check_download_response(
+    response: Response,
+    download_target: &DownloadTarget,
+    model_version_meta: &model::ModelVersion,
+) -> Result<Response, backoff::Error<anyhow::Error>> {
+    if response.status() == StatusCode::FORBIDDEN
+        && let Some(ends_at) = model_version_meta.early_access_ends_at()
+        && model_version_meta.is_early_access()
+    {
+        return Err(backoff::Error::permanent(anyhow!(
+            "Civitai refused the download, version {} is in early access until {}. Only accounts that purchased early access can download it before then.",
+            model_version_meta.name(),
+            datetime_to_date_string(&ends_at)
+        )));
+    }
+    let status = response.status();
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+    if is_html {
+        // 返回了网页而不是模型文件，可能是Cloudflare的验证页面
+        let headers = response.headers().clone();
+        let body = read_body_prefix(response, ERROR_PAGE_READ_SIZE).await;
+        if let Some(challenge) = CloudflareChallengeError::detect(status, &headers, &body) {
+            return Err(backoff::Error::retry_after(
+                anyhow!(challenge),
+                CloudflareChallengeError::RETRY_DELAY,
+            ));
+        }
+        let error = anyhow!(UnexpectedResponseError::new(
+            &download_target.display_url,
+            status,
+            Some("text/html"),
+            &body,
+            "expected model file content",
+        ));
+        return Err(if status.is_server_error() {
+            backoff::Error::transient(error)
+        } else {
+            backoff::Error::permanent(error)
+        });
+    }
+    response.error_for_status().map_err(|e| {
+        let e = download_target.redact(e);
+        if e.status().is_some_and(|s| s.is_server_error()) {
+            backoff::Error::transient(anyhow!(e))
+        } else {
+            backoff::Error::permanent(anyhow!(e))
+        }
+    })
+}
+
+/// Appends the content of the response to the sink, from where the last attempt stopped when
+/// the server resumes it.
+async fn transfer_body<S: DownloadSink>(
+    response: Response,
+    download_target: &DownloadTarget,
+    sink: &mut S,
+    downloaded_size: &mut u64,
+    reporter: &mut dyn DownloadReporter,
+    idle_timeout: Duration,
+) -> Result<(), backoff::Error<anyhow::Error>> {
+    if *downloaded_size > 0 && response.status() != StatusCode::PARTIAL_CONTENT {
+        // Server does not support resuming, start over.
+        sink.restart()
+            .await
+            .map_err(|e| backoff::Error::permanent(anyhow!(e)))?;
+        *downloaded_size = 0;
+    }
+    let remaining_length = response
+        .content_length()
+        .ok_or(backoff::Error::permanent(anyhow!(
+            "Incorrect model file length"
+        )))?;
+    let file_length = *downloaded_size + remaining_length;
+    reporter.on_start(file_length);
+    reporter.on_progress(*downloaded_size);
+
+    // 代理可能改写Content-Length，超出后不再信任它，完成后按元数据的大小和哈希校验
+    let mut length_exceeded = false;
+    let mut download_stream = response.bytes_stream();
+    loop {
+        let chunk = match tokio::time::timeout(idle_timeout, download_stream.next()).await {
+            Err(_) => {
+                return Err(backoff::Error::transient(anyhow!(TransferError::Stalled(
+                    format_duration(&idle_timeout)
+                ))));
+            }
+            Ok(None) => break,
+            Ok(Some(Err(e))) => {
+                return Err(backoff::Error::transient(anyhow!(
+                    TransferError::Interrupted(download_target.redact(e))
+                )));
+            }
+            Ok(Some(Ok(chunk))) => chunk,
+        };
+        sink.write_all(&chunk)
+            .await
+            .map_err(|e| backoff::Error::permanent(anyhow!(e)))?;
+        *downloaded_size += chunk.len() as u64;
+        if *downloaded_size > file_length && !length_exceeded {
+            length_exceeded = true;
+            reporter.on_length_exceeded(file_length);
+        }
+        reporter.on_progress(*downloaded_size);
+    }
+    if *downloaded_size < file_length {
+        return Err(backoff::Error::transient(anyhow!(
+            TransferError::ClosedEarly
+        )));
+    }
+
+    Ok(())
+}
+
+fn is_size_within_tolerance(received: u64, expected: u64) -> bool {
+    // sizeKB from Civitai is a rounded float, allow a small deviation.
+    let tolerance = (expected as f64 * SIZE_TOLERANCE_RATIO).max(1024.0);
+    (received as f64 - expected as f64).abs() <= tolerance
 }
 
 #[allow(dead_code)]
@@ -103,11 +936,18 @@ pub enum ModelVersionFileNamePresent {
     PrimaryFile,
 }
 
+/// Downloads the cover of the version beside its file, transfers and messages go to the
+/// reporter.
+#[allow(clippy::too_many_arguments)]
 pub async fn download_model_version_cover_image(
     client: &Client,
+    config: &Configuration,
     version_meta: &model::ModelVersion,
     file_present: ModelVersionFileNamePresent,
     destination_path: Option<&PathBuf>,
+    refresh: bool,
+    picked_cover: Option<&str>,
+    reporter: &mut dyn DownloadReporter,
 ) -> anyhow::Result<Option<String>> {
     let file_name = match file_present {
         ModelVersionFileNamePresent::FileID(file_id) => {
@@ -136,77 +976,881 @@ pub async fn download_model_version_cover_image(
         }
     };
 
-    let downloaded_file_name = file_name
-        .map(PathBuf::from)
-        .and_then(|p| p.file_stem().map(|fs| fs.to_string_lossy().into_owned()))
+    let Some(file_name) = file_name else {
+        bail!("Metadata of downloaded file is not found");
+    };
+    let downloaded_file_name = Path::new(&file_name)
+        .file_stem()
+        .map(|fs| sanitize_file_name(&fs.to_string_lossy()))
         .ok_or(anyhow!("Metadata of downloaded file is not found"))?;
-    let cover_image = version_meta
-        .images()?
+    let target_dir = match destination_path {
+        Some(given_path) => given_path.clone(),
+        None => env::current_dir()?,
+    };
+    let model_file_path = target_dir.join(&file_name);
+    // 已有封面时不再下载，离线时也只能沿用已有的封面
+    let existing_cover = existing_cover_file_name(&target_dir, &downloaded_file_name);
+    let refresh = refresh || picked_cover.is_some();
+    if crate::downloader::is_offline() || (!refresh && existing_cover.is_some()) {
+        return Ok(existing_cover);
+    }
+    let mut images = version_meta.images()?;
+    // 选中的图片排在最前，选中视频时不再尝试其他图片
+    let mut picked_video = false;
+    if let Some(picked) = picked_cover
+        && let Some(index) = images.iter().position(|img| img.url() == picked)
+    {
+        let image = images.remove(index);
+        picked_video = image.media_type().eq_ignore_ascii_case("video");
+        images.insert(0, image);
+    }
+    let (mut cover_candidates, video_candidates): (Vec<_>, Vec<_>) = images
         .into_iter()
-        .find(|img| !img.media_type().eq_ignore_ascii_case("video"));
+        .partition(|img| !img.media_type().eq_ignore_ascii_case("video"));
+    if picked_video {
+        cover_candidates.clear();
+    }
+    // 封面来源没有变化时保留已有封面
+    if let Some(existing_cover) = existing_cover {
+        let preferred_source = cover_candidates
+            .first()
+            .or(video_candidates.first())
+            .map(|img| img.url());
+        let recorded_source = super::sidecar::load_sidecar(&model_file_path)
+            .ok()
+            .flatten()
+            .and_then(|sidecar| sidecar.cover_source);
+        if preferred_source.is_some() && preferred_source == recorded_source {
+            reporter.on_message("Cover image is unchanged, keep the existing one.");
+            return Ok(Some(existing_cover));
+        }
+    }
 
-    if cover_image.is_none() {
+    // 图片先写入临时文件再解码，内存中不保留整个图片文件
+    let scratch_path = staging::staging_path(
+        &config.download,
+        &target_dir.join(format!("{downloaded_file_name}.cover.part")),
+    )
+    .await?;
+    // 逐个尝试候选图片，直到有一张可以成功下载并解码
+    let cover_width = config.cover.width;
+    let mut cover_image = None;
+    for candidate in cover_candidates.iter() {
+        if let Some(resized_url) = cdn::resized_image_url(&candidate.url(), cover_width) {
+            match fetch_cover_image(client, config, reporter, &resized_url, &scratch_path).await {
+                Ok(image) => {
+                    cover_image = Some((image, candidate.url()));
+                    break;
+                }
+                Err(e) => reporter.on_message(&format!(
+                    "Resized cover {resized_url} is not usable, fall back to the original: {e:#}"
+                )),
+            }
+        }
+        match fetch_cover_image(client, config, reporter, &candidate.url(), &scratch_path).await {
+            Ok(image) => {
+                cover_image = Some((image, candidate.url()));
+                break;
+            }
+            Err(e) => reporter.on_message(&format!(
+                "Cover candidate {} is not usable, try next one: {e:#}",
+                candidate.url()
+            )),
+        }
+    }
+    if cover_image.is_none() && !video_candidates.is_empty() {
+        match config.cover.video {
+            VideoCoverMode::Skip => {}
+            VideoCoverMode::Poster => {
+                for candidate in video_candidates.iter() {
+                    let Some(poster_url) = cdn::video_poster_url(&candidate.url()) else {
+                        continue;
+                    };
+                    match fetch_cover_image(client, config, reporter, &poster_url, &scratch_path)
+                        .await
+                    {
+                        Ok(image) => {
+                            cover_image = Some((image, candidate.url()));
+                            break;
+                        }
+                        Err(e) => reporter.on_message(&format!(
+                            "Poster of video {} is not usable, try next one: {e:#}",
+                            candidate.url()
+                        )),
+                    }
+                }
+            }
+            VideoCoverMode::Video => {
+                for candidate in video_candidates.iter() {
+                    match fetch_cover_to_file(
+                        client,
+                        config,
+                        reporter,
+                        &candidate.url(),
+                        &scratch_path,
+                    )
+                    .await
+                    {
+                        Ok(content_type) => {
+                            let extension = video_extension(content_type.as_deref());
+                            let video_filename =
+                                format!("{downloaded_file_name}.cover.{extension}");
+                            remove_other_covers(
+                                &target_dir,
+                                &downloaded_file_name,
+                                &video_filename,
+                            )
+                            .await?;
+                            staging::finish(&scratch_path, &target_dir.join(&video_filename))
+                                .await?;
+                            record_cover_source(&model_file_path, &candidate.url()).await;
+                            return Ok(Some(video_filename));
+                        }
+                        Err(e) => {
+                            let _ = tokio::fs::remove_file(&scratch_path).await;
+                            reporter.on_message(&format!(
+                                "Video {} is not usable, try next one: {e:#}",
+                                candidate.url()
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let Some((image, source)) = cover_image else {
+        if !cover_candidates.is_empty() || !video_candidates.is_empty() {
+            reporter.on_message("WARNING: None of the images can be used as cover image.");
+        }
         return Ok(None);
+    };
+
+    let preview_image_filename = format!("{downloaded_file_name}.cover.png");
+    remove_other_covers(&target_dir, &downloaded_file_name, &preview_image_filename).await?;
+    let target_image_path = target_dir.join(&preview_image_filename);
+    let staged_image_path = staging::staging_path(&config.download, &target_image_path).await?;
+    if let Err(e) = image.save_with_format(&staged_image_path, image::ImageFormat::Png) {
+        staging::discard(&staged_image_path, &target_image_path).await;
+        return Err(e.into());
     }
-    let cover_image = cover_image.unwrap();
-
-    let task = async || {
-        println!("Try to fetch cover image.");
-        let config = crate::configuration::CONFIGURATION.read().await;
-        let civitai_auth_key = config.civitai.api_key.clone().unwrap_or_default();
-        let download_request = client
-            .request(reqwest::Method::GET, cover_image.url())
-            .bearer_auth(civitai_auth_key);
-        let request = download_request.build().map_err(|e| {
-            backoff::Error::transient(anyhow!("Failed to build cover image download request: {e}"))
-        })?;
+    staging::finish(&staged_image_path, &target_image_path).await?;
+    record_cover_source(&model_file_path, &source).await;
 
-        let response = client.execute(request).await.map_err(|e| {
-            backoff::Error::transient(anyhow!(
-                "Failed to execute cover image download request: {e}"
-            ))
-        })?;
-        let image_bytes = response.bytes().await.map_err(|e| {
-            backoff::Error::transient(anyhow!("Failed to read cover image content: {e}"))
-        })?;
+    Ok(Some(preview_image_filename))
+}
+
+/// File name of the cover saved before, images are saved as PNG and videos keep their own
+/// format.
+fn existing_cover_file_name(target_dir: &Path, downloaded_file_name: &str) -> Option<String> {
+    COVER_EXTENSIONS
+        .iter()
+        .map(|extension| format!("{downloaded_file_name}.cover.{extension}"))
+        .find(|file_name| target_dir.join(file_name).is_file())
+}
+
+/// Removes covers in other formats, including the JPEG covers saved by earlier versions, so
+/// only the new cover is left.
+async fn remove_other_covers(
+    target_dir: &Path,
+    downloaded_file_name: &str,
+    kept_file_name: &str,
+) -> anyhow::Result<()> {
+    for extension in COVER_EXTENSIONS.iter().chain(std::iter::once(&"jpg")) {
+        let file_name = format!("{downloaded_file_name}.cover.{extension}");
+        let clear_path = target_dir.join(&file_name);
+        if file_name != kept_file_name && clear_path.is_file() {
+            tokio::fs::remove_file(clear_path).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn record_cover_source(model_file_path: &Path, source: &str) {
+    if let Err(e) = super::sidecar::save_cover_source(model_file_path, source).await {
+        events::message(format!("Failed to record the cover source: {e}"));
+    }
+}
+
+fn video_extension(content_type: Option<&str>) -> &'static str {
+    match content_type.map(|t| t.split(';').next().unwrap_or(t).trim()) {
+        Some("video/webm") => "webm",
+        Some("video/quicktime") => "mov",
+        Some("image/gif") => "gif",
+        _ => "mp4",
+    }
+}
 
-        Ok(image_bytes)
+/// Downloads one cover image candidate into the scratch file and decodes it from there, the
+/// scratch file is removed afterwards.
+async fn fetch_cover_image(
+    client: &Client,
+    config: &Configuration,
+    reporter: &mut dyn DownloadReporter,
+    url: &str,
+    scratch_path: &Path,
+) -> anyhow::Result<image::DynamicImage> {
+    let result = match fetch_cover_to_file(client, config, reporter, url, scratch_path).await {
+        Ok(_) => ImageReader::open(scratch_path)
+            .context("Unable to open downloaded image")
+            .and_then(|reader| {
+                reader
+                    .with_guessed_format()
+                    .context("Unregconized image format")?
+                    .decode()
+                    .context("Unable to decode image")
+            }),
+        Err(e) => Err(e),
     };
-    let notify_op = |_: anyhow::Error, d| {
-        println!(
-            "Failed to download cover image, will try again after {}.",
-            duration_to_sec_string(&d)
-        );
+    let _ = tokio::fs::remove_file(scratch_path).await;
+    result
+}
+
+/// Requests the cover, with the access key only when the host is Civitai itself and `with_key`
+/// is set. Returns the response and whether the key was sent.
+async fn send_cover_request(
+    client: &Client,
+    config: &Configuration,
+    url: &str,
+    with_key: bool,
+) -> anyhow::Result<(reqwest::Response, bool)> {
+    let mut request = client
+        .request(reqwest::Method::GET, url)
+        .timeout(config.network.request_timeout());
+    let key = config
+        .civitai
+        .api_key
+        .as_ref()
+        .filter(|key| with_key && !key.is_empty() && config.civitai.sends_key_to(url));
+    if let Some(key) = key {
+        request = request.bearer_auth(key);
+    }
+    let sent_key = key.is_some();
+    let request = request
+        .build()
+        .map_err(|e| anyhow!("Failed to build cover image download request: {e}"))?;
+    let response = crate::downloader::execute(client, &config.proxy, request)
+        .await
+        .map_err(|e| anyhow!("Failed to execute cover image download request: {e}"))?;
+    Ok((response, sent_key))
+}
+
+/// Streams cover content into the file, returns its content type. Client errors like 404 are
+/// not retried, except 401 and 403 answered to a request with the access key, which are tried
+/// once more without it.
+async fn fetch_cover_to_file(
+    client: &Client,
+    config: &Configuration,
+    reporter: &mut dyn DownloadReporter,
+    url: &str,
+    target_path: &Path,
+) -> anyhow::Result<Option<String>> {
+    let url = config.civitai.rewrite_download_url(url);
+    let mut policy = make_backoff_policy(&config.backoff, 300);
+    loop {
+        match fetch_cover_attempt(client, config, reporter, &url, target_path).await {
+            Ok(content_type) => {
+                reporter.on_finish();
+                return Ok(content_type);
+            }
+            Err(backoff::Error::Permanent(e)) => {
+                reporter.on_finish();
+                return Err(e.context("Download cover image"));
+            }
+            Err(backoff::Error::Transient { err, retry_after }) => match policy.next_backoff() {
+                Some(wait) => {
+                    let wait = retry_after.map_or(wait, |delay| delay.max(wait));
+                    reporter.on_retry(wait, &format!("Failed to download cover image: {err}"));
+                    tokio::time::sleep(wait).await;
+                }
+                None => {
+                    reporter.on_finish();
+                    return Err(err.context("Download cover image"));
+                }
+            },
+        }
+    }
+}
+
+/// One request for the cover, written into the file from its start.
+async fn fetch_cover_attempt(
+    client: &Client,
+    config: &Configuration,
+    reporter: &mut dyn DownloadReporter,
+    url: &str,
+    target_path: &Path,
+) -> Result<Option<String>, backoff::Error<anyhow::Error>> {
+    {
+        let (mut response, sent_key) = send_cover_request(client, config, url, true)
+            .await
+            .map_err(backoff::Error::transient)?;
+        // 图片CDN可能拒绝携带访问密钥的请求，不带密钥再请求一次
+        if sent_key
+            && matches!(
+                response.status(),
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+            )
+        {
+            response = send_cover_request(client, config, url, false)
+                .await
+                .map_err(backoff::Error::transient)?
+                .0;
+        }
+        let response = response.error_for_status().map_err(|e| {
+            if e.status().is_some_and(|s| s.is_server_error()) {
+                backoff::Error::transient(anyhow!(e))
+            } else {
+                backoff::Error::permanent(anyhow!(e))
+            }
+        })?;
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string);
+        // 每次重试都从头写入文件
+        let mut file = File::create(target_path)
+            .await
+            .map_err(|e| backoff::Error::permanent(anyhow!(e)))?;
+        reporter.on_start(response.content_length().unwrap_or_default());
+        let mut received_size: u64 = 0;
+        let mut content_stream = response.bytes_stream();
+        while let Some(chunk) = content_stream.next().await {
+            let chunk = chunk.map_err(|e| {
+                backoff::Error::transient(anyhow!("Failed to read cover image content: {e}"))
+            })?;
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| backoff::Error::permanent(anyhow!(e)))?;
+            received_size += chunk.len() as u64;
+            reporter.on_progress(received_size);
+        }
+        file.flush()
+            .await
+            .map_err(|e| backoff::Error::permanent(anyhow!(e)))?;
+
+        Ok(content_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path, query_param},
     };
-    let policy = make_backoff_policy(300).await;
-    let image_bytes = backoff::future::retry_notify(policy, task, notify_op)
+
+    use super::*;
+    use crate::configuration::{CivitaiConfig, DownloadAuthMode, UrlRewrite};
+
+    const KEY: &str = "secret-key";
+
+    /// Serves a download on `civitai` that redirects to `storage`, like a pre-signed URL.
+    async fn redirecting_servers() -> (MockServer, MockServer) {
+        let civitai = MockServer::start().await;
+        let storage = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/download/models/10"))
+            .respond_with(
+                ResponseTemplate::new(307)
+                    .insert_header("Location", format!("{}/storage/f", storage.uri())),
+            )
+            .mount(&civitai)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/storage/f"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0u8; 16]))
+            .mount(&storage)
+            .await;
+        (civitai, storage)
+    }
+
+    /// Configuration using the mock server as Civitai API host.
+    fn civitai_config(api_host: &MockServer, auth_mode: DownloadAuthMode) -> CivitaiConfig {
+        CivitaiConfig {
+            api_key: Some(KEY.to_string()),
+            api_base_url: Some(format!("{}/api/v1", api_host.uri())),
+            auth_mode,
+            ..Default::default()
+        }
+    }
+
+    async fn download(target: &DownloadTarget) {
+        let response = send_download_request(&Client::new(), &ProxyConfig::default(), target, 0)
+            .await
+            .map_err(|e| anyhow!("{e:?}"))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    fn bearer(request: &wiremock::Request) -> Option<&str> {
+        request
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    fn query_token(request: &wiremock::Request) -> Option<String> {
+        request
+            .url
+            .query_pairs()
+            .find(|(name, _)| name == "token")
+            .map(|(_, value)| value.into_owned())
+    }
+
+    async fn only_request(server: &MockServer) -> wiremock::Request {
+        let mut requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        requests.remove(0)
+    }
+
+    #[tokio::test]
+    async fn header_is_not_forwarded_to_redirected_host() {
+        for auth_mode in [DownloadAuthMode::Header, DownloadAuthMode::Auto] {
+            let (civitai, storage) = redirecting_servers().await;
+            let url = format!("{}/api/download/models/10", civitai.uri());
+            let config = civitai_config(&civitai, auth_mode);
+            download(&DownloadTarget::new(10, &url, &config).unwrap()).await;
+
+            let requested = only_request(&civitai).await;
+            assert_eq!(bearer(&requested), Some(format!("Bearer {KEY}").as_str()));
+            assert_eq!(query_token(&requested), None);
+            let redirected = only_request(&storage).await;
+            assert_eq!(bearer(&redirected), None);
+            assert_eq!(query_token(&redirected), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn query_token_is_not_forwarded_to_redirected_host() {
+        let (civitai, storage) = redirecting_servers().await;
+        let url = format!("{}/api/download/models/10", civitai.uri());
+        let config = civitai_config(&civitai, DownloadAuthMode::Query);
+        download(&DownloadTarget::new(10, &url, &config).unwrap()).await;
+
+        let requested = only_request(&civitai).await;
+        assert_eq!(bearer(&requested), None);
+        assert_eq!(query_token(&requested).as_deref(), Some(KEY));
+        let redirected = only_request(&storage).await;
+        assert_eq!(bearer(&redirected), None);
+        assert_eq!(query_token(&redirected), None);
+    }
+
+    #[tokio::test]
+    async fn rewritten_mirror_never_gets_the_key() {
+        let api = MockServer::start().await;
+        for auth_mode in [
+            DownloadAuthMode::Header,
+            DownloadAuthMode::Query,
+            DownloadAuthMode::Auto,
+        ] {
+            let (mirror, storage) = redirecting_servers().await;
+            let config = CivitaiConfig {
+                download_url_rewrite: Some(UrlRewrite {
+                    from: "https://civitai.com/".to_string(),
+                    to: format!("{}/", mirror.uri()),
+                }),
+                ..civitai_config(&api, auth_mode)
+            };
+            let url = config.rewrite_download_url("https://civitai.com/api/download/models/10");
+            download(&DownloadTarget::new(10, &url, &config).unwrap()).await;
+
+            for request in [only_request(&mirror).await, only_request(&storage).await] {
+                assert_eq!(bearer(&request), None);
+                assert_eq!(query_token(&request), None);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn auto_sends_query_token_when_header_is_rejected() {
+        for (auth_mode, file_id) in [(DownloadAuthMode::Auto, 41), (DownloadAuthMode::Header, 42)] {
+            let civitai = MockServer::start().await;
+            let download_path = format!("/api/download/models/{file_id}");
+            Mock::given(method("GET"))
+                .and(path(download_path.as_str()))
+                .and(query_param("token", KEY))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0u8; 16]))
+                .mount(&civitai)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(download_path.as_str()))
+                .respond_with(ResponseTemplate::new(401))
+                .mount(&civitai)
+                .await;
+            let version = version_with_file(&civitai, file_id, 0.016);
+            let config = civitai_config(&civitai, auth_mode);
+            let url = format!("{}{download_path}", civitai.uri());
+            let target = DownloadTarget::new(file_id, &url, &config).unwrap();
+
+            let opened = open_download(
+                &Client::new(),
+                &ProxyConfig::default(),
+                &target,
+                &version,
+                0,
+            )
+            .await;
+
+            let requests = civitai.received_requests().await.unwrap();
+            assert_eq!(bearer(&requests[0]), Some(format!("Bearer {KEY}").as_str()));
+            assert_eq!(query_token(&requests[0]), None);
+            if auth_mode == DownloadAuthMode::Auto {
+                assert_eq!(opened.map_err(|e| anyhow!("{e:?}")).unwrap().status(), 200);
+                assert_eq!(requests.len(), 2);
+                assert_eq!(bearer(&requests[1]), None);
+                assert_eq!(query_token(&requests[1]).as_deref(), Some(KEY));
+            } else {
+                assert!(opened.is_err());
+                assert_eq!(requests.len(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn key_is_kept_for_civitai_site() {
+        let config = CivitaiConfig {
+            api_key: Some(KEY.to_string()),
+            ..Default::default()
+        };
+        let target =
+            DownloadTarget::new(10, "https://civitai.com/api/download/models/10", &config).unwrap();
+        assert_eq!(target.bearer_key.as_deref(), Some(KEY));
+        assert!(target.url.query().is_none());
+    }
+
+    /// Keeps the callbacks of a transfer as lines, like `start 10`.
+    #[derive(Default)]
+    struct RecordingReporter {
+        calls: Vec<String>,
+    }
+
+    impl DownloadReporter for RecordingReporter {
+        fn on_start(&mut self, total: u64) {
+            self.calls.push(format!("start {total}"));
+        }
+
+        fn on_progress(&mut self, bytes: u64) {
+            self.calls.push(format!("progress {bytes}"));
+        }
+
+        fn on_length_exceeded(&mut self, advertised: u64) {
+            self.calls.push(format!("exceeded {advertised}"));
+        }
+
+        fn on_retry(&mut self, _delay: Duration, _reason: &str) {
+            self.calls.push("retry".to_string());
+        }
+
+        fn on_finish(&mut self) {
+            self.calls.push("finish".to_string());
+        }
+
+        fn on_message(&mut self, message: &str) {
+            self.calls.push(format!("message {message}"));
+        }
+    }
+
+    impl RecordingReporter {
+        fn has(&self, call: &str) -> bool {
+            self.calls.iter().any(|recorded| recorded == call)
+        }
+
+        fn messages(&self) -> Vec<&String> {
+            self.calls
+                .iter()
+                .filter(|call| call.starts_with("message "))
+                .collect()
+        }
+    }
+
+    fn version_with_file(server: &MockServer, file_id: u64, size_kb: f64) -> model::ModelVersion {
+        let value = serde_json::json!({
+            "id": 20,
+            "modelId": 1,
+            "name": "v1",
+            "images": [],
+            "files": [{
+                "id": file_id,
+                "sizeKB": size_kb,
+                "name": "model.safetensors",
+                "primary": true,
+                "downloadUrl": format!("{}/api/download/models/{file_id}", server.uri()),
+                "hashes": {}
+            }]
+        });
+        model::ModelVersion::try_from(&value).unwrap()
+    }
+
+    async fn stream_model(
+        server: &MockServer,
+        file_id: u64,
+        size_kb: f64,
+        body: Vec<u8>,
+    ) -> RecordingReporter {
+        Mock::given(method("GET"))
+            .and(path(format!("/api/download/models/{file_id}")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body))
+            .mount(server)
+            .await;
+        let version = version_with_file(server, file_id, size_kb);
+        let mut reporter = RecordingReporter::default();
+        let summary = stream_single_model_file(
+            &Client::new(),
+            &Configuration::default(),
+            &version,
+            file_id,
+            tokio::io::sink(),
+            &mut reporter,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.size, 2048);
+        reporter
+    }
+
+    #[tokio::test]
+    async fn model_transfer_is_reported() {
+        let server = MockServer::start().await;
+        let reporter = stream_model(&server, 31, 2.0, vec![7u8; 2048]).await;
+
+        assert!(reporter.has("start 2048"));
+        assert!(reporter.has("progress 2048"));
+        assert_eq!(reporter.calls.last().map(String::as_str), Some("finish"));
+        assert!(reporter.messages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn size_mismatch_is_reported_as_message() {
+        let server = MockServer::start().await;
+        let reporter = stream_model(&server, 32, 4.0, vec![7u8; 2048]).await;
+
+        let messages = reporter.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("WARNING"));
+    }
+
+    /// Body whose Content-Length is smaller than what it sends, like one rewritten by a proxy.
+    /// A real server can not send more than it announces, so the response is built in place.
+    struct MisreportedBody {
+        chunks: Vec<hyper::body::Bytes>,
+        advertised: u64,
+    }
+
+    impl hyper::body::Body for MisreportedBody {
+        type Data = hyper::body::Bytes;
+        type Error = std::convert::Infallible;
+
+        fn poll_frame(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+            let chunk = (!self.chunks.is_empty()).then(|| self.chunks.remove(0));
+            std::task::Poll::Ready(chunk.map(|chunk| Ok(hyper::body::Frame::data(chunk))))
+        }
+
+        fn size_hint(&self) -> hyper::body::SizeHint {
+            hyper::body::SizeHint::with_exact(self.advertised)
+        }
+    }
+
+    #[tokio::test]
+    async fn progress_counter_switches_when_body_outgrows_content_length() {
+        let body = MisreportedBody {
+            chunks: vec![hyper::body::Bytes::from(vec![7u8; 1024]); 2],
+            advertised: 1024,
+        };
+        let response = Response::from(hyper::Response::new(reqwest::Body::wrap(body)));
+        assert_eq!(response.content_length(), Some(1024));
+        let target = DownloadTarget::new(
+            10,
+            "https://civitai.com/api/download/models/10",
+            &CivitaiConfig::default(),
+        )
+        .unwrap();
+        let scratch = tempfile::tempdir().unwrap();
+        let target_path = scratch.path().join("model.part");
+        let mut file = File::create(&target_path).await.unwrap();
+        let mut downloaded_size = 0;
+        let mut reporter = RecordingReporter::default();
+
+        transfer_body(
+            response,
+            &target,
+            &mut file,
+            &mut downloaded_size,
+            &mut reporter,
+            Duration::from_secs(5),
+        )
         .await
-        .context("Download cover image")?;
+        .unwrap();
+        file.flush().await.unwrap();
 
-    let image_buffer = Cursor::new(image_bytes);
-    let image = ImageReader::new(image_buffer)
-        .with_guessed_format()
-        .context("Unregconized image format")?
-        .decode()
-        .context("Unable to decode image")?;
+        assert_eq!(
+            reporter.calls,
+            [
+                "start 1024",
+                "progress 0",
+                "progress 1024",
+                "exceeded 1024",
+                "progress 2048"
+            ]
+        );
+        assert_eq!(downloaded_size, 2048);
+        assert_eq!(std::fs::metadata(&target_path).unwrap().len(), 2048);
+    }
 
-    let old_preview_image_filename = format!("{downloaded_file_name}.cover.jpg");
-    let clear_path = match destination_path {
-        Some(given_path) => given_path.clone(),
-        None => env::current_dir()?,
+    #[tokio::test]
+    async fn cover_transfer_and_retry_are_reported() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"0123456789".to_vec()))
+            .mount(&server)
+            .await;
+        let scratch = tempfile::tempdir().unwrap();
+        let target_path = scratch.path().join("cover.part");
+        let mut config = Configuration::default();
+        config.backoff.initial_interval = 1;
+        let mut reporter = RecordingReporter::default();
+
+        fetch_cover_to_file(
+            &Client::new(),
+            &config,
+            &mut reporter,
+            &format!("{}/cover.png", server.uri()),
+            &target_path,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(reporter.calls[0], "retry");
+        assert!(reporter.has("start 10"));
+        assert!(reporter.has("progress 10"));
+        assert_eq!(reporter.calls.last().map(String::as_str), Some("finish"));
+        assert_eq!(std::fs::read(&target_path).unwrap(), b"0123456789");
     }
-    .join(&old_preview_image_filename);
-    if clear_path.exists() && clear_path.is_file() {
-        tokio::fs::remove_file(clear_path).await?;
+
+    #[tokio::test]
+    async fn large_cover_is_streamed_through_scratch_file() {
+        // 噪声图片几乎无法压缩，编码后有数MB
+        let mut seed: u32 = 1;
+        let image = image::RgbImage::from_fn(1200, 1200, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let [r, g, b, _] = seed.to_le_bytes();
+            image::Rgb([r, g, b])
+        });
+        let mut encoded = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut encoded, image::ImageFormat::Png)
+            .unwrap();
+        let encoded = encoded.into_inner();
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/large.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(encoded.clone()))
+            .mount(&server)
+            .await;
+        let scratch = tempfile::tempdir().unwrap();
+        let scratch_path = scratch.path().join("model.cover.part");
+        let url = format!("{}/large.png", server.uri());
+        let mut reporter = RecordingReporter::default();
+
+        fetch_cover_to_file(
+            &Client::new(),
+            &Configuration::default(),
+            &mut reporter,
+            &url,
+            &scratch_path,
+        )
+        .await
+        .unwrap();
+
+        // 响应体按块写入文件，每块都远小于整个图片，内存中从不保留完整的响应体
+        let progress = std::iter::once(0)
+            .chain(
+                reporter
+                    .calls
+                    .iter()
+                    .filter_map(|call| call.strip_prefix("progress "))
+                    .map(|bytes| bytes.parse::<usize>().unwrap()),
+            )
+            .collect::<Vec<_>>();
+        assert!(progress.len() > 2, "{progress:?}");
+        assert!(
+            progress
+                .windows(2)
+                .all(|pair| pair[1] - pair[0] < encoded.len() / 2)
+        );
+        assert_eq!(progress.last(), Some(&encoded.len()));
+        assert_eq!(std::fs::read(&scratch_path).unwrap(), encoded);
+
+        // 解码直接读取临时文件，完成后删除临时文件
+        let decoded = fetch_cover_image(
+            &Client::new(),
+            &Configuration::default(),
+            &mut RecordingReporter::default(),
+            &url,
+            &scratch_path,
+        )
+        .await
+        .unwrap();
+        assert_eq!(decoded.to_rgb8(), image);
+        assert!(!scratch_path.exists());
     }
 
-    let preview_image_filename = format!("{downloaded_file_name}.cover.png");
-    let target_image_path = match destination_path {
-        Some(given_path) => given_path.clone(),
-        None => env::current_dir()?,
+    #[tokio::test]
+    async fn covers_in_other_formats_are_removed() {
+        let directory = tempfile::tempdir().unwrap();
+        for file_name in [
+            "style.cover.jpg",
+            "style.cover.mp4",
+            "style.cover.png",
+            "style.cover.webm",
+            "other.cover.jpg",
+            "style.safetensors",
+        ] {
+            std::fs::write(directory.path().join(file_name), b"").unwrap();
+        }
+        assert_eq!(
+            existing_cover_file_name(directory.path(), "style").as_deref(),
+            Some("style.cover.png")
+        );
+
+        remove_other_covers(directory.path(), "style", "style.cover.webm")
+            .await
+            .unwrap();
+
+        let mut left = std::fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(
+            left,
+            ["other.cover.jpg", "style.cover.webm", "style.safetensors"]
+        );
+        assert_eq!(
+            existing_cover_file_name(directory.path(), "style").as_deref(),
+            Some("style.cover.webm")
+        );
     }
-    .join(&preview_image_filename);
-    image.save_with_format(&target_image_path, image::ImageFormat::Png)?;
 
-    Ok(Some(preview_image_filename))
+    #[test]
+    fn archive_companions_are_named_after_the_archive() {
+        let archive_path = Path::new("/models/poses/hand poses.zip");
+        assert_eq!(
+            archive_companions(archive_path),
+            [
+                PathBuf::from("/models/poses/hand poses.blake3"),
+                PathBuf::from("/models/poses/hand poses.md"),
+                PathBuf::from("/models/poses/hand poses.civitai.json"),
+            ]
+        );
+    }
 }
@@ -0,0 +1,135 @@
+//! Picking the files of a version without prompting, by a preference like
+//! `fp16,pruned,safetensor+vae`: the model file matching most of the comma separated terms,
+//! plus the files of each type named after `+` when the version has them.
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::{configuration::SelectionRule, errors::InvalidInputError, events, utils::glob_match};
+
+use super::model::ModelVersionFile;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePreference {
+    /// Format, precision or size terms the model file should match, like `fp16` or `pruned`.
+    terms: Vec<String>,
+    /// File types taken along with the model file, like `vae` or `config`.
+    extra_types: Vec<String>,
+}
+
+impl FromStr for FilePreference {
+    type Err = InvalidInputError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts = text.split('+').map(str::trim);
+        let terms = parts
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .map(|term| term.trim().to_ascii_lowercase())
+            .filter(|term| !term.is_empty())
+            .collect::<Vec<_>>();
+        let extra_types = parts.map(str::to_ascii_lowercase).collect::<Vec<_>>();
+        if extra_types.iter().any(String::is_empty) || (terms.is_empty() && extra_types.is_empty())
+        {
+            return Err(InvalidInputError(format!(
+                "Invalid file preference \"{text}\", expected terms like fp16,pruned,safetensor, optionally followed by +vae"
+            )));
+        }
+        Ok(Self { terms, extra_types })
+    }
+}
+
+impl Display for FilePreference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.terms.join(","))?;
+        for extra_type in self.extra_types.iter() {
+            write!(f, "+{extra_type}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Civitai spells formats like `SafeTensor`, accept `safetensors` as well.
+fn normalize(value: &str) -> String {
+    value.to_ascii_lowercase().trim_end_matches('s').to_string()
+}
+
+impl FilePreference {
+    /// Ids of the files to download, the best matching model file first. Without any model
+    /// file matching a term, the primary file is taken.
+    pub fn pick(&self, files: &[ModelVersionFile]) -> Vec<u64> {
+        let is_model = |file: &ModelVersionFile| {
+            file.file_type()
+                .is_none_or(|file_type| file_type.eq_ignore_ascii_case("model"))
+        };
+        let score = |file: &ModelVersionFile| {
+            let values = file
+                .variant_params()
+                .into_iter()
+                .map(|(_, value)| normalize(&value))
+                .collect::<Vec<_>>();
+            self.terms
+                .iter()
+                .filter(|term| values.contains(&normalize(term)))
+                .count()
+        };
+        // 同分时优先主文件，其次按列出的顺序
+        let mut picked = files
+            .iter()
+            .filter(|file| is_model(file))
+            .max_by_key(|file| {
+                (
+                    score(file),
+                    file.is_primary().unwrap_or_default(),
+                    std::cmp::Reverse(files.iter().position(|f| f.id() == file.id())),
+                )
+            })
+            .or(files.first())
+            .map(|file| vec![file.id()])
+            .unwrap_or_default();
+        for extra_type in self.extra_types.iter() {
+            picked.extend(
+                files
+                    .iter()
+                    .filter(|file| {
+                        file.file_type()
+                            .is_some_and(|file_type| file_type.eq_ignore_ascii_case(extra_type))
+                            && !picked.contains(&file.id())
+                    })
+                    .map(ModelVersionFile::id)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        picked
+    }
+}
+
+/// Preference of the first rule matching the base model, logged at `-v`.
+pub fn preference_for_base_model(
+    rules: &[SelectionRule],
+    base_model: Option<&str>,
+) -> Option<FilePreference> {
+    let base_model = base_model?;
+    let rule = rules
+        .iter()
+        .find(|rule| glob_match(&rule.base_model, base_model))?;
+    match rule.prefer.parse::<FilePreference>() {
+        Ok(preference) => {
+            events::verbose(
+                1,
+                format!(
+                    "Base model {base_model} matches selection rule \"{}\", prefer {preference}",
+                    rule.base_model
+                ),
+            );
+            Some(preference)
+        }
+        Err(e) => {
+            events::message(format!(
+                "WARNING: Selection rule \"{}\" is ignored: {e}",
+                rule.base_model
+            ));
+            None
+        }
+    }
+}
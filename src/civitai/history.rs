@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use time::{UtcDateTime, macros::format_description};
+
+use crate::cache_db;
+
+/// A model file downloaded from Civitai, kept in cache database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadRecord {
+    pub model_id: u64,
+    pub model_name: String,
+    pub version_id: u64,
+    pub version_name: String,
+    pub file_id: u64,
+    pub file_name: String,
+    pub path: PathBuf,
+    pub downloaded_at: i64,
+    /// Page of the version on Civitai website, missing in records of older versions.
+    #[serde(default)]
+    pub page_url: Option<String>,
+    /// Profile in use when the file was downloaded.
+    pub profile: Option<String>,
+    /// Fingerprint of the API key the file was downloaded with, `None` for linked copies.
+    pub key_fingerprint: Option<String>,
+}
+
+impl DownloadRecord {
+    pub fn save(&self) -> Result<()> {
+        cache_db::store_download_record(self)
+    }
+
+    /// Records from the newest, at most `limit` of them.
+    pub fn list(limit: usize) -> Result<Vec<Self>> {
+        cache_db::list_download_records(limit)
+    }
+
+    pub fn print(&self) {
+        let downloaded_at = UtcDateTime::from_unix_timestamp(self.downloaded_at)
+            .ok()
+            .and_then(|t| {
+                t.format(format_description!("[year]-[month]-[day] [hour]:[minute]"))
+                    .ok()
+            })
+            .unwrap_or_default();
+        let account = match (self.profile.as_deref(), self.key_fingerprint.as_deref()) {
+            (_, None) => "linked from a local copy".to_string(),
+            (Some(profile), Some(fingerprint)) => format!("profile {profile}, key {fingerprint}"),
+            (None, Some(fingerprint)) => format!("key {fingerprint}"),
+        };
+        println!(
+            "{downloaded_at}  {} - {} / {}  [{account}]",
+            self.model_name, self.version_name, self.file_name
+        );
+        println!("  {}", self.path.display());
+        println!(
+            "  {}",
+            self.page_url
+                .clone()
+                .unwrap_or_else(|| { super::model_page_url(self.model_id, self.version_id) })
+        );
+    }
+}
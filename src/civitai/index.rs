@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cache_db;
+
+use super::{
+    model::ModelVersion,
+    readme::{existing_cover, recorded_hash},
+    sidecar,
+    upstream::{self, RemovalKind},
+};
+
+/// What an index page shows of a downloaded model file, read from its sidecar and cache only.
+pub struct IndexEntry {
+    pub model_file: PathBuf,
+    pub model_name: String,
+    pub model_type: Option<String>,
+    pub version_name: String,
+    pub base_model: Option<String>,
+    pub trained_words: Vec<String>,
+    /// Readme file beside the model file, when it exists.
+    pub readme: Option<PathBuf>,
+    /// Cover file beside the model file, when it exists.
+    pub cover: Option<PathBuf>,
+    /// How the version left Civitai, as recorded by an earlier check.
+    pub removed_upstream: Option<RemovalKind>,
+}
+
+/// Collects the index entry of a model file without requesting Civitai, `None` when neither a
+/// sidecar nor a cached version with its recorded hash identifies it.
+pub fn index_entry(model_file: &Path) -> Result<Option<IndexEntry>> {
+    let (model_id, sidecar_model, version) = match sidecar::load_sidecar(model_file)? {
+        Some(sidecar) => {
+            // 侧车文件中的版本元数据可能不完整，只取其中的基本信息
+            let version =
+                cache_db::retreive_civitai_model_version(sidecar.model_id, sidecar.version_id)?
+                    .map(|version| VersionFacts::from(&version))
+                    .unwrap_or_else(|| VersionFacts {
+                        id: sidecar.version_id,
+                        name: sidecar.version_name.clone(),
+                        base_model: sidecar.base_model.clone(),
+                        trained_words: sidecar.model_version["trainedWords"]
+                            .as_array()
+                            .map(|words| {
+                                words
+                                    .iter()
+                                    .filter_map(|word| word.as_str().map(String::from))
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                    });
+            (
+                sidecar.model_id,
+                Some((sidecar.model_name, sidecar.model_type)),
+                version,
+            )
+        }
+        None => {
+            let Some(hash) = recorded_hash(model_file) else {
+                return Ok(None);
+            };
+            let Some(version) = cache_db::find_civitai_model_version(|version| {
+                version
+                    .files()
+                    .is_ok_and(|files| files.iter().any(|file| file.match_by_blake3(&hash)))
+            })?
+            else {
+                return Ok(None);
+            };
+            (
+                version.model_id(),
+                version.model_name().map(|name| (name, None)),
+                VersionFacts::from(&version),
+            )
+        }
+    };
+    let (model_name, model_type) = match cache_db::retreive_civitai_model(model_id)? {
+        Some(model) => (model.name(), model.model_type()),
+        None => sidecar_model.unwrap_or_else(|| (format!("Model {model_id}"), None)),
+    };
+
+    let readme = super::meta::readme_path(model_file);
+    Ok(Some(IndexEntry {
+        model_file: model_file.to_path_buf(),
+        model_name,
+        model_type,
+        version_name: version.name,
+        base_model: version.base_model,
+        trained_words: version.trained_words,
+        readme: readme.is_file().then_some(readme),
+        cover: existing_cover(model_file).map(|cover| model_file.with_file_name(cover)),
+        removed_upstream: upstream::recorded_removal(version.id),
+    }))
+}
+
+struct VersionFacts {
+    id: u64,
+    name: String,
+    base_model: Option<String>,
+    trained_words: Vec<String>,
+}
+
+impl From<&ModelVersion> for VersionFacts {
+    fn from(version: &ModelVersion) -> Self {
+        Self {
+            id: version.id(),
+            name: version.name(),
+            base_model: version.base_model(),
+            trained_words: version.trained_words(),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+
+use crate::{cache_db, configuration::Configuration};
+
+use super::{
+    is_not_found_error, meta,
+    model::{Model, ModelVersion, ModelVersionFile, model_page_url},
+    readme::recorded_hash,
+    sidecar,
+};
+
+/// A model version found by the hash of one of its files.
+pub struct LookupResult {
+    pub model: Model,
+    pub version: ModelVersion,
+    pub file: Option<ModelVersionFile>,
+}
+
+impl LookupResult {
+    pub fn page_url(&self) -> String {
+        self.version.page_url()
+    }
+}
+
+/// Civitai page of a local model file without requesting Civitai, found by its sidecar, its
+/// location recorded in cache or the hash recorded beside it. `None` when none of them knows it.
+pub fn local_page_url(model_file: &Path) -> Result<Option<String>> {
+    if let Some(sidecar) = sidecar::load_sidecar(model_file)? {
+        return Ok(Some(sidecar.page_url.unwrap_or_else(|| {
+            model_page_url(sidecar.model_id, sidecar.version_id)
+        })));
+    }
+    if let Ok(location) = model_file.canonicalize()
+        && let Some(page_url) = cache_db::retreive_civitai_model_page_url_by_location(location)?
+    {
+        return Ok(Some(page_url));
+    }
+    let Some(hash) = recorded_hash(model_file) else {
+        return Ok(None);
+    };
+    if let Some(page_url) = cache_db::retreive_civitai_model_page_url_by_blake3(&hash)? {
+        return Ok(Some(page_url));
+    }
+    let version = cache_db::find_civitai_model_version(|version| {
+        version
+            .files()
+            .is_ok_and(|files| files.iter().any(|file| file.match_by_blake3(&hash)))
+    })?;
+    Ok(version.map(|version| version.page_url()))
+}
+
+/// Cached model and version metadata of a local model file with a sidecar, without requesting
+/// Civitai. Stats are as fresh as the last fetch of the metadata.
+pub fn local_metadata(model_file: &Path) -> Option<(Model, ModelVersion)> {
+    let sidecar = sidecar::load_sidecar(model_file).ok()??;
+    let model = cache_db::retreive_civitai_model(sidecar.model_id).ok()??;
+    let version =
+        cache_db::retreive_civitai_model_version(sidecar.model_id, sidecar.version_id).ok()??;
+    Some((model, version))
+}
+
+/// Resolves a hash to its model version, `None` when Civitai knows no file with the hash. The
+/// fetched metadata is kept in cache database, and so is the location of the local file if given.
+pub async fn lookup_by_hash(
+    client: &Client,
+    config: &Configuration,
+    hash: &str,
+    local_file: Option<&Path>,
+) -> Result<Option<LookupResult>> {
+    let version = match meta::fetch_model_version_meta_by_hash(client, config, hash).await {
+        Ok(version) => version,
+        Err(e) if is_not_found_error(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let model = meta::fetch_model_metadata(client, config, version.model_id())
+        .await
+        .context("Request for model metadata")?;
+    let file = version
+        .files()?
+        .into_iter()
+        .find(|file| file.match_by_hash(hash));
+
+    if let (Some(local_file), Some(file), Some(blake3)) = (
+        local_file,
+        file.as_ref(),
+        file.as_ref().and_then(ModelVersionFile::blake3_hash),
+    ) {
+        cache_db::store_civitai_model_file_location(
+            model.id(),
+            version.id(),
+            file.id(),
+            &blake3,
+            local_file,
+        )
+        .context("Record model file location")?;
+    }
+
+    Ok(Some(LookupResult {
+        model,
+        version,
+        file,
+    }))
+}
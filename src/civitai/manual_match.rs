@@ -0,0 +1,43 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use time::UtcDateTime;
+
+use crate::cache_db;
+
+/// Model version a local file was associated with by hand, kept in cache database by the BLAKE3
+/// hash of the file so that later renews and scans use it instead of the lookup by hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManualMatch {
+    pub model_id: u64,
+    pub version_id: u64,
+    /// Hash of the version file equal to the local one, `None` when associated with `--force`.
+    pub matched_hash: Option<String>,
+    pub matched_at: i64,
+}
+
+impl ManualMatch {
+    pub fn new(model_id: u64, version_id: u64, matched_hash: Option<String>) -> Self {
+        Self {
+            model_id,
+            version_id,
+            matched_hash,
+            matched_at: UtcDateTime::now().unix_timestamp(),
+        }
+    }
+
+    pub fn load(blake3: &str) -> Result<Option<Self>> {
+        cache_db::retreive_manual_match(blake3)
+    }
+
+    pub fn save(&self, blake3: &str) -> Result<()> {
+        cache_db::store_manual_match(blake3, self)
+    }
+}
+
+/// Model or model version given by user for a local file, at least one of them is present.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ManualTarget {
+    pub model_id: Option<u64>,
+    pub version_id: Option<u64>,
+}
@@ -1,19 +1,53 @@
 use std::{
+    collections::{HashMap, hash_map::Entry},
     env,
     io::{BufReader, Read},
     path::{Path, PathBuf},
-    time::Duration,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, anyhow, bail};
+use indicatif::{MultiProgress, ProgressBar};
 use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
-use reqwest::{Client, Method, header};
-use serde_json::Value;
+use reqwest::{Client, Method, StatusCode, header};
+use serde::Serialize;
+use serde_json::{Value, json};
+use time::UtcDateTime;
 use tokio::{fs::File, io::AsyncWriteExt};
 
-use crate::{cache_db, downloader::make_backoff_policy, utils::duration_to_sec_string};
+use crate::{
+    cache_db,
+    configuration::Configuration,
+    downloader::{make_backoff_policy, read_body_limited},
+    errors::{
+        CivitaiApiError, CloudflareChallengeError, OfflineAndUncachedError, ResponseTooLargeError,
+        UnexpectedResponseError,
+    },
+    events,
+    safetensors::SafetensorsHeader,
+    utils::{
+        bytes_to_human_string, datetime_to_date_string, format_duration, kilobytes_to_human_string,
+        sanitize_file_name,
+    },
+};
+
+use super::{
+    model::{self, ImageMeta, ModelStats},
+    pagination::Paginator,
+    readme_template, schema, upstream,
+};
 
-use super::model::{self, ImageMeta};
+/// Files larger than this show a spinner while hashing.
+const HASH_PROGRESS_THRESHOLD: u64 = 1024 * 1024 * 1024;
+/// Largest metadata response read into memory, far above any real model or image listing.
+const MAX_METADATA_BODY_SIZE: usize = 64 * 1024 * 1024;
+/// Most community images collected for a model.
+const COMMUNITY_IMAGES_LIMIT: usize = 50;
+/// Most community image prompts listed in a readme.
+pub const DEFAULT_MAX_PROMPT_SECTIONS: usize = 15;
+/// Characters of a prompt shown in a readme before it is cut.
+pub const DEFAULT_MAX_PROMPT_LENGTH: usize = 1000;
 
 const FILENAME_SET: &AsciiSet = &NON_ALPHANUMERIC
     .remove(b'.')
@@ -22,28 +56,179 @@ const FILENAME_SET: &AsciiSet = &NON_ALPHANUMERIC
     .remove(b'/')
     .remove(b':');
 
-pub async fn fetch_model_metadata(client: &Client, model_id: u64) -> Result<model::Model> {
-    let config = crate::configuration::CONFIGURATION.read().await;
-    let model_meta_url = format!("https://civitai.com/api/v1/models/{model_id}");
-    let civitai_auth_key = config.civitai.api_key.clone().unwrap_or_default();
-    let meta_request_builder = client
-        .request(Method::GET, model_meta_url)
-        .bearer_auth(civitai_auth_key)
-        .header(header::ACCEPT, "application/json");
-    let request = meta_request_builder.build()?;
-
-    let meta_response = client
-        .execute(request)
+/// Markdown embedding the cover beside the readme, videos are embedded as HTML. The file name is
+/// percent-encoded so that spaces, brackets and other characters can not break the link.
+fn cover_markdown(cover_file_name: &str) -> String {
+    let encoded_file_path = utf8_percent_encode(cover_file_name, FILENAME_SET).to_string();
+    let is_video = [".mp4", ".webm", ".mov"]
+        .iter()
+        .any(|ext| cover_file_name.to_ascii_lowercase().ends_with(ext));
+    if is_video {
+        format!("<video src=\"./{encoded_file_path}\" controls loop muted></video>")
+    } else {
+        format!("![](./{encoded_file_path})")
+    }
+}
+
+/// Requests a Civitai API endpoint and parses the response as JSON, retrying with the
+/// shared backoff policy on network failures, server errors and rate limiting.
+pub(super) async fn fetch_civitai_json<Q>(
+    client: &Client,
+    config: &Configuration,
+    url: &str,
+    query: &Q,
+    resource: &str,
+) -> Result<Value>
+where
+    Q: Serialize + ?Sized,
+{
+    if crate::downloader::is_offline() {
+        return Err(OfflineAndUncachedError {
+            resource: resource.to_string(),
+        }
+        .into());
+    }
+    let max_retry = config.backoff.max_retry;
+    // 带有retry_after的错误不计入退避策略，需要自行限制重试次数
+    let cloudflare_retries = AtomicU32::new(0);
+    let task = async || {
+        let mut request = client
+            .request(Method::GET, url)
+            .header(header::ACCEPT, "application/json")
+            .query(query)
+            .timeout(config.network.request_timeout());
+        // 访问密钥只发送给Civitai，例如分页地址指向其他主机时不发送
+        if config.civitai.sends_key_to(url) {
+            request = request.bearer_auth(config.civitai.api_key.clone().unwrap_or_default());
+        }
+        let request = request
+            .build()
+            .map_err(|e| backoff::Error::permanent(anyhow!("Failed to build request: {e}")))?;
+
+        let requested_at = Instant::now();
+        let response = crate::downloader::execute(client, &config.proxy, request)
+            .await
+            .map_err(|e| backoff::Error::transient(anyhow!("Failed to request {url}: {e}")))?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        schema::report_api_headers(&headers);
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        // reqwest解压后会移除Content-Encoding和Content-Length，只能得到解压后的大小
+        let raw_content = read_body_limited(response, MAX_METADATA_BODY_SIZE)
+            .await
+            .map_err(|e| {
+                if e.downcast_ref::<ResponseTooLargeError>().is_some() {
+                    backoff::Error::permanent(e)
+                } else {
+                    backoff::Error::transient(anyhow!("Failed to read response: {e}"))
+                }
+            })?;
+        events::verbose(
+            2,
+            format!(
+                "Received {} of {resource} (decompressed) in {} ms.",
+                kilobytes_to_human_string(raw_content.len() as f64 / 1024.0),
+                requested_at.elapsed().as_millis()
+            ),
+        );
+        let value = serde_json::from_slice::<Value>(&raw_content);
+        if value.is_err()
+            && let Some(challenge) =
+                CloudflareChallengeError::detect(status, &headers, &raw_content)
+        {
+            return if cloudflare_retries.fetch_add(1, Ordering::Relaxed) < max_retry {
+                Err(backoff::Error::retry_after(
+                    anyhow!(challenge),
+                    CloudflareChallengeError::RETRY_DELAY,
+                ))
+            } else {
+                Err(backoff::Error::permanent(anyhow!(challenge)))
+            };
+        }
+
+        if let Err(api_error) = CivitaiApiError::check(status, value.as_ref().ok(), resource) {
+            return if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                Err(backoff::Error::transient(anyhow!(api_error)))
+            } else {
+                Err(backoff::Error::permanent(anyhow!(api_error)))
+            };
+        }
+        value.map_err(|e| {
+            backoff::Error::transient(anyhow!(UnexpectedResponseError::new(
+                url,
+                status,
+                content_type.as_deref(),
+                &raw_content,
+                e,
+            )))
+        })
+    };
+    let notify_op = |e: anyhow::Error, d| {
+        events::message(format!(
+            "{e}, will try again after {}.",
+            format_duration(&d)
+        ))
+    };
+    let policy = make_backoff_policy(&config.backoff, config.network.request_timeout);
+    backoff::future::retry_notify(policy, task, notify_op)
         .await
-        .context("Failed to retreive model meta info")?;
-    let raw_content = meta_response
-        .bytes()
+        .map_err(CloudflareChallengeError::advise)
+}
+
+pub(super) fn civitai_api_url(config: &Configuration, path: &str) -> String {
+    format!("{}/{path}", config.civitai.api_base())
+}
+
+/// Checks the given access key with a cheap authenticated request, without retrying.
+pub async fn verify_api_key(client: &Client, config: &Configuration, api_key: &str) -> Result<()> {
+    crate::downloader::ensure_online("Validating access key")?;
+    let url = civitai_api_url(config, "models");
+    let request_timeout = config.network.request_timeout();
+    let request = client
+        .request(Method::GET, &url)
+        .bearer_auth(api_key)
+        .header(header::ACCEPT, "application/json")
+        .query(&[("limit", "1")])
+        .timeout(request_timeout)
+        .build()?;
+    let response = crate::downloader::execute(client, &config.proxy, request)
         .await
-        .context("Failed to retreive model meta info")?;
-    let content = String::from_utf8_lossy(&raw_content);
+        .with_context(|| format!("Failed to request {url}"))?;
+    let status = response.status();
+    let value = response.json::<Value>().await.ok();
+    CivitaiApiError::check(status, value.as_ref(), "models")?;
+    Ok(())
+}
 
-    let raw_model_meta =
-        serde_json::from_str::<Value>(&content).context("Failed to parse model meta info")?;
+pub async fn fetch_model_metadata(
+    client: &Client,
+    config: &Configuration,
+    model_id: u64,
+) -> Result<model::Model> {
+    if crate::downloader::is_offline()
+        && let Some(cached_model) = cache_db::retreive_civitai_model(model_id)?
+    {
+        return Ok(cached_model);
+    }
+    let model_meta_url = civitai_api_url(config, &format!("models/{model_id}"));
+    let raw_model_meta = fetch_civitai_json(
+        client,
+        config,
+        &model_meta_url,
+        &(),
+        &format!("model {model_id}"),
+    )
+    .await
+    .context("Failed to retreive model meta info")?;
+    schema::guard(
+        &raw_model_meta,
+        &format!("models/{model_id}"),
+        schema::check_model,
+    )?;
     let model_meta = model::Model::try_from(&raw_model_meta)?;
 
     cache_db::store_civitai_model(&model_meta)?;
@@ -53,68 +238,68 @@ pub async fn fetch_model_metadata(client: &Client, model_id: u64) -> Result<mode
 
 pub async fn fetch_model_version_meta(
     client: &Client,
+    config: &Configuration,
     version_id: u64,
 ) -> Result<model::ModelVersion> {
-    let config = crate::configuration::CONFIGURATION.read().await;
-    let model_meta_url = format!("https://civitai.com/api/v1/model-versions/{version_id}");
-    let civitai_auth_key = config.civitai.api_key.clone().unwrap_or_default();
-    let meta_request_builder = client
-        .request(Method::GET, model_meta_url)
-        .bearer_auth(civitai_auth_key)
-        .header(header::ACCEPT, "application/json");
-    let request = meta_request_builder.build()?;
-
-    let meta_response = client
-        .execute(request)
-        .await
-        .context("Failed to retreive model version meta info")?;
-    let raw_content = meta_response
-        .bytes()
-        .await
-        .context("Failed to retreive model version meta info")?;
-    let content = String::from_utf8_lossy(&raw_content);
-
-    let raw_model_version_meta = serde_json::from_str::<Value>(&content)
-        .context("Failed to parse model version meta info")?;
+    if crate::downloader::is_offline()
+        && let Some(cached_version) =
+            cache_db::find_civitai_model_version(|version| version.id() == version_id)?
+    {
+        return Ok(cached_version);
+    }
+    let model_meta_url = civitai_api_url(config, &format!("model-versions/{version_id}"));
+    let raw_model_version_meta = fetch_civitai_json(
+        client,
+        config,
+        &model_meta_url,
+        &(),
+        &format!("model version {version_id}"),
+    )
+    .await
+    .context("Failed to retreive model version meta info")?;
+    schema::guard(
+        &raw_model_version_meta,
+        &format!("model-versions/{version_id}"),
+        schema::check_model_version,
+    )?;
     let model_version_meta = model::ModelVersion::try_from(&raw_model_version_meta)?;
 
     cache_db::store_civitai_model_version(&model_version_meta)?;
+    upstream::record_seen(&model_version_meta)?;
 
     Ok(model_version_meta)
 }
 
-#[allow(dead_code)]
-pub async fn fetch_model_version_meta_by_blake3(
+/// Civitai resolves a file by any of its hashes: BLAKE3, SHA256, AutoV2, AutoV1 or CRC32.
+pub async fn fetch_model_version_meta_by_hash(
     client: &Client,
+    config: &Configuration,
     model_hash: &str,
 ) -> Result<model::ModelVersion> {
-    let config = crate::configuration::CONFIGURATION.read().await;
-    let model_meta_url = format!("https://civitai.com/api/v1/model-versions/by-hash/{model_hash}");
-    let civitai_auth_key = config.civitai.api_key.clone().unwrap_or_default();
-    let meta_request_builder = client
-        .request(Method::GET, model_meta_url)
-        .bearer_auth(civitai_auth_key)
-        .header(header::ACCEPT, "application/json");
-    let request = meta_request_builder.build()?;
-
-    let meta_response = client
-        .execute(request)
-        .await
-        .context("Failed to retreive model version meta info")?;
-    let raw_content = meta_response
-        .bytes()
-        .await
-        .context("Failed to retreive model version meta info")?;
-    let content = String::from_utf8_lossy(&raw_content);
-
-    let raw_model_version_meta = serde_json::from_str::<Value>(&content)
-        .context("Failed to parse model version meta info")?;
-    if let Some(err_field) = raw_model_version_meta.get("error") {
-        bail!(
-            "Civitai.com returns error: {}",
-            err_field.as_str().unwrap_or_default()
-        );
+    if crate::downloader::is_offline()
+        && let Some(cached_version) = cache_db::find_civitai_model_version(|version| {
+            version
+                .files()
+                .is_ok_and(|files| files.iter().any(|file| file.match_by_hash(model_hash)))
+        })?
+    {
+        return Ok(cached_version);
     }
+    let model_meta_url = civitai_api_url(config, &format!("model-versions/by-hash/{model_hash}"));
+    let raw_model_version_meta = fetch_civitai_json(
+        client,
+        config,
+        &model_meta_url,
+        &(),
+        &format!("model version with hash {model_hash}"),
+    )
+    .await
+    .context("Failed to retreive model version meta info")?;
+    schema::guard(
+        &raw_model_version_meta,
+        "model-versions/by-hash",
+        schema::check_model_version,
+    )?;
     let model_version_meta = model::ModelVersion::try_from(&raw_model_version_meta)?;
 
     cache_db::store_civitai_model_version(&model_version_meta)?;
@@ -122,225 +307,552 @@ pub async fn fetch_model_version_meta_by_blake3(
     Ok(model_version_meta)
 }
 
+pub async fn fetch_image_meta(
+    client: &Client,
+    config: &Configuration,
+    image_id: u64,
+) -> Result<model::ModelCommunityImage> {
+    let image_meta_url = civitai_api_url(config, "images");
+    let raw_response_value = fetch_civitai_json(
+        client,
+        config,
+        &image_meta_url,
+        &[("imageId", image_id)],
+        &format!("image {image_id}"),
+    )
+    .await
+    .context("Failed to retreive image meta info")?;
+    schema::guard(
+        &raw_response_value,
+        "images",
+        schema::check_community_images,
+    )?;
+    let image = model::try_parse_community_images(&raw_response_value)?
+        .into_iter()
+        .find(|image| image.id() == image_id)
+        .ok_or(anyhow!("Image {image_id} is not found"))?;
+
+    Ok(image)
+}
+
+/// Fetches community images of the model, served from cache database when fetched within the
+/// configured time unless `refresh` is set.
 pub async fn fetch_model_community_images(
     client: &Client,
+    config: &Configuration,
     model_id: u64,
+    refresh: bool,
 ) -> Result<Vec<model::ModelCommunityImage>> {
-    let task = async || {
-        println!("Try to fetch the metadata of up to 50 images from the header.");
-        let config = crate::configuration::CONFIGURATION.read().await;
-        let model_meta_url = format!("https://civitai.com/api/v1/images");
-        let civitai_auth_key = config.civitai.api_key.clone().unwrap_or_default();
-        let meta_request_builder = client
-            .request(Method::GET, model_meta_url)
-            .bearer_auth(civitai_auth_key)
-            .header(header::ACCEPT, "application/json")
-            .query(&[("modelId", model_id), ("limit", 50)])
-            .timeout(Duration::from_secs(45));
-        let request = meta_request_builder
-            .build()
-            .map_err(|e| anyhow!("Failed to build community images metadata retreive request: {e}"))
-            .map_err(backoff::Error::transient)?;
-
-        let meta_response = client.execute(request).await;
-
-        match meta_response {
-            Ok(response) => Ok(response),
-            Err(e) => Err(backoff::Error::transient(anyhow!(
-                "Failed to retreive community images metadata: {e}"
-            ))),
-        }
+    let cache_ttl = config.civitai.images_cache_ttl();
+    // 离线模式下忽略缓存有效期
+    let cache_ttl = if crate::downloader::is_offline() {
+        Duration::MAX
+    } else {
+        cache_ttl
     };
-    let notify_op = |_, d| {
-        println!(
-            "Failed to retreive community images metadata, will try again after {}.",
-            duration_to_sec_string(&d)
-        )
-    };
-    let policy = make_backoff_policy(45).await;
-    let meta_response = backoff::future::retry_notify(policy, task, notify_op)
-        .await
-        .context("Retreive community images metadata")?;
-
-    let raw_content = meta_response
-        .bytes()
-        .await
-        .map_err(|e| anyhow!("Failed to retreive model meta info: {}", e.to_string()))
-        .context("Request for community images")?;
-    let content = String::from_utf8_lossy(&raw_content);
-
-    let raw_response_value = serde_json::from_str::<Value>(&content);
-    if raw_response_value.is_err() {
-        println!(
-            "Failed to retreive community images metadata, cancel community images collection.\nCancel community images collection."
-        );
-        return Ok(Vec::new());
-    }
-    let raw_response_value = raw_response_value.unwrap();
-    let err_field = raw_response_value.get("error");
-    if let Some(err_field) = err_field {
-        println!(
-            "Civitai.com returns error: {}\nCancel community images collection.",
-            err_field.as_str().unwrap_or_default()
-        );
-        return Ok(Vec::new());
-    }
-    let response_items = raw_response_value.get("items");
-    if response_items.is_none() {
-        println!(
-            "Retreived community images response is missing required field - [items]\nCancel community images collection."
-        );
-        return Ok(Vec::new());
-    }
-    let response_items = response_items.unwrap();
-    if !response_items.is_array() {
-        println!(
-            "Retreived community images response is not valid.\nCancel community images collection."
-        );
-        return Ok(Vec::new());
+    if (!refresh || crate::downloader::is_offline())
+        && !cache_ttl.is_zero()
+        && let Ok(Some(cached_images)) =
+            cache_db::retreive_civitai_community_images(model_id, cache_ttl)
+    {
+        return Ok(cached_images);
     }
 
-    let mut model_community_images = Vec::new();
-    let items = response_items.as_array().unwrap();
-    for item in items {
-        let image = model::ModelCommunityImage::try_from(item).context("Parse community image")?;
-        model_community_images.push(image);
+    let model_community_images = Paginator::new(
+        client,
+        config,
+        civitai_api_url(config, "images"),
+        vec![
+            ("modelId", model_id.to_string()),
+            ("limit", COMMUNITY_IMAGES_LIMIT.to_string()),
+        ],
+        format!("images of model {model_id}"),
+    )
+    .max_items(COMMUNITY_IMAGES_LIMIT)
+    .collect(|item| {
+        schema::guard(item, "images", schema::check_community_image)?;
+        model::ModelCommunityImage::try_from(item).map_err(anyhow::Error::from)
+    })
+    .await
+    .context("Failed to retreive community images metadata")?;
+    if let Err(e) = cache_db::store_civitai_community_images(model_id, &model_community_images) {
+        events::message(format!("Failed to cache community images metadata: {e}"));
     }
 
     Ok(model_community_images)
 }
 
-async fn write_image_meta(file: &mut File, image: &dyn ImageMeta) -> Result<()> {
-    let posi_prompt = image.positive_prompt();
-    if !posi_prompt.is_some() {
-        bail!("No valid positive prompt");
-    }
-    file.write_all(b"===\n\n").await?;
-
-    let image_url = image.url();
-    let encoed_url = utf8_percent_encode(&image_url, &FILENAME_SET).to_string();
-    file.write_all(format!("[Click to view sample image]({})\n\n", encoed_url).as_bytes())
-        .await?;
+/// How many sample image prompts the readme lists and how long they are.
+#[derive(Debug, Clone, Copy)]
+pub struct PromptLimits {
+    /// Most community images listed, after merging the ones with identical prompts.
+    pub max_sections: usize,
+    /// Prompts longer than this many characters are cut, 0 keeps them whole.
+    pub max_length: usize,
+}
 
-    if let Some(prompt) = posi_prompt {
-        file.write_all(format!("**Positive Prompt:**\n\n{prompt}\n\n").as_bytes())
-            .await?;
+impl Default for PromptLimits {
+    fn default() -> Self {
+        Self {
+            max_sections: DEFAULT_MAX_PROMPT_SECTIONS,
+            max_length: DEFAULT_MAX_PROMPT_LENGTH,
+        }
     }
-    if let Some(neg_prompt) = image.negative_prompt() {
-        file.write_all(format!("**Negative Prompt:**\n\n{neg_prompt}\n\n").as_bytes())
-            .await?;
+}
+
+impl PromptLimits {
+    pub fn new(max_sections: Option<usize>, max_length: Option<usize>) -> Self {
+        let defaults = Self::default();
+        Self {
+            max_sections: max_sections.unwrap_or(defaults.max_sections),
+            max_length: max_length.unwrap_or(defaults.max_length),
+        }
     }
-    if let Some(sampler) = image.sampler() {
-        file.write_all(format!("**Sampler:** {sampler}\n\n").as_bytes())
-            .await?;
+}
+
+/// Cuts the prompt to the character count, marking the cut by an ellipsis.
+fn truncate_prompt(prompt: String, max_length: usize) -> (String, bool) {
+    if max_length == 0 || prompt.chars().count() <= max_length {
+        return (prompt, false);
     }
-    if let Some(scheduler) = image.scheduler() {
-        file.write_all(format!("**Scheduler:** {scheduler}\n\n").as_bytes())
-            .await?;
+    let kept = prompt.chars().take(max_length).collect::<String>();
+    (format!("{}…", kept.trim_end()), true)
+}
+
+/// Variables of a sample image in the readme template. `similar` counts the images left out for
+/// having the same prompts.
+fn image_meta_context(image: &dyn ImageMeta, limits: PromptLimits, similar: usize) -> Value {
+    let (positive_prompt, positive_truncated) = match image.positive_prompt() {
+        Some(prompt) => {
+            let (prompt, truncated) = truncate_prompt(prompt, limits.max_length);
+            (Some(prompt), truncated)
+        }
+        None => (None, false),
+    };
+    let (negative_prompt, negative_truncated) = match image.negative_prompt() {
+        Some(prompt) => {
+            let (prompt, truncated) = truncate_prompt(prompt, limits.max_length);
+            (Some(prompt), truncated)
+        }
+        None => (None, false),
+    };
+    json!({
+        "url": utf8_percent_encode(&image.url(), FILENAME_SET).to_string(),
+        "page_url": image.page_url(),
+        "positive_prompt": positive_prompt,
+        "negative_prompt": negative_prompt,
+        "truncated": positive_truncated || negative_truncated,
+        "similar": similar,
+        "sampler": image.sampler(),
+        "scheduler": image.scheduler(),
+        "seed": image.seed(),
+        "steps": image.steps(),
+        "cfg_scale": image.cfg_scale().map(|cfg_scale| format!("{cfg_scale:.2}")),
+    })
+}
+
+/// Community images with prompts listed in the readme, the ones repeating the positive and
+/// negative prompts of an earlier image are merged into it. Also returns the number of images
+/// left out by the section limit.
+fn community_prompt_sections(
+    images: &[model::ModelCommunityImage],
+    limits: PromptLimits,
+) -> (Vec<Value>, usize) {
+    let mut sections: Vec<(&model::ModelCommunityImage, usize)> = Vec::new();
+    let mut section_by_prompts: HashMap<(String, String), usize> = HashMap::new();
+    for image in images {
+        let Some(positive_prompt) = image.positive_prompt() else {
+            continue;
+        };
+        let prompts = (
+            positive_prompt.trim().to_string(),
+            image
+                .negative_prompt()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+        );
+        match section_by_prompts.entry(prompts) {
+            Entry::Occupied(entry) => sections[*entry.get()].1 += 1,
+            Entry::Vacant(entry) => {
+                entry.insert(sections.len());
+                sections.push((image, 0));
+            }
+        }
     }
-    if let Some(seed) = image.seed() {
-        file.write_all(format!("**Seed:** {seed}\n\n").as_bytes())
-            .await?;
+    let omitted = sections.len().saturating_sub(limits.max_sections);
+    let sections = sections
+        .into_iter()
+        .take(limits.max_sections)
+        .map(|(image, similar)| image_meta_context(image, limits, similar))
+        .collect();
+    (sections, omitted)
+}
+
+/// Files of a model distributed as zip archive, read from the archive or, once it is deleted,
+/// from the directory it was extracted into. Empty for other model files.
+fn archive_contents(model_file_path: &Path) -> Vec<Value> {
+    let content =
+        |path: String, size: u64| json!({"path": path, "size": bytes_to_human_string(size)});
+    if model_file_path.exists() {
+        if !crate::archive::is_zip_file(model_file_path) {
+            return Vec::new();
+        }
+        return crate::archive::list_entries(model_file_path)
+            .map(|entries| {
+                entries
+                    .into_iter()
+                    .filter(|entry| !entry.is_dir)
+                    .map(|entry| content(entry.name, entry.size))
+                    .collect()
+            })
+            .unwrap_or_default();
     }
-    if let Some(steps) = image.steps() {
-        file.write_all(format!("**Steps:** {steps}\n\n").as_bytes())
-            .await?;
+    if !model_file_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+    {
+        return Vec::new();
     }
-    if let Some(cfg_scale) = image.cfg_scale() {
-        file.write_all(format!("**CFG Scale:** {cfg_scale:.2}\n\n").as_bytes())
-            .await?;
+    let extraction_dir = crate::archive::extraction_dir(model_file_path);
+    let mut contents = Vec::new();
+    let mut pending = vec![extraction_dir.clone()];
+    while let Some(dir) = pending.pop() {
+        let Ok(read_dir) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in read_dir.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => pending.push(path),
+                Ok(metadata) => {
+                    let relative = path.strip_prefix(&extraction_dir).unwrap_or(&path);
+                    contents.push(content(
+                        relative.to_string_lossy().replace('\\', "/"),
+                        metadata.len(),
+                    ));
+                }
+                Err(_) => {}
+            }
+        }
     }
-    file.write_all(b"===\n\n").await?;
+    contents.sort_by(|a, b| a["path"].as_str().cmp(&b["path"].as_str()));
+    contents
+}
 
-    Ok(())
+/// Another version of the model listed in the readme, without description when it could not be
+/// fetched.
+pub struct VersionHistoryEntry {
+    pub name: String,
+    pub published_at: Option<UtcDateTime>,
+    pub description: Option<String>,
 }
 
+/// Collects at most `limit` other versions of the model, newest first. Descriptions are taken
+/// from the model metadata, then the cache, and requested only when neither has them.
+pub async fn collect_version_history(
+    client: &Client,
+    config: &Configuration,
+    model: &model::Model,
+    current_version_id: u64,
+    limit: usize,
+) -> Vec<VersionHistoryEntry> {
+    let mut entries = Vec::new();
+    for version in model
+        .versions()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|version| version.id() != current_version_id)
+        .take(limit)
+    {
+        let description = match version.markdown_description() {
+            Some(description) => Some(description),
+            // 单个旧版本获取失败时只列出名称
+            None => match cache_db::retreive_civitai_model_version(model.id(), version.id()) {
+                Ok(Some(cached)) => cached.markdown_description(),
+                _ => fetch_model_version_meta(client, config, version.id())
+                    .await
+                    .ok()
+                    .and_then(|fetched| fetched.markdown_description()),
+            },
+        };
+        entries.push(VersionHistoryEntry {
+            name: version.name(),
+            published_at: version.published_at(),
+            description: description.filter(|description| !description.trim().is_empty()),
+        });
+    }
+    entries
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn save_model_version_readme(
+    config: &Configuration,
     model: &model::Model,
     model_version: &model::ModelVersion,
     community_images: &[model::ModelCommunityImage],
+    version_history: &[VersionHistoryEntry],
     cover_image_filename: Option<String>,
     destination_path: Option<&PathBuf>,
     meta_filename: String,
-) -> Result<()> {
+    prompt_limits: PromptLimits,
+) -> Result<PathBuf> {
     let target_dir = match destination_path {
         Some(path) => path.clone(),
         None => std::env::current_dir()?,
     };
-    let filename = PathBuf::from(meta_filename);
-    let basename = filename.file_stem().unwrap_or_default();
-    let meta_file_path = target_dir.join(format!("{}.md", basename.to_string_lossy()));
+    let model_file_path = target_dir.join(meta_filename);
+    let meta_file_path = readme_path(&model_file_path);
+
+    let dates = [
+        ("Published", model_version.published_at()),
+        ("Updated", model_version.updated_at()),
+    ]
+    .into_iter()
+    .filter_map(|(label, datetime)| {
+        datetime.map(|datetime| format!("{label}: {}", datetime_to_date_string(&datetime)))
+    })
+    .collect::<Vec<_>>();
+    let show_stats = config.download.readme_stats;
+    let model_stats = model.stats();
+    let version_stats = model_version.stats();
+    let stats = if show_stats {
+        stats_line(&model_stats, &version_stats)
+    } else {
+        String::new()
+    };
+    let cover = cover_image_filename.as_deref().map(cover_markdown);
+    let files = model_version
+        .files()?
+        .iter()
+        .map(|file| {
+            json!({
+                "name": file.name(),
+                "size": kilobytes_to_human_string(file.size()),
+                "pickle_scan": file.pickle_scan_result().unwrap_or("-".to_string()),
+                "virus_scan": file.virus_scan_result().unwrap_or("-".to_string()),
+            })
+        })
+        .collect::<Vec<_>>();
+    let other_versions = version_history
+        .iter()
+        .map(|entry| {
+            json!({
+                "name": entry.name,
+                "published": entry.published_at.map(|published_at| datetime_to_date_string(&published_at)),
+                "description": entry.description.as_deref().map(str::trim),
+            })
+        })
+        .collect::<Vec<_>>();
+    let version_images = model_version
+        .images()?
+        .iter()
+        .map(|image| image_meta_context(image, prompt_limits, 0))
+        .collect::<Vec<_>>();
+    let (community_images, community_images_omitted) =
+        community_prompt_sections(community_images, prompt_limits);
+    let context = json!({
+        "model_name": model.name(),
+        "model_url": model_version.page_url(),
+        "model_description": model.markdown_description(),
+        "version_name": model_version.name(),
+        "version_description": model_version.markdown_description(),
+        "published": model_version.published_at().map(|datetime| datetime_to_date_string(&datetime)),
+        "updated": model_version.updated_at().map(|datetime| datetime_to_date_string(&datetime)),
+        "dates": dates.join(" · "),
+        "air": model_version.air().map(|air| air.to_string()),
+        "stats": stats,
+        "model_stats": model_stats,
+        "version_stats": version_stats,
+        "permissions": model
+            .permissions()
+            .lines()
+            .into_iter()
+            .map(|(label, value)| json!({"label": label, "value": value}))
+            .collect::<Vec<_>>(),
+        "cover": cover,
+        "cover_path": cover_image_filename,
+        "trained_words": model_version.trained_words(),
+        "files": files,
+        "archive_contents": archive_contents(&model_file_path),
+        "other_versions": other_versions,
+        "version_images": version_images,
+        "community_images": community_images,
+        "community_images_omitted": community_images_omitted,
+    });
+    let readme = readme_template::load_readme_template()?.render(&context)?;
+    tokio::fs::write(&meta_file_path, readme).await?;
+
+    Ok(meta_file_path)
+}
+
+/// One line of the counters Civitai shows, the ones missing from the metadata are left out.
+fn stats_line(model_stats: &ModelStats, version_stats: &ModelStats) -> String {
+    let mut parts = Vec::new();
+    if let Some(downloads) = model_stats.download_count {
+        parts.push(match version_stats.download_count {
+            Some(version_downloads) => {
+                format!("Downloads: {downloads} ({version_downloads} this version)")
+            }
+            None => format!("Downloads: {downloads}"),
+        });
+    }
+    if let Some(thumbs_up) = model_stats.thumbs_up_count {
+        parts.push(format!("Thumbs up: {thumbs_up}"));
+    }
+    if let Some(favorites) = model_stats.favorite_count {
+        parts.push(format!("Favorites: {favorites}"));
+    }
+    if let Some(rating) = model_stats.rating.filter(|rating| *rating > 0.0) {
+        parts.push(match model_stats.rating_count {
+            Some(count) => format!("Rating: {rating:.1} ({count})"),
+            None => format!("Rating: {rating:.1}"),
+        });
+    }
+    parts.join(" · ")
+}
 
-    let model_description = model.markdown_description();
-    let model_version_description = model_version.markdown_description();
+/// Path of the readme written beside the model file, `<sanitized file stem>.md`.
+pub fn readme_path<P: AsRef<Path>>(model_file: P) -> PathBuf {
+    let model_file = model_file.as_ref();
+    let stem = model_file.file_stem().unwrap_or_default().to_string_lossy();
+    model_file.with_file_name(format!("{}.md", sanitize_file_name(&stem)))
+}
+
+/// Saves a readme built from the metadata embedded in the model file, used for models that
+/// are not found on Civitai.
+pub async fn save_local_model_readme<P: AsRef<Path>>(
+    model_file_path: P,
+    header: &SafetensorsHeader,
+) -> Result<PathBuf> {
+    let model_file_path = model_file_path.as_ref();
+    let basename = model_file_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let meta_file_path = readme_path(model_file_path);
 
-    let mut meta_file = File::create(meta_file_path).await?;
+    let mut meta_file = File::create(&meta_file_path).await?;
     meta_file
-        .write_all(format!("# {}\n\n", model.name()).as_bytes())
+        .write_all(format!("# {}\n\n", header.title().unwrap_or(basename)).as_bytes())
         .await?;
-    meta_file.write_all(model_description.as_bytes()).await?;
     meta_file
-        .write_all(format!("\n\n## Version: {}\n\n", model_version.name()).as_bytes())
+        .write_all(
+            b"> This model is not found on Civitai, following information is read from the metadata embedded in model file.\n\n",
+        )
         .await?;
-
-    if let Some(image) = cover_image_filename {
-        let encoded_file_path = utf8_percent_encode(&image, &FILENAME_SET).to_string();
+    if let Some(base_model) = header.base_model() {
         meta_file
-            .write_all(format!("![](./{encoded_file_path})\n\n").as_bytes())
+            .write_all(format!("**Base Model:** {base_model}\n\n").as_bytes())
             .await?;
     }
-
-    if let Some(description) = model_version_description {
-        meta_file.write_all(description.as_bytes()).await?;
+    if let Some(network) = header.network_summary() {
+        meta_file
+            .write_all(format!("**Network:** {network}\n\n").as_bytes())
+            .await?;
     }
-    meta_file.write_all(b"\n\n").await?;
-
-    let trained_words = model_version.trained_words();
-    if trained_words.len() > 0 {
-        meta_file.write_all(b"## Trained Words\n\n").await?;
-        for word in trained_words.iter() {
-            meta_file
-                .write_all(format!("- {word}\n").as_bytes())
-                .await?;
-        }
+    if let Some(alpha) = header.network_alpha() {
+        meta_file
+            .write_all(format!("**Network Alpha:** {alpha}\n\n").as_bytes())
+            .await?;
     }
-
-    let version_cover_images = model_version.images()?;
-    if !version_cover_images.is_empty() {
-        meta_file.write_all(b"## Cover image prompts\n\n").await?;
-        for image in version_cover_images {
-            if image.positive_prompt().is_some() {
-                write_image_meta(&mut meta_file, &image).await?;
-            }
-        }
+    if let Some(trigger) = header.trigger_phrase() {
+        meta_file
+            .write_all(format!("## Trigger Phrase\n\n{trigger}\n\n").as_bytes())
+            .await?;
     }
-
-    if !community_images.is_empty() {
+    if let Some(comment) = header.training_comment() {
         meta_file
-            .write_all(b"## Community image prompts\n\n")
+            .write_all(format!("## Training Comment\n\n{comment}\n\n").as_bytes())
             .await?;
-        for image in community_images {
-            if image.positive_prompt().is_some() {
-                write_image_meta(&mut meta_file, image).await?;
-            }
-        }
     }
-
     meta_file.flush().await?;
 
-    Ok(())
+    Ok(meta_file_path)
+}
+
+/// Calculates blake3 hash of the file on a blocking thread. A spinner is attached to the given
+/// progress display when the file is large enough to take noticeable time.
+pub async fn blake3_hash<P: AsRef<Path>>(
+    target_file: P,
+    progress: Option<&MultiProgress>,
+) -> Result<String> {
+    let target_file_path = target_file.as_ref().to_path_buf();
+    let spinner = hash_spinner(&target_file_path, progress)?;
+    let hash_str = tokio::task::spawn_blocking(move || blake3_hash_blocking(&target_file_path))
+        .await
+        .context("Hash calculation is interrupted")??;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+
+    Ok(hash_str)
+}
+
+/// Calculates sha256 hash of the file on a blocking thread, in upper case like blake3.
+pub async fn sha256_hash<P: AsRef<Path>>(
+    target_file: P,
+    progress: Option<&MultiProgress>,
+) -> Result<String> {
+    let target_file_path = target_file.as_ref().to_path_buf();
+    let spinner = hash_spinner(&target_file_path, progress)?;
+    let hashes = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&target_file_path)?;
+        crate::hashing::hash_reader(BufReader::new(file), None, false, true)
+    })
+    .await
+    .context("Hash calculation is interrupted")??;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+
+    hashes
+        .sha256
+        .ok_or_else(|| anyhow!("SHA256 hash is not calculated"))
 }
 
-#[allow(dead_code)]
-pub fn blake3_hash<P: AsRef<Path>>(target_file: P) -> Result<String> {
-    let target_file_path = target_file.as_ref();
+/// A spinner on the progress display while hashing a file large enough to take noticeable time.
+fn hash_spinner(
+    target_file_path: &Path,
+    progress: Option<&MultiProgress>,
+) -> Result<Option<ProgressBar>> {
     if !target_file_path.exists() {
         bail!("Request file {} not exists", target_file_path.display());
     }
+    let file_size = std::fs::metadata(target_file_path)?.len();
+    Ok(progress
+        .filter(|_| file_size >= HASH_PROGRESS_THRESHOLD)
+        .map(|multi| {
+            let spinner = multi.add(ProgressBar::new_spinner());
+            spinner.set_message(format!(
+                "Calculating hash of {}...",
+                target_file_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+            ));
+            spinner.enable_steady_tick(Duration::from_millis(120));
+            spinner
+        }))
+}
+
+fn blake3_hash_blocking(target_file_path: &Path) -> Result<String> {
+    // 优先使用内存映射多线程计算，无法映射的文件使用流式读取
+    #[cfg(target_pointer_width = "64")]
+    {
+        let mut hasher = blake3::Hasher::new();
+        if hasher.update_mmap_rayon(target_file_path).is_ok() {
+            return Ok(hasher.finalize().to_hex().to_string().to_uppercase());
+        }
+    }
+
+    blake3_hash_streaming(target_file_path)
+}
 
+fn blake3_hash_streaming(target_file_path: &Path) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
     let mut file = std::fs::File::open(target_file_path)?;
     let mut reader = BufReader::new(&mut file);
-    let mut hasher = blake3::Hasher::new();
     let mut buffer = [0u8; 512 * 1024];
 
     loop {
@@ -357,23 +869,315 @@ pub fn blake3_hash<P: AsRef<Path>>(target_file: P) -> Result<String> {
 }
 
 pub async fn save_version_file_hash<P: AsRef<Path>>(source_file_path: P, hash: &str) -> Result<()> {
-    let source_file = source_file_path.as_ref();
+    let hash_file_path = hash_file_path(source_file_path.as_ref(), "blake3")?;
+    let mut hash_file = File::create(hash_file_path).await?;
+    let blake3_str = hash.to_string().to_uppercase();
+    hash_file.write_all(blake3_str.as_bytes()).await?;
+    hash_file.flush().await?;
+
+    Ok(())
+}
+
+/// Saves the trained words of the version comma separated in `<stem>.txt` beside the model file,
+/// the caption format trainers and prompt tools read. Nothing is written for versions without
+/// trained words.
+pub async fn save_trained_words<P: AsRef<Path>>(
+    source_file_path: P,
+    model_version: &model::ModelVersion,
+) -> Result<Option<PathBuf>> {
+    let trained_words = model_version.trained_words();
+    if trained_words.is_empty() {
+        return Ok(None);
+    }
+    let words_file_path = hash_file_path(source_file_path.as_ref(), "txt")?;
+    tokio::fs::write(&words_file_path, trained_words.join(", ")).await?;
+    Ok(Some(words_file_path))
+}
+
+/// Saves sha256 hash beside the model file, so files found by it are not hashed again.
+pub async fn save_version_file_sha256<P: AsRef<Path>>(
+    source_file_path: P,
+    hash: &str,
+) -> Result<()> {
+    let hash_file_path = hash_file_path(source_file_path.as_ref(), "sha256")?;
+    tokio::fs::write(hash_file_path, hash.to_uppercase()).await?;
+    Ok(())
+}
+
+/// Reads the sha256 hash saved beside the model file, ignoring anything that is not one.
+pub fn load_version_file_sha256<P: AsRef<Path>>(source_file_path: P) -> Option<String> {
+    let hash_file_path = hash_file_path(source_file_path.as_ref(), "sha256").ok()?;
+    let hash = std::fs::read_to_string(hash_file_path).ok()?;
+    let hash = hash.trim();
+    (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())).then(|| hash.to_uppercase())
+}
 
+/// Path of the hash file beside the model file, like `<file stem>.blake3`.
+fn hash_file_path(source_file: &Path, extension: &str) -> Result<PathBuf> {
     let model_file_name = source_file
         .file_stem()
-        .map(|s| s.to_string_lossy().into_owned())
+        .map(|s| sanitize_file_name(&s.to_string_lossy()))
         .unwrap();
-    let hash_file_name = format!("{model_file_name}.blake3");
-    let hash_file_path = match source_file.parent() {
+    Ok(match source_file.parent() {
         Some(dir) => dir.to_path_buf(),
         None => env::current_dir()?,
     }
-    .join(hash_file_name);
+    .join(format!("{model_file_name}.{extension}")))
+}
 
-    let mut hash_file = File::create(hash_file_path).await?;
-    let blake3_str = hash.to_string().to_uppercase();
-    hash_file.write_all(blake3_str.as_bytes()).await?;
-    hash_file.flush().await?;
+#[cfg(test)]
+mod tests {
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers::path};
 
-    Ok(())
+    use super::*;
+
+    #[test]
+    fn blake3_paths_agree_with_one_shot_hash() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_path = directory.path().join("model.safetensors");
+        // 跨越多个读取缓冲区且长度不对齐，覆盖多线程和流式两种路径
+        let content = (0..3 * 1024 * 1024 + 12345)
+            .map(|i: u32| (i.wrapping_mul(31) >> 3) as u8)
+            .collect::<Vec<_>>();
+        std::fs::write(&file_path, &content).unwrap();
+
+        let expected = blake3::hash(&content).to_hex().to_string().to_uppercase();
+        assert_eq!(blake3_hash_blocking(&file_path).unwrap(), expected);
+        assert_eq!(blake3_hash_streaming(&file_path).unwrap(), expected);
+    }
+
+    #[test]
+    fn cover_links_are_percent_encoded() {
+        assert_eq!(
+            cover_markdown("style v1.0.cover.png"),
+            "![](./style%20v1.0.cover.png)"
+        );
+        assert_eq!(
+            cover_markdown("(detail) [x]_y-z.cover.png"),
+            "![](./%28detail%29%20%5Bx%5D_y-z.cover.png)"
+        );
+        assert_eq!(
+            cover_markdown("🔥风格.cover.png"),
+            "![](./%F0%9F%94%A5%E9%A3%8E%E6%A0%BC.cover.png)"
+        );
+        assert_eq!(
+            cover_markdown("clip #1.cover.MP4"),
+            "<video src=\"./clip%20%231.cover.MP4\" controls loop muted></video>"
+        );
+    }
+
+    #[test]
+    fn readme_path_uses_sanitized_stem() {
+        assert_eq!(
+            readme_path("/models/style v1.0.safetensors"),
+            PathBuf::from("/models/style v1.0.md")
+        );
+        assert_eq!(
+            readme_path("/models/style | v2 .safetensors"),
+            PathBuf::from("/models/style _ v2.md")
+        );
+        assert_eq!(readme_path("model.ckpt"), PathBuf::from("model.md"));
+    }
+
+    fn community_image(
+        id: u64,
+        prompt: Option<&str>,
+        negative: Option<&str>,
+    ) -> model::ModelCommunityImage {
+        model::ModelCommunityImage::try_from(&json!({
+            "id": id,
+            "url": format!("https://image.civitai.com/{id}.png"),
+            "meta": { "prompt": prompt, "negativePrompt": negative },
+        }))
+        .unwrap()
+    }
+
+    fn prompts(sections: &[Value]) -> Vec<(&str, u64)> {
+        sections
+            .iter()
+            .map(|section| {
+                (
+                    section["positive_prompt"].as_str().unwrap(),
+                    section["similar"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_community_prompts_are_merged() {
+        let images = [
+            community_image(1, Some("a cat"), Some("blurry")),
+            community_image(2, Some("  a cat \n"), Some("blurry ")),
+            community_image(3, None, Some("blurry")),
+            community_image(4, Some("a cat"), None),
+            community_image(5, Some("a dog"), Some("blurry")),
+            community_image(6, Some("a cat"), Some("blurry")),
+        ];
+        let (sections, omitted) = community_prompt_sections(&images, PromptLimits::new(None, None));
+
+        assert_eq!(
+            prompts(&sections),
+            [("a cat", 2), ("a cat", 0), ("a dog", 0)]
+        );
+        assert_eq!(sections[0]["url"], "https://image.civitai.com/1.png");
+        assert_eq!(sections[1]["negative_prompt"], Value::Null);
+        assert_eq!(omitted, 0);
+    }
+
+    #[test]
+    fn community_prompts_are_capped_after_merging() {
+        let images = [
+            community_image(1, Some("one"), None),
+            community_image(2, Some("one"), None),
+            community_image(3, Some("two"), None),
+            community_image(4, Some("three"), None),
+            community_image(5, Some("four"), None),
+        ];
+        let (sections, omitted) =
+            community_prompt_sections(&images, PromptLimits::new(Some(2), None));
+        assert_eq!(prompts(&sections), [("one", 1), ("two", 0)]);
+        assert_eq!(omitted, 2);
+
+        let (sections, omitted) =
+            community_prompt_sections(&images, PromptLimits::new(Some(0), None));
+        assert!(sections.is_empty());
+        assert_eq!(omitted, 4);
+    }
+
+    #[test]
+    fn long_prompts_are_truncated() {
+        assert_eq!(
+            truncate_prompt("short".to_string(), 5),
+            ("short".to_string(), false)
+        );
+        assert_eq!(
+            truncate_prompt("a cat, a dog".to_string(), 6),
+            ("a cat,…".to_string(), true)
+        );
+        assert_eq!(
+            truncate_prompt("a cat, a dog".to_string(), 7),
+            ("a cat,…".to_string(), true)
+        );
+        assert_eq!(
+            truncate_prompt("猫和狗和鸟".to_string(), 3),
+            ("猫和狗…".to_string(), true)
+        );
+        assert_eq!(
+            truncate_prompt("kept whole".to_string(), 0),
+            ("kept whole".to_string(), false)
+        );
+
+        let image = community_image(1, Some("a very long prompt"), Some("short"));
+        let section = image_meta_context(&image, PromptLimits::new(None, Some(6)), 0);
+        assert_eq!(section["positive_prompt"], "a very…");
+        assert_eq!(section["negative_prompt"], "short");
+        assert_eq!(section["truncated"], true);
+        assert_eq!(section["page_url"], image.page_url().unwrap());
+    }
+
+    /// Settings sending the key to the mock server, retrying after about one second until about
+    /// three seconds have passed.
+    fn api_config(server: &MockServer) -> Configuration {
+        let mut config = Configuration::default();
+        config.civitai.api_key = Some("fixture-key".to_string());
+        config.civitai.api_base_url = Some(format!("{}/api/v1", server.uri()));
+        config.backoff = crate::configuration::BackoffConfig {
+            initial_interval: 1,
+            multiplier: 2.0,
+            max_retry: 1,
+        };
+        config.network.request_timeout = 1;
+        config
+    }
+
+    /// Requests model 1 from the mock server, returning the result with the requests received.
+    async fn fetch_model(server: &MockServer) -> (Result<Value>, usize) {
+        let config = api_config(server);
+        let url = civitai_api_url(&config, "models/1");
+        let result = fetch_civitai_json(&Client::new(), &config, &url, &(), "model 1").await;
+        let requests = server.received_requests().await.unwrap().len();
+        (result, requests)
+    }
+
+    #[tokio::test]
+    async fn not_found_is_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(path("/api/v1/models/1"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({ "error": "No model" })))
+            .mount(&server)
+            .await;
+
+        let (result, requests) = fetch_model(&server).await;
+        assert!(result.is_err());
+        assert_eq!(requests, 1);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let server = MockServer::start().await;
+        Mock::given(path("/api/v1/models/1"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/api/v1/models/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 1 })))
+            .mount(&server)
+            .await;
+
+        let (result, requests) = fetch_model(&server).await;
+        assert_eq!(result.unwrap()["id"], 1);
+        assert_eq!(requests, 2);
+    }
+
+    #[tokio::test]
+    async fn unparsable_body_is_retried_and_reported() {
+        let server = MockServer::start().await;
+        Mock::given(path("/api/v1/models/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{\"id\": 1"))
+            .mount(&server)
+            .await;
+
+        let (result, requests) = fetch_model(&server).await;
+        // 无法解析的内容可能是被截断的响应，重试后仍失败时报告响应的内容
+        let error = result.unwrap_err();
+        let unexpected = error.downcast_ref::<UnexpectedResponseError>().unwrap();
+        assert_eq!(unexpected.status, 200);
+        assert!(unexpected.snippet.contains("{\"id\": 1"), "{error:#}");
+        assert!(requests > 1);
+    }
+
+    #[tokio::test]
+    async fn api_errors_name_the_failure() {
+        for (status, body, expected) in [
+            (
+                404,
+                json!({ "error": "No model with id 1" }),
+                "Civitai: model 1 not found (404)",
+            ),
+            (
+                401,
+                json!({ "error": "Unauthorized" }),
+                "Civitai: access key is invalid or missing, check it with `imd config get civitai` (401)",
+            ),
+            (
+                429,
+                json!({ "message": "Too Many Requests" }),
+                "Civitai: too many requests, rate limited (429)",
+            ),
+        ] {
+            let server = MockServer::start().await;
+            Mock::given(path("/api/v1/models/1"))
+                .respond_with(ResponseTemplate::new(status).set_body_json(body))
+                .mount(&server)
+                .await;
+
+            let (result, _) = fetch_model(&server).await;
+            let error = result.unwrap_err();
+            let api_error = error.downcast_ref::<CivitaiApiError>().unwrap();
+            assert_eq!(api_error.status, status);
+            assert_eq!(error.to_string(), expected);
+        }
+    }
 }
@@ -1,237 +1,1018 @@
-use std::{
-    env,
-    path::{Path, PathBuf},
-};
+use std::path::PathBuf;
 
-use anyhow::{Context, Result, anyhow, bail};
-use reqwest::{Client, Url};
+use anyhow::{Context, Result, bail};
+use reqwest::{Client, StatusCode, Url};
 
+mod access;
+mod air;
+mod cdn;
+mod collection;
+mod complete_meta;
+mod cover_picker;
+mod decisions;
 mod download_task;
+mod file_preference;
+mod history;
+mod index;
+mod lookup;
+mod manual_match;
 mod meta;
 mod model;
+mod pagination;
+mod permissions;
+mod plan;
+mod readme;
+mod readme_template;
+mod resolved_url;
+mod schema;
 mod selections;
+mod session;
+mod sidecar;
+mod transfer_stats;
+mod upstream;
 
+pub use access::FileAccess;
+pub use air::{AIR_SHAPE, Air, is_air};
+pub use collection::{
+    CIVITAI_SYNC_URL_SHAPES, fetch_source_models, fetch_user_models, try_parse_civitai_sync_url,
+};
+pub use complete_meta::{CompletionBehavior, complete_file_meta, complete_file_meta_with_hash};
+pub use decisions::{ExistingFileDecision, forget_decisions};
+pub use download_task::{download_single_model_file, stream_single_model_file};
+pub use file_preference::FilePreference;
+pub use history::DownloadRecord;
+pub use index::{IndexEntry, index_entry};
+pub use lookup::{LookupResult, local_metadata, local_page_url, lookup_by_hash};
+pub use manual_match::{ManualMatch, ManualTarget};
+pub use meta::{
+    DEFAULT_MAX_PROMPT_LENGTH, DEFAULT_MAX_PROMPT_SECTIONS, PromptLimits, blake3_hash,
+    fetch_model_community_images, fetch_model_metadata, fetch_model_version_meta,
+    fetch_model_version_meta_by_hash, readme_path, save_version_file_hash, verify_api_key,
+};
 pub use model::*;
+pub use permissions::{CommercialUsePolicy, ModelPermissions};
+pub use plan::{DownloadBehavior, SizeBudget};
+pub use readme::{ReadmeRegeneration, regenerate_readme};
+pub use readme_template::{load_readme_template, readme_template_path};
+use selections::{ExistingFileAction, OverBudgetAction};
+pub use selections::{VersionSelection, model_summary_rows, select_models};
+pub use session::DownloadSession;
+use session::SessionFileState;
+pub use sidecar::{CivitaiSidecar, load_sidecar};
+pub use transfer_stats::{FailureCategory, FileTransferStats};
+pub use upstream::{
+    RemovalKind, UpstreamState, check_version_upstream, local_removal, recorded_removal,
+};
+
+use crate::{
+    artifacts::Artifact,
+    configuration::Configuration,
+    errors::{CivitaiApiError, InvalidInputError, SizeBudgetExceededError},
+    events::{self, Event},
+    progress::{
+        ArtifactKind, FileSummary, OperationSummary, ReporterKind, SkipReason, StepProgress,
+    },
+    utils::bytes_to_human_string,
+};
 
-use crate::cache_db;
+/// Steps shown for each downloaded version: version metadata, files, cover image, community
+/// images and readme.
+const STEPS_PER_VERSION: usize = 5;
 
-pub fn try_parse_civitai_model_url(url: &Url) -> Result<(String, Option<String>)> {
-    let path_segments = url.path_segments();
-    let model_id = if let Some(mut segments) = path_segments {
+/// URL shapes accepted by download command, shown when the given URL is not recognized.
+pub const CIVITAI_URL_SHAPES: &str = "https://civitai.com/models/<model id>, https://civitai.com/models/<model id>?modelVersionId=<version id>, https://civitai.com/images/<image id>, https://civitai.com/user/<username> or an AIR like urn:air:sd1:lora:civitai:<model id>@<version id>";
+
+/// Parses the model id and the optional model version id from a model page URL.
+pub fn try_parse_civitai_model_url(url: &Url) -> Result<(u64, Option<u64>)> {
+    let model_id_segment = url.path_segments().and_then(|mut segments| {
         segments
             .clone()
             .position(|s| s.eq_ignore_ascii_case("models"))
             .and_then(|index| segments.nth(index + 1))
-            .map(ToString::to_string)
-            .ok_or(anyhow!("The given url does not contain any model id."))?
-    } else {
-        bail!("The given url does not contain any model id.");
+    });
+    let Some(model_id_segment) = model_id_segment.filter(|s| !s.is_empty()) else {
+        return Err(InvalidInputError(format!(
+            "\"{url}\" does not contain any model id, expected {CIVITAI_URL_SHAPES}"
+        ))
+        .into());
     };
+    let model_id = model_id_segment.parse::<u64>().map_err(|_| {
+        InvalidInputError(format!(
+            "\"{model_id_segment}\" in \"{url}\" is not a valid model id, expected {CIVITAI_URL_SHAPES}"
+        ))
+    })?;
 
     let model_version_id = url
         .query_pairs()
         .find(|(key, _)| key.eq_ignore_ascii_case("modelVersionId"))
-        .map(|(_, value)| value.to_string());
+        .map(|(_, value)| {
+            value.parse::<u64>().map_err(|_| {
+                InvalidInputError(format!(
+                    "\"{value}\" in \"{url}\" is not a valid model version id, expected {CIVITAI_URL_SHAPES}"
+                ))
+            })
+        })
+        .transpose()?;
 
     Ok((model_id, model_version_id))
 }
 
-pub async fn download_from_civitai(
-    client: &reqwest::Client,
-    model_id: u64,
-    version_id: Option<u64>,
-    destination_path: Option<&PathBuf>,
-    skip_community: bool,
-) -> Result<()> {
-    println!("Fetching model metadata...");
-    let model_meta = meta::fetch_model_metadata(client, model_id).await?;
-    let selected_version = selections::select_model_version(&model_meta, version_id)
-        .context("Unable to confirm model version")?;
+/// Username of a user page like `https://civitai.com/user/<username>/models`.
+pub fn try_parse_civitai_user_url(url: &Url) -> Option<String> {
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+    match (segments.next(), segments.next()) {
+        (Some(kind), Some(username)) if kind.eq_ignore_ascii_case("user") => {
+            Some(username.to_string())
+        }
+        _ => None,
+    }
+}
 
-    println!("Fetching specified version metadata...");
-    let selected_version_meta = meta::fetch_model_version_meta(client, selected_version)
+pub fn try_parse_civitai_image_url(url: &Url) -> Option<u64> {
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    segments
+        .iter()
+        .position(|s| s.eq_ignore_ascii_case("images"))
+        .and_then(|index| segments.get(index + 1))
+        .and_then(|id| id.parse::<u64>().ok())
+}
+
+/// Resolves the model versions used by an image, and lets user choose one of them.
+/// Returns the model id and the model version id of the chosen one.
+pub async fn select_image_model_version(
+    client: &Client,
+    config: &Configuration,
+    image_id: u64,
+) -> Result<(u64, u64)> {
+    events::message("Fetching image metadata...");
+    let image_meta = meta::fetch_image_meta(client, config, image_id)
         .await
-        .with_context(|| format!("Failed to fetch version {selected_version} detail metadata"))?;
+        .with_context(|| format!("Failed to fetch image {image_id} metadata"))?;
+    let version_ids = image_meta.referenced_version_ids();
+    if version_ids.is_empty() {
+        bail!("Image {image_id} does not contain any information of the models used");
+    }
 
-    let selected_version_file_ids = selections::select_model_version_files(&selected_version_meta)
-        .context("Failed to confirm model version files")?;
+    events::message("Fetching metadata of the models used by image...");
+    let mut versions = Vec::new();
+    for version_id in version_ids {
+        match meta::fetch_model_version_meta(client, config, version_id).await {
+            Ok(version_meta) => versions.push(version_meta),
+            Err(e) => events::message(format!("Skip model version {version_id}: {e}")),
+        }
+    }
+    if versions.is_empty() {
+        bail!("None of the models used by image {image_id} is available");
+    }
+
+    let selected_version =
+        selections::select_image_resource(&versions).context("Unable to confirm model version")?;
+    Ok((selected_version.model_id(), selected_version.id()))
+}
 
-    let version_files = selected_version_meta.files()?;
-    let primary_file_id = version_files
+/// Streams the primary file of the model version to stdout, to be piped into another program.
+/// Nothing else is saved, neither metadata, cover nor readme, and progress goes to stderr.
+pub async fn stream_from_civitai(
+    client: &reqwest::Client,
+    config: &Configuration,
+    model_id: Option<u64>,
+    version_id: u64,
+    allow_unsafe: bool,
+) -> Result<OperationSummary> {
+    crate::downloader::ensure_online("Downloading models")?;
+    let mut progress = StepProgress::new(2);
+    progress.begin(format!("Fetching version {version_id} metadata..."));
+    let version =
+        progress.track(meta::fetch_model_version_meta(client, config, version_id).await)?;
+    if let Some(model_id) = model_id
+        && version.model_id() != model_id
+    {
+        return Err(InvalidInputError(format!(
+            "Model version {version_id} belongs to model {}, not model {model_id}",
+            version.model_id()
+        ))
+        .into());
+    }
+    let version_files = version.files()?;
+    let Some(primary_file) = version_files
         .iter()
         .find(|f| f.is_primary().unwrap_or_default())
-        .map(|f| f.id())
-        .unwrap_or_else(|| version_files[0].id());
-    let mut target_meta_filename = String::new();
-
-    let version_file_name = |id: u64| -> Option<String> {
-        version_files
-            .iter()
-            .find(|f| f.id() == id)
-            .map(ModelVersionFile::name)
-    };
-    let version_file_hash = |id: u64| -> Option<String> {
-        version_files
-            .iter()
-            .find(|f| f.id() == id)
-            .map(ModelVersionFile::blake3_hash)
-            .flatten()
+        .or(version_files.first())
+    else {
+        bail!("Model version {} has no file to download", version.name());
     };
+    if primary_file.is_unsafe() && !allow_unsafe {
+        return Err(InvalidInputError(format!(
+            "Refuse to download {}, Civitai marked it as dangerous [{}]. Use --allow-unsafe to download it anyway.",
+            primary_file.name(),
+            primary_file.scan_warning().unwrap_or_default()
+        ))
+        .into());
+    }
 
-    for file_id in selected_version_file_ids {
-        // 检查缓存数据库中是否已经存在该模型的下载记录，对比数据库中记录的文件位置列表
-        // 未下载过的和未使用renew命令的文件将会直接重新下载。
-        if let Some(hash) = version_file_hash(file_id) {
-            // 只有在存在有效hash数据的时候才进行判断
-            let file_locations = cache_db::retreive_civitai_model_locations_by_blake3(&hash);
-            if let Ok(Some(locations)) = file_locations {
-                let first_exists_location = locations.iter().find(|loc| loc.exists());
-                if let Some(file_path) = first_exists_location
-                    && !selections::decide_proceeding_or_not(file_path)
-                {
+    progress.begin(format!("Streaming {}...", primary_file.name()));
+    let mut reporter = ReporterKind::Bar.create(&primary_file.name(), &progress);
+    let streamed_file = download_task::stream_single_model_file(
+        client,
+        config,
+        &version,
+        primary_file.id(),
+        tokio::io::stdout(),
+        reporter.as_mut(),
+    )
+    .await;
+    let streamed_file = progress.track(streamed_file)?;
+
+    let mut summary = OperationSummary::default();
+    if streamed_file.hash_matched == Some(false) {
+        summary.fail(
+            streamed_file.name.clone(),
+            ArtifactKind::HashCheck,
+            "blake3 differs from the hash declared by Civitai",
+        );
+    }
+    summary.files.push(streamed_file);
+    summary.print(progress.elapsed());
+    Ok(summary)
+}
+
+pub async fn download_from_civitai(
+    client: &reqwest::Client,
+    config: &Configuration,
+    model_id: u64,
+    version_selection: &VersionSelection,
+    destination_path: Option<&PathBuf>,
+    behavior: &DownloadBehavior,
+) -> Result<OperationSummary> {
+    // 试运行只需要元数据，离线时可以使用缓存
+    if !behavior.dry_run {
+        crate::downloader::ensure_online("Downloading models")?;
+    }
+    let mut progress = StepProgress::new(1 + STEPS_PER_VERSION);
+    let mut summary = OperationSummary::default();
+
+    let mut download_plan = plan::plan_download(
+        client,
+        config,
+        model_id,
+        version_selection,
+        destination_path,
+        behavior,
+        &mut progress,
+    )
+    .await?;
+    summary
+        .warnings
+        .extend(download_plan.license_warning.clone());
+    // 无人值守时先确认访问密钥可以下载，避免中途失败
+    if behavior.unattended && !behavior.dry_run && !behavior.meta_only() {
+        access::preflight_plan(client, config, &mut download_plan).await;
+    }
+    if let Some(budget) = behavior
+        .size_budget
+        .as_ref()
+        .filter(|_| !behavior.meta_only())
+    {
+        progress
+            .multi()
+            .suspend(|| fit_size_budget(&mut download_plan, budget, behavior))?;
+    }
+
+    if behavior.dry_run {
+        progress.set_total_steps(1 + download_plan.versions.len());
+        drop(progress);
+        download_plan.print(behavior.artifacts);
+        return Ok(summary);
+    }
+
+    let mut session = DownloadSession::from_plan(&download_plan, behavior);
+    if !behavior.meta_only()
+        && let Err(e) = session.save()
+    {
+        progress.println(format!("Failed to record download session: {e}"));
+    }
+
+    let mut community_images = None;
+    for version_plan in download_plan.versions.iter() {
+        let selected_version_meta = &version_plan.version;
+        if version_plan.already_downloaded {
+            progress.println(format!(
+                "All files of version {} already exist, skip it.",
+                selected_version_meta.name()
+            ));
+            for file_plan in version_plan.files.iter() {
+                summary.skip(file_plan.file.name(), SkipReason::AlreadyPresent);
+            }
+            for _ in 1..STEPS_PER_VERSION {
+                progress.begin("Skipping version...");
+                progress.skip();
+            }
+            continue;
+        }
+        if version_plan.is_inaccessible() {
+            for file_plan in version_plan.files.iter() {
+                if let Some(access) = file_plan.inaccessible {
+                    progress.println(format!(
+                        "File {} can not be downloaded with the access key ({access}), skip it.",
+                        file_plan.file.name()
+                    ));
+                    if let Some(reason) = access.skip_reason() {
+                        summary.skip(file_plan.file.name(), reason);
+                    }
+                }
+            }
+            for _ in 1..STEPS_PER_VERSION {
+                progress.begin("Skipping version...");
+                progress.skip();
+            }
+            continue;
+        }
+        std::fs::create_dir_all(&version_plan.destination).with_context(|| {
+            format!(
+                "Failed to create directory {}",
+                version_plan.destination.display()
+            )
+        })?;
+        let version_destination = Some(&version_plan.destination);
+
+        if behavior.meta_only() {
+            progress.begin(format!(
+                "Saving file metadata of version {}...",
+                selected_version_meta.name()
+            ));
+            if behavior.artifacts.contains(Artifact::Json) {
+                progress.track(
+                    save_version_files_meta(&download_plan.model, version_plan, &progress).await,
+                )?;
+                summary.produce(Artifact::Json);
+            } else {
+                progress.skip();
+            }
+        } else {
+            progress.begin(format!(
+                "Downloading files of version {}...",
+                selected_version_meta.name()
+            ));
+            progress.track(
+                download_model_version_files(
+                    client,
+                    config,
+                    &download_plan.model,
+                    version_plan,
+                    &progress,
+                    behavior,
+                    &mut session,
+                    &mut summary,
+                )
+                .await,
+            )?;
+        }
+
+        if behavior.artifacts.contains(Artifact::Triggers) {
+            let primary_path = version_plan
+                .destination
+                .join(&version_plan.primary_file_name);
+            match meta::save_trained_words(&primary_path, selected_version_meta).await {
+                Ok(Some(_)) => summary.produce(Artifact::Triggers),
+                Ok(None) => {}
+                Err(e) => {
+                    progress.println(format!("Failed to save trained words: {e:#}"));
+                    summary.fail(
+                        version_plan.primary_file_name.clone(),
+                        ArtifactKind::TrainedWords,
+                        e,
+                    );
+                }
+            }
+        }
+
+        progress.begin("Downloading cover image...");
+        // 封面失败不中断下载，记录在总结中
+        let cover_image_filename = if behavior.artifacts.contains(Artifact::Cover) {
+            let picked_cover = if behavior.pick_cover && !behavior.unattended {
+                pick_version_cover(client, selected_version_meta).await
+            } else {
+                None
+            };
+            let cover_label = format!("{} cover", version_plan.primary_file_name);
+            let mut reporter = behavior.reporter.create(&cover_label, &progress);
+            match progress.track(
+                download_task::download_model_version_cover_image(
+                    client,
+                    config,
+                    selected_version_meta,
+                    download_task::ModelVersionFileNamePresent::FileName(
+                        version_plan.primary_file_name.clone(),
+                    ),
+                    version_destination,
+                    false,
+                    picked_cover.as_deref(),
+                    reporter.as_mut(),
+                )
+                .await,
+            ) {
+                Ok(file_name) => {
+                    summary.produce(Artifact::Cover);
+                    file_name
+                }
+                Err(e) => {
+                    summary.fail(
+                        version_plan.primary_file_name.clone(),
+                        ArtifactKind::Cover,
+                        e,
+                    );
+                    None
+                }
+            }
+        } else {
+            progress.skip();
+            None
+        };
+        if behavior
+            .model_dirs
+            .as_ref()
+            .is_some_and(|dirs| dirs.civitai_helper_files)
+        {
+            let cover_path = cover_image_filename
+                .as_ref()
+                .map(|name| version_plan.destination.join(name));
+            for file_plan in version_plan.files.iter() {
+                if !file_plan.target_path.exists() && !behavior.meta_only() {
                     continue;
                 }
+                if let Err(e) = crate::integrations::webui::save_civitai_helper_files(
+                    &file_plan.target_path,
+                    selected_version_meta,
+                    cover_path.as_deref(),
+                ) {
+                    progress.println(format!("Failed to save Civitai Helper files: {e:#}"));
+                    summary.fail(file_plan.file.name(), ArtifactKind::CivitaiHelperFiles, e);
+                }
             }
         }
 
-        // 下载指定的文件
-        println!("Downloading file(s)...");
-        let file_name = version_file_name(file_id)
-            .with_context(|| format!("Failed to confirm model version file {file_id} name"))?;
-        let model_file_name = download_task::download_single_model_file(
-            client,
-            &selected_version_meta,
-            file_id,
-            destination_path.as_deref(),
-        )
-        .await
-        .with_context(|| format!("Failed to download model file {file_name}"))?;
-        if file_id == primary_file_id {
-            target_meta_filename = model_file_name;
+        progress.begin("Fetching community posted images metadata...");
+        if community_images.is_none() {
+            // 社区图片只用于说明文件，不保存说明文件时无需获取
+            community_images = Some(
+                if behavior.artifacts.contains(Artifact::Samples)
+                    && behavior.artifacts.contains(Artifact::Readme)
+                {
+                    match progress.track(
+                        meta::fetch_model_community_images(client, config, model_id, false).await,
+                    ) {
+                        Ok(images) => images,
+                        Err(e) => {
+                            summary.fail(
+                                version_plan.primary_file_name.clone(),
+                                ArtifactKind::CommunityImages,
+                                e,
+                            );
+                            Vec::new()
+                        }
+                    }
+                } else {
+                    progress.skip();
+                    Vec::new()
+                },
+            );
+        } else {
+            progress.done();
+        }
+
+        progress.begin("Saving readme file...");
+        if !behavior.artifacts.contains(Artifact::Readme) {
+            progress.skip();
+            continue;
+        }
+        let version_history = match behavior.version_history {
+            Some(limit) => {
+                meta::collect_version_history(
+                    client,
+                    config,
+                    &download_plan.model,
+                    selected_version_meta.id(),
+                    limit,
+                )
+                .await
+            }
+            None => Vec::new(),
+        };
+        let readme_path = progress
+            .track(
+                meta::save_model_version_readme(
+                    config,
+                    &download_plan.model,
+                    selected_version_meta,
+                    community_images.as_deref().unwrap_or_default(),
+                    &version_history,
+                    cover_image_filename,
+                    version_destination,
+                    version_plan.primary_file_name.clone(),
+                    behavior.prompt_limits,
+                )
+                .await,
+            )
+            .context("Failed to save model version description file")?;
+        summary.readme_files.push(readme_path);
+        summary.produce(Artifact::Readme);
+        if !community_images.as_deref().unwrap_or_default().is_empty() {
+            summary.produce(Artifact::Samples);
         }
     }
 
-    let cover_image_filename = download_task::download_model_version_cover_image(
+    // 超出预算的文件留给之后的会话继续下载
+    // 只保存元数据时没有记录会话，不能清除之前中断的下载
+    if !behavior.meta_only() {
+        if download_plan.over_budget_files().is_empty() {
+            if let Err(e) = session.finish() {
+                progress.println(format!("Failed to clean up download session: {e}"));
+            }
+        } else {
+            progress.println(format!(
+                "Files exceeding the size budget are left, download them later by \"imd download --resume-session {model_id}\"."
+            ));
+        }
+    }
+    summary.print(progress.elapsed());
+    Ok(summary)
+}
+
+/// Resolves the versions and primary files like a download does, then checks whether the access
+/// key may download each of them without transferring anything. Returns the number of files it
+/// may not download.
+pub async fn check_model_access(
+    client: &reqwest::Client,
+    config: &Configuration,
+    model_id: u64,
+    version_selection: &VersionSelection,
+    destination_path: Option<&PathBuf>,
+    behavior: &DownloadBehavior,
+) -> Result<usize> {
+    crate::downloader::ensure_online("Checking access to models")?;
+    let mut progress = StepProgress::new(1 + STEPS_PER_VERSION);
+    let download_plan = plan::plan_download(
         client,
-        &selected_version_meta,
-        download_task::ModelVersionFileNamePresent::FileID(primary_file_id),
-        destination_path.as_deref(),
+        config,
+        model_id,
+        version_selection,
+        destination_path,
+        behavior,
+        &mut progress,
     )
-    .await
-    .with_context(|| {
-        format!("Failed to download cover image for model version {selected_version}")
-    })?;
-
-    let community_images = if !skip_community {
-        println!("Fetching community posted images metadata related to model...");
-        meta::fetch_model_community_images(client, model_id)
-            .await
-            .with_context(|| {
-                format!("Failed to fetch community posted images coorespond to model {model_id}")
-            })?
-    } else {
-        println!("Skip retreiving community images metadata related to model.");
-        Vec::new()
-    };
+    .await?;
+    drop(progress);
+    Ok(access::report_access(client, config, &download_plan).await)
+}
 
-    meta::save_model_version_readme(
-        &model_meta,
-        &selected_version_meta,
-        &community_images,
-        cover_image_filename,
-        destination_path.as_deref(),
-        target_meta_filename,
-    )
-    .await
-    .context("Failed to save model version description file")?;
+/// Leaves out the planned files exceeding the remaining size budget, asks what to do when
+/// someone can answer.
+fn fit_size_budget(
+    download_plan: &mut plan::DownloadPlan,
+    budget: &SizeBudget,
+    behavior: &DownloadBehavior,
+) -> Result<()> {
+    let planned = download_plan.total_transfer_bytes();
+    let remaining = budget.remaining();
+    if planned <= remaining {
+        return Ok(());
+    }
+    let over_budget_files = download_plan.fit_size_budget(remaining);
+    events::message(format!(
+        "Size budget of {} has {} left, {} exceeding it:",
+        bytes_to_human_string(budget.limit()),
+        bytes_to_human_string(remaining),
+        if over_budget_files.len() == 1 {
+            "1 file is".to_string()
+        } else {
+            format!("{} files are", over_budget_files.len())
+        }
+    ));
+    for file_plan in over_budget_files {
+        events::message(format!(
+            "  {} ({})",
+            file_plan.file.name(),
+            bytes_to_human_string(file_plan.file.size_in_bytes())
+        ));
+    }
+    let fitted = download_plan.total_transfer_bytes();
 
+    if !behavior.dry_run && !behavior.unattended && !events::enabled() {
+        match selections::decide_over_budget_action(planned, remaining) {
+            OverBudgetAction::WithinBudget => {}
+            OverBudgetAction::Everything => {
+                download_plan.ignore_size_budget();
+                return Ok(());
+            }
+            OverBudgetAction::Cancel => {
+                return Err(SizeBudgetExceededError(bytes_to_human_string(budget.limit())).into());
+            }
+        }
+    }
+    if fitted == 0 {
+        return Err(SizeBudgetExceededError(bytes_to_human_string(budget.limit())).into());
+    }
     Ok(())
 }
 
-pub async fn complete_file_meta<P>(
-    client: &Client,
-    source_file: P,
-    skip_community: bool,
-) -> Result<()>
-where
-    P: AsRef<Path>,
-{
-    let source_file_path = source_file.as_ref();
-    let source_file_path = if let Some(parent) = source_file_path.parent()
-        && parent.to_string_lossy().is_empty()
-    {
-        let parent_dir = env::current_dir().context("Unable to get current working directory")?;
-        parent_dir.join(source_file_path)
-    } else {
-        source_file_path.to_path_buf()
-    };
-    let working_dir = source_file_path.parent().map(Path::to_path_buf).unwrap();
-    if !working_dir.exists() || !working_dir.is_dir() {
-        bail!("Source file path is not a valid directory");
+/// URL of the image chosen as cover, a failed prompt falls back to the usual cover.
+async fn pick_version_cover(client: &Client, version: &ModelVersion) -> Option<String> {
+    let images = version.images().ok()?;
+    match cover_picker::pick_cover(client, &images).await {
+        Ok(picked) => picked.map(|index| images[index].url()),
+        Err(e) => {
+            events::message(format!("WARNING: Failed to pick the cover image: {e:#}"));
+            None
+        }
     }
+}
 
-    println!("Start to calculate file hash...");
-    let source_file_hash = meta::blake3_hash(&source_file_path).context("Calculate file hash")?;
-    println!("File hash: {}", source_file_hash.to_ascii_uppercase());
+fn is_not_found_error(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<CivitaiApiError>()
+        .is_some_and(|e| e.status == StatusCode::NOT_FOUND.as_u16())
+}
 
-    println!("Save file hash...");
-    meta::save_version_file_hash(&source_file_path, &source_file_hash)
+/// Saves the metadata sidecar of the planned files without downloading them. Nothing is recorded
+/// in the file location cache, renew records the files once they are in place.
+async fn save_version_files_meta(
+    model_meta: &Model,
+    version_plan: &plan::VersionPlan,
+    progress: &StepProgress,
+) -> Result<()> {
+    for file_plan in version_plan.files.iter() {
+        sidecar::save_sidecar(
+            &file_plan.target_path,
+            model_meta,
+            &version_plan.version,
+            Some(&file_plan.file),
+        )
         .await
-        .context("Save file hash")?;
-
-    println!("Request model version metadata...");
-    let model_version_meta =
-        match meta::fetch_model_version_meta_by_blake3(client, &source_file_hash).await {
-            Ok(meta) => meta,
-            Err(e) => {
-                return Err(e);
+        .context("Failed to save model metadata sidecar")?;
+        progress.println(format!(
+            "Saved metadata for {}, the model file is not downloaded.",
+            file_plan.target_path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Downloads the planned files of a model version.
+#[allow(clippy::too_many_arguments)]
+async fn download_model_version_files(
+    client: &reqwest::Client,
+    config: &Configuration,
+    model_meta: &Model,
+    version_plan: &plan::VersionPlan,
+    progress: &StepProgress,
+    behavior: &DownloadBehavior,
+    session: &mut DownloadSession,
+    summary: &mut OperationSummary,
+) -> Result<()> {
+    for file_plan in version_plan.files.iter() {
+        let version_file = &file_plan.file;
+        if session
+            .is_completed(file_plan, Some(progress.multi()))
+            .await
+        {
+            progress.println(format!(
+                "File {} was downloaded before interruption, skip it.",
+                version_file.name()
+            ));
+            summary.skip(version_file.name(), SkipReason::AlreadyPresent);
+            continue;
+        }
+        if file_plan.over_budget {
+            progress.println(format!(
+                "File {} exceeds the size budget, leave it for a later session.",
+                version_file.name()
+            ));
+            summary.skip(version_file.name(), SkipReason::OverBudget);
+            continue;
+        }
+        if let Some(access) = file_plan.inaccessible {
+            progress.println(format!(
+                "File {} can not be downloaded with the access key ({access}), skip it.",
+                version_file.name()
+            ));
+            if let Some(reason) = access.skip_reason() {
+                summary.skip(version_file.name(), reason);
             }
-        };
+            continue;
+        }
+        if version_file.is_processing() {
+            progress.println(format!(
+                "Skip {}, Civitai is still processing it.",
+                version_file.name()
+            ));
+            summary.skip(version_file.name(), SkipReason::Processing);
+            continue;
+        }
+        if file_plan.refused {
+            progress.println(format!(
+                "Refuse to download {}, Civitai marked it as dangerous [{}]. Use --allow-unsafe to download it anyway.",
+                version_file.name(),
+                version_file.scan_warning().unwrap_or_default()
+            ));
+            summary.skip(version_file.name(), SkipReason::Unsafe);
+            continue;
+        }
 
-    println!("Collecting related model metadata...");
-    let model_meta = meta::fetch_model_metadata(client, model_version_meta.model_id())
-        .await
-        .context("Request for model metadata")?;
-    let source_file_name = source_file_path
-        .file_name()
-        .unwrap()
-        .to_string_lossy()
-        .into_owned();
-    println!("Download cover image...");
-    let cover_image_file_name = download_task::download_model_version_cover_image(
-        client,
-        &model_version_meta,
-        download_task::ModelVersionFileNamePresent::FileName(source_file_name.clone()),
-        Some(&working_dir),
-    )
-    .await
-    .inspect_err(|e| println!("Model version cover download failed: {e}"))
-    .ok()
-    .flatten();
-
-    let related_community_images = if !skip_community {
-        println!("Collecting related community images metadata...");
-        meta::fetch_model_community_images(client, model_meta.id())
+        // 缓存中没有下载记录时，检查输出目录中已有的同名文件
+        if file_plan.existing_location.is_none() {
+            let check = config.download.existing_check;
+            let kept = download_task::check_file_at_target(
+                &version_plan.version,
+                version_file.id(),
+                &file_plan.target_path,
+                check,
+                progress,
+            )
             .await
-            .inspect_err(|e| println!("Community images metadata retreive failed: {e}"))
-            .ok()
-            .unwrap_or(Vec::new())
-    } else {
-        println!("Skip collect related community images metadata.");
-        Vec::new()
-    };
+            .unwrap_or_else(|e| {
+                progress.println(format!("Unable to check the existing file: {e:#}"));
+                false
+            });
+            if kept {
+                progress.println(format!(
+                    "File {} is already in the output directory, skip it.",
+                    version_file.name()
+                ));
+                if behavior.artifacts.contains(Artifact::Hash)
+                    && let Some(hash) = version_file.blake3_hash()
+                {
+                    save_version_file_hash(&file_plan.target_path, &hash)
+                        .await
+                        .context("Save file blake3 hash record")?;
+                    summary.produce(Artifact::Hash);
+                }
+                if behavior.artifacts.contains(Artifact::Json) {
+                    sidecar::save_sidecar(
+                        &file_plan.target_path,
+                        model_meta,
+                        &version_plan.version,
+                        Some(version_file),
+                    )
+                    .await
+                    .context("Failed to save model metadata sidecar")?;
+                    summary.produce(Artifact::Json);
+                }
+                record_session_state(
+                    session,
+                    progress,
+                    version_file.id(),
+                    SessionFileState::Completed,
+                    version_file.blake3_hash(),
+                );
+                summary.skip(version_file.name(), SkipReason::AlreadyPresent);
+                continue;
+            }
+        }
 
-    println!("Save model version readme file...");
-    meta::save_model_version_readme(
-        &model_meta,
-        &model_version_meta,
-        &related_community_images,
-        cover_image_file_name,
-        Some(&working_dir),
-        source_file_name,
-    )
-    .await
-    .context("Failed to save model version readme file")?;
+        // 检查缓存数据库中是否已经存在该模型的下载记录，对比数据库中记录的文件位置列表
+        // 未下载过的和未使用renew命令的文件将会直接重新下载。
+        let mut link_source = None;
+        if let Some(file_path) = file_plan.existing_location.as_ref() {
+            let mut answered = false;
+            let skip_decision_ttl = config.download.skip_decision_ttl();
+            let remembered_skip = version_file
+                .blake3_hash()
+                .and_then(|hash| decisions::remembered_skip(&hash, skip_decision_ttl));
+            let action = if behavior.link_existing {
+                ExistingFileAction::Link
+            } else if events::enabled() || behavior.unattended {
+                // 不能交互时不重新下载已存在的文件
+                progress.println(format!(
+                    "File {} already exists at {}, skip it.",
+                    version_file.name(),
+                    file_path.display()
+                ));
+                summary.skip(version_file.name(), SkipReason::AlreadyPresent);
+                continue;
+            } else if decisions::redownload_all() {
+                ExistingFileAction::Redownload
+            } else if let Some(decision) = remembered_skip {
+                // 之前选择跳过的文件不再询问
+                progress.println(format!(
+                    "File {} already exists at {}, skip it as decided on {}. Run \"imd cache forget-decisions\" to be asked again.",
+                    version_file.name(),
+                    file_path.display(),
+                    time::UtcDateTime::from_unix_timestamp(decision.decided_at)
+                        .map(|datetime| crate::utils::datetime_to_date_string(&datetime))
+                        .unwrap_or_default()
+                ));
+                summary.skip(version_file.name(), SkipReason::Declined);
+                continue;
+            } else {
+                let answer = progress
+                    .multi()
+                    .suspend(|| selections::decide_existing_file_action(file_path));
+                answered = answer.is_some();
+                answer.unwrap_or(ExistingFileAction::Skip)
+            };
+            match action {
+                ExistingFileAction::Skip | ExistingFileAction::SkipForever => {
+                    // 超时或没有终端时取的默认值不是用户的回答，不记住
+                    if answered
+                        && let Some(hash) = version_file.blake3_hash()
+                        && let Err(e) = decisions::remember_skip(
+                            &hash,
+                            action == ExistingFileAction::SkipForever,
+                        )
+                    {
+                        progress.println(format!("Failed to remember the decision: {e}"));
+                    }
+                    summary.skip(version_file.name(), SkipReason::Declined);
+                    continue;
+                }
+                ExistingFileAction::RedownloadAll => decisions::set_redownload_all(),
+                ExistingFileAction::Redownload => {}
+                ExistingFileAction::Link => link_source = Some(file_path),
+            }
+        }
+
+        // 下载指定的文件
+        let file_name = version_file.name();
+        record_session_state(
+            session,
+            progress,
+            version_file.id(),
+            SessionFileState::InFlight,
+            None,
+        );
+        let mut reporter = behavior.reporter.create(&file_name, progress);
+        let linked_file = match link_source {
+            Some(source_file_path) => download_task::link_existing_model_file(
+                &version_plan.version,
+                version_file.id(),
+                source_file_path,
+                &file_plan.target_path,
+                reporter.as_mut(),
+            )
+            .await
+            .inspect_err(|e| {
+                progress.println(format!(
+                    "Unable to use the existing file, download it: {e:#}"
+                ))
+            })
+            .ok(),
+            None => None,
+        };
+        let linked = linked_file.is_some();
+        let downloaded_file = match linked_file {
+            Some(linked_file) => linked_file,
+            None => download_task::download_single_model_file(
+                client,
+                config,
+                &version_plan.version,
+                version_file.id(),
+                &file_plan.target_path,
+                reporter.as_mut(),
+            )
+            .await
+            .with_context(|| format!("Failed to download model file {file_name}"))?,
+        };
+        if !linked && let Some(budget) = behavior.size_budget.as_ref() {
+            budget.consume(version_file.size_in_bytes());
+        }
+        record_session_state(
+            session,
+            progress,
+            version_file.id(),
+            SessionFileState::Completed,
+            downloaded_file.blake3.clone(),
+        );
+        events::emit(&Event::FileCompleted(downloaded_file.as_event()));
+        if downloaded_file.hash_matched == Some(false) {
+            summary.fail(
+                downloaded_file.name.clone(),
+                ArtifactKind::HashCheck,
+                "blake3 differs from the hash declared by Civitai",
+            );
+        }
+        summary.produce(Artifact::Model);
+        if behavior.artifacts.contains(Artifact::Hash)
+            && let Some(hash) = downloaded_file.blake3.as_deref()
+        {
+            save_version_file_hash(&downloaded_file.path, hash)
+                .await
+                .context("Save file blake3 hash record")?;
+            summary.produce(Artifact::Hash);
+        }
+        if behavior.artifacts.contains(Artifact::Json) {
+            sidecar::save_sidecar(
+                &downloaded_file.path,
+                model_meta,
+                &version_plan.version,
+                Some(version_file),
+            )
+            .await
+            .context("Failed to save model metadata sidecar")?;
+            summary.produce(Artifact::Json);
+        }
+        record_download(
+            config,
+            model_meta,
+            &version_plan.version,
+            &downloaded_file,
+            version_file.id(),
+            linked,
+        )
+        .await
+        .unwrap_or_else(|e| progress.println(format!("Failed to record download history: {e}")));
+        // 解压失败时保留压缩包，记录在总结中
+        if behavior.extract_archives
+            && crate::archive::is_zip_file(&downloaded_file.path)
+            && let Err(e) = download_task::extract_model_archive(
+                &downloaded_file.path,
+                behavior.remove_archive,
+                progress,
+            )
+            .await
+        {
+            progress.println(format!("Failed to extract {}: {e:#}", downloaded_file.name));
+            summary.fail(downloaded_file.name.clone(), ArtifactKind::Extraction, e);
+        }
+        summary.files.push(downloaded_file);
+    }
 
     Ok(())
 }
+
+/// Records the downloaded file with the account it was downloaded by.
+async fn record_download(
+    config: &Configuration,
+    model_meta: &Model,
+    version: &ModelVersion,
+    downloaded_file: &FileSummary,
+    file_id: u64,
+    linked: bool,
+) -> Result<()> {
+    let profile = config.active_profile.clone();
+    let key_fingerprint = config.civitai_key_fingerprint().filter(|_| !linked);
+    DownloadRecord {
+        model_id: model_meta.id(),
+        model_name: model_meta.name(),
+        version_id: version.id(),
+        version_name: version.name(),
+        file_id,
+        file_name: downloaded_file.name.clone(),
+        path: downloaded_file.path.clone(),
+        downloaded_at: time::UtcDateTime::now().unix_timestamp(),
+        page_url: Some(version.page_url()),
+        profile,
+        key_fingerprint,
+    }
+    .save()
+}
+
+/// Records the state of a file in download session, failing to record never fails the download.
+fn record_session_state(
+    session: &mut DownloadSession,
+    progress: &StepProgress,
+    file_id: u64,
+    state: SessionFileState,
+    blake3: Option<String>,
+) {
+    if let Err(e) = session.set_state(file_id, state, blake3) {
+        progress.println(format!("Failed to record download session: {e}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(url: &str) -> Result<(u64, Option<u64>)> {
+        try_parse_civitai_model_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn model_url_is_parsed_on_every_civitai_host() {
+        for url in [
+            "https://civitai.com/models/123?modelVersionId=456",
+            "https://civitai.green/models/123?modelVersionId=456",
+            "https://www.civitai.com/models/123/some-name?modelVersionId=456",
+            "https://CIVITAI.COM/Models/123?modelversionid=456",
+        ] {
+            assert_eq!(parse(url).unwrap(), (123, Some(456)), "{url}");
+        }
+        assert_eq!(
+            parse("https://civitai.com/models/123").unwrap(),
+            (123, None)
+        );
+    }
+
+    #[test]
+    fn model_url_without_valid_ids_is_rejected() {
+        for url in [
+            "https://civitai.com/models/",
+            "https://civitai.com/images/123",
+            "https://civitai.com/models/abc",
+            "https://civitai.com/models/123?modelVersionId=abc",
+        ] {
+            let error = parse(url).unwrap_err();
+            assert!(error.is::<InvalidInputError>(), "{url}: {error}");
+        }
+    }
+}
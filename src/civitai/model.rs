@@ -1,7 +1,14 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use time::{UtcDateTime, format_description::well_known::Rfc3339};
 
-use crate::errors::CivitaiParseError;
+use crate::{errors::CivitaiParseError, utils::datetime_to_date_string};
+
+use super::{
+    permissions::ModelPermissions,
+    schema::{FieldKind, check_fields},
+};
 
 pub struct Model(Value);
 pub struct ModelVersionBrief(Value);
@@ -10,8 +17,55 @@ pub struct ModelVersionFile(Value);
 pub struct ModelImage(Value);
 pub struct ModelCommunityImage(Value);
 
+/// Model page on Civitai website, the API mirror is not used here.
+const CIVITAI_MODEL_PAGE_BASE: &str = "https://civitai.com/models";
+
+/// Page of the model version on Civitai website.
+pub fn model_page_url(model_id: u64, version_id: u64) -> String {
+    format!("{CIVITAI_MODEL_PAGE_BASE}/{model_id}?modelVersionId={version_id}")
+}
+
+/// Page of the image on Civitai website, showing its full generation data.
+pub fn image_page_url(image_id: u64) -> String {
+    format!("https://civitai.com/images/{image_id}")
+}
+
+/// Counters Civitai shows on model and version pages. Every field is optional and known older
+/// names are accepted, so a renamed or dropped counter only goes missing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelStats {
+    #[serde(default, alias = "downloads")]
+    pub download_count: Option<u64>,
+    #[serde(default, alias = "thumbsUp", alias = "likeCount")]
+    pub thumbs_up_count: Option<u64>,
+    #[serde(default, alias = "thumbsDown", alias = "dislikeCount")]
+    pub thumbs_down_count: Option<u64>,
+    #[serde(default, alias = "collectedCount")]
+    pub favorite_count: Option<u64>,
+    #[serde(default)]
+    pub comment_count: Option<u64>,
+    #[serde(default, alias = "ratingAverage")]
+    pub rating: Option<f64>,
+    #[serde(default)]
+    pub rating_count: Option<u64>,
+    #[serde(default, alias = "tippedAmount")]
+    pub tipped_amount_count: Option<u64>,
+}
+
+impl ModelStats {
+    fn parse(value: &Value) -> Self {
+        serde_json::from_value(value["stats"].clone()).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 pub trait ImageMeta {
     fn url(&self) -> String;
+    fn page_url(&self) -> Option<String>;
     fn sampler(&self) -> Option<String>;
     fn scheduler(&self) -> Option<String>;
     fn seed(&self) -> Option<u64>;
@@ -24,38 +78,68 @@ pub trait ImageMeta {
     fn negative_prompt(&self) -> Option<String>;
 }
 
-macro_rules! ensure_required_field {
-    ($value:expr, $struct_name:expr, $field_name:expr) => {
-        if $value[$field_name].is_null() {
-            return Err(crate::errors::CivitaiParseError::MissingRequiredField(
-                $struct_name.to_string(),
-                $field_name.to_string(),
-            ));
-        }
-    };
+/// Parses a timestamp field, missing or malformed timestamps are taken as unknown.
+fn parse_datetime(value: &Value, field: &str) -> Option<UtcDateTime> {
+    value[field]
+        .as_str()
+        .and_then(|s| UtcDateTime::parse(s, &Rfc3339).ok())
 }
 
+/// Checks the fields read by the accessors are present in the types they are read as, and
+/// implements parsing from a JSON value on top of the check.
 macro_rules! impl_try_from_value_for_meta {
-    ($struct_name:ident, $($field_name:expr),+) => {
+    ($struct_name:ident, $($field_name:literal: $kind:ident),+) => {
+        impl $struct_name {
+            pub(super) fn check(value: &Value) -> Result<(), CivitaiParseError> {
+                check_fields(
+                    value,
+                    stringify!($struct_name),
+                    &[$(($field_name, FieldKind::$kind)),+],
+                )
+            }
+        }
+
         impl TryFrom<&Value> for $struct_name {
             type Error = CivitaiParseError;
 
             fn try_from(value: &Value) -> Result<Self, Self::Error> {
-                $(
-                    ensure_required_field!(value, stringify!($struct_name), $field_name);
-                )+
+                Self::check(value)?;
                 Ok(Self(value.clone()))
             }
         }
     };
 }
 
-impl_try_from_value_for_meta!(Model, "id", "name", "description", "modelVersions");
-impl_try_from_value_for_meta!(ModelVersionBrief, "id", "name", "index");
-impl_try_from_value_for_meta!(ModelVersion, "id", "modelId", "name", "files", "images");
-impl_try_from_value_for_meta!(ModelVersionFile, "id", "sizeKB", "name", "downloadUrl");
-impl_try_from_value_for_meta!(ModelImage, "url", "hasMeta", "hasPositivePrompt");
-impl_try_from_value_for_meta!(ModelCommunityImage, "id", "url");
+impl_try_from_value_for_meta!(
+    Model,
+    "id": Integer,
+    "name": String,
+    "description": String,
+    "modelVersions": Array
+);
+impl_try_from_value_for_meta!(ModelVersionBrief, "id": Integer, "name": String, "index": Integer);
+impl_try_from_value_for_meta!(
+    ModelVersion,
+    "id": Integer,
+    "modelId": Integer,
+    "name": String,
+    "files": Array,
+    "images": Array
+);
+impl_try_from_value_for_meta!(
+    ModelVersionFile,
+    "id": Integer,
+    "sizeKB": Number,
+    "name": String
+);
+impl_try_from_value_for_meta!(
+    ModelImage,
+    "url": String,
+    "type": String,
+    "hasMeta": Bool,
+    "hasPositivePrompt": Bool
+);
+impl_try_from_value_for_meta!(ModelCommunityImage, "id": Integer, "url": String);
 
 impl Model {
     pub fn id(&self) -> u64 {
@@ -77,6 +161,33 @@ impl Model {
             .unwrap()
     }
 
+    /// Model type on Civitai, e.g. `Checkpoint`, `LORA` or `TextualInversion`.
+    pub fn model_type(&self) -> Option<String> {
+        self.0["type"].as_str().map(String::from)
+    }
+
+    pub fn stats(&self) -> ModelStats {
+        ModelStats::parse(&self.0)
+    }
+
+    pub fn permissions(&self) -> ModelPermissions {
+        ModelPermissions::parse(&self.0)
+    }
+
+    /// Latest publish or update time among the versions of the model, `None` when no version
+    /// carries a timestamp.
+    pub fn last_updated(&self) -> Option<UtcDateTime> {
+        self.0["modelVersions"]
+            .as_array()?
+            .iter()
+            .flat_map(|version| {
+                ["publishedAt", "updatedAt", "createdAt"]
+                    .into_iter()
+                    .filter_map(|field| parse_datetime(version, field))
+            })
+            .max()
+    }
+
     pub fn versions(&self) -> Result<Vec<ModelVersionBrief>, CivitaiParseError> {
         let versions = &self.0["modelVersions"];
         if !versions.is_array() {
@@ -91,6 +202,7 @@ impl Model {
             let parsed_version = ModelVersionBrief::try_from(version)?;
             collected_versions.push(parsed_version);
         }
+        collected_versions.sort_by_key(ModelVersionBrief::index);
 
         Ok(collected_versions)
     }
@@ -117,8 +229,74 @@ impl ModelVersionBrief {
         self.0["description"].as_str().map(String::from)
     }
 
+    pub fn markdown_description(&self) -> Option<String> {
+        self.0["description"].as_str().map(html2md::parse_html)
+    }
+
+    pub fn base_model(&self) -> Option<String> {
+        self.0["baseModel"].as_str().map(String::from)
+    }
+
+    pub fn published_at(&self) -> Option<UtcDateTime> {
+        parse_datetime(&self.0, "publishedAt")
+    }
+
+    pub fn updated_at(&self) -> Option<UtcDateTime> {
+        parse_datetime(&self.0, "updatedAt")
+    }
+
+    pub fn created_at(&self) -> Option<UtcDateTime> {
+        parse_datetime(&self.0, "createdAt")
+    }
+
+    /// When the version became available, unpublished versions fall back to their creation time.
+    pub fn released_at(&self) -> Option<UtcDateTime> {
+        self.published_at().or_else(|| self.created_at())
+    }
+
+    pub fn download_count(&self) -> Option<u64> {
+        self.stats().download_count
+    }
+
+    pub fn stats(&self) -> ModelStats {
+        ModelStats::parse(&self.0)
+    }
+
+    /// Whether the version lists any file, versions listed without the files are taken as having
+    /// them.
+    pub fn has_files(&self) -> bool {
+        self.0["files"]
+            .as_array()
+            .is_none_or(|files| !files.is_empty())
+    }
+
+    pub fn total_size_kb(&self) -> f64 {
+        self.0["files"]
+            .as_array()
+            .map(|files| files.iter().filter_map(|f| f["sizeKB"].as_f64()).sum())
+            .unwrap_or_default()
+    }
+
+    pub fn early_access_ends_at(&self) -> Option<UtcDateTime> {
+        parse_datetime(&self.0, "earlyAccessEndsAt")
+    }
+
+    pub fn is_early_access(&self) -> bool {
+        self.early_access_ends_at()
+            .map(|ends_at| UtcDateTime::now() <= ends_at)
+            .unwrap_or_default()
+    }
+
     pub fn choice(&self) -> (u64, String) {
-        (self.id(), self.name())
+        let label = match self.early_access_ends_at() {
+            Some(ends_at) if self.is_early_access() => format!(
+                "{} [early access until {}]",
+                self.name(),
+                datetime_to_date_string(&ends_at)
+            ),
+            _ => self.name(),
+        };
+        (self.id(), label)
     }
 }
 
@@ -131,6 +309,10 @@ impl ModelVersion {
         self.0["modelId"].as_u64().unwrap()
     }
 
+    pub fn page_url(&self) -> String {
+        model_page_url(self.model_id(), self.id())
+    }
+
     pub fn name(&self) -> String {
         self.0["name"].as_str().map(String::from).unwrap()
     }
@@ -147,23 +329,43 @@ impl ModelVersion {
         self.0["description"].as_str().map(html2md::parse_html)
     }
 
+    pub fn base_model(&self) -> Option<String> {
+        self.0["baseModel"].as_str().map(String::from)
+    }
+
+    pub fn stats(&self) -> ModelStats {
+        ModelStats::parse(&self.0)
+    }
+
+    pub fn as_value(&self) -> &Value {
+        &self.0
+    }
+
     pub fn air(&self) -> Option<String> {
         self.0["air"].as_str().map(String::from)
     }
 
+    /// Publishing state like `Published` or `Unpublished`, not every response carries it.
+    pub fn status(&self) -> Option<String> {
+        self.0["status"].as_str().map(String::from)
+    }
+
+    pub fn published_at(&self) -> Option<UtcDateTime> {
+        parse_datetime(&self.0, "publishedAt")
+    }
+
+    pub fn updated_at(&self) -> Option<UtcDateTime> {
+        parse_datetime(&self.0, "updatedAt")
+    }
+
+    pub fn early_access_ends_at(&self) -> Option<UtcDateTime> {
+        parse_datetime(&self.0, "earlyAccessEndsAt")
+    }
+
     pub fn is_early_access(&self) -> bool {
-        let early_access_ends_str = &self.0["earlyAccessEndsAt"];
-        if early_access_ends_str.is_null() {
-            return false;
-        }
-        let early_access_ends_at = early_access_ends_str
-            .as_str()
-            .and_then(|s| UtcDateTime::parse(s, &Rfc3339).ok());
-        if let Some(ends_at) = early_access_ends_at {
-            return UtcDateTime::now() <= ends_at;
-        } else {
-            return false;
-        }
+        self.early_access_ends_at()
+            .map(|ends_at| UtcDateTime::now() <= ends_at)
+            .unwrap_or_default()
     }
 
     pub fn trained_words(&self) -> Vec<String> {
@@ -234,8 +436,57 @@ impl ModelVersionFile {
         self.0["name"].as_str().map(String::from).unwrap()
     }
 
-    pub fn download_url(&self) -> String {
-        self.0["downloadUrl"].as_str().map(String::from).unwrap()
+    /// `None` while Civitai is still processing a freshly uploaded file.
+    pub fn download_url(&self) -> Option<String> {
+        self.0["downloadUrl"].as_str().map(String::from)
+    }
+
+    /// Whether Civitai is still processing the file, it can not be downloaded until it is done.
+    pub fn is_processing(&self) -> bool {
+        self.download_url().is_none()
+    }
+
+    pub fn file_type(&self) -> Option<String> {
+        self.0["type"].as_str().map(String::from)
+    }
+
+    pub fn variant_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        if let Some(file_type) = self.file_type() {
+            params.push(("type", file_type));
+        }
+        for (param, field) in [("format", "format"), ("size", "size"), ("fp", "fp")] {
+            if let Some(value) = self.0["metadata"][field].as_str() {
+                params.push((param, value.to_string()));
+            }
+        }
+        params
+    }
+
+    /// Download URL carrying the variant selectors of this file, so that the
+    /// download endpoint serves exactly this file rather than the primary one.
+    pub fn variant_download_url(&self) -> Option<String> {
+        let base_url = self.download_url()?;
+        let Ok(mut url) = Url::parse(&base_url) else {
+            return Some(base_url);
+        };
+        let existing_keys = url
+            .query_pairs()
+            .map(|(key, _)| key.into_owned())
+            .collect::<Vec<_>>();
+        {
+            let mut pairs = url.query_pairs_mut();
+            for (param, value) in self.variant_params() {
+                if !existing_keys.iter().any(|k| k.eq_ignore_ascii_case(param)) {
+                    pairs.append_pair(param, &value);
+                }
+            }
+        }
+        Some(url.to_string())
+    }
+
+    pub fn size_in_bytes(&self) -> u64 {
+        (self.size() * 1024.0).round() as u64
     }
 
     pub fn is_primary(&self) -> Option<bool> {
@@ -260,8 +511,59 @@ impl ModelVersionFile {
             .map(|s| s.to_lowercase())
     }
 
+    pub fn pickle_scan_result(&self) -> Option<String> {
+        self.0["pickleScanResult"].as_str().map(String::from)
+    }
+
+    pub fn pickle_scan_message(&self) -> Option<String> {
+        self.0["pickleScanMessage"].as_str().map(String::from)
+    }
+
+    pub fn virus_scan_result(&self) -> Option<String> {
+        self.0["virusScanResult"].as_str().map(String::from)
+    }
+
+    /// Whether Civitai's pickle scan or virus scan marked the file as dangerous.
+    pub fn is_unsafe(&self) -> bool {
+        [self.pickle_scan_result(), self.virus_scan_result()]
+            .iter()
+            .flatten()
+            .any(|result| result.eq_ignore_ascii_case("Danger"))
+    }
+
+    /// Short description of scan results that are not a success, e.g. "⚠ pickle scan: Danger".
+    pub fn scan_warning(&self) -> Option<String> {
+        let warnings = [
+            ("pickle scan", self.pickle_scan_result()),
+            ("virus scan", self.virus_scan_result()),
+        ]
+        .into_iter()
+        .filter_map(|(scan, result)| {
+            result
+                .filter(|r| !r.eq_ignore_ascii_case("Success"))
+                .map(|r| format!("{scan}: {r}"))
+        })
+        .collect::<Vec<_>>();
+        (!warnings.is_empty()).then(|| format!("⚠ {}", warnings.join(", ")))
+    }
+
+    pub fn is_pickle_format(&self) -> bool {
+        let name = self.name().to_lowercase();
+        [".ckpt", ".pt", ".pth", ".bin"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+    }
+
     pub fn choice(&self) -> (u64, String) {
-        (self.id(), self.name())
+        match self.scan_warning() {
+            Some(warning) => (self.id(), format!("{} [{warning}]", self.name())),
+            None => (self.id(), self.name()),
+        }
+    }
+
+    /// Label of a file left out of the selection while Civitai processes it.
+    pub fn processing_label(&self) -> String {
+        format!("{} (processing, not yet downloadable)", self.name())
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -273,6 +575,17 @@ impl ModelVersionFile {
             .map(|hash| hash.eq_ignore_ascii_case(blake3_str))
             .unwrap_or_default()
     }
+
+    /// Whether the file has the BLAKE3 or SHA256 hash, or a SHA256 prefix like AutoV2.
+    pub fn match_by_hash(&self, hash: &str) -> bool {
+        [self.blake3_hash(), self.sha256_hash()]
+            .into_iter()
+            .flatten()
+            .any(|file_hash| file_hash.eq_ignore_ascii_case(hash))
+            || self.sha256_hash().is_some_and(|sha256| {
+                sha256.len() > hash.len() && sha256[..hash.len()].eq_ignore_ascii_case(hash)
+            })
+    }
 }
 
 impl ModelImage {
@@ -284,6 +597,18 @@ impl ModelImage {
         self.0["hash"].as_str().map(String::from)
     }
 
+    pub fn id(&self) -> Option<u64> {
+        self.0["id"].as_u64()
+    }
+
+    pub fn width(&self) -> Option<u64> {
+        self.0["width"].as_u64()
+    }
+
+    pub fn height(&self) -> Option<u64> {
+        self.0["height"].as_u64()
+    }
+
     pub fn has_meta(&self) -> bool {
         self.0["hasMeta"].as_bool().unwrap_or_default()
     }
@@ -298,6 +623,10 @@ impl ImageMeta for ModelImage {
         self.0["url"].as_str().map(String::from).unwrap()
     }
 
+    fn page_url(&self) -> Option<String> {
+        self.id().map(image_page_url)
+    }
+
     fn sampler(&self) -> Option<String> {
         self.0["meta"]["sampler"].as_str().map(String::from)
     }
@@ -363,6 +692,32 @@ impl ModelCommunityImage {
         self.0["id"].as_u64().unwrap()
     }
 
+    /// Model versions used to generate the image, collected from both the image
+    /// record and the Civitai resources embedded in the generation meta.
+    pub fn referenced_version_ids(&self) -> Vec<u64> {
+        let mut version_ids = Vec::new();
+        let resource_ids = self.0["meta"]["civitaiResources"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|resource| resource["modelVersionId"].as_u64());
+        let record_ids = self.0["modelVersionIds"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_u64);
+        for id in resource_ids.chain(record_ids) {
+            if !version_ids.contains(&id) {
+                version_ids.push(id);
+            }
+        }
+        version_ids
+    }
+
+    pub fn as_value(&self) -> &Value {
+        &self.0
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         serde_json::to_vec(&self.0).unwrap()
     }
@@ -373,6 +728,10 @@ impl ImageMeta for ModelCommunityImage {
         self.0["url"].as_str().map(String::from).unwrap()
     }
 
+    fn page_url(&self) -> Option<String> {
+        Some(image_page_url(self.id()))
+    }
+
     fn seed(&self) -> Option<u64> {
         self.0["meta"]["seed"].as_u64()
     }
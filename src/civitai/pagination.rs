@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use reqwest::{Client, Url};
+use serde_json::Value;
+
+use crate::{configuration::Configuration, errors::PaginationLoopError};
+
+use super::meta;
+
+/// Where the next page of a list response is, from its `metadata`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum NextPage {
+    /// Full URL of the next page, carrying every query parameter.
+    Url(String),
+    /// Cursor given as `cursor` parameter to the same request.
+    Cursor(String),
+}
+
+/// A page of a Civitai list response, `{ "items": [...], "metadata": { ... } }`.
+#[derive(Debug)]
+struct ListPage {
+    items: Vec<Value>,
+    next: Option<NextPage>,
+}
+
+impl ListPage {
+    fn parse(mut value: Value, resource: &str) -> Result<Self> {
+        let items = match value.get_mut("items").map(Value::take) {
+            Some(Value::Array(items)) => items,
+            _ => anyhow::bail!("Response of {resource} is missing required field - [items]"),
+        };
+        let metadata = value.get("metadata");
+        let next_page = metadata
+            .and_then(|metadata| metadata.get("nextPage"))
+            .and_then(Value::as_str)
+            .filter(|url| !url.is_empty())
+            .map(|url| NextPage::Url(url.to_string()));
+        // 游标可能是数字，也可能是字符串
+        let next_cursor = metadata
+            .and_then(|metadata| metadata.get("nextCursor"))
+            .and_then(|cursor| match cursor {
+                Value::String(cursor) if !cursor.is_empty() => Some(cursor.clone()),
+                Value::Number(cursor) => Some(cursor.to_string()),
+                _ => None,
+            })
+            .map(NextPage::Cursor);
+        Ok(Self {
+            items,
+            next: next_page.or(next_cursor),
+        })
+    }
+}
+
+fn without_cursor(url: &str) -> String {
+    let Ok(mut url) = Url::parse(url) else {
+        return url.to_string();
+    };
+    let pairs = url
+        .query_pairs()
+        .filter(|(name, _)| name != "cursor")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+/// Follows the pages of a Civitai list response, stopping at the last page or once the item
+/// cap is reached. Every page is requested with the retries and rate limit handling of metadata
+/// requests.
+pub struct Paginator<'a> {
+    client: &'a Client,
+    config: &'a Configuration,
+    resource: String,
+    url: String,
+    query: Vec<(String, String)>,
+    max_items: Option<usize>,
+    fetched_items: usize,
+    /// Next pages followed so far, a repeated one means the pages loop.
+    followed: HashSet<NextPage>,
+    finished: bool,
+}
+
+impl<'a> Paginator<'a> {
+    pub fn new<S: Into<String>>(
+        client: &'a Client,
+        config: &'a Configuration,
+        url: String,
+        query: Vec<(&str, String)>,
+        resource: S,
+    ) -> Self {
+        Self {
+            client,
+            config,
+            resource: resource.into(),
+            url,
+            query: query
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            max_items: None,
+            fetched_items: 0,
+            followed: HashSet::new(),
+            finished: false,
+        }
+    }
+
+    /// Stops after the given number of items, the last page is cut to fit.
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = Some(max_items);
+        self
+    }
+
+    /// Items of the next page, `None` when there are no more pages.
+    pub async fn next_page(&mut self) -> Result<Option<Vec<Value>>> {
+        if self.finished || self.max_items.is_some_and(|max| self.fetched_items >= max) {
+            return Ok(None);
+        }
+        let value = meta::fetch_civitai_json(
+            self.client,
+            self.config,
+            &self.url,
+            &self.query,
+            &self.resource,
+        )
+        .await?;
+        let ListPage { mut items, next } = ListPage::parse(value, &self.resource)?;
+        if let Some(max) = self.max_items {
+            items.truncate(max - self.fetched_items);
+        }
+        self.fetched_items += items.len();
+
+        match next {
+            Some(next) if !items.is_empty() => {
+                if !self.followed.insert(next.clone()) {
+                    return Err(PaginationLoopError {
+                        resource: self.resource.clone(),
+                        page: match next {
+                            NextPage::Url(url) => url,
+                            NextPage::Cursor(cursor) => format!("cursor {cursor}"),
+                        },
+                    }
+                    .into());
+                }
+                match next {
+                    NextPage::Url(url) => {
+                        // 下一页的地址已经包含了全部查询参数
+                        self.url = url;
+                        self.query.clear();
+                    }
+                    NextPage::Cursor(cursor) => {
+                        // 上一页的地址中可能已有游标，需要替换而不是追加
+                        self.url = without_cursor(&self.url);
+                        self.query.retain(|(name, _)| name != "cursor");
+                        self.query.push(("cursor".to_string(), cursor));
+                    }
+                }
+            }
+            _ => self.finished = true,
+        }
+        Ok(Some(items))
+    }
+
+    /// Items of every page up to the item cap, parsed by the given function.
+    pub async fn collect<T, F>(mut self, parse: F) -> Result<Vec<T>>
+    where
+        F: Fn(&Value) -> Result<T>,
+    {
+        let mut collected = Vec::new();
+        while let Some(items) = self.next_page().await? {
+            for item in items.iter() {
+                collected
+                    .push(parse(item).with_context(|| format!("Parse item of {}", self.resource))?);
+            }
+        }
+        Ok(collected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path, query_param, query_param_is_missing},
+    };
+
+    use super::*;
+    use crate::configuration::CivitaiConfig;
+
+    fn page(ids: &[u64], metadata: Value) -> ResponseTemplate {
+        let items = ids.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>();
+        ResponseTemplate::new(200).set_body_json(json!({ "items": items, "metadata": metadata }))
+    }
+
+    fn config(server: &MockServer) -> Configuration {
+        let mut config = Configuration::default();
+        config.civitai = CivitaiConfig {
+            api_base_url: Some(format!("{}/api/v1", server.uri())),
+            ..Default::default()
+        };
+        config
+    }
+
+    fn paginator<'a>(
+        client: &'a Client,
+        config: &'a Configuration,
+        server: &MockServer,
+    ) -> Paginator<'a> {
+        Paginator::new(
+            client,
+            config,
+            format!("{}/api/v1/models", server.uri()),
+            vec![("limit", "2".to_string())],
+            "models",
+        )
+    }
+
+    /// Serves three pages, the first points at the second by cursor and the second at the third
+    /// by URL.
+    async fn three_pages() -> MockServer {
+        let server = MockServer::start().await;
+        let third_page = format!("{}/api/v1/models?limit=2&page=3", server.uri());
+        Mock::given(method("GET"))
+            .and(path("/api/v1/models"))
+            .and(query_param("limit", "2"))
+            .and(query_param_is_missing("cursor"))
+            .and(query_param_is_missing("page"))
+            .respond_with(page(&[1, 2], json!({ "nextCursor": 2 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/models"))
+            .and(query_param("cursor", "2"))
+            .respond_with(page(&[3, 4], json!({ "nextPage": third_page })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/models"))
+            .and(query_param("page", "3"))
+            .respond_with(page(&[5], json!({})))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn ids(items: &[Value]) -> Vec<u64> {
+        items
+            .iter()
+            .filter_map(|item| item["id"].as_u64())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_are_followed_by_cursor_and_url() {
+        let server = three_pages().await;
+        let (client, config) = (Client::new(), config(&server));
+        let items = paginator(&client, &config, &server)
+            .collect(|item| Ok(item.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&items), [1, 2, 3, 4, 5]);
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].url.query(), Some("limit=2&cursor=2"));
+    }
+
+    #[tokio::test]
+    async fn item_cap_cuts_the_last_page() {
+        let server = three_pages().await;
+        let (client, config) = (Client::new(), config(&server));
+        let mut pages = paginator(&client, &config, &server).max_items(3);
+
+        assert_eq!(ids(&pages.next_page().await.unwrap().unwrap()), [1, 2]);
+        assert_eq!(ids(&pages.next_page().await.unwrap().unwrap()), [3]);
+        assert!(pages.next_page().await.unwrap().is_none());
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn looping_pages_are_refused() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/models"))
+            .respond_with(page(&[1], json!({ "nextCursor": "again" })))
+            .mount(&server)
+            .await;
+        let (client, config) = (Client::new(), config(&server));
+        let Err(error) = paginator(&client, &config, &server)
+            .collect(|item| Ok(item.clone()))
+            .await
+        else {
+            panic!("looping pages should fail");
+        };
+
+        assert!(
+            error.downcast_ref::<PaginationLoopError>().is_some(),
+            "{error:#}"
+        );
+        assert!(error.to_string().contains("cursor again"));
+        // 第二次遇到同一个游标时停止
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn empty_pages_and_cursors_end_the_list() {
+        let parsed = ListPage::parse(
+            json!({ "items": [], "metadata": { "nextCursor": "more" } }),
+            "models",
+        )
+        .unwrap();
+        assert_eq!(parsed.next, Some(NextPage::Cursor("more".to_string())));
+        let parsed = ListPage::parse(
+            json!({ "items": [{}], "metadata": { "nextCursor": "", "nextPage": "" } }),
+            "models",
+        )
+        .unwrap();
+        assert_eq!(parsed.next, None);
+        assert!(ListPage::parse(json!({ "metadata": {} }), "models").is_err());
+        assert_eq!(
+            without_cursor("https://civitai.com/api/v1/models?cursor=1&limit=2"),
+            "https://civitai.com/api/v1/models?limit=2"
+        );
+        assert_eq!(
+            without_cursor("https://civitai.com/api/v1/models?cursor=1"),
+            "https://civitai.com/api/v1/models"
+        );
+    }
+}
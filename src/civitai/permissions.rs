@@ -0,0 +1,160 @@
+//! License and permissions the creator sets for using a model, and the gate refusing models
+//! that may not be used commercially.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{errors::CommercialUseNotAllowedError, events};
+
+use super::model::Model;
+
+/// Shown for permissions Civitai does not give.
+const UNSPECIFIED: &str = "unspecified";
+
+/// Permissions of a model as declared on Civitai, `None` when a field is missing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPermissions {
+    pub license: Option<String>,
+    /// Whether the model may be used without crediting the creator.
+    pub allow_no_credit: Option<bool>,
+    /// Commercial uses allowed, like `Image` or `Sell`, empty when none is.
+    pub allow_commercial_use: Option<Vec<String>>,
+    /// Whether merges of the model may be shared.
+    pub allow_derivatives: Option<bool>,
+    /// Whether shared merges may use other permissions.
+    pub allow_different_license: Option<bool>,
+}
+
+impl ModelPermissions {
+    pub(super) fn parse(value: &Value) -> Self {
+        // 旧接口的商用许可是单个字符串，新接口是列表
+        let allow_commercial_use = match &value["allowCommercialUse"] {
+            Value::Array(uses) => Some(
+                uses.iter()
+                    .filter_map(Value::as_str)
+                    .filter(|usage| *usage != "None")
+                    .map(String::from)
+                    .collect(),
+            ),
+            Value::String(usage) if usage == "None" => Some(Vec::new()),
+            Value::String(usage) => Some(vec![usage.clone()]),
+            _ => None,
+        };
+        let license = match &value["license"] {
+            Value::String(license) => Some(license.clone()),
+            Value::Object(license) => license
+                .get("name")
+                .and_then(Value::as_str)
+                .map(String::from),
+            _ => None,
+        }
+        .filter(|license| !license.trim().is_empty());
+        Self {
+            license,
+            allow_no_credit: value["allowNoCredit"].as_bool(),
+            allow_commercial_use,
+            allow_derivatives: value["allowDerivatives"].as_bool(),
+            allow_different_license: value["allowDifferentLicense"].as_bool(),
+        }
+    }
+
+    /// Whether any commercial use is allowed, `None` when Civitai does not tell.
+    pub fn allows_commercial_use(&self) -> Option<bool> {
+        self.allow_commercial_use
+            .as_ref()
+            .map(|uses| !uses.is_empty())
+    }
+
+    /// Labeled lines describing every permission, the ones missing read as unspecified.
+    pub fn lines(&self) -> Vec<(&'static str, String)> {
+        let flag = |allowed: Option<bool>, yes: &str, no: &str| {
+            match allowed {
+                Some(true) => yes,
+                Some(false) => no,
+                None => UNSPECIFIED,
+            }
+            .to_string()
+        };
+        let commercial_use = match self.allow_commercial_use.as_deref() {
+            None => UNSPECIFIED.to_string(),
+            Some([]) => "not allowed".to_string(),
+            Some(uses) => uses
+                .iter()
+                .map(|usage| describe_commercial_use(usage))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        vec![
+            (
+                "License",
+                self.license
+                    .clone()
+                    .unwrap_or_else(|| UNSPECIFIED.to_string()),
+            ),
+            (
+                "Credit",
+                flag(
+                    self.allow_no_credit,
+                    "not required",
+                    "required, credit the creator when using it",
+                ),
+            ),
+            ("Commercial use", commercial_use),
+            (
+                "Sharing merges",
+                flag(self.allow_derivatives, "allowed", "not allowed"),
+            ),
+            (
+                "Merges under other permissions",
+                flag(self.allow_different_license, "allowed", "not allowed"),
+            ),
+        ]
+    }
+}
+
+fn describe_commercial_use(usage: &str) -> String {
+    match usage {
+        "Image" => "selling generated images".to_string(),
+        "RentCivit" => "use on Civitai generation service".to_string(),
+        "Rent" => "use on other generation services".to_string(),
+        "Sell" => "selling the model or merges".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// What to do with a model that does not allow commercial use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CommercialUsePolicy {
+    /// Refuse to download the model.
+    Abort,
+    /// Download it with a warning.
+    Warn,
+}
+
+/// Applies the policy to the model. Returns the warning to record when the model is downloaded
+/// anyway, fails when it is refused. Models not telling their permissions only get a warning.
+pub fn check_commercial_use(
+    model: &Model,
+    policy: Option<CommercialUsePolicy>,
+) -> Result<Option<String>, CommercialUseNotAllowedError> {
+    let Some(policy) = policy else {
+        return Ok(None);
+    };
+    let warning = match model.permissions().allows_commercial_use() {
+        Some(true) => return Ok(None),
+        Some(false) if policy == CommercialUsePolicy::Abort => {
+            return Err(CommercialUseNotAllowedError {
+                model_id: model.id(),
+                model_name: model.name(),
+            });
+        }
+        Some(false) => format!("{} does not allow commercial use", model.name()),
+        None => format!(
+            "{} does not tell whether commercial use is allowed",
+            model.name()
+        ),
+    };
+    events::message(format!("WARNING: {warning}."));
+    Ok(Some(warning))
+}
@@ -0,0 +1,787 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use backoff::ExponentialBackoffBuilder;
+use reqwest::Client;
+
+use crate::{
+    artifacts::{Artifact, ArtifactSet},
+    cache_db,
+    configuration::{Configuration, VideoCoverMode},
+    errors::{FileStillProcessingError, InvalidInputError, VersionWithoutFilesError},
+    events::{self, Event, PlannedFileEvent},
+    integrations::ModelDirectories,
+    progress::{ReporterKind, StepProgress},
+    utils::{
+        ByteUnits, LONGEST_COMPANION_SUFFIX, MAX_PATH_LENGTH, extended_length_path, format_bytes,
+        format_duration, path_length, sanitize_file_name,
+    },
+};
+
+use super::{
+    access::FileAccess,
+    file_preference::{FilePreference, preference_for_base_model},
+    meta,
+    model::{Model, ModelVersion, ModelVersionFile},
+    permissions::{self, CommercialUsePolicy},
+    selections::{self, VersionSelection},
+    session::DownloadSession,
+    sidecar,
+};
+
+/// Shortened file stems are kept at least this long to stay recognizable.
+const MIN_STEM_LENGTH: usize = 16;
+/// Seconds before asking Civitai again for a file it is still processing, growing up to the
+/// maximum.
+const PROCESSING_POLL_INTERVAL: u64 = 15;
+const MAX_PROCESSING_POLL_INTERVAL: u64 = 120;
+
+/// Switches changing how a model is downloaded.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadBehavior {
+    /// Files to produce for the model, without the model file only the metadata is saved.
+    pub artifacts: ArtifactSet,
+    /// Download files that Civitai marked as dangerous.
+    pub allow_unsafe: bool,
+    /// Only resolve and print what would be downloaded.
+    pub dry_run: bool,
+    /// Save every version into `<model name>/<version name>/` under the destination.
+    pub folder_per_model: bool,
+    /// Never prompt: only primary files are picked and files present locally are skipped.
+    pub unattended: bool,
+    /// Hardlink or copy files downloaded to another location instead of downloading them.
+    pub link_existing: bool,
+    /// Files to download from the selected versions without prompting, e.g. from a manifest.
+    pub file_ids: Vec<u64>,
+    /// Place the model into the directory of its type instead of the destination.
+    pub model_dirs: Option<ModelDirectories>,
+    /// Bytes allowed to transfer, shared by every download of a batch.
+    pub size_budget: Option<Arc<SizeBudget>>,
+    /// Where file transfer progress is reported.
+    pub reporter: ReporterKind,
+    /// Session of an interrupted download to resume, files are taken from it without prompting.
+    pub resumed: Option<DownloadSession>,
+    /// Number of other versions listed in the readme with their descriptions, `None` lists none.
+    pub version_history: Option<usize>,
+    /// File name a metadata only download is saved for, instead of the primary file name.
+    pub meta_file_name: Option<String>,
+    /// Prompt for the cover among the images of the version instead of taking the first.
+    pub pick_cover: bool,
+    /// How many community image prompts the readme lists and how long they are.
+    pub prompt_limits: meta::PromptLimits,
+    /// Extract downloaded zip archives into a directory named after them.
+    pub extract_archives: bool,
+    /// Delete the archive once it is extracted.
+    pub remove_archive: bool,
+    /// Files to pick without prompting, overriding the configured selection rules.
+    pub file_preference: Option<FilePreference>,
+    /// What to do with models that do not allow commercial use, `None` does not check.
+    pub commercial_use_policy: Option<CommercialUsePolicy>,
+}
+
+impl DownloadBehavior {
+    /// Only the metadata of the primary file is saved, without downloading it.
+    pub fn meta_only(&self) -> bool {
+        !self.artifacts.contains(Artifact::Model)
+    }
+}
+
+/// Cap of bytes to transfer, consumed by every download sharing it. Only model files are
+/// counted, covers and metadata are small enough to be left out.
+#[derive(Debug)]
+pub struct SizeBudget {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl SizeBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used.load(Ordering::Relaxed))
+    }
+
+    pub fn consume(&self, bytes: u64) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Everything a download would fetch and write, resolved before any file content is
+/// transferred.
+pub struct DownloadPlan {
+    pub model: Model,
+    /// Directory the model is placed into, before the folder named after the model.
+    pub destination: PathBuf,
+    pub versions: Vec<VersionPlan>,
+    pub video_cover_mode: VideoCoverMode,
+    /// Why the model is downloaded against the commercial use policy.
+    pub license_warning: Option<String>,
+}
+
+pub struct VersionPlan {
+    pub version: ModelVersion,
+    pub destination: PathBuf,
+    /// Every file of the version has been downloaded before and still exists.
+    pub already_downloaded: bool,
+    pub primary_file_name: String,
+    pub files: Vec<FilePlan>,
+}
+
+pub struct FilePlan {
+    pub file: ModelVersionFile,
+    pub target_path: PathBuf,
+    /// Location of a previously downloaded copy, recorded in cache database.
+    pub existing_location: Option<PathBuf>,
+    /// Civitai marked the file as dangerous and unsafe files are not allowed.
+    pub refused: bool,
+    /// The file does not fit into the size budget and is left for a later session.
+    pub over_budget: bool,
+    /// The access key may not download the file, found by the preflight of unattended downloads.
+    pub inaccessible: Option<FileAccess>,
+}
+
+/// Fetches metadata and resolves the versions and files to download. Model metadata and
+/// every version metadata are fetched as steps of the given progress.
+pub async fn plan_download(
+    client: &Client,
+    config: &Configuration,
+    model_id: u64,
+    version_selection: &VersionSelection,
+    destination_path: Option<&PathBuf>,
+    behavior: &DownloadBehavior,
+    progress: &mut StepProgress,
+) -> Result<DownloadPlan> {
+    progress.begin("Fetching model metadata...");
+    let model_meta = progress.track(meta::fetch_model_metadata(client, config, model_id).await)?;
+    // 在选择版本之前检查许可，被拒绝的模型不再提示
+    let license_warning = progress.multi().suspend(|| {
+        permissions::check_commercial_use(&model_meta, behavior.commercial_use_policy)
+    })?;
+    let selected_versions = selections::select_model_versions(&model_meta, version_selection)
+        .context("Unable to confirm model version")?;
+    progress.set_total_steps(1 + super::STEPS_PER_VERSION * selected_versions.len());
+
+    // 选择了多个版本时，每个版本存放在以版本名称命名的子目录中
+    let separate_version_dirs = selected_versions.len() > 1;
+    let base_dir = match (behavior.model_dirs.as_ref(), destination_path) {
+        (Some(model_dirs), _) => model_type_dir(model_dirs, &model_meta)?,
+        (None, Some(path)) => path.clone(),
+        (None, None) => {
+            std::env::current_dir().context("Unable to get current working directory")?
+        }
+    };
+    let destination = base_dir;
+    let base_dir = if behavior.folder_per_model {
+        destination.join(sanitize_file_name(&model_meta.name()))
+    } else {
+        destination.clone()
+    };
+
+    let mut versions = Vec::new();
+    for selected_version in selected_versions {
+        progress.begin(format!("Fetching version {selected_version} metadata..."));
+        let mut version_meta = progress
+            .track(meta::fetch_model_version_meta(client, config, selected_version).await)
+            .with_context(|| {
+                format!("Failed to fetch version {selected_version} detail metadata")
+            })?;
+        let version_files = version_meta.files()?;
+        // 指定偏好或无人值守时按偏好选择文件，否则偏好只作为提示框的默认选项
+        let preference = match behavior.file_preference.clone() {
+            Some(preference) => Some(preference),
+            None => preference_for_base_model(
+                &config.selection_rules,
+                version_meta.base_model().as_deref(),
+            ),
+        };
+        let preferred_file_ids = preference
+            .as_ref()
+            .map(|preference| preference.pick(&version_files));
+        let picks_preferred = preferred_file_ids.is_some()
+            && behavior.resumed.is_none()
+            && behavior.file_ids.is_empty()
+            && (behavior.unattended || events::enabled() || behavior.file_preference.is_some());
+        let primary_file = version_files
+            .iter()
+            .find(|f| f.is_primary().unwrap_or_default())
+            .or(version_files.first())
+            .ok_or_else(|| VersionWithoutFilesError {
+                version_name: version_meta.name(),
+                versions_with_files: model_meta
+                    .versions()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|v| v.id() != version_meta.id() && v.has_files())
+                    .map(|v| format!("{} ({})", v.name(), v.id()))
+                    .collect(),
+            })?;
+        let primary_file_name = match behavior
+            .meta_file_name
+            .as_ref()
+            .filter(|_| behavior.meta_only())
+        {
+            Some(file_name) => sanitize_file_name(file_name),
+            None => {
+                // 按偏好选择时，以选中的模型文件作为文件名的基础
+                let naming_file = preferred_file_ids
+                    .as_ref()
+                    .filter(|_| picks_preferred)
+                    .and_then(|ids| ids.first())
+                    .and_then(|id| version_files.iter().find(|f| f.id() == *id))
+                    .unwrap_or(primary_file);
+                sanitize_file_name(&naming_file.name())
+            }
+        };
+
+        let mut already_downloaded = separate_version_dirs
+            && !behavior.meta_only()
+            && !behavior.link_existing
+            && is_version_downloaded(&version_meta);
+        let destination = if separate_version_dirs || behavior.folder_per_model {
+            base_dir.join(sanitize_file_name(&version_meta.name()))
+        } else {
+            base_dir.clone()
+        };
+
+        let files = if already_downloaded {
+            Vec::new()
+        } else if behavior.meta_only() {
+            // 只保存元数据时，以主文件（或指定的文件名）作为文件名的基础
+            let primary_file_id = primary_file.id();
+            version_files
+                .into_iter()
+                .filter(|f| f.id() == primary_file_id)
+                .map(|file| FilePlan {
+                    target_path: destination.join(&primary_file_name),
+                    existing_location: None,
+                    refused: false,
+                    over_budget: false,
+                    inaccessible: None,
+                    file,
+                })
+                .collect::<Vec<_>>()
+        } else {
+            let selected_file_ids = match behavior.resumed.as_ref() {
+                Some(session) => session.file_ids(),
+                None if !behavior.file_ids.is_empty() => behavior.file_ids.clone(),
+                None if picks_preferred => preferred_file_ids.clone().unwrap_or_default(),
+                None if behavior.unattended => vec![primary_file.id()],
+                None => selections::select_model_version_files(
+                    &version_meta,
+                    preferred_file_ids.as_deref(),
+                )
+                .context("Failed to confirm model version files")?,
+            };
+            // 明确指定的文件仍在处理中时，等待Civitai处理完成
+            let version_files = if !behavior.dry_run
+                && (behavior.resumed.is_some() || !behavior.file_ids.is_empty())
+            {
+                version_meta =
+                    wait_for_processed_files(client, config, version_meta, &selected_file_ids)
+                        .await?;
+                version_meta.files()?
+            } else {
+                version_files
+            };
+            version_files
+                .into_iter()
+                .filter(|f| selected_file_ids.contains(&f.id()))
+                .map(|file| FilePlan {
+                    target_path: destination.join(sanitize_file_name(&file.name())),
+                    existing_location: existing_file_location(&file),
+                    refused: file.is_unsafe() && !behavior.allow_unsafe,
+                    over_budget: false,
+                    inaccessible: None,
+                    file,
+                })
+                .collect::<Vec<_>>()
+        };
+        // 无人值守时，本地已有的版本不再处理
+        if behavior.unattended
+            && !behavior.meta_only()
+            && !files.is_empty()
+            && files
+                .iter()
+                .all(|file| file.is_present(behavior.link_existing))
+        {
+            already_downloaded = true;
+        }
+
+        let mut version_plan = VersionPlan {
+            version: version_meta,
+            destination,
+            already_downloaded,
+            primary_file_name,
+            files,
+        };
+        if !version_plan.already_downloaded {
+            progress
+                .multi()
+                .suspend(|| version_plan.fit_path_limit(behavior));
+        }
+        versions.push(version_plan);
+    }
+
+    let video_cover_mode = config.cover.video;
+    Ok(DownloadPlan {
+        model: model_meta,
+        destination,
+        versions,
+        video_cover_mode,
+        license_warning,
+    })
+}
+
+/// Directory of the model type in a model UI, models of unknown types go to the fallback
+/// directory with a warning.
+fn model_type_dir(model_dirs: &ModelDirectories, model_meta: &Model) -> Result<PathBuf> {
+    let model_type = model_meta.model_type();
+    let category = model_type.as_deref().and_then(model_dirs.category_of);
+    if let Some(dir) = category.and_then(|category| model_dirs.category_dir(category)) {
+        return Ok(dir.to_path_buf());
+    }
+    let model_type = model_type.unwrap_or("unknown".to_string());
+    match model_dirs.fallback.as_ref() {
+        Some(fallback) => {
+            events::message(format!(
+                "WARNING: {} has no directory for {model_type} models, saving to {}.",
+                model_dirs.ui_name,
+                fallback.display()
+            ));
+            Ok(fallback.clone())
+        }
+        None => Err(InvalidInputError(format!(
+            "{} has no directory for {model_type} models{}, configure a directory for other models first.",
+            model_dirs.ui_name,
+            category
+                .map(|category| format!(" (category {category})"))
+                .unwrap_or_default()
+        ))
+        .into()),
+    }
+}
+
+/// Checks whether every file of the version has been downloaded before and still exists.
+fn is_version_downloaded(version_meta: &ModelVersion) -> bool {
+    let Ok(version_files) = version_meta.files() else {
+        return false;
+    };
+    !version_files.is_empty()
+        && version_files
+            .iter()
+            .all(|file| existing_file_location(file).is_some())
+}
+
+fn existing_file_location(file: &ModelVersionFile) -> Option<PathBuf> {
+    file.blake3_hash()
+        .and_then(|hash| cache_db::retreive_civitai_model_locations_by_blake3(&hash).ok())
+        .flatten()
+        .and_then(|locations| locations.into_iter().find(|loc| loc.exists()))
+}
+
+fn stem_of(file_name: &str) -> String {
+    Path::new(file_name)
+        .file_stem()
+        .map(|s| sanitize_file_name(&s.to_string_lossy()))
+        .unwrap_or_default()
+}
+
+impl VersionPlan {
+    /// The access key may download none of the files of the version.
+    pub fn is_inaccessible(&self) -> bool {
+        !self.files.is_empty() && self.files.iter().all(|f| f.inaccessible.is_some())
+    }
+
+    /// Length of the longest path written for the version, companion files included.
+    fn longest_path_length(&self) -> usize {
+        let destination_length = path_length(&self.destination);
+        self.files
+            .iter()
+            .map(|file| file.target_file_name())
+            .chain(std::iter::once(self.primary_file_name.clone()))
+            .map(|name| destination_length + 1 + companion_length(&name))
+            .max()
+            .unwrap_or(destination_length)
+    }
+
+    /// Warns when paths of the version would exceed the platform limit, and shortens the file
+    /// names when confirmed. Paths still too long take the extended-length form on Windows.
+    fn fit_path_limit(&mut self, behavior: &DownloadBehavior) {
+        let longest_length = self.longest_path_length();
+        if longest_length <= MAX_PATH_LENGTH {
+            return;
+        }
+        events::message(format!(
+            "WARNING: Files of version {} would be saved to paths of {longest_length} characters, longer than {MAX_PATH_LENGTH} allowed by the system.",
+            self.version.name()
+        ));
+
+        let shorten = behavior.unattended
+            || events::enabled()
+            || selections::decide_shortening_or_not(&self.version.name());
+        let budget = MAX_PATH_LENGTH.saturating_sub(path_length(&self.destination) + 1);
+        if shorten && budget >= MIN_STEM_LENGTH + LONGEST_COMPANION_SUFFIX.len() {
+            self.primary_file_name = fit_file_name(&self.primary_file_name, budget);
+            for file in self.files.iter_mut() {
+                let file_name = fit_file_name(&file.target_file_name(), budget);
+                file.target_path = file.target_path.with_file_name(file_name);
+            }
+            events::message(format!(
+                "Shortened file names of version {}, primary file is saved as {}.",
+                self.version.name(),
+                self.primary_file_name
+            ));
+        } else if shorten {
+            events::message(format!(
+                "Destination {} is too long to shorten file names, use a shorter output directory.",
+                self.destination.display()
+            ));
+        }
+
+        // 仍然超长的路径在Windows上使用扩展长度形式
+        if self.longest_path_length() > MAX_PATH_LENGTH {
+            self.destination = extended_length_path(&self.destination);
+            for file in self.files.iter_mut() {
+                file.target_path = extended_length_path(&file.target_path);
+            }
+        }
+    }
+
+    pub fn readme_path(&self) -> PathBuf {
+        self.destination
+            .join(format!("{}.md", stem_of(&self.primary_file_name)))
+    }
+
+    pub fn triggers_path(&self) -> PathBuf {
+        self.destination
+            .join(format!("{}.txt", stem_of(&self.primary_file_name)))
+    }
+
+    /// Expected cover path, videos are saved as `.mp4` unless Civitai serves another format.
+    pub fn cover_path(&self, video_cover_mode: VideoCoverMode) -> Option<PathBuf> {
+        let images = self.version.images().unwrap_or_default();
+        let has_still = images
+            .iter()
+            .any(|img| !img.media_type().eq_ignore_ascii_case("video"));
+        let extension = match (has_still, images.is_empty(), video_cover_mode) {
+            (true, _, _) => "png",
+            (false, true, _) | (false, false, VideoCoverMode::Skip) => return None,
+            (false, false, VideoCoverMode::Poster) => "png",
+            (false, false, VideoCoverMode::Video) => "mp4",
+        };
+        Some(self.destination.join(format!(
+            "{}.cover.{extension}",
+            stem_of(&self.primary_file_name)
+        )))
+    }
+}
+
+/// Fetches the version again until none of the requested files is still processed by Civitai,
+/// waiting up to the configured time. Returns the version with download URLs of every file.
+async fn wait_for_processed_files(
+    client: &Client,
+    config: &Configuration,
+    version: ModelVersion,
+    file_ids: &[u64],
+) -> Result<ModelVersion> {
+    let processing_file = |version: &ModelVersion| {
+        version
+            .files()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|file| file_ids.contains(&file.id()) && file.is_processing())
+    };
+    let Some(file) = processing_file(&version) else {
+        return Ok(version);
+    };
+    let wait = config.download.processing_wait();
+    if wait.is_zero() || crate::downloader::is_offline() {
+        return Err(FileStillProcessingError(file.name()).into());
+    }
+    events::message(format!(
+        "File {} is still being processed by Civitai, wait up to {} for it...",
+        file.name(),
+        format_duration(&wait)
+    ));
+    let policy = ExponentialBackoffBuilder::default()
+        .with_initial_interval(Duration::from_secs(PROCESSING_POLL_INTERVAL))
+        .with_multiplier(1.5)
+        .with_max_interval(Duration::from_secs(MAX_PROCESSING_POLL_INTERVAL))
+        .with_max_elapsed_time(Some(wait))
+        .build();
+    let version_id = version.id();
+    let task = async || -> Result<ModelVersion, backoff::Error<anyhow::Error>> {
+        let version = meta::fetch_model_version_meta(client, config, version_id)
+            .await
+            .map_err(backoff::Error::permanent)?;
+        match processing_file(&version) {
+            Some(file) => Err(backoff::Error::transient(anyhow::Error::from(
+                FileStillProcessingError(file.name()),
+            ))),
+            None => Ok(version),
+        }
+    };
+    let version = backoff::future::retry(policy, task).await?;
+    events::message("Civitai has finished processing the requested files.");
+    Ok(version)
+}
+
+/// Splits the file name into stem and extension with the leading dot.
+fn split_file_name(file_name: &str) -> (String, String) {
+    let path = Path::new(file_name);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (stem, extension)
+}
+
+/// Length of the file name or of its longest companion file name.
+fn companion_length(file_name: &str) -> usize {
+    let (stem, extension) = split_file_name(file_name);
+    stem.chars().count()
+        + extension
+            .chars()
+            .count()
+            .max(LONGEST_COMPANION_SUFFIX.len())
+}
+
+/// Shortens the stem of the file name so that the name and its companion files fit in the
+/// budget.
+fn fit_file_name(file_name: &str, budget: usize) -> String {
+    if companion_length(file_name) <= budget {
+        return file_name.to_string();
+    }
+    let (stem, extension) = split_file_name(file_name);
+    let stem_length = budget.saturating_sub(
+        extension
+            .chars()
+            .count()
+            .max(LONGEST_COMPANION_SUFFIX.len()),
+    );
+    let stem = stem.chars().take(stem_length).collect::<String>();
+    format!("{}{extension}", stem.trim_end_matches(['.', ' ']))
+}
+
+impl FilePlan {
+    pub fn target_file_name(&self) -> String {
+        self.target_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn hash_path(&self) -> PathBuf {
+        let stem = stem_of(&self.file.name());
+        self.target_path.with_file_name(format!("{stem}.blake3"))
+    }
+
+    pub fn sidecar_path(&self) -> PathBuf {
+        sidecar::sidecar_path(&self.target_path)
+    }
+
+    /// Whether the file exists locally, either recorded in cache database or saved with its
+    /// sidecar at the target path. Copies elsewhere do not count when they are to be linked.
+    pub fn is_present(&self, link_existing: bool) -> bool {
+        (self.existing_location.is_some() && !link_existing)
+            || (self.target_path.exists() && self.sidecar_path().exists())
+    }
+
+    /// Whether the file will be transferred, files downloaded before are assumed to be
+    /// downloaded again only when confirmed.
+    pub fn will_transfer(&self) -> bool {
+        !self.refused
+            && !self.over_budget
+            && self.inaccessible.is_none()
+            && self.existing_location.is_none()
+            && !self.file.is_processing()
+    }
+}
+
+fn existence_label(path: &Path) -> &'static str {
+    if path.exists() { "exists" } else { "new" }
+}
+
+impl DownloadPlan {
+    /// Bytes of file content to transfer, excluding files already downloaded or refused.
+    pub fn total_transfer_bytes(&self) -> u64 {
+        self.versions
+            .iter()
+            .flat_map(|v| v.files.iter())
+            .filter(|f| f.will_transfer())
+            .map(|f| f.file.size_in_bytes())
+            .sum()
+    }
+
+    /// Marks the files not fitting into the given bytes in plan order, returns the marked
+    /// files.
+    pub fn fit_size_budget(&mut self, remaining: u64) -> Vec<&FilePlan> {
+        let mut planned = 0u64;
+        for file_plan in self.versions.iter_mut().flat_map(|v| v.files.iter_mut()) {
+            if !file_plan.will_transfer() {
+                continue;
+            }
+            let size = file_plan.file.size_in_bytes();
+            if planned + size <= remaining {
+                planned += size;
+            } else {
+                file_plan.over_budget = true;
+            }
+        }
+        self.over_budget_files()
+    }
+
+    pub fn over_budget_files(&self) -> Vec<&FilePlan> {
+        self.versions
+            .iter()
+            .flat_map(|v| v.files.iter())
+            .filter(|f| f.over_budget)
+            .collect()
+    }
+
+    /// Takes back the files marked by [`DownloadPlan::fit_size_budget`].
+    pub fn ignore_size_budget(&mut self) {
+        for file_plan in self.versions.iter_mut().flat_map(|v| v.files.iter_mut()) {
+            file_plan.over_budget = false;
+        }
+    }
+
+    pub fn print(&self, artifacts: ArtifactSet) {
+        events::message(format!(
+            "\nModel: {} ({})",
+            self.model.name(),
+            self.model.id()
+        ));
+        events::message(format!("Artifacts: {artifacts}"));
+        for version_plan in self.versions.iter() {
+            let version = &version_plan.version;
+            events::message(format!(
+                "Version: {} ({}) -> {}",
+                version.name(),
+                version.id(),
+                version_plan.destination.display()
+            ));
+            if version_plan.already_downloaded {
+                events::message("  All files already downloaded, will be skipped.");
+                continue;
+            }
+            for file_plan in version_plan.files.iter() {
+                let file = &file_plan.file;
+                events::message(format!(
+                    "  File: {} ({})",
+                    file.name(),
+                    format_bytes(file.size_in_bytes(), ByteUnits::Binary)
+                ));
+                if let Some(hash) = file.blake3_hash() {
+                    events::message(format!("    BLAKE3: {hash}"));
+                }
+                if let Some(hash) = file.sha256_hash() {
+                    events::message(format!("    SHA256: {hash}"));
+                }
+                if file_plan.refused {
+                    events::message(format!(
+                        "    Will be refused, Civitai marked it as dangerous [{}].",
+                        file.scan_warning().unwrap_or_default()
+                    ));
+                }
+                if file_plan.over_budget {
+                    events::message(
+                        "    Exceeds the size budget, will be left for a later session.",
+                    );
+                }
+                if file.is_processing() {
+                    events::message(
+                        "    Still being processed by Civitai, not yet downloadable, will be skipped.",
+                    );
+                }
+                if let Some(location) = file_plan.existing_location.as_ref() {
+                    events::message(format!(
+                        "    Downloaded before at {}, will ask before downloading again.",
+                        location.display()
+                    ));
+                }
+                let paths = [
+                    (Artifact::Model, file_plan.target_path.clone()),
+                    (Artifact::Hash, file_plan.hash_path()),
+                    (Artifact::Json, file_plan.sidecar_path()),
+                ];
+                for (_, path) in paths
+                    .iter()
+                    .filter(|(artifact, _)| artifacts.contains(*artifact))
+                {
+                    events::message(format!(
+                        "    -> {} [{}]",
+                        path.display(),
+                        existence_label(path)
+                    ));
+                }
+                events::emit(&Event::PlannedFile(PlannedFileEvent {
+                    version_id: version.id(),
+                    name: &file.name(),
+                    path: &file_plan.target_path,
+                    size: file.size_in_bytes(),
+                    blake3: file
+                        .blake3_hash()
+                        .map(|hash| hash.to_ascii_uppercase())
+                        .as_deref(),
+                    sha256: file
+                        .sha256_hash()
+                        .map(|hash| hash.to_ascii_uppercase())
+                        .as_deref(),
+                    exists: file_plan.target_path.exists(),
+                    will_transfer: file_plan.will_transfer(),
+                }));
+            }
+            if let Some(cover_path) = version_plan
+                .cover_path(self.video_cover_mode)
+                .filter(|_| artifacts.contains(Artifact::Cover))
+            {
+                events::message(format!(
+                    "  Cover: {} [{}]",
+                    cover_path.display(),
+                    existence_label(&cover_path)
+                ));
+            }
+            if artifacts.contains(Artifact::Triggers) && !version.trained_words().is_empty() {
+                let triggers_path = version_plan.triggers_path();
+                events::message(format!(
+                    "  Trained words: {} [{}]",
+                    triggers_path.display(),
+                    existence_label(&triggers_path)
+                ));
+            }
+            if artifacts.contains(Artifact::Readme) {
+                let readme_path = version_plan.readme_path();
+                events::message(format!(
+                    "  Readme: {} [{}]",
+                    readme_path.display(),
+                    existence_label(&readme_path)
+                ));
+            }
+        }
+        events::message(format!(
+            "Total to transfer: {}",
+            format_bytes(self.total_transfer_bytes(), ByteUnits::Binary)
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+
+use crate::{cache_db, configuration::Configuration, utils::sanitize_file_name};
+
+use super::{meta, model::ModelVersion, sidecar};
+
+/// Outcome of regenerating the readme of a model file.
+pub enum ReadmeRegeneration {
+    Regenerated(PathBuf),
+    /// Neither a sidecar nor a hash file tells which Civitai model the file is.
+    Unidentified,
+    /// The model is known, but its metadata is not cached and fetching is not allowed.
+    MissingMetadata(String),
+}
+
+/// Writes the readme of a downloaded model file again from cached metadata. Metadata missing
+/// from cache is fetched only when a client is given.
+pub async fn regenerate_readme(
+    model_file: &Path,
+    client: Option<&Client>,
+    config: &Configuration,
+    prompt_limits: meta::PromptLimits,
+) -> Result<ReadmeRegeneration> {
+    let model_version = match identified_version(model_file, client, config).await? {
+        Some(Ok(version)) => version,
+        Some(Err(missing)) => return Ok(ReadmeRegeneration::MissingMetadata(missing)),
+        None => return Ok(ReadmeRegeneration::Unidentified),
+    };
+    let model_id = model_version.model_id();
+    let model = match (cache_db::retreive_civitai_model(model_id)?, client) {
+        (Some(model), _) => model,
+        (None, Some(client)) => meta::fetch_model_metadata(client, config, model_id)
+            .await
+            .context("Request for model metadata")?,
+        (None, None) => {
+            return Ok(ReadmeRegeneration::MissingMetadata(format!(
+                "model {model_id}"
+            )));
+        }
+    };
+    let fetched_images = match client {
+        Some(client) => meta::fetch_model_community_images(client, config, model_id, false)
+            .await
+            .ok(),
+        None => None,
+    };
+    // 获取失败时使用缓存中的社区图片，无论缓存时间
+    let community_images = fetched_images.unwrap_or_else(|| {
+        cache_db::retreive_civitai_community_images(model_id, Duration::MAX)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    });
+
+    let target_dir = model_file
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let file_name = model_file
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let readme_path = meta::save_model_version_readme(
+        config,
+        &model,
+        &model_version,
+        &community_images,
+        &[],
+        existing_cover(model_file),
+        Some(&target_dir),
+        file_name,
+        prompt_limits,
+    )
+    .await?;
+    Ok(ReadmeRegeneration::Regenerated(readme_path))
+}
+
+/// Model version of the file, found by its sidecar or its recorded hash. `Some(Err)` names the
+/// metadata that is missing.
+async fn identified_version(
+    model_file: &Path,
+    client: Option<&Client>,
+    config: &Configuration,
+) -> Result<Option<Result<ModelVersion, String>>> {
+    if let Some(sidecar) = sidecar::load_sidecar(model_file)? {
+        let version_id = sidecar.version_id;
+        if let Some(version) =
+            cache_db::retreive_civitai_model_version(sidecar.model_id, version_id)?
+        {
+            return Ok(Some(Ok(version)));
+        }
+        if let Some(client) = client {
+            return Ok(Some(Ok(meta::fetch_model_version_meta(
+                client, config, version_id,
+            )
+            .await?)));
+        }
+        // 侧车文件中保存了完整的版本元数据
+        return Ok(Some(
+            ModelVersion::try_from(&sidecar.model_version)
+                .map_err(|_| format!("model version {version_id}")),
+        ));
+    }
+
+    let Some(hash) = recorded_hash(model_file) else {
+        return Ok(None);
+    };
+    let cached = cache_db::find_civitai_model_version(|version| {
+        version
+            .files()
+            .is_ok_and(|files| files.iter().any(|file| file.match_by_blake3(&hash)))
+    })?;
+    match (cached, client) {
+        (Some(version), _) => Ok(Some(Ok(version))),
+        (None, Some(client)) => Ok(Some(Ok(meta::fetch_model_version_meta_by_hash(
+            client, config, &hash,
+        )
+        .await?))),
+        (None, None) => Ok(Some(Err(format!("model version with hash {hash}")))),
+    }
+}
+
+pub(super) fn recorded_hash(model_file: &Path) -> Option<String> {
+    let stem = model_file.file_stem()?.to_string_lossy();
+    let hash_path = model_file.with_file_name(format!("{}.blake3", sanitize_file_name(&stem)));
+    std::fs::read_to_string(hash_path)
+        .ok()
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+}
+
+/// File name of the cover saved beside the model file before.
+pub(super) fn existing_cover(model_file: &Path) -> Option<String> {
+    let stem = model_file.file_stem()?.to_string_lossy();
+    let prefix = format!("{}.cover.", sanitize_file_name(&stem));
+    let mut covers = std::fs::read_dir(model_file.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(&prefix))
+        .collect::<Vec<_>>();
+    covers.sort();
+    covers.into_iter().next()
+}
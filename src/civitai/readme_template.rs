@@ -0,0 +1,597 @@
+//! Layout of the generated readme, a user template or the built-in one.
+//!
+//! Templates are markdown with a few tags:
+//! - `{{name}}` inserts a variable, `{{file.name}}` a field of it, `{{this}}` the current item;
+//! - `{{#if name}} ... {{else}} ... {{/if}}` keeps a part when the variable is not empty;
+//! - `{{#each name}} ... {{/each}}` repeats a part for every item of a list;
+//! - `{{! comment }}` is dropped.
+//!
+//! A block tag alone on its line takes the whole line, so blocks do not leave blank lines.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use thiserror::Error;
+
+/// The built-in layout, used when no user template exists.
+pub const DEFAULT_README_TEMPLATE: &str = include_str!("readme.tmpl");
+
+#[derive(Debug, Error)]
+#[error("{source_name}, line {line}: {message}")]
+pub struct TemplateError {
+    pub source_name: String,
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Variable(String, usize),
+    If {
+        name: String,
+        line: usize,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        name: String,
+        line: usize,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    Tag(Tag),
+}
+
+#[derive(Debug)]
+enum Tag {
+    Variable(String),
+    If(String),
+    Else,
+    EndIf,
+    Each(String),
+    EndEach,
+    Comment,
+}
+
+impl Tag {
+    fn is_block(&self) -> bool {
+        !matches!(self, Tag::Variable(_))
+    }
+}
+
+/// Line and message of an error found while parsing.
+type ParseError = (usize, String);
+/// Tag ending a block and its line.
+type BlockEnd = (Tag, usize);
+
+#[derive(Debug)]
+pub struct ReadmeTemplate {
+    source_name: String,
+    nodes: Vec<Node>,
+}
+
+/// Where the user template is looked for.
+pub fn readme_template_path() -> Option<PathBuf> {
+    crate::configuration::config_dir().map(|dir| dir.join("readme.tmpl"))
+}
+
+/// The user template when one exists, otherwise the built-in layout. Read on every call, so
+/// changes to the template apply to the next readme written.
+pub fn load_readme_template() -> Result<ReadmeTemplate> {
+    if let Some(path) = readme_template_path()
+        && path.exists()
+    {
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read readme template {}", path.display()))?;
+        return Ok(ReadmeTemplate::parse(&path.display().to_string(), &text)?);
+    }
+    Ok(ReadmeTemplate::parse(
+        "built-in readme template",
+        DEFAULT_README_TEMPLATE,
+    )?)
+}
+
+impl ReadmeTemplate {
+    pub fn parse(source_name: &str, text: &str) -> Result<Self, TemplateError> {
+        let error = |line: usize, message: String| TemplateError {
+            source_name: source_name.to_string(),
+            line,
+            message,
+        };
+        let mut tokens = tokenize(text)
+            .map_err(|(line, message)| error(line, message))?
+            .into_iter();
+        let (nodes, end) =
+            parse_nodes(&mut tokens).map_err(|(line, message)| error(line, message))?;
+        if let Some((tag, line)) = end {
+            return Err(error(
+                line,
+                format!("{} without an opening tag", tag_text(&tag)),
+            ));
+        }
+        Ok(Self {
+            source_name: source_name.to_string(),
+            nodes,
+        })
+    }
+
+    /// Renders the template with the variables of the JSON object.
+    pub fn render(&self, context: &Value) -> Result<String, TemplateError> {
+        let mut output = String::new();
+        let mut scopes = vec![context];
+        self.render_nodes(&self.nodes, &mut scopes, &mut output)?;
+        Ok(output)
+    }
+
+    fn render_nodes(
+        &self,
+        nodes: &[Node],
+        scopes: &mut Vec<&Value>,
+        output: &mut String,
+    ) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => output.push_str(text),
+                Node::Variable(name, line) => match self.lookup(name, *line, scopes)? {
+                    Value::Null => {}
+                    Value::String(text) => output.push_str(text),
+                    Value::Bool(value) => output.push_str(&value.to_string()),
+                    Value::Number(value) => output.push_str(&value.to_string()),
+                    Value::Array(_) | Value::Object(_) => {
+                        return Err(self.error(
+                            *line,
+                            format!("`{name}` is a list or an object, use {{{{#each {name}}}}}"),
+                        ));
+                    }
+                },
+                Node::If {
+                    name,
+                    line,
+                    then,
+                    otherwise,
+                } => {
+                    let branch = if is_truthy(self.lookup(name, *line, scopes)?) {
+                        then
+                    } else {
+                        otherwise
+                    };
+                    self.render_nodes(branch, scopes, output)?;
+                }
+                Node::Each { name, line, body } => {
+                    let items = match self.lookup(name, *line, scopes)? {
+                        Value::Null => continue,
+                        Value::Array(items) => items,
+                        _ => {
+                            return Err(self.error(*line, format!("`{name}` is not a list")));
+                        }
+                    };
+                    for item in items {
+                        scopes.push(item);
+                        let result = self.render_nodes(body, scopes, output);
+                        scopes.pop();
+                        result?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the variable in the innermost scope having it, then the fields under it.
+    fn lookup<'v>(
+        &self,
+        name: &str,
+        line: usize,
+        scopes: &[&'v Value],
+    ) -> Result<&'v Value, TemplateError> {
+        let mut parts = name.split('.');
+        let first = parts.next().unwrap_or_default();
+        let mut value = if first == "this" {
+            scopes.last().copied()
+        } else {
+            scopes.iter().rev().find_map(|scope| scope.get(first))
+        }
+        .ok_or_else(|| self.error(line, format!("unknown variable `{name}`")))?;
+        for part in parts {
+            value = value
+                .get(part)
+                .ok_or_else(|| self.error(line, format!("unknown variable `{name}`")))?;
+        }
+        Ok(value)
+    }
+
+    fn error(&self, line: usize, message: String) -> TemplateError {
+        TemplateError {
+            source_name: self.source_name.clone(),
+            line,
+            message,
+        }
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(value) => value.as_f64() != Some(0.0),
+        Value::String(text) => !text.trim().is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
+
+fn tag_text(tag: &Tag) -> String {
+    match tag {
+        Tag::Variable(name) => format!("{{{{{name}}}}}"),
+        Tag::If(name) => format!("{{{{#if {name}}}}}"),
+        Tag::Else => "{{else}}".to_string(),
+        Tag::EndIf => "{{/if}}".to_string(),
+        Tag::Each(name) => format!("{{{{#each {name}}}}}"),
+        Tag::EndEach => "{{/each}}".to_string(),
+        Tag::Comment => "{{!}}".to_string(),
+    }
+}
+
+/// Parses nodes until a tag closing the enclosing block, returned with its line.
+fn parse_nodes(
+    tokens: &mut impl Iterator<Item = (Token, usize)>,
+) -> Result<(Vec<Node>, Option<BlockEnd>), ParseError> {
+    let mut nodes = Vec::new();
+    while let Some((token, line)) = tokens.next() {
+        let tag = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+        match tag {
+            Tag::Comment => {}
+            Tag::Variable(name) => nodes.push(Node::Variable(name, line)),
+            Tag::If(name) => {
+                let (then, end) = parse_nodes(tokens)?;
+                let (otherwise, end) = match end {
+                    Some((Tag::Else, _)) => parse_nodes(tokens)?,
+                    end => (Vec::new(), end),
+                };
+                expect_end(end, Tag::EndIf, &Tag::If(name.clone()), line)?;
+                nodes.push(Node::If {
+                    name,
+                    line,
+                    then,
+                    otherwise,
+                });
+            }
+            Tag::Each(name) => {
+                let (body, end) = parse_nodes(tokens)?;
+                expect_end(end, Tag::EndEach, &Tag::Each(name.clone()), line)?;
+                nodes.push(Node::Each { name, line, body });
+            }
+            Tag::Else | Tag::EndIf | Tag::EndEach => return Ok((nodes, Some((tag, line)))),
+        }
+    }
+    Ok((nodes, None))
+}
+
+/// Checks the block opened on `open_line` is closed by the expected tag.
+fn expect_end(
+    end: Option<BlockEnd>,
+    expected: Tag,
+    open: &Tag,
+    open_line: usize,
+) -> Result<(), ParseError> {
+    match end {
+        Some((tag, _)) if std::mem::discriminant(&tag) == std::mem::discriminant(&expected) => {
+            Ok(())
+        }
+        Some((tag, line)) => Err((
+            line,
+            format!(
+                "{} does not close {} opened on line {open_line}",
+                tag_text(&tag),
+                tag_text(open)
+            ),
+        )),
+        None => Err((open_line, format!("{} is never closed", tag_text(open)))),
+    }
+}
+
+/// Splits the template into text and tags with their line numbers.
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut rest = text;
+    let mut line = 1;
+    // 上一个标签之后是否位于行首，独占一行的标签会移除其后的换行
+    let mut starts_line = true;
+    while let Some(start) = rest.find("{{") {
+        let (before, after) = rest.split_at(start);
+        let tag_line = line + before.matches('\n').count();
+        let Some(end) = after.find("}}") else {
+            return Err((tag_line, "`{{` is never closed by `}}`".to_string()));
+        };
+        let content = &after[2..end];
+        let tag = parse_tag(content).map_err(|message| (tag_line, message))?;
+        let mut before = before.to_string();
+        let mut after = &after[end + 2..];
+        line = tag_line + content.matches('\n').count();
+
+        // 独占一行的块标签连同所在行一起移除
+        let after_line_start = std::mem::replace(&mut starts_line, false);
+        if tag.is_block() {
+            let line_start = before.rfind('\n').map(|index| index + 1);
+            let at_line_start = match line_start {
+                Some(index) => before[index..].trim().is_empty(),
+                None => after_line_start && before.trim().is_empty(),
+            };
+            let line_end = after.find('\n');
+            let at_line_end = after[..line_end.unwrap_or(after.len())].trim().is_empty();
+            if at_line_start && at_line_end {
+                before.truncate(line_start.unwrap_or(0));
+                match line_end {
+                    Some(index) => {
+                        after = &after[index + 1..];
+                        line += 1;
+                    }
+                    None => after = "",
+                }
+                starts_line = true;
+            }
+        }
+        if !before.is_empty() {
+            tokens.push((Token::Text(before), tag_line));
+        }
+        tokens.push((Token::Tag(tag), tag_line));
+        rest = after;
+    }
+    if !rest.is_empty() {
+        tokens.push((Token::Text(rest.to_string()), line));
+    }
+    Ok(tokens)
+}
+
+fn parse_tag(content: &str) -> Result<Tag, String> {
+    if content.starts_with('!') {
+        return Ok(Tag::Comment);
+    }
+    let content = content.trim();
+    let mut words = content.split_whitespace();
+    let tag = match (words.next(), words.next()) {
+        (Some("#if"), Some(name)) => Tag::If(name.to_string()),
+        (Some("#each"), Some(name)) => Tag::Each(name.to_string()),
+        (Some("else"), None) => Tag::Else,
+        (Some("/if"), None) => Tag::EndIf,
+        (Some("/each"), None) => Tag::EndEach,
+        (Some(name), None) if !name.starts_with(['#', '/']) => Tag::Variable(name.to_string()),
+        _ => return Err(format!("unknown tag `{{{{{content}}}}}`")),
+    };
+    if words.next().is_some() {
+        return Err(format!("unknown tag `{{{{{content}}}}}`"));
+    }
+    Ok(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn render(text: &str, context: Value) -> Result<String, TemplateError> {
+        ReadmeTemplate::parse("test template", text)?.render(&context)
+    }
+
+    fn parse_error(text: &str) -> String {
+        ReadmeTemplate::parse("test template", text)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        for (text, expected) in [
+            (
+                "# Title\n\n{{#if air}}\nAIR\n",
+                "test template, line 3: {{#if air}} is never closed",
+            ),
+            (
+                "line\n{{#each files}}\n{{name}}\n{{/if}}\n",
+                "test template, line 4: {{/if}} does not close {{#each files}} opened on line 2",
+            ),
+            (
+                "a\nb\n{{/each}}",
+                "test template, line 3: {{/each}} without an opening tag",
+            ),
+            (
+                "{{#if a}}\n{{else}}\n{{else}}\n{{/if}}",
+                "test template, line 3: {{else}} does not close {{#if a}} opened on line 1",
+            ),
+            (
+                "a\n\nb {{name",
+                "test template, line 3: `{{` is never closed by `}}`",
+            ),
+            (
+                "{{#unless a}}",
+                "test template, line 1: unknown tag `{{#unless a}}`",
+            ),
+            (
+                "\n{{model name}}",
+                "test template, line 2: unknown tag `{{model name}}`",
+            ),
+            (
+                "{{! a\nlong\ncomment }}\n{{/if}}",
+                "test template, line 4: {{/if}} without an opening tag",
+            ),
+        ] {
+            assert_eq!(parse_error(text), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn render_errors_name_the_line() {
+        let error = render("a\n\n{{missing}}", json!({})).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test template, line 3: unknown variable `missing`"
+        );
+        let error = render("{{files}}", json!({ "files": [] })).unwrap_err();
+        assert!(error.to_string().contains("use {{#each files}}"), "{error}");
+        let error = render("\n{{#each name}}{{/each}}", json!({ "name": "x" })).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test template, line 2: `name` is not a list"
+        );
+    }
+
+    #[test]
+    fn if_and_each_blocks_take_their_whole_lines() {
+        let text = "\
+{{#if words}}
+Words:
+{{#each words}}
+- {{this}}
+{{/each}}
+{{else}}
+No words.
+{{/if}}
+{{#each files}}
+{{name}}: {{size}} ({{model}})
+{{/each}}
+End";
+        let context = json!({
+            "model": "LoRA",
+            "words": ["a", "b"],
+            "files": [{ "name": "x.pt", "size": 2 }, { "name": "y.pt", "size": 3 }],
+        });
+        assert_eq!(
+            render(text, context).unwrap(),
+            "Words:\n- a\n- b\nx.pt: 2 (LoRA)\ny.pt: 3 (LoRA)\nEnd"
+        );
+        let context = json!({ "model": "LoRA", "words": [], "files": null });
+        assert_eq!(render(text, context).unwrap(), "No words.\nEnd");
+        // 行内的块标签不移除换行
+        assert_eq!(
+            render(
+                "### {{name}}{{#if date}} ({{date}}){{/if}}\n",
+                json!({ "name": "v1", "date": "" })
+            )
+            .unwrap(),
+            "### v1\n"
+        );
+    }
+
+    #[test]
+    fn falsy_values_skip_if_blocks() {
+        for value in [
+            json!(null),
+            json!(false),
+            json!(0),
+            json!("  "),
+            json!([]),
+            json!({}),
+        ] {
+            assert_eq!(
+                render("{{#if v}}yes{{else}}no{{/if}}", json!({ "v": value })).unwrap(),
+                "no"
+            );
+        }
+        for value in [
+            json!(true),
+            json!(1.5),
+            json!("x"),
+            json!([0]),
+            json!({ "a": 1 }),
+        ] {
+            assert_eq!(
+                render("{{#if v}}yes{{else}}no{{/if}}", json!({ "v": value })).unwrap(),
+                "yes"
+            );
+        }
+    }
+
+    #[test]
+    fn built_in_template_reproduces_the_old_layout() {
+        let image = |prompt: Option<&str>| {
+            json!({
+                "url": "https://image.civitai.com/1.png",
+                "positive_prompt": prompt,
+                "negative_prompt": "blurry",
+                "sampler": "Euler a",
+                "scheduler": null,
+                "seed": 42,
+                "steps": 20,
+                "cfg_scale": "7.00",
+                "truncated": false,
+                "page_url": null,
+                "similar": 0,
+            })
+        };
+        let context = json!({
+            "model_name": "Fixture LoRA",
+            "model_url": "https://civitai.com/models/1?modelVersionId=10",
+            "model_description": "A model.",
+            "version_name": "v1",
+            "version_description": "First version.",
+            "dates": "Published: 2024-01-01 · Updated: 2024-02-01",
+            "air": "urn:air:sdxl:lora:civitai:1@10",
+            "stats": "",
+            "permissions": [{ "label": "Commercial use", "value": "Image" }],
+            "cover": "![](./fixture.cover.png)",
+            "trained_words": ["fixture style", "another"],
+            "files": [{
+                "name": "fixture.safetensors",
+                "size": "2.00 KB",
+                "pickle_scan": "Success",
+                "virus_scan": "-",
+            }],
+            "archive_contents": null,
+            "other_versions": [
+                { "name": "v0", "published": "2023-12-01", "description": "Older." },
+                { "name": "v-1", "published": null, "description": null },
+            ],
+            "version_images": [image(Some("a fixture")), image(None)],
+            "community_images": [image(Some("a community image"))],
+            "community_images_omitted": 0,
+        });
+        let prompt = |prompt: &str| {
+            format!(
+                "===\n\n[Click to view sample image](https://image.civitai.com/1.png)\n\n\
+                 **Positive Prompt:**\n\n{prompt}\n\n**Negative Prompt:**\n\nblurry\n\n\
+                 **Sampler:** Euler a\n\n**Seed:** 42\n\n**Steps:** 20\n\n\
+                 **CFG Scale:** 7.00\n\n===\n\n"
+            )
+        };
+        // 旧版直接写入的排版，许可一节是之后加入的
+        let expected = [
+            "# Fixture LoRA\n\n",
+            "[View on Civitai](https://civitai.com/models/1?modelVersionId=10)\n\n",
+            "A model.",
+            "\n\n## Version: v1\n\n",
+            "Published: 2024-01-01 · Updated: 2024-02-01\n\n",
+            "AIR: `urn:air:sdxl:lora:civitai:1@10`\n\n",
+            "![](./fixture.cover.png)\n\n",
+            "First version.",
+            "\n\n",
+            "## Trained Words\n\n- fixture style\n- another\n",
+            "\n## Files\n\n| File | Size | Pickle Scan | Virus Scan |\n| --- | --- | --- | --- |\n",
+            "| fixture.safetensors | 2.00 KB | Success | - |\n",
+            "\n",
+            "## License / Permissions\n\n- Commercial use: Image\n\n",
+            "## Other versions\n\n",
+            "### v0 (2023-12-01)\n\nOlder.\n\n",
+            "### v-1\n\n",
+            "## Cover image prompts\n\n",
+            &prompt("a fixture"),
+            "## Community image prompts\n\n",
+            &prompt("a community image"),
+        ]
+        .concat();
+        let template = ReadmeTemplate::parse("built-in", DEFAULT_README_TEMPLATE).unwrap();
+        assert_eq!(template.render(&context).unwrap(), expected);
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use reqwest::Url;
+use time::{PrimitiveDateTime, UtcDateTime, macros::format_description};
+
+/// Pre-signed storage URLs of model files by file id, resolved from Civitai's download redirects
+/// in this run. Retries, resumes and repeated downloads of a file reuse them until they expire.
+static RESOLVED_URLS: LazyLock<Mutex<HashMap<u64, ResolvedUrl>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Seconds before the expiry a URL stops being reused, so that a request never starts on a URL
+/// about to expire.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+#[derive(Debug, Clone)]
+struct ResolvedUrl {
+    url: Url,
+    /// Unix timestamp the signature of the URL expires at.
+    expires_at: i64,
+}
+
+/// Expiry of a pre-signed URL, from `X-Amz-Date` and `X-Amz-Expires` of S3 signature v4, or
+/// `Expires` of signature v2 and CloudFront.
+fn expires_at(url: &Url) -> Option<i64> {
+    let query = url
+        .query_pairs()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.into_owned()))
+        .collect::<HashMap<_, _>>();
+    if let (Some(signed_at), Some(expires_in)) =
+        (query.get("x-amz-date"), query.get("x-amz-expires"))
+    {
+        let signed_at = PrimitiveDateTime::parse(
+            signed_at,
+            format_description!("[year][month][day]T[hour][minute][second]Z"),
+        )
+        .ok()?
+        .as_utc();
+        return Some(signed_at.unix_timestamp() + expires_in.parse::<i64>().ok()?);
+    }
+    query.get("expires")?.parse::<i64>().ok()
+}
+
+/// Remembers the URL a file download was redirected to. URLs without a known expiry are not
+/// kept, they may not be valid for long.
+pub fn remember(file_id: u64, url: &Url) {
+    let Some(expires_at) = expires_at(url) else {
+        return;
+    };
+    if let Ok(mut urls) = RESOLVED_URLS.lock() {
+        urls.insert(
+            file_id,
+            ResolvedUrl {
+                url: url.clone(),
+                expires_at,
+            },
+        );
+    }
+}
+
+/// Resolved URL of the file that is still valid, expired ones are dropped.
+pub fn lookup(file_id: u64) -> Option<Url> {
+    let mut urls = RESOLVED_URLS.lock().ok()?;
+    let resolved = urls.get(&file_id)?;
+    if UtcDateTime::now().unix_timestamp() + EXPIRY_MARGIN_SECS < resolved.expires_at {
+        return Some(resolved.url.clone());
+    }
+    urls.remove(&file_id);
+    None
+}
+
+/// Drops the resolved URL of the file, e.g. when the storage refuses it.
+pub fn forget(file_id: u64) {
+    if let Ok(mut urls) = RESOLVED_URLS.lock() {
+        urls.remove(&file_id);
+    }
+}
@@ -0,0 +1,307 @@
+//! Guards against changes of Civitai API responses. The accessors of the metadata wrappers read
+//! fields by index and expect them in the types checked here, so a changed response fails once
+//! with a clear error instead of panicking somewhere later.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+use time::UtcDateTime;
+
+use crate::{
+    cache_db,
+    errors::{CivitaiParseError, SchemaMismatchError},
+    events,
+    utils::sanitize_file_name,
+};
+
+/// Headers announcing that the requested API is deprecated and when it goes away.
+const DEPRECATION_HEADERS: [&str; 2] = ["deprecation", "sunset"];
+/// Headers carrying the version of the API that answered.
+const VERSION_HEADERS: [&str; 2] = ["api-version", "x-api-version"];
+
+static DEPRECATION_REPORTED: AtomicBool = AtomicBool::new(false);
+static VERSION_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// JSON type a field is read as.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum FieldKind {
+    Integer,
+    Number,
+    String,
+    Bool,
+    Array,
+}
+
+impl FieldKind {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Self::Integer => value.is_u64(),
+            Self::Number => value.is_number(),
+            Self::String => value.is_string(),
+            Self::Bool => value.is_boolean(),
+            Self::Array => value.is_array(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Integer => "an integer",
+            Self::Number => "a number",
+            Self::String => "a string",
+            Self::Bool => "a boolean",
+            Self::Array => "an array",
+        }
+    }
+}
+
+/// Checks the fields are present and in the expected types.
+pub(super) fn check_fields(
+    value: &Value,
+    struct_name: &str,
+    fields: &[(&str, FieldKind)],
+) -> Result<(), CivitaiParseError> {
+    for (field, kind) in fields.iter() {
+        let field_value = &value[field];
+        if field_value.is_null() {
+            return Err(CivitaiParseError::MissingRequiredField(
+                struct_name.to_string(),
+                field.to_string(),
+            ));
+        }
+        if !kind.matches(field_value) {
+            return Err(CivitaiParseError::InvalidFieldValue(
+                struct_name.to_string(),
+                format!("{field} is expected to be {}", kind.name()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks every item of an array field, a missing field is left to the check of its owner.
+fn check_items<F>(value: &Value, field: &str, check: F) -> Result<(), CivitaiParseError>
+where
+    F: Fn(&Value) -> Result<(), CivitaiParseError>,
+{
+    value[field]
+        .as_array()
+        .map(|items| items.iter().try_for_each(check))
+        .unwrap_or(Ok(()))
+}
+
+/// Response of `models/{id}` and items of `models`, with the versions listed in it.
+pub(super) fn check_model(value: &Value) -> Result<(), CivitaiParseError> {
+    super::model::Model::check(value)?;
+    check_items(
+        value,
+        "modelVersions",
+        super::model::ModelVersionBrief::check,
+    )
+}
+
+/// Response of `model-versions/{id}` and `model-versions/by-hash/{hash}`, with its files that
+/// downloads and hash lookups rely on and its images that covers are picked from.
+pub(super) fn check_model_version(value: &Value) -> Result<(), CivitaiParseError> {
+    super::model::ModelVersion::check(value)?;
+    check_items(value, "files", super::model::ModelVersionFile::check)?;
+    check_items(value, "images", super::model::ModelImage::check)
+}
+
+/// Items of `images`.
+pub(super) fn check_community_image(value: &Value) -> Result<(), CivitaiParseError> {
+    super::model::ModelCommunityImage::check(value)
+}
+
+/// Response of `images`, a page of community images.
+pub(super) fn check_community_images(value: &Value) -> Result<(), CivitaiParseError> {
+    check_fields(value, "CommunityImages", &[("items", FieldKind::Array)])?;
+    check_items(value, "items", check_community_image)
+}
+
+/// Runs the check of the endpoint on its response. A mismatch fails with an error naming the
+/// endpoint and the field, and at `-v` the response is saved under the cache directory to be
+/// attached to a bug report.
+pub(super) fn guard<F>(value: &Value, endpoint: &str, check: F) -> Result<()>
+where
+    F: Fn(&Value) -> Result<(), CivitaiParseError>,
+{
+    let Err(e) = check(value) else {
+        return Ok(());
+    };
+    if events::is_verbose(1) {
+        match dump_response(value, endpoint) {
+            Ok(path) => events::message(format!(
+                "Response of {endpoint} is saved to {}, please attach it to the bug report.",
+                path.display()
+            )),
+            Err(dump_error) => events::message(format!(
+                "Failed to save response of {endpoint}: {dump_error}"
+            )),
+        }
+    }
+    Err(SchemaMismatchError {
+        endpoint: endpoint.to_string(),
+        reason: e.to_string(),
+    }
+    .into())
+}
+
+fn dump_response(value: &Value, endpoint: &str) -> Result<std::path::PathBuf> {
+    let debug_dir = cache_db::cache_dir()
+        .ok_or(anyhow::anyhow!("Failed to get cache directory"))?
+        .join("debug");
+    std::fs::create_dir_all(&debug_dir)?;
+    let file_name = sanitize_file_name(&format!(
+        "{}-{}.json",
+        UtcDateTime::now().unix_timestamp(),
+        endpoint.replace('/', "-")
+    ));
+    let path = debug_dir.join(file_name);
+    std::fs::write(&path, serde_json::to_vec_pretty(value)?)?;
+    Ok(path)
+}
+
+/// Prints the deprecation notice Civitai sends with its responses once in a run, and at `-v`
+/// the API version that answered.
+pub(super) fn report_api_headers(headers: &HeaderMap) {
+    let header_values = |names: &[&str]| {
+        names
+            .iter()
+            .filter_map(|name| {
+                headers
+                    .get(*name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| format!("{name}: {value}"))
+            })
+            .collect::<Vec<_>>()
+    };
+    let deprecation = header_values(&DEPRECATION_HEADERS);
+    if !deprecation.is_empty() && !DEPRECATION_REPORTED.swap(true, Ordering::Relaxed) {
+        events::message(format!(
+            "WARNING: Civitai marks its API in use as deprecated ({}), imd may need an update.",
+            deprecation.join(", ")
+        ));
+    }
+    let version = header_values(&VERSION_HEADERS);
+    if !version.is_empty()
+        && events::is_verbose(1)
+        && !VERSION_REPORTED.swap(true, Ordering::Relaxed)
+    {
+        events::message(format!("Civitai API version: {}", version.join(", ")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Fixture shared with the integration tests, shaped like the real responses.
+    fn fixture(name: &str) -> Value {
+        let text = match name {
+            "model" => include_str!("../../tests/fixtures/civitai/model.json"),
+            "version" => include_str!("../../tests/fixtures/civitai/version.json"),
+            "images" => include_str!("../../tests/fixtures/civitai/images.json"),
+            _ => unreachable!(),
+        };
+        serde_json::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn fixtures_pass_their_checks() {
+        check_model(&fixture("model")).unwrap();
+        check_model_version(&fixture("version")).unwrap();
+        check_community_images(&fixture("images")).unwrap();
+    }
+
+    #[test]
+    fn missing_fields_are_named() {
+        for (mut value, pointer, check, expected) in [
+            (
+                fixture("model"),
+                "",
+                check_model as fn(&Value) -> Result<(), CivitaiParseError>,
+                "Missing required field in Model: modelVersions",
+            ),
+            (
+                fixture("model"),
+                "/modelVersions/0",
+                check_model,
+                "Missing required field in ModelVersionBrief: index",
+            ),
+            (
+                fixture("version"),
+                "/files/0",
+                check_model_version,
+                "Missing required field in ModelVersionFile: sizeKB",
+            ),
+            (
+                fixture("version"),
+                "/images/0",
+                check_model_version,
+                "Missing required field in ModelImage: type",
+            ),
+            (
+                fixture("images"),
+                "/items/0",
+                check_community_images,
+                "Missing required field in ModelCommunityImage: url",
+            ),
+        ] {
+            let field = expected.rsplit(": ").next().unwrap();
+            value
+                .pointer_mut(pointer)
+                .and_then(Value::as_object_mut)
+                .unwrap()
+                .remove(field);
+            assert_eq!(check(&value).unwrap_err().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn changed_field_types_are_named() {
+        let mut model = fixture("model");
+        model["id"] = json!("1");
+        assert_eq!(
+            check_model(&model).unwrap_err().to_string(),
+            "Invalid field value in Model: id is expected to be an integer"
+        );
+        let mut version = fixture("version");
+        version["files"][0]["sizeKB"] = json!("2 KB");
+        assert_eq!(
+            check_model_version(&version).unwrap_err().to_string(),
+            "Invalid field value in ModelVersionFile: sizeKB is expected to be a number"
+        );
+        let mut images = fixture("images");
+        images["items"] = json!({});
+        assert_eq!(
+            check_community_images(&images).unwrap_err().to_string(),
+            "Invalid field value in CommunityImages: items is expected to be an array"
+        );
+    }
+
+    #[test]
+    fn guard_names_the_endpoint() {
+        let mut version = fixture("version");
+        version["files"] = json!(null);
+        let error = guard(&version, "model-versions/10", check_model_version).unwrap_err();
+        let mismatch = error.downcast_ref::<SchemaMismatchError>().unwrap();
+        assert_eq!(mismatch.endpoint, "model-versions/10");
+        assert_eq!(
+            mismatch.reason,
+            "Missing required field in ModelVersion: files"
+        );
+        assert!(error.to_string().contains("try updating imd"));
+        assert!(
+            guard(
+                &fixture("version"),
+                "model-versions/10",
+                check_model_version
+            )
+            .is_ok()
+        );
+    }
+}
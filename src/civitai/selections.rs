@@ -1,14 +1,26 @@
-use std::path::Path;
+use std::{fmt::Display, io::IsTerminal, path::Path};
 
-use dialoguer::{MultiSelect, Select};
+use anyhow::{Context, anyhow, bail};
+use dialoguer::{Confirm, FuzzySelect, Input, MultiSelect, Select};
 
-use super::{ModelVersionBrief, ModelVersionFile, model};
+use crate::{
+    errors::{EarlyAccessOnlyError, NoDownloadableVersionError},
+    events, prompt,
+    utils::{
+        bytes_to_human_string, datetime_to_date_string, format_age, kilobytes_to_human_string,
+    },
+};
+
+use super::{ImageMeta, ModelVersionBrief, ModelVersionFile, model};
+
+/// Lists longer than this can be narrowed down by typing.
+const FILTERABLE_LENGTH: usize = 7;
 
 struct DownloadChoice(u64, String);
 
-impl ToString for DownloadChoice {
-    fn to_string(&self) -> String {
-        self.1.clone()
+impl Display for DownloadChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.1)
     }
 }
 
@@ -18,57 +30,440 @@ impl From<(u64, String)> for DownloadChoice {
     }
 }
 
-pub fn select_model_version(
+/// How the model version to download is determined.
+#[derive(Debug, Clone, Default)]
+pub struct VersionSelection {
+    /// Version preselected in the prompt, usually parsed from the model URL.
+    pub preferred_id: Option<u64>,
+    pub skip_early_access: bool,
+    /// Pick the newest version without prompting.
+    pub latest: bool,
+    /// Pick the newest version whose name contains this pattern without prompting.
+    pub name_pattern: Option<String>,
+    /// Versions explicitly requested, no prompting when given.
+    pub ids: Vec<u64>,
+    /// Pick all versions without prompting.
+    pub all: bool,
+    /// Allow selecting multiple versions in the prompt.
+    pub multi: bool,
+}
+
+fn version_choice_labels(versions: &[&ModelVersionBrief]) -> Vec<String> {
+    let rows = versions
+        .iter()
+        .map(|v| {
+            [
+                v.choice().1,
+                v.base_model().unwrap_or_default(),
+                v.released_at()
+                    .map(|d| format!("{} ({})", datetime_to_date_string(&d), format_age(&d)))
+                    .unwrap_or_default(),
+                kilobytes_to_human_string(v.total_size_kb()),
+                v.download_count()
+                    .map(|c| format!("{c} downloads"))
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    align_columns(&rows, [false, false, false, true, true])
+}
+
+/// Pads the columns of every row to the same width, numbers are right aligned.
+fn align_columns<const N: usize>(rows: &[[String; N]], right_aligned: [bool; N]) -> Vec<String> {
+    let mut widths = [0usize; N];
+    for row in rows.iter() {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(widths.iter().zip(right_aligned.iter()))
+                .map(|(column, (width, right))| {
+                    if *right {
+                        format!("{column:>width$}")
+                    } else {
+                        format!("{column:<width$}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+        })
+        .collect()
+}
+
+/// One line per model with its type, and the base model and size of its newest version.
+pub fn model_summary_rows(models: &[model::Model]) -> Vec<String> {
+    let rows = models
+        .iter()
+        .map(|m| {
+            let versions = m.versions().unwrap_or_default();
+            let newest = versions.iter().find(|v| v.has_files());
+            [
+                m.name(),
+                m.model_type().unwrap_or_default(),
+                newest.and_then(|v| v.base_model()).unwrap_or_default(),
+                newest
+                    .map(|v| kilobytes_to_human_string(v.total_size_kb()))
+                    .unwrap_or_default(),
+                match versions.len() {
+                    1 => "1 version".to_string(),
+                    count => format!("{count} versions"),
+                },
+            ]
+        })
+        .collect::<Vec<_>>();
+    align_columns(&rows, [false, false, false, true, true])
+}
+
+/// One line per image of a version with its position, media type, size and file name.
+pub fn cover_choice_labels(images: &[model::ModelImage]) -> Vec<String> {
+    let rows = images
+        .iter()
+        .enumerate()
+        .map(|(index, image)| {
+            let url = image.url();
+            [
+                format!("#{}", index + 1),
+                image.media_type(),
+                match (image.width(), image.height()) {
+                    (Some(width), Some(height)) => format!("{width}x{height}"),
+                    _ => String::new(),
+                },
+                url.rsplit('/').next().unwrap_or(&url).to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    align_columns(&rows, [true, false, true, false])
+}
+
+/// Prompts for the cover image from a plain list, the first image is the default.
+pub fn select_cover(labels: &[String]) -> anyhow::Result<usize> {
+    let choices = labels
+        .iter()
+        .enumerate()
+        .map(|(index, label)| DownloadChoice(index as u64, label.clone()))
+        .collect::<Vec<_>>();
+    select_one("Select the cover image", &choices, 0)
+}
+
+/// Prompts for the models to download from a list, like the models of a user.
+pub fn select_models(models: &[model::Model]) -> anyhow::Result<Vec<usize>> {
+    let choices = models
+        .iter()
+        .zip(model_summary_rows(models))
+        .map(|(m, label)| DownloadChoice(m.id(), label))
+        .collect::<Vec<_>>();
+    let selected = select_many(
+        "Select models to download",
+        &choices,
+        &vec![false; choices.len()],
+    )?;
+    if selected.is_empty() {
+        bail!("No model selected");
+    }
+    Ok(selected)
+}
+
+fn ensure_interactive() -> anyhow::Result<()> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        bail!("Selection requires an interactive terminal");
+    }
+    Ok(())
+}
+
+/// Prompts for one of the choices, long lists are filtered by fuzzy matching the typed text.
+fn select_one(prompt: &str, choices: &[DownloadChoice], default: usize) -> anyhow::Result<usize> {
+    ensure_interactive()?;
+    let prompt = prompt.to_string();
+    let labels = choices.iter().map(|c| c.1.clone()).collect::<Vec<_>>();
+    let default_label = labels.get(default).cloned().unwrap_or_default();
+    prompt::interact(
+        move || {
+            if labels.len() > FILTERABLE_LENGTH {
+                FuzzySelect::new()
+                    .with_prompt(format!("{prompt}(type to filter) "))
+                    .max_length(FILTERABLE_LENGTH)
+                    .items(&labels)
+                    .default(default)
+                    .interact()
+            } else {
+                Select::new()
+                    .with_prompt(prompt)
+                    .items(&labels)
+                    .default(default)
+                    .interact()
+            }
+        },
+        default,
+        default_label.trim_end(),
+    )
+    .context("Failed to read selection")
+}
+
+/// Prompts for some of the choices, long lists are narrowed down by a filter text first.
+fn select_many(
+    prompt: &str,
+    choices: &[DownloadChoice],
+    defaults: &[bool],
+) -> anyhow::Result<Vec<usize>> {
+    ensure_interactive()?;
+    let visible_indexes = if choices.len() > FILTERABLE_LENGTH {
+        loop {
+            let filter = prompt::interact(
+                || {
+                    Input::<String>::new()
+                        .with_prompt("Filter the list, leave empty to show all")
+                        .allow_empty(true)
+                        .interact_text()
+                },
+                String::new(),
+                "the whole list",
+            )?;
+            let filter = filter.trim().to_lowercase();
+            let matched = choices
+                .iter()
+                .enumerate()
+                .filter(|(_, choice)| choice.1.to_lowercase().contains(&filter))
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>();
+            if !matched.is_empty() {
+                break matched;
+            }
+            println!("Nothing matches \"{filter}\", please try again.");
+        }
+    } else {
+        (0..choices.len()).collect()
+    };
+    let visible_labels = visible_indexes
+        .iter()
+        .map(|index| choices[*index].1.clone())
+        .collect::<Vec<_>>();
+    let visible_defaults = visible_indexes
+        .iter()
+        .map(|index| defaults.get(*index).copied().unwrap_or_default())
+        .collect::<Vec<_>>();
+    let default_selected = (0..visible_indexes.len())
+        .filter(|index| visible_defaults[*index])
+        .collect::<Vec<_>>();
+    let default_label = default_selected
+        .iter()
+        .map(|index| visible_labels[*index].trim_end())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let prompt = prompt.to_string();
+    let selected = prompt::interact(
+        move || {
+            MultiSelect::new()
+                .with_prompt(prompt)
+                .max_length(FILTERABLE_LENGTH)
+                .items(&visible_labels)
+                .defaults(&visible_defaults)
+                .interact()
+        },
+        default_selected,
+        &default_label,
+    )
+    .context("Failed to read selection")?;
+    Ok(selected
+        .into_iter()
+        .map(|index| visible_indexes[index])
+        .collect())
+}
+
+pub fn select_model_versions(
     model_meta: &model::Model,
-    default_choice_id: Option<u64>,
-) -> anyhow::Result<u64> {
-    let version_choices = model_meta
-        .versions()?
+    selection: &VersionSelection,
+) -> anyhow::Result<Vec<u64>> {
+    let versions = model_meta.versions()?;
+    // 被下架的模型仍然有元数据，但没有可下载的版本或文件
+    if !versions.iter().any(ModelVersionBrief::has_files) {
+        return Err(NoDownloadableVersionError(model_meta.id()).into());
+    }
+    let early_access_ids = versions
         .iter()
-        .map(ModelVersionBrief::choice)
-        .map(DownloadChoice::from)
+        .filter(|v| v.is_early_access())
+        .map(ModelVersionBrief::id)
         .collect::<Vec<_>>();
+    let mut candidates = versions
+        .iter()
+        .filter(|v| !selection.skip_early_access || !v.is_early_access())
+        .collect::<Vec<_>>();
+    // 最新发布的版本排在最前，没有时间的版本保持原有顺序排在最后
+    candidates.sort_by_key(|v| std::cmp::Reverse(v.released_at()));
+    if candidates.is_empty() {
+        return Err(EarlyAccessOnlyError(model_meta.id()).into());
+    }
+    let early_access_fallback = selection.skip_early_access
+        && selection
+            .preferred_id
+            .is_some_and(|default_choice| early_access_ids.contains(&default_choice));
+    if early_access_fallback && let Some(default_choice) = selection.preferred_id {
+        events::message(format!(
+            "Version {default_choice} is in early access, fall back to the newest non-early-access version."
+        ));
+    }
 
-    let default_choice_index = if let Some(default_choice) = default_choice_id {
-        version_choices
+    let selected_version_ids = if !selection.ids.is_empty() {
+        for id in selection.ids.iter() {
+            if !versions.iter().any(|v| v.id() == *id) {
+                bail!("Version {id} does not belong to this model");
+            }
+        }
+        selection.ids.clone()
+    } else if selection.all {
+        candidates.iter().map(|v| v.id()).collect()
+    } else if let Some(pattern) = selection.name_pattern.as_ref() {
+        let pattern = pattern.to_lowercase();
+        candidates
             .iter()
-            .position(|choice| choice.0 == default_choice)
-            .unwrap_or(0)
+            .find(|v| v.name().to_lowercase().contains(&pattern))
+            .map(|v| vec![v.id()])
+            .ok_or(anyhow!("No version name matches \"{pattern}\""))?
+    } else if selection.latest || early_access_fallback {
+        vec![candidates[0].id()]
     } else {
-        0
+        let version_choices = candidates
+            .iter()
+            .map(|v| v.id())
+            .zip(version_choice_labels(&candidates))
+            .map(DownloadChoice::from)
+            .collect::<Vec<_>>();
+
+        let default_choice_index = if let Some(default_choice) = selection.preferred_id {
+            version_choices
+                .iter()
+                .position(|choice| choice.0 == default_choice)
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        if selection.multi {
+            let defaults = (0..version_choices.len())
+                .map(|index| index == default_choice_index)
+                .collect::<Vec<_>>();
+            let selected_versions = select_many(
+                "Select the versions of model to download ",
+                &version_choices,
+                &defaults,
+            )?;
+            if selected_versions.is_empty() {
+                bail!("No version selected");
+            }
+            selected_versions
+                .iter()
+                .map(|index| version_choices[*index].0)
+                .collect()
+        } else {
+            let interact_selection = select_one(
+                "Select the version of model to download ",
+                &version_choices,
+                default_choice_index,
+            )?;
+
+            vec![version_choices[interact_selection].0]
+        }
     };
 
-    let interact_selection = Select::new()
-        .with_prompt("Select the version of model to download ")
-        .max_length(7)
-        .items(&version_choices)
-        .default(default_choice_index)
-        .interact()
-        .unwrap();
+    if selected_version_ids
+        .iter()
+        .any(|id| early_access_ids.contains(id))
+    {
+        events::message(
+            "WARNING: The selected version is in early access, downloading it requires a Civitai account that has purchased early access or supports the creator.",
+        );
+    }
+    Ok(selected_version_ids)
+}
 
-    let selected_version_id = version_choices[interact_selection].0;
-    Ok(selected_version_id)
+pub fn select_image_resource(
+    versions: &[model::ModelVersion],
+) -> anyhow::Result<&model::ModelVersion> {
+    let resource_choices = versions
+        .iter()
+        .map(|v| {
+            (
+                v.id(),
+                format!(
+                    "{} - {}",
+                    v.model_name().unwrap_or(format!("Model {}", v.model_id())),
+                    v.name()
+                ),
+            )
+        })
+        .map(DownloadChoice::from)
+        .collect::<Vec<_>>();
+
+    if events::enabled() {
+        if versions.len() > 1 {
+            bail!(
+                "Image uses {} models, which one to download can not be decided without prompting",
+                versions.len()
+            );
+        }
+        return Ok(&versions[0]);
+    }
+    let interact_selection = select_one(
+        "Select the model used by image to download ",
+        &resource_choices,
+        0,
+    )?;
+
+    Ok(&versions[interact_selection])
 }
 
+/// Prompts for the files to download, the preferred ones or the primary file checked by default.
 pub fn select_model_version_files(
     selected_version: &model::ModelVersion,
+    preferred_file_ids: Option<&[u64]>,
 ) -> anyhow::Result<Vec<u64>> {
-    let file_choices = selected_version
+    // 仍在处理中的文件不能下载，不放入选择列表
+    let (processing_files, files): (Vec<_>, Vec<_>) = selected_version
         .files()?
+        .into_iter()
+        .partition(ModelVersionFile::is_processing);
+    for file in processing_files.iter() {
+        events::message(format!("Left out {}.", file.processing_label()));
+    }
+    let file_choices = files
         .iter()
         .map(ModelVersionFile::choice)
         .map(DownloadChoice::from)
         .collect::<Vec<_>>();
 
+    if file_choices.is_empty() && !processing_files.is_empty() {
+        bail!(
+            "Every file of version {} is still being processed by Civitai, try again later",
+            selected_version.name()
+        );
+    }
+    if file_choices.is_empty() {
+        bail!("Version {} has no file", selected_version.name());
+    }
     if file_choices.len() == 1 {
         return Ok(file_choices.iter().map(|choice| choice.0).collect());
     }
+    // 不能交互时只下载主文件
+    if events::enabled() {
+        if let Some(preferred_file_ids) = preferred_file_ids {
+            return Ok(preferred_file_ids.to_vec());
+        }
+        let primary_file = files
+            .iter()
+            .find(|file| file.is_primary().unwrap_or_default())
+            .unwrap_or(&files[0]);
+        return Ok(vec![primary_file.id()]);
+    }
     let defaultes = file_choices
         .iter()
         .map(|choice| {
-            selected_version
-                .files()
-                .unwrap_or_default()
+            if let Some(preferred_file_ids) = preferred_file_ids {
+                return preferred_file_ids.contains(&choice.0);
+            }
+            files
                 .iter()
                 .find(|file| file.id() == choice.0)
                 .and_then(|file| file.is_primary())
@@ -76,13 +471,10 @@ pub fn select_model_version_files(
         })
         .collect::<Vec<_>>();
 
-    let selected_files = MultiSelect::new()
-        .with_prompt("Select files to download ")
-        .max_length(7)
-        .items(&file_choices)
-        .defaults(defaultes.as_slice())
-        .interact()
-        .unwrap();
+    let selected_files = select_many("Select files to download ", &file_choices, &defaultes)?;
+    if selected_files.is_empty() {
+        bail!("No file selected");
+    }
 
     Ok(selected_files
         .iter()
@@ -90,22 +482,177 @@ pub fn select_model_version_files(
         .collect())
 }
 
-pub fn decide_proceeding_or_not<P: AsRef<Path>>(exists_file_location: P) -> bool {
-    let choices = vec!["Yes", "No"];
-    let default_choice: usize = 1;
+/// Picks one of the versions having the same file, the newest one is taken when no one can
+/// answer.
+pub fn select_hash_version(versions: &[ModelVersionBrief]) -> usize {
+    if ensure_interactive().is_err() {
+        return 0;
+    }
+    let versions = versions.iter().collect::<Vec<_>>();
+    let version_choices = versions
+        .iter()
+        .map(|v| v.id())
+        .zip(version_choice_labels(&versions))
+        .map(DownloadChoice::from)
+        .collect::<Vec<_>>();
+    select_one(
+        "The file belongs to several versions, select the one to describe it ",
+        &version_choices,
+        0,
+    )
+    .unwrap_or(0)
+}
+
+/// What to do with a file that has been downloaded to another location before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingFileAction {
+    /// Hardlink or copy the existing file into the destination.
+    Link,
+    Redownload,
+    /// Redownload this file and every later one of the run without asking.
+    RedownloadAll,
+    Skip,
+    /// Skip the file and never ask about it again.
+    SkipForever,
+}
+
+/// Asks what to do with a file found downloaded before. `None` when no answer was given, because
+/// the prompt timed out or could not read the terminal, and the file is skipped.
+pub fn decide_existing_file_action<P: AsRef<Path>>(
+    exists_file_location: P,
+) -> Option<ExistingFileAction> {
+    let choices = vec![
+        "Link or copy it here",
+        "Redownload it",
+        "Redownload it and every other file downloaded before",
+        "Skip it",
+        "Skip it, never ask again for this file",
+    ];
+    let actions = [
+        ExistingFileAction::Link,
+        ExistingFileAction::Redownload,
+        ExistingFileAction::RedownloadAll,
+        ExistingFileAction::Skip,
+        ExistingFileAction::SkipForever,
+    ];
+    let default_choice: usize = 3;
     let file_path = exists_file_location.as_ref();
     let file_name = file_path.file_name().unwrap().to_string_lossy();
     let file_location = file_path.parent().unwrap().to_string_lossy();
+    let prompt = format!("File {file_name} already exists in {file_location}, what to do?");
+
+    let interact_selection = prompt::answer(
+        move || {
+            Select::new()
+                .with_prompt(prompt)
+                .items(&choices)
+                .default(default_choice)
+                .interact()
+        },
+        "to skip it",
+    )
+    .ok()
+    .flatten()?;
+
+    Some(actions[interact_selection])
+}
 
-    let interact_selection = Select::new()
-        .with_prompt(format!(
-            "File {} already exists in {}, redownload it?",
-            file_name, file_location
-        ))
-        .items(&choices)
-        .default(default_choice)
-        .interact()
-        .unwrap_or(1);
-
-    interact_selection == 0
+/// What to do with the files exceeding the size budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverBudgetAction {
+    /// Download the files fitting in, leave the rest for a later session.
+    WithinBudget,
+    /// Download everything regardless of the budget.
+    Everything,
+    Cancel,
+}
+
+pub fn decide_over_budget_action(planned: u64, remaining: u64) -> OverBudgetAction {
+    let choices = vec![
+        "Download only the files within the budget",
+        "Download everything, exceeding the budget",
+        "Cancel",
+    ];
+    let actions = [
+        OverBudgetAction::WithinBudget,
+        OverBudgetAction::Everything,
+        OverBudgetAction::Cancel,
+    ];
+    let default_choice: usize = 0;
+    let prompt = format!(
+        "The planned files take {}, only {} of the size budget is left, what to do?",
+        bytes_to_human_string(planned),
+        bytes_to_human_string(remaining)
+    );
+
+    let interact_selection = prompt::interact(
+        move || {
+            Select::new()
+                .with_prompt(prompt)
+                .items(&choices)
+                .default(default_choice)
+                .interact()
+        },
+        default_choice,
+        "to download the files within the budget",
+    )
+    .unwrap_or(default_choice);
+
+    actions[interact_selection]
+}
+
+/// Asks whether to shorten file names whose paths would exceed the platform limit, shortening
+/// is taken when no one can answer.
+pub fn decide_shortening_or_not(version_name: &str) -> bool {
+    if ensure_interactive().is_err() {
+        return true;
+    }
+    let prompt = format!("Shorten the file names of version {version_name} to fit?");
+    prompt::interact(
+        move || Confirm::new().with_prompt(prompt).default(true).interact(),
+        true,
+        "to shorten",
+    )
+    .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn early_access_url_version_falls_back_without_prompting() {
+        let model = model::Model::try_from(&json!({
+            "id": 1,
+            "name": "model",
+            "description": "",
+            "modelVersions": [
+                {
+                    "id": 12, "name": "v3", "index": 0,
+                    "publishedAt": "2024-03-01T00:00:00.000Z",
+                    "earlyAccessEndsAt": "2999-01-01T00:00:00.000Z",
+                    "files": [{ "id": 120 }]
+                },
+                {
+                    "id": 11, "name": "v2", "index": 1,
+                    "publishedAt": "2024-02-01T00:00:00.000Z",
+                    "files": [{ "id": 110 }]
+                },
+                {
+                    "id": 10, "name": "v1", "index": 2,
+                    "publishedAt": "2024-01-01T00:00:00.000Z",
+                    "files": [{ "id": 100 }]
+                }
+            ]
+        }))
+        .unwrap();
+        let selection = VersionSelection {
+            preferred_id: Some(12),
+            skip_early_access: true,
+            ..Default::default()
+        };
+        assert_eq!(select_model_versions(&model, &selection).unwrap(), [11]);
+    }
 }
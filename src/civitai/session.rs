@@ -0,0 +1,358 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use indicatif::MultiProgress;
+use serde::{Deserialize, Serialize};
+use time::UtcDateTime;
+
+use crate::{
+    artifacts::{Artifact, ArtifactSet},
+    cache_db,
+    utils::datetime_to_date_string,
+};
+
+use super::plan::{DownloadBehavior, DownloadPlan, FilePlan};
+
+/// Sessions not touched for this long are dropped when sessions are listed.
+const SESSION_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionFileState {
+    Pending,
+    InFlight,
+    Completed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionFile {
+    pub version_id: u64,
+    pub file_id: u64,
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub state: SessionFileState,
+    pub blake3: Option<String>,
+}
+
+/// Record of a model download, kept in cache database until every planned file is downloaded,
+/// so an interrupted download can be resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadSession {
+    pub model_id: u64,
+    pub model_name: String,
+    pub version_ids: Vec<u64>,
+    /// Output directory the download started with.
+    pub destination: Option<PathBuf>,
+    pub skip_community: bool,
+    /// Files produced for the model, sessions recorded before artifacts could be chosen only
+    /// have `skip_community`.
+    #[serde(default)]
+    pub artifacts: Option<ArtifactSet>,
+    pub allow_unsafe: bool,
+    pub folder_per_model: bool,
+    pub started_at: i64,
+    /// Last time the session was saved, sessions recorded before only have `started_at`.
+    #[serde(default)]
+    pub updated_at: i64,
+    pub files: Vec<SessionFile>,
+}
+
+impl DownloadSession {
+    /// Creates a session from the plan, states of files carried over from the resumed session.
+    pub fn from_plan(plan: &DownloadPlan, behavior: &DownloadBehavior) -> Self {
+        let resumed = behavior.resumed.as_ref();
+        let files = plan
+            .versions
+            .iter()
+            .flat_map(|version_plan| {
+                version_plan.files.iter().map(|file_plan| SessionFile {
+                    version_id: version_plan.version.id(),
+                    file_id: file_plan.file.id(),
+                    name: file_plan.file.name(),
+                    path: file_plan.target_path.clone(),
+                    size: file_plan.file.size_in_bytes(),
+                    state: SessionFileState::Pending,
+                    blake3: None,
+                })
+            })
+            .map(|mut file| {
+                if let Some(previous) = resumed.and_then(|s| s.file(file.file_id))
+                    && previous.state == SessionFileState::Completed
+                {
+                    file.state = SessionFileState::Completed;
+                    file.blake3 = previous.blake3.clone();
+                }
+                file
+            })
+            .collect();
+        Self {
+            model_id: plan.model.id(),
+            model_name: plan.model.name(),
+            version_ids: plan.versions.iter().map(|v| v.version.id()).collect(),
+            // 记录解析后的目录，恢复时不再依赖模型界面的目录配置
+            destination: Some(plan.destination.clone()),
+            skip_community: !behavior.artifacts.contains(Artifact::Samples),
+            artifacts: Some(behavior.artifacts),
+            allow_unsafe: behavior.allow_unsafe,
+            folder_per_model: behavior.folder_per_model,
+            started_at: resumed
+                .map(|s| s.started_at)
+                .unwrap_or_else(|| UtcDateTime::now().unix_timestamp()),
+            updated_at: UtcDateTime::now().unix_timestamp(),
+            files,
+        }
+    }
+
+    /// Identifies the session among downloads of the same model, like `123:456,789`.
+    pub fn key(&self) -> String {
+        let mut version_ids = self.version_ids.clone();
+        version_ids.sort_unstable();
+        let version_ids = version_ids
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("{}:{version_ids}", self.model_id)
+    }
+
+    /// Whether the session has not been saved within the session TTL.
+    fn is_expired(&self, now: i64) -> bool {
+        let last_active = self.updated_at.max(self.started_at);
+        now - last_active > SESSION_TTL.as_secs() as i64
+    }
+
+    /// Sessions that can be resumed, expired ones are removed from cache database.
+    pub fn list() -> Result<Vec<Self>> {
+        let now = UtcDateTime::now().unix_timestamp();
+        let (expired, sessions) = cache_db::list_download_sessions()?
+            .into_iter()
+            .partition::<Vec<_>, _>(|session| session.is_expired(now));
+        for session in expired.iter() {
+            cache_db::remove_download_session(session)?;
+        }
+        Ok(sessions)
+    }
+
+    /// Sessions selected by a key, or by a model id for every session of the model.
+    pub fn find(selector: &str) -> Result<Vec<Self>> {
+        let selector = selector.trim();
+        Ok(Self::list()?
+            .into_iter()
+            .filter(|session| session.key() == selector || session.model_id.to_string() == selector)
+            .collect())
+    }
+
+    /// Removes every session from cache database, returns how many were removed.
+    pub fn forget_all() -> Result<usize> {
+        cache_db::remove_download_sessions()
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        self.updated_at = UtcDateTime::now().unix_timestamp();
+        cache_db::store_download_session(self)
+    }
+
+    /// Removes the session from cache database, called after every file is downloaded.
+    pub fn finish(&self) -> Result<()> {
+        cache_db::remove_download_session(self)
+    }
+
+    fn file(&self, file_id: u64) -> Option<&SessionFile> {
+        self.files.iter().find(|f| f.file_id == file_id)
+    }
+
+    pub fn set_state(
+        &mut self,
+        file_id: u64,
+        state: SessionFileState,
+        blake3: Option<String>,
+    ) -> Result<()> {
+        if let Some(file) = self.files.iter_mut().find(|f| f.file_id == file_id) {
+            file.state = state;
+            file.blake3 = blake3;
+        }
+        self.save()
+    }
+
+    /// Whether the file has been downloaded in this session and is still intact, by its size
+    /// and, when recorded, its blake3 hash.
+    pub async fn is_completed(
+        &self,
+        file_plan: &FilePlan,
+        progress: Option<&MultiProgress>,
+    ) -> bool {
+        let Some(file) = self.file(file_plan.file.id()) else {
+            return false;
+        };
+        let intact_size = file.state == SessionFileState::Completed
+            && file_plan
+                .target_path
+                .metadata()
+                .is_ok_and(|meta| meta.len() == file.size);
+        if !intact_size {
+            return false;
+        }
+        match file.blake3.as_deref() {
+            Some(expected) => super::blake3_hash(&file_plan.target_path, progress)
+                .await
+                .is_ok_and(|hash| hash.eq_ignore_ascii_case(expected)),
+            None => true,
+        }
+    }
+
+    /// Behavior resuming this session with the switches it started with.
+    pub fn resume_behavior(&self) -> DownloadBehavior {
+        DownloadBehavior {
+            artifacts: self
+                .artifacts
+                .unwrap_or_else(|| ArtifactSet::standard().skipping_community(self.skip_community)),
+            allow_unsafe: self.allow_unsafe,
+            dry_run: false,
+            folder_per_model: self.folder_per_model,
+            unattended: false,
+            link_existing: false,
+            file_ids: Vec::new(),
+            model_dirs: None,
+            size_budget: None,
+            reporter: Default::default(),
+            resumed: Some(self.clone()),
+            version_history: None,
+            meta_file_name: None,
+            pick_cover: false,
+            prompt_limits: Default::default(),
+            extract_archives: false,
+            remove_archive: false,
+            file_preference: None,
+            commercial_use_policy: None,
+        }
+    }
+
+    /// File IDs planned in this session.
+    pub fn file_ids(&self) -> Vec<u64> {
+        self.files.iter().map(|f| f.file_id).collect()
+    }
+
+    pub fn print(&self) {
+        let started_at = UtcDateTime::from_unix_timestamp(self.started_at)
+            .map(|t| datetime_to_date_string(&t))
+            .unwrap_or_default();
+        let count_of = |state| self.files.iter().filter(|f| f.state == state).count();
+        println!(
+            "{} - {} (started {started_at})",
+            self.key(),
+            self.model_name
+        );
+        println!(
+            "  {} completed, {} interrupted, {} pending, into {}",
+            count_of(SessionFileState::Completed),
+            count_of(SessionFileState::InFlight),
+            count_of(SessionFileState::Pending),
+            self.destination
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or("[CURRENT DIRECTORY]".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(model_id: u64, version_ids: &[u64], updated_at: i64) -> DownloadSession {
+        DownloadSession {
+            model_id,
+            model_name: format!("model {model_id}"),
+            version_ids: version_ids.to_vec(),
+            destination: None,
+            skip_community: false,
+            artifacts: None,
+            allow_unsafe: false,
+            folder_per_model: false,
+            started_at: updated_at,
+            updated_at,
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn key_holds_model_and_sorted_versions() {
+        assert_eq!(session(1, &[30, 20], 0).key(), "1:20,30");
+        assert_eq!(session(1, &[20, 30], 0).key(), "1:20,30");
+    }
+
+    #[test]
+    fn sessions_of_other_versions_are_kept_apart() {
+        let mut first = session(9101, &[1], 0);
+        let mut second = session(9101, &[2], 0);
+        first.save().unwrap();
+        second.save().unwrap();
+
+        assert_eq!(DownloadSession::find("9101").unwrap().len(), 2);
+        let found = DownloadSession::find("9101:2").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].version_ids, [2]);
+
+        first.finish().unwrap();
+        let left = DownloadSession::find("9101").unwrap();
+        assert_eq!(left.iter().map(|s| s.key()).collect::<Vec<_>>(), ["9101:2"]);
+        second.finish().unwrap();
+    }
+
+    #[test]
+    fn expired_sessions_are_dropped_when_listed() {
+        let now = UtcDateTime::now().unix_timestamp();
+        let expired = session(9102, &[1], now - SESSION_TTL.as_secs() as i64 - 1);
+        // 直接写入，保存会刷新更新时间
+        cache_db::store_download_session(&expired).unwrap();
+        assert!(expired.is_expired(now));
+        assert!(!session(9102, &[2], now - 60).is_expired(now));
+
+        assert!(DownloadSession::find("9102").unwrap().is_empty());
+        assert!(
+            cache_db::list_download_sessions()
+                .unwrap()
+                .iter()
+                .all(|s| s.model_id != 9102)
+        );
+    }
+
+    #[tokio::test]
+    async fn completed_file_is_verified_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("model.safetensors");
+        std::fs::write(&target_path, b"0123456789").unwrap();
+        let file_plan = FilePlan {
+            file: super::super::model::ModelVersionFile::try_from(
+                &serde_json::json!({ "id": 5, "name": "model.safetensors", "sizeKB": 0.01 }),
+            )
+            .unwrap(),
+            target_path: target_path.clone(),
+            existing_location: None,
+            refused: false,
+            over_budget: false,
+            inaccessible: None,
+        };
+        let mut recorded = session(9103, &[1], 0);
+        recorded.files.push(SessionFile {
+            version_id: 1,
+            file_id: 5,
+            name: "model.safetensors".to_string(),
+            path: target_path.clone(),
+            size: 10,
+            state: SessionFileState::Completed,
+            blake3: Some(blake3::hash(b"0123456789").to_hex().to_string()),
+        });
+        assert!(recorded.is_completed(&file_plan, None).await);
+
+        // 大小不变但内容被破坏
+        std::fs::write(&target_path, b"9876543210").unwrap();
+        assert!(!recorded.is_completed(&file_plan, None).await);
+
+        recorded.files[0].blake3 = None;
+        assert!(recorded.is_completed(&file_plan, None).await);
+    }
+}
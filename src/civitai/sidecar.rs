@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    model::{Model, ModelVersion, ModelVersionFile},
+    permissions::ModelPermissions,
+};
+
+/// Metadata of a downloaded model file, saved beside it as `<file stem>.civitai.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CivitaiSidecar {
+    pub model_id: u64,
+    pub model_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_type: Option<String>,
+    pub version_id: u64,
+    pub version_name: String,
+    pub base_model: Option<String>,
+    /// AI Resource Name of the version, like `urn:air:sd1:lora:civitai:123@456`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub air: Option<String>,
+    /// Page of the version on Civitai website.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_url: Option<String>,
+    pub file: Option<SidecarFile>,
+    /// Model version metadata as returned by Civitai API.
+    pub model_version: Value,
+    /// URL of the image or video the cover was made from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_source: Option<String>,
+    /// License and permissions of the model when it was saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<ModelPermissions>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SidecarFile {
+    pub id: u64,
+    pub name: String,
+    #[serde(rename = "sizeKB")]
+    pub size_kb: f64,
+    pub blake3: Option<String>,
+    pub pickle_scan_result: Option<String>,
+    pub pickle_scan_message: Option<String>,
+    pub virus_scan_result: Option<String>,
+}
+
+impl From<&ModelVersionFile> for SidecarFile {
+    fn from(file: &ModelVersionFile) -> Self {
+        Self {
+            id: file.id(),
+            name: file.name(),
+            size_kb: file.size(),
+            blake3: file.blake3_hash(),
+            pickle_scan_result: file.pickle_scan_result(),
+            pickle_scan_message: file.pickle_scan_message(),
+            virus_scan_result: file.virus_scan_result(),
+        }
+    }
+}
+
+pub fn sidecar_path<P: AsRef<Path>>(model_file_path: P) -> PathBuf {
+    let model_file_path = model_file_path.as_ref();
+    let stem = model_file_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    model_file_path.with_file_name(format!("{stem}.civitai.json"))
+}
+
+/// Reads the sidecar beside a model file, `None` when the file has no sidecar.
+pub fn load_sidecar<P: AsRef<Path>>(model_file_path: P) -> Result<Option<CivitaiSidecar>> {
+    let path = sidecar_path(model_file_path);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read(&path)?;
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+pub async fn save_sidecar<P: AsRef<Path>>(
+    model_file_path: P,
+    model: &Model,
+    model_version: &ModelVersion,
+    file: Option<&ModelVersionFile>,
+) -> Result<PathBuf> {
+    // 重新保存时保留封面来源，用于判断封面是否需要更新
+    let cover_source = load_sidecar(&model_file_path)
+        .ok()
+        .flatten()
+        .and_then(|sidecar| sidecar.cover_source);
+    let sidecar = CivitaiSidecar {
+        model_id: model.id(),
+        model_name: model.name(),
+        model_type: model.model_type(),
+        version_id: model_version.id(),
+        version_name: model_version.name(),
+        base_model: model_version.base_model(),
+        air: model_version.air(),
+        page_url: Some(model_version.page_url()),
+        file: file.map(SidecarFile::from),
+        model_version: model_version.as_value().clone(),
+        cover_source,
+        permissions: Some(model.permissions()),
+    };
+    let path = sidecar_path(model_file_path);
+    tokio::fs::write(&path, serde_json::to_vec_pretty(&sidecar)?).await?;
+    Ok(path)
+}
+
+/// Records the source of the cover in the sidecar beside a model file, nothing is written when
+/// the model file has no sidecar.
+pub async fn save_cover_source<P: AsRef<Path>>(model_file_path: P, source: &str) -> Result<()> {
+    let Some(mut sidecar) = load_sidecar(&model_file_path)? else {
+        return Ok(());
+    };
+    sidecar.cover_source = Some(source.to_string());
+    let path = sidecar_path(model_file_path);
+    tokio::fs::write(&path, serde_json::to_vec_pretty(&sidecar)?).await?;
+    Ok(())
+}
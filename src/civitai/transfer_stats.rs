@@ -0,0 +1,170 @@
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use time::UtcDateTime;
+
+use crate::{
+    cache_db,
+    errors::{CloudflareChallengeError, TransferError, UnexpectedResponseError},
+};
+
+/// Why a model file transfer attempt failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureCategory {
+    Timeout,
+    Connection,
+    Stalled,
+    Interrupted,
+    Cloudflare,
+    ServerError,
+    Refused,
+    UnexpectedResponse,
+    LocalIo,
+    Other,
+}
+
+impl FailureCategory {
+    pub fn classify(error: &anyhow::Error) -> Self {
+        if error.downcast_ref::<CloudflareChallengeError>().is_some() {
+            return Self::Cloudflare;
+        }
+        if error.downcast_ref::<UnexpectedResponseError>().is_some() {
+            return Self::UnexpectedResponse;
+        }
+        if let Some(transfer_error) = error.downcast_ref::<TransferError>() {
+            return match transfer_error {
+                TransferError::Request(e) => Self::of_request(e),
+                TransferError::Stalled(_) => Self::Stalled,
+                TransferError::Interrupted(_) | TransferError::ClosedEarly => Self::Interrupted,
+            };
+        }
+        if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+            return Self::of_request(e);
+        }
+        if error.downcast_ref::<std::io::Error>().is_some() {
+            return Self::LocalIo;
+        }
+        Self::Other
+    }
+
+    fn of_request(error: &reqwest::Error) -> Self {
+        match error.status() {
+            Some(status) if status.is_server_error() => Self::ServerError,
+            Some(_) => Self::Refused,
+            None if error.is_timeout() => Self::Timeout,
+            None if error.is_connect() => Self::Connection,
+            None => Self::Interrupted,
+        }
+    }
+
+    /// Whether the failure comes from the connection between here and Civitai, rather than
+    /// from Civitai itself.
+    pub fn is_network_side(&self) -> bool {
+        matches!(
+            self,
+            Self::Timeout | Self::Connection | Self::Stalled | Self::Interrupted
+        )
+    }
+}
+
+impl Display for FailureCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::Timeout => "request timed out",
+            Self::Connection => "connection failed",
+            Self::Stalled => "no data received",
+            Self::Interrupted => "transfer interrupted",
+            Self::Cloudflare => "blocked by Cloudflare",
+            Self::ServerError => "server error",
+            Self::Refused => "refused by server",
+            Self::UnexpectedResponse => "unexpected response",
+            Self::LocalIo => "local file error",
+            Self::Other => "other",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Attempts of downloading a model file, kept in cache database for `imd diagnose`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileTransferStats {
+    pub file_id: u64,
+    pub file_name: String,
+    /// Host the file was served from at last, after redirects.
+    pub host: Option<String>,
+    pub attempts: u64,
+    pub failures: u64,
+    /// Bytes received by attempts that failed afterwards.
+    pub retried_bytes: u64,
+    pub failure_categories: BTreeMap<FailureCategory, u64>,
+    /// Bytes and time of the transfers of completed downloads, failed attempts included.
+    pub transferred_bytes: u64,
+    pub transfer_secs: f64,
+    /// Whether a proxy server was in use at the last attempt.
+    pub via_proxy: bool,
+    pub updated_at: i64,
+}
+
+/// Counts the attempts of one download. The cache database is written only when an attempt
+/// fails and once when the download completes.
+pub struct TransferTracker {
+    file_id: u64,
+    file_name: String,
+    via_proxy: bool,
+    /// Host the last attempt was served from.
+    pub host: Option<String>,
+    transferred_bytes: u64,
+    transfer_time: Duration,
+}
+
+impl TransferTracker {
+    pub fn new(file_id: u64, file_name: String, via_proxy: bool) -> Self {
+        Self {
+            file_id,
+            file_name,
+            via_proxy,
+            host: None,
+            transferred_bytes: 0,
+            transfer_time: Duration::ZERO,
+        }
+    }
+
+    pub fn attempt_finished(&mut self, received_bytes: u64, elapsed: Duration) {
+        self.transferred_bytes += received_bytes;
+        self.transfer_time += elapsed;
+    }
+
+    pub fn record_failure(&self, error: &anyhow::Error, received_bytes: u64) {
+        let category = FailureCategory::classify(error);
+        // 统计数据仅用于诊断，写入失败不影响下载
+        let _ = self.update(|stats| {
+            stats.failures += 1;
+            stats.retried_bytes += received_bytes;
+            *stats.failure_categories.entry(category).or_default() += 1;
+        });
+    }
+
+    pub fn record_success(&self) {
+        let _ = self.update(|stats| {
+            stats.transferred_bytes += self.transferred_bytes;
+            stats.transfer_secs += self.transfer_time.as_secs_f64();
+        });
+    }
+
+    fn update<F: FnOnce(&mut FileTransferStats)>(&self, update: F) -> Result<()> {
+        cache_db::update_file_transfer_stats(self.file_id, |stats| {
+            stats.file_id = self.file_id;
+            stats.file_name = self.file_name.clone();
+            if self.host.is_some() {
+                stats.host = self.host.clone();
+            }
+            stats.attempts += 1;
+            stats.via_proxy = self.via_proxy;
+            stats.updated_at = UtcDateTime::now().unix_timestamp();
+            update(stats);
+        })
+    }
+}
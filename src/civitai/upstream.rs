@@ -0,0 +1,100 @@
+//! Whether downloaded versions still exist on Civitai, as last seen by this tool.
+
+use std::{fmt::Display, path::Path};
+
+use anyhow::Result;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use time::UtcDateTime;
+
+use crate::{cache_db, configuration::Configuration};
+
+use super::{is_not_found_error, meta, model::ModelVersion, sidecar};
+
+/// How a version left Civitai.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalKind {
+    /// The version endpoint answers 404.
+    Deleted,
+    /// The version is still returned, but no longer published.
+    Unpublished,
+}
+
+impl Display for RemovalKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Deleted => "deleted",
+            Self::Unpublished => "unpublished",
+        })
+    }
+}
+
+/// Last known state of a version on Civitai, kept in cache database by version id.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpstreamState {
+    /// Unix timestamp the version was last returned by Civitai.
+    pub last_seen_at: Option<i64>,
+    /// Set once the version is found gone, cleared when it shows up published again.
+    pub removed: Option<RemovalKind>,
+    pub checked_at: i64,
+}
+
+impl UpstreamState {
+    pub fn load(version_id: u64) -> Result<Option<Self>> {
+        cache_db::retreive_version_upstream(version_id)
+    }
+
+    fn save(&self, version_id: u64) -> Result<()> {
+        cache_db::store_version_upstream(version_id, self)
+    }
+}
+
+/// Records that Civitai returned the version just now, and whether it is still published.
+pub(super) fn record_seen(version: &ModelVersion) -> Result<()> {
+    let now = UtcDateTime::now().unix_timestamp();
+    let removed = version
+        .status()
+        .filter(|status| !status.eq_ignore_ascii_case("published"))
+        .map(|_| RemovalKind::Unpublished);
+    UpstreamState {
+        last_seen_at: Some(now),
+        removed,
+        checked_at: now,
+    }
+    .save(version.id())
+}
+
+/// Requests the version from Civitai and records whether it still exists. Failures other than
+/// 404 tell nothing about the version, they are returned without recording anything.
+pub async fn check_version_upstream(
+    client: &Client,
+    config: &Configuration,
+    version_id: u64,
+) -> Result<UpstreamState> {
+    match meta::fetch_model_version_meta(client, config, version_id).await {
+        Ok(_) => Ok(UpstreamState::load(version_id)?.unwrap_or_default()),
+        Err(e) if is_not_found_error(&e) => {
+            let state = UpstreamState {
+                removed: Some(RemovalKind::Deleted),
+                checked_at: UtcDateTime::now().unix_timestamp(),
+                ..UpstreamState::load(version_id)?.unwrap_or_default()
+            };
+            state.save(version_id)?;
+            Ok(state)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// How the version of a local model file left Civitai, as recorded by an earlier check. `None`
+/// when it is not known to be gone.
+pub fn local_removal(model_file: &Path) -> Option<RemovalKind> {
+    let sidecar = sidecar::load_sidecar(model_file).ok()??;
+    recorded_removal(sidecar.version_id)
+}
+
+pub fn recorded_removal(version_id: u64) -> Option<RemovalKind> {
+    UpstreamState::load(version_id).ok()??.removed
+}
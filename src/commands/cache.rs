@@ -0,0 +1,44 @@
+use anyhow::Context;
+use clap::{Args, Subcommand};
+
+#[derive(Args)]
+pub struct CacheOptions {
+    #[command(subcommand)]
+    pub action: CacheAction,
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    #[command(
+        about = "Forget the remembered answers to the prompt about files downloaded before, to be asked again."
+    )]
+    ForgetDecisions,
+    #[command(
+        about = "Forget every interrupted download, sessions not resumed within 30 days are forgotten by themselves."
+    )]
+    ForgetSessions,
+}
+
+pub async fn process_cache_options(options: &CacheOptions) -> anyhow::Result<()> {
+    match &options.action {
+        CacheAction::ForgetDecisions => {
+            let forgotten =
+                crate::civitai::forget_decisions().context("Failed to forget decisions")?;
+            match forgotten {
+                0 => println!("No remembered decision."),
+                1 => println!("Forgot 1 remembered decision."),
+                count => println!("Forgot {count} remembered decisions."),
+            }
+        }
+        CacheAction::ForgetSessions => {
+            let forgotten = crate::civitai::DownloadSession::forget_all()
+                .context("Failed to forget download sessions")?;
+            match forgotten {
+                0 => println!("No interrupted download."),
+                1 => println!("Forgot 1 interrupted download."),
+                count => println!("Forgot {count} interrupted downloads."),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, bail};
+use clap::Args;
+use dialoguer::Confirm;
+
+use super::collector::split_meta_file_name;
+use crate::{errors::InvalidInputError, utils::bytes_to_human_string};
+
+/// Extensions of unfinished or broken downloads, after the model file name.
+const PARTIAL_EXTENSIONS: [&str; 2] = ["part", "corrupt"];
+
+#[derive(Args)]
+pub struct CleanOptions {
+    #[arg(help = "The directory to clean, defaults to current directory.")]
+    pub path: Option<PathBuf>,
+    #[arg(
+        long,
+        short = 'r',
+        help = "Also clean subdirectories.",
+        default_value = "false"
+    )]
+    pub recursive: bool,
+    #[arg(
+        long,
+        help = "Only delete metadata files whose model file no longer exists.",
+        default_value = "false",
+        conflicts_with = "partials_only"
+    )]
+    pub orphans_only: bool,
+    #[arg(
+        long,
+        help = "Only delete .part and .corrupt files.",
+        default_value = "false"
+    )]
+    pub partials_only: bool,
+    #[arg(
+        long,
+        value_name = "AGE",
+        value_parser = crate::utils::parse_age,
+        help = "Only delete files not modified for the given age, like 12h or 7d, so running downloads keep their partial files."
+    )]
+    pub older_than: Option<Duration>,
+    #[arg(
+        long,
+        short = 'y',
+        help = "Delete without confirmation.",
+        default_value = "false"
+    )]
+    pub yes: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LeftoverKind {
+    Partial,
+    Orphan,
+}
+
+struct Leftover {
+    path: PathBuf,
+    kind: LeftoverKind,
+    size: u64,
+}
+
+pub async fn process_clean_options(options: &CleanOptions) -> anyhow::Result<()> {
+    let directory = match options.path.as_ref() {
+        Some(path) => path.clone(),
+        None => std::env::current_dir().context("Unable to get current working directory")?,
+    };
+    if !directory.is_dir() {
+        return Err(
+            InvalidInputError(format!("\"{}\" is not a directory", directory.display())).into(),
+        );
+    }
+
+    let mut leftovers = Vec::new();
+    let mut pending_dirs = vec![directory.clone()];
+    while let Some(dir) = pending_dirs.pop() {
+        let (found, subdirs) = find_leftovers(&dir)
+            .with_context(|| format!("Failed to list files in {}", dir.display()))?;
+        leftovers.extend(found);
+        if options.recursive {
+            pending_dirs.extend(subdirs);
+        }
+    }
+    let now = SystemTime::now();
+    leftovers.retain(|leftover| {
+        let kind_selected = match leftover.kind {
+            LeftoverKind::Partial => !options.orphans_only,
+            LeftoverKind::Orphan => !options.partials_only,
+        };
+        kind_selected
+            && options.older_than.is_none_or(|age| {
+                std::fs::metadata(&leftover.path)
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() >= age)
+            })
+    });
+    leftovers.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.path.cmp(&b.path)));
+    if leftovers.is_empty() {
+        println!("Nothing to clean.");
+        return Ok(());
+    }
+
+    for (kind, title) in [
+        (LeftoverKind::Partial, "Partial and corrupt downloads:"),
+        (LeftoverKind::Orphan, "Metadata files without model file:"),
+    ] {
+        let mut listed = leftovers.iter().filter(|leftover| leftover.kind == kind);
+        if let Some(first) = listed.next() {
+            println!("{title}");
+            for leftover in std::iter::once(first).chain(listed) {
+                println!(
+                    "  {} ({})",
+                    leftover.path.display(),
+                    bytes_to_human_string(leftover.size)
+                );
+            }
+        }
+    }
+    let total_size = leftovers.iter().map(|leftover| leftover.size).sum::<u64>();
+    println!(
+        "{} files, {} in total.",
+        leftovers.len(),
+        bytes_to_human_string(total_size)
+    );
+
+    if !options.yes {
+        if !std::io::stdin().is_terminal() {
+            bail!(
+                "Cleaning requires confirmation, run it in an interactive terminal or give --yes."
+            );
+        }
+        let prompt = format!("Delete these {} files?", leftovers.len());
+        let confirmed = crate::prompt::interact(
+            move || Confirm::new().with_prompt(prompt).default(false).interact(),
+            false,
+            "no",
+        )?;
+        if !confirmed {
+            println!("Nothing deleted.");
+            return Ok(());
+        }
+    }
+
+    let mut freed_size = 0;
+    let mut failed_files = Vec::new();
+    for leftover in leftovers.iter() {
+        match std::fs::remove_file(&leftover.path) {
+            Ok(()) => freed_size += leftover.size,
+            Err(e) => {
+                eprintln!("Failed to delete {}: {e}", leftover.path.display());
+                failed_files.push(leftover.path.display().to_string());
+            }
+        }
+    }
+    println!(
+        "Deleted {} files, freed {}.",
+        leftovers.len() - failed_files.len(),
+        bytes_to_human_string(freed_size)
+    );
+    if !failed_files.is_empty() {
+        bail!("{} files failed to delete", failed_files.len());
+    }
+    Ok(())
+}
+
+/// Whether the suffix is only ever generated from model metadata, by imd or the Civitai Helper
+/// extension. A lone readme or text file may be written by hand, so metadata files are only taken
+/// as orphaned together with one of these.
+fn is_generated_suffix(suffix: &str) -> bool {
+    matches!(
+        suffix,
+        "blake3" | "sha256" | "civitai.json" | "civitai.info" | "preview.png"
+    ) || suffix.starts_with("cover.")
+}
+
+/// Finds the leftovers directly in the directory, also returns its subdirectories.
+fn find_leftovers(directory: &Path) -> anyhow::Result<(Vec<Leftover>, Vec<PathBuf>)> {
+    let mut leftovers = Vec::new();
+    let mut subdirs = Vec::new();
+    let mut model_stems = HashSet::new();
+    let mut meta_groups: BTreeMap<String, Vec<(PathBuf, bool)>> = BTreeMap::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            subdirs.push(path);
+            continue;
+        }
+        if !path.is_file() {
+            continue;
+        }
+        let is_partial = path.extension().is_some_and(|extension| {
+            PARTIAL_EXTENSIONS
+                .iter()
+                .any(|partial| extension.eq_ignore_ascii_case(partial))
+        });
+        // 未完成的下载仍然拥有它的元数据文件
+        let owner_file = if is_partial {
+            path.with_extension("")
+        } else {
+            path.clone()
+        };
+        let file_name = owner_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let meta_name = split_meta_file_name(&file_name);
+        // 压缩包、gguf和onnx等模型也拥有同名的元数据，所以元数据以外的文件都算作模型
+        if meta_name.is_none() {
+            model_stems.insert(
+                owner_file
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        if is_partial {
+            leftovers.push(Leftover {
+                size: path.metadata()?.len(),
+                path,
+                kind: LeftoverKind::Partial,
+            });
+            continue;
+        }
+        if let Some((stem, suffix)) = meta_name {
+            let generated = is_generated_suffix(suffix);
+            meta_groups
+                .entry(stem.to_string())
+                .or_default()
+                .push((path.clone(), generated));
+        }
+    }
+
+    for (stem, files) in meta_groups {
+        if model_stems.contains(&stem) || !files.iter().any(|(_, generated)| *generated) {
+            continue;
+        }
+        for (path, _) in files {
+            leftovers.push(Leftover {
+                size: path.metadata()?.len(),
+                path,
+                kind: LeftoverKind::Orphan,
+            });
+        }
+    }
+    Ok((leftovers, subdirs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leftover_names(directory: &Path) -> Vec<String> {
+        let (leftovers, _) = find_leftovers(directory).unwrap();
+        let mut names = leftovers
+            .iter()
+            .map(|leftover| {
+                leftover
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn metadata_of_any_model_file_is_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        for name in [
+            "archive.zip",
+            "archive.md",
+            "archive.blake3",
+            "archive.civitai.json",
+            "archive.cover.png",
+            "quantized.gguf",
+            "quantized.blake3",
+            "removed.md",
+            "removed.blake3",
+            "helper.safetensors",
+            "helper.civitai.info",
+            "helper.preview.png",
+            "gone.civitai.info",
+            "gone.preview.png",
+            "unfinished.onnx.part",
+            "unfinished.cover.png",
+        ] {
+            std::fs::write(directory.path().join(name), b"x").unwrap();
+        }
+
+        assert_eq!(
+            leftover_names(directory.path()),
+            [
+                "gone.civitai.info",
+                "gone.preview.png",
+                "removed.blake3",
+                "removed.md",
+                "unfinished.onnx.part"
+            ]
+        );
+    }
+}
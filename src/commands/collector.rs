@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+/// Suffixes of the files generated beside a model file, after the model file stem.
+const META_SUFFIXES: [&str; 7] = [
+    "md",
+    "txt",
+    "blake3",
+    "sha256",
+    "civitai.json",
+    "civitai.info",
+    "preview.png",
+];
+
+pub fn is_legal_model_file<P: AsRef<Path>>(file_path: P) -> bool {
+    let extensions = ["ckpt", "safetensors", "pt", "bin"];
+    let file_extension = file_path.as_ref().extension();
+    if file_extension.is_none() {
+        return false;
+    }
+    let file_extension = file_extension.unwrap().to_string_lossy();
+    extensions
+        .iter()
+        .any(|ext| ext.eq_ignore_ascii_case(&file_extension))
+}
+
+/// Collects model files in the given directory, sorted by path.
+pub fn collect_model_files<P: AsRef<Path>>(
+    directory: P,
+    recursive: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut model_files = Vec::new();
+    let mut pending_dirs = vec![directory.as_ref().to_path_buf()];
+
+    while let Some(dir) = pending_dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if recursive {
+                    pending_dirs.push(path);
+                }
+            } else if path.is_file() && is_legal_model_file(&path) {
+                model_files.push(path);
+            }
+        }
+    }
+    model_files.sort();
+
+    Ok(model_files)
+}
+
+/// Whether the part of a file name after `<model file stem>.` names a readme, cover, hash or
+/// sidecar file generated beside the model file.
+pub fn is_meta_suffix(suffix: &str) -> bool {
+    // 只匹配已知后缀，避免误删同名前缀的其他模型
+    let is_cover = suffix
+        .strip_prefix("cover.")
+        .is_some_and(|extension| !extension.is_empty() && !extension.contains('.'));
+    is_cover || META_SUFFIXES.contains(&suffix)
+}
+
+/// Splits the name of a file generated beside a model file into the model file stem and the
+/// suffix, e.g. `a.cover.png` into `a` and `cover.png`.
+pub fn split_meta_file_name(file_name: &str) -> Option<(&str, &str)> {
+    let cover = file_name
+        .rfind(".cover.")
+        .map(|position| (&file_name[..position], &file_name[position + 1..]));
+    cover
+        .into_iter()
+        .chain(META_SUFFIXES.iter().filter_map(|suffix| {
+            file_name
+                .strip_suffix(suffix)
+                .and_then(|stem| stem.strip_suffix('.'))
+                .map(|stem| (stem, &file_name[stem.len() + 1..]))
+        }))
+        .find(|(stem, suffix)| !stem.is_empty() && is_meta_suffix(suffix))
+}
+
+/// Collects readme, cover, hash and sidecar files named after the model file stem, including the
+/// files written for the Civitai Helper extension.
+pub fn collect_meta_files(model_file: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let Some(directory) = model_file.parent() else {
+        return Ok(Vec::new());
+    };
+    let stem = model_file
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let prefix = format!("{stem}.");
+
+    let mut meta_files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if !path.is_file() || path == model_file {
+            continue;
+        }
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if file_name.strip_prefix(&prefix).is_some_and(is_meta_suffix) {
+            meta_files.push(path);
+        }
+    }
+    meta_files.sort();
+
+    Ok(meta_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helper_files_are_metadata_of_the_model() {
+        assert_eq!(
+            split_meta_file_name("style.civitai.info"),
+            Some(("style", "civitai.info"))
+        );
+        assert_eq!(
+            split_meta_file_name("style v2.preview.png"),
+            Some(("style v2", "preview.png"))
+        );
+        assert_eq!(split_meta_file_name("style.png"), None);
+
+        let directory = tempfile::tempdir().unwrap();
+        for name in [
+            "style.safetensors",
+            "style.civitai.info",
+            "style.preview.png",
+            "style.cover.png",
+            "style.extra.png",
+            "style v2.preview.png",
+        ] {
+            std::fs::write(directory.path().join(name), b"x").unwrap();
+        }
+        let names = collect_meta_files(&directory.path().join("style.safetensors"))
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["style.civitai.info", "style.cover.png", "style.preview.png"]
+        );
+    }
+}
@@ -1,5 +1,14 @@
+use std::time::Duration;
+
+use anyhow::Context;
 use clap::{Args, Subcommand};
 
+use crate::{
+    configuration::{DefaultFlag, PROXY_ENV_VARS, ProxyMode},
+    errors::InvalidInputError,
+    utils::format_duration,
+};
+
 #[derive(Args)]
 pub struct ConfigOptions {
     #[command(subcommand, help = "Inspect or modify downloader configuration.")]
@@ -33,11 +42,42 @@ pub enum WriteableContent {
     CivitaiKey {
         #[arg(help = "Civitai access key.")]
         key: String,
+        #[arg(long, help = "Save the key without validating it against Civitai.")]
+        no_verify: bool,
+    },
+    #[command(
+        name = "civitai-api",
+        alias = "civitai-api-url",
+        about = "Operate Civitai API base URL."
+    )]
+    CivitaiApiBase {
+        #[arg(help = "Civitai API base URL, e.g. https://civitai.com/api/v1.")]
+        url: String,
+    },
+    #[command(
+        name = "download-url-rewrite",
+        about = "Operate the rewrite of file and image download URLs."
+    )]
+    DownloadUrlRewrite {
+        #[arg(help = "URL prefix to replace, e.g. https://civitai.com/.")]
+        from: String,
+        #[arg(help = "URL prefix to use instead, e.g. https://mirror.example.com/civitai/.")]
+        to: String,
+    },
+    #[command(
+        name = "auth-mode",
+        about = "Operate how the Civitai access key is sent with model file downloads."
+    )]
+    AuthMode {
+        #[arg(value_enum, help = "Download authorization mode.")]
+        mode: crate::configuration::DownloadAuthMode,
     },
     #[command(name = "huggingface", about = "Operate HuggingFace Access key.")]
     HuggingFaceKey {
         #[arg(help = "HuggingFace access key.")]
         key: String,
+        #[arg(long, help = "Save the key without validating it against HuggingFace.")]
+        no_verify: bool,
     },
     #[command(name = "enable-proxy", about = "Switch whether to use a proxy server.")]
     EnableProxy {
@@ -62,27 +102,283 @@ pub enum WriteableContent {
         #[arg[long, short = 'm', help = "Retry interval increament multiplier."]]
         multiplier: Option<f32>,
     },
+    #[command(name = "output-dir", about = "Operate default output directory.")]
+    OutputDir {
+        #[arg(help = "Directory stores the download files by default.")]
+        path: std::path::PathBuf,
+    },
+    #[command(
+        name = "temp-dir",
+        about = "Operate the directory partial downloads are written to."
+    )]
+    TempDir {
+        #[arg(
+            help = "Directory on a fast local disk, finished files are moved to the destination."
+        )]
+        path: std::path::PathBuf,
+    },
+    #[command(name = "comfyui", about = "Operate ComfyUI model paths.")]
+    ComfyUi {
+        #[arg(help = "Path of ComfyUI extra_model_paths.yaml.")]
+        model_paths: std::path::PathBuf,
+        #[arg(
+            long,
+            help = "Directory of models whose type has no ComfyUI model category."
+        )]
+        default_dir: Option<std::path::PathBuf>,
+    },
+    #[command(
+        name = "webui",
+        about = "Operate Stable Diffusion webui root directory."
+    )]
+    Webui {
+        #[arg(help = "Root directory of Stable Diffusion webui.")]
+        root: std::path::PathBuf,
+        #[arg(
+            long,
+            help = "Directory of models whose type has no webui model directory."
+        )]
+        default_dir: Option<std::path::PathBuf>,
+    },
+    #[command(
+        name = "video-cover",
+        about = "Operate what to save as cover when a version only has videos."
+    )]
+    VideoCover {
+        #[arg(value_enum, help = "Video cover mode.")]
+        mode: crate::configuration::VideoCoverMode,
+    },
+    #[command(
+        name = "existing-check",
+        about = "Operate how a file already at the download target is checked before it is kept."
+    )]
+    ExistingCheck {
+        #[arg(value_enum, help = "Check level of existing files.")]
+        check: crate::configuration::ExistingCheck,
+    },
+    #[command(
+        name = "skip-decision-ttl",
+        about = "Operate how long a file skipped at the existing file prompt is not asked about again."
+    )]
+    SkipDecisionTtl {
+        #[arg(help = "Hours a skipped file is skipped without asking, 0 to ask every time.")]
+        hours: u64,
+    },
+    #[command(
+        name = "processing-wait",
+        about = "Operate how long a requested file still processed by Civitai is waited for."
+    )]
+    ProcessingWait {
+        #[arg(help = "Minutes to wait for the file, 0 to give up at once.")]
+        minutes: u64,
+    },
+    #[command(
+        name = "folder-per-model",
+        about = "Switch whether to save downloads into <model name>/<version name>/ subdirectories."
+    )]
+    FolderPerModel {
+        #[arg(help = "Folder per model layout enable state.")]
+        flag: bool,
+    },
+    #[command(
+        name = "terminal-title",
+        about = "Switch whether to show download progress in the terminal window title."
+    )]
+    TerminalTitle {
+        #[arg(help = "Terminal title progress enable state.", action = clap::ArgAction::Set)]
+        flag: bool,
+    },
+    #[command(
+        name = "readme-stats",
+        about = "Switch whether to show download counts and ratings in the readme."
+    )]
+    ReadmeStats {
+        #[arg(help = "Readme stats enable state.", action = clap::ArgAction::Set)]
+        flag: bool,
+    },
+    #[command(
+        name = "cover-width",
+        about = "Operate width of downloaded cover images."
+    )]
+    CoverWidth {
+        #[arg(help = "Cover image width in pixels, 0 to download the original images.")]
+        width: u32,
+    },
+    #[command(
+        name = "images-cache-ttl",
+        about = "Operate how long community images metadata is cached."
+    )]
+    ImagesCacheTtl {
+        #[arg(help = "Hours cached community images metadata is used, 0 to disable the cache.")]
+        hours: u64,
+    },
+    #[command(name = "user-agent", about = "Operate user agent sent with requests.")]
+    UserAgent {
+        #[arg(help = "User agent string.")]
+        user_agent: String,
+    },
+    #[command(
+        name = "header",
+        about = "Add an extra header sent with every request."
+    )]
+    Header {
+        #[arg(help = "Header name.")]
+        name: String,
+        #[arg(help = "Header value.")]
+        value: String,
+    },
+    #[command(
+        name = "selection-rule",
+        about = "Set the files picked without prompting for versions of matching base models."
+    )]
+    SelectionRule {
+        #[arg(help = "Base model pattern, * and ? are wildcards, like \"SD 1.5\" or \"Flux*\".")]
+        base_model: String,
+        #[arg(
+            help = "File preference, comma separated terms the model file should match, then +<file type> for files taken along, like fp16,pruned,safetensor+vae.",
+            required_unless_present = "remove"
+        )]
+        preference: Option<String>,
+        #[arg(
+            long,
+            help = "Remove the rule of the pattern instead.",
+            conflicts_with = "preference"
+        )]
+        remove: bool,
+    },
+    #[command(
+        name = "default",
+        about = "Operate the default of a download, renew and scan flag."
+    )]
+    Default {
+        #[arg(value_enum, help = "The flag to set the default of.")]
+        key: DefaultFlag,
+        #[arg(help = "Default value, true or false for switches, a number for history-limit.")]
+        value: String,
+    },
+    #[command(name = "network", about = "Network timeout configuration.")]
+    Network {
+        #[arg(long, short = 'c', help = "Connect timeout in seconds.")]
+        connect_timeout: Option<u64>,
+        #[arg(long, short = 'r', help = "Metadata request timeout in seconds.")]
+        request_timeout: Option<u64>,
+        #[arg(
+            long,
+            short = 'i',
+            help = "Seconds to wait for data during downloading before retrying."
+        )]
+        idle_timeout: Option<u64>,
+    },
 }
 
 #[derive(Subcommand)]
 pub enum ReadableContent {
     #[command(name = "civitai", about = "Show Civitai access key.")]
     CivitaiKey,
+    #[command(
+        name = "civitai-api",
+        alias = "civitai-api-url",
+        about = "Show Civitai API base URL."
+    )]
+    CivitaiApiBase,
+    #[command(
+        name = "download-url-rewrite",
+        about = "Show the rewrite of file and image download URLs."
+    )]
+    DownloadUrlRewrite,
+    #[command(
+        name = "auth-mode",
+        about = "Show how the Civitai access key is sent with model file downloads."
+    )]
+    AuthMode,
     #[command(name = "huggingface", about = "Show HuggingFace Access key.")]
     HuggingFaceKey,
     #[command(name = "proxy", about = "Show proxy.")]
     Proxy,
     #[command(name = "retry", about = "Show retry policy.")]
     Retry,
+    #[command(name = "network", about = "Show network configuration.")]
+    Network,
+    #[command(name = "output-dir", about = "Show default output directory.")]
+    OutputDir,
+    #[command(
+        name = "temp-dir",
+        about = "Show the directory partial downloads are written to."
+    )]
+    TempDir,
+    #[command(name = "comfyui", about = "Show ComfyUI model paths.")]
+    ComfyUi,
+    #[command(name = "webui", about = "Show Stable Diffusion webui root directory.")]
+    Webui,
+    #[command(
+        name = "video-cover",
+        about = "Show what to save as cover when a version only has videos."
+    )]
+    VideoCover,
+    #[command(
+        name = "folder-per-model",
+        about = "Show whether downloads are saved into per model subdirectories."
+    )]
+    FolderPerModel,
+    #[command(
+        name = "terminal-title",
+        about = "Show whether download progress is shown in the terminal window title."
+    )]
+    TerminalTitle,
+    #[command(
+        name = "readme-stats",
+        about = "Show whether download counts and ratings are shown in the readme."
+    )]
+    ReadmeStats,
+    #[command(
+        name = "existing-check",
+        about = "Show how a file already at the download target is checked before it is kept."
+    )]
+    ExistingCheck,
+    #[command(
+        name = "skip-decision-ttl",
+        about = "Show how long a file skipped at the existing file prompt is not asked about again."
+    )]
+    SkipDecisionTtl,
+    #[command(
+        name = "processing-wait",
+        about = "Show how long a requested file still processed by Civitai is waited for."
+    )]
+    ProcessingWait,
+    #[command(name = "cover-width", about = "Show width of downloaded cover images.")]
+    CoverWidth,
+    #[command(
+        name = "images-cache-ttl",
+        about = "Show how long community images metadata is cached."
+    )]
+    ImagesCacheTtl,
+    #[command(name = "user-agent", about = "Show user agent sent with requests.")]
+    UserAgent,
+    #[command(
+        name = "headers",
+        about = "Show extra headers sent with every request."
+    )]
+    Headers,
+    #[command(
+        name = "selection-rules",
+        about = "Show the rules picking files per base model, in the order they are tried."
+    )]
+    SelectionRules,
+    #[command(
+        name = "defaults",
+        about = "Show the configured defaults of download, renew and scan flags."
+    )]
+    Defaults,
 }
 
-pub async fn process_config_options(options: &ConfigOptions) {
+pub async fn process_config_options(options: &ConfigOptions) -> anyhow::Result<()> {
     match &options.action {
         ConfigAction::Get { action } => show_config(action).await,
-        ConfigAction::Set { action } => set_config(action).await,
-        ConfigAction::Clear { action } => clear_config(action).await,
+        ConfigAction::Set { action } => set_config(action).await?,
+        ConfigAction::Clear { action } => clear_config(action).await?,
         ConfigAction::All => show_all_config().await,
     }
+    Ok(())
 }
 
 async fn show_config(action: &ReadableContent) {
@@ -95,6 +391,16 @@ async fn show_config(action: &ReadableContent) {
                 println!("Civitai access key has not been set.")
             }
         }
+        ReadableContent::CivitaiApiBase => {
+            println!("Civitai API base URL: {}", configuration.civitai.api_base())
+        }
+        ReadableContent::DownloadUrlRewrite => print_download_url_rewrite(&configuration.civitai),
+        ReadableContent::AuthMode => {
+            println!(
+                "Download authorization: {}",
+                configuration.civitai.auth_mode
+            )
+        }
         ReadableContent::HuggingFaceKey => {
             if let Some(key) = &configuration.huggingface.api_key {
                 println!("HuggingFace access key: {key}")
@@ -102,43 +408,300 @@ async fn show_config(action: &ReadableContent) {
                 println!("HuggingFace access key has not been set.")
             }
         }
-        ReadableContent::Proxy => {
-            if let Some(proxy) = configuration.proxy.get_proxy_url() {
-                if configuration.proxy.use_proxy {
-                    println!("Using proxy server: {proxy}")
-                } else {
-                    println!("Downloader will not using proxy server.")
+        ReadableContent::Proxy => match configuration.proxy.mode() {
+            ProxyMode::Configured(proxy) => println!("Using proxy server: {proxy}"),
+            ProxyMode::Disabled => {
+                println!("Proxy server is disabled, downloader connects directly.")
+            }
+            ProxyMode::Environment => {
+                println!("Proxy has not been set, following proxy environment variables.");
+                let env_proxies = PROXY_ENV_VARS
+                    .iter()
+                    .filter_map(|var| std::env::var(var).ok().map(|value| (var, value)))
+                    .filter(|(_, value)| !value.is_empty())
+                    .collect::<Vec<_>>();
+                if env_proxies.is_empty() {
+                    println!("No proxy environment variable found, connecting directly.");
+                }
+                for (var, value) in env_proxies {
+                    println!("  {var}: {value}");
                 }
+            }
+        },
+        ReadableContent::Retry => print_retry_policy(&configuration.backoff),
+        ReadableContent::Network => print_network_config(&configuration.network),
+        ReadableContent::VideoCover => {
+            println!("Video cover mode: {}", configuration.cover.video)
+        }
+        ReadableContent::FolderPerModel => println!(
+            "Folder per model layout: {}",
+            configuration.download.folder_per_model
+        ),
+        ReadableContent::ExistingCheck => println!(
+            "Existing file check: {}",
+            configuration.download.existing_check
+        ),
+        ReadableContent::SkipDecisionTtl => print_skip_decision_ttl(&configuration.download),
+        ReadableContent::ProcessingWait => print_processing_wait(&configuration.download),
+        ReadableContent::TerminalTitle => println!(
+            "Terminal title progress: {}",
+            configuration.download.terminal_title
+        ),
+        ReadableContent::ReadmeStats => {
+            println!("Readme stats: {}", configuration.download.readme_stats)
+        }
+        ReadableContent::CoverWidth => print_cover_width(configuration.cover.width),
+        ReadableContent::ImagesCacheTtl => print_images_cache_ttl(&configuration.civitai),
+        ReadableContent::OutputDir => {
+            if let Some(path) = &configuration.download.output_dir {
+                println!("Default output directory: {}", path.display())
             } else {
-                println!("Proxy has not been set.")
+                println!("Default output directory has not been set.")
             }
         }
-        ReadableContent::Retry => {
+        ReadableContent::TempDir => print_temp_dir(&configuration.download),
+        ReadableContent::ComfyUi => print_comfyui_config(&configuration.comfyui),
+        ReadableContent::Webui => print_webui_config(&configuration.webui),
+        ReadableContent::UserAgent => {
             println!(
-                "When action failed, will retry in {} seconds, increase {:.02}x time when continuous failing, and keep retrying in {} times.",
-                configuration.backoff.initial_interval,
-                configuration.backoff.multiplier,
-                configuration.backoff.max_retry,
-            );
+                "User agent: {}",
+                configuration.network.effective_user_agent()
+            )
+        }
+        ReadableContent::Headers => print_extra_headers(&configuration.network),
+        ReadableContent::SelectionRules => print_selection_rules(&configuration.selection_rules),
+        ReadableContent::Defaults => print_default_flags(&configuration.defaults),
+    }
+}
+
+/// Checks the API base URL is a plain http(s) URL, returns it without the trailing slash.
+fn validate_api_base_url(url: &str) -> anyhow::Result<String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| {
+        InvalidInputError(format!(
+            "\"{url}\" is not a valid Civitai API base URL ({e})"
+        ))
+    })?;
+    if !matches!(parsed.scheme(), "http" | "https") || !parsed.has_host() {
+        return Err(InvalidInputError(format!(
+            "\"{url}\" is not a valid Civitai API base URL, expected an http or https URL"
+        ))
+        .into());
+    }
+    if parsed.query().is_some() || parsed.fragment().is_some() {
+        return Err(InvalidInputError(format!(
+            "\"{url}\" should not contain query or fragment, expected a base URL like https://civitai.com/api/v1"
+        ))
+        .into());
+    }
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+fn print_comfyui_config(comfyui: &crate::configuration::ComfyUiConfig) {
+    let Some(model_paths) = comfyui.model_paths.as_ref() else {
+        println!("ComfyUI model paths: [NOT SET]");
+        return;
+    };
+    println!("ComfyUI model paths: {}", model_paths.display());
+    if let Some(default_dir) = comfyui.default_dir.as_ref() {
+        println!("  Other models: {}", default_dir.display());
+    }
+    match crate::integrations::comfyui::load_model_paths(model_paths) {
+        Ok(categories) => {
+            for (category, dir) in categories {
+                println!("  {category}: {}", dir.display());
+            }
+        }
+        Err(e) => println!("  {e:#}"),
+    }
+}
+
+fn print_retry_policy(backoff: &crate::configuration::BackoffConfig) {
+    println!(
+        "When action failed, will retry in {}, increase {:.02}x time when continuous failing, and keep retrying in {} times.",
+        format_duration(&Duration::from_secs(backoff.initial_interval)),
+        backoff.multiplier,
+        backoff.max_retry,
+    );
+    if let Err(e) = backoff.validate() {
+        println!("  {e}, adjusted when retrying.");
+    }
+    println!("  Effective schedule: {}.", backoff.describe_schedule());
+}
+
+fn print_webui_config(webui: &crate::configuration::WebuiConfig) {
+    match webui.root.as_ref() {
+        Some(root) => println!("Stable Diffusion webui root: {}", root.display()),
+        None => println!("Stable Diffusion webui root: [NOT SET]"),
+    }
+    if let Some(default_dir) = webui.default_dir.as_ref() {
+        println!("  Other models: {}", default_dir.display());
+    }
+}
+
+fn print_download_url_rewrite(civitai: &crate::configuration::CivitaiConfig) {
+    match civitai.download_url_rewrite.as_ref() {
+        Some(rewrite) => println!("Download URL rewrite: {} -> {}", rewrite.from, rewrite.to),
+        None => println!("Download URL rewrite: [NOT SET]"),
+    }
+}
+
+fn print_images_cache_ttl(civitai: &crate::configuration::CivitaiConfig) {
+    match civitai.images_cache_ttl().as_secs() / 3600 {
+        0 => println!("Community images cache: disabled"),
+        hours => println!("Community images cache: {hours} hours"),
+    }
+}
+
+fn print_skip_decision_ttl(download: &crate::configuration::DownloadConfig) {
+    match download.skip_decision_ttl().as_secs() / 3600 {
+        0 => println!("Skipped existing files: asked every time"),
+        hours => println!("Skipped existing files: not asked again for {hours} hours"),
+    }
+}
+
+fn print_processing_wait(download: &crate::configuration::DownloadConfig) {
+    match download.processing_wait().as_secs() / 60 {
+        0 => println!("Files still processed by Civitai: not waited for"),
+        minutes => println!("Files still processed by Civitai: waited for {minutes} minutes"),
+    }
+}
+
+fn print_temp_dir(download: &crate::configuration::DownloadConfig) {
+    match download.temp_dir.as_ref() {
+        Some(path) => println!("Temporary directory: {}", path.display()),
+        None => println!("Temporary directory: [NOT SET], beside the destination"),
+    }
+}
+
+fn print_cover_width(width: u32) {
+    if width == 0 {
+        println!("Cover width: original");
+    } else {
+        println!("Cover width: {width}px");
+    }
+}
+
+fn print_selection_rules(rules: &[crate::configuration::SelectionRule]) {
+    if rules.is_empty() {
+        println!("Selection rules: [NOT SET]");
+    } else {
+        println!("Selection rules:");
+        for (index, rule) in rules.iter().enumerate() {
+            println!("  {}. {} -> {}", index + 1, rule.base_model, rule.prefer);
         }
     }
 }
 
-async fn set_config(action: &WriteableContent) {
+fn print_extra_headers(network: &crate::configuration::NetworkConfig) {
+    if network.headers.is_empty() {
+        println!("Extra headers: [NOT SET]");
+    } else {
+        println!("Extra headers:");
+        for (name, value) in network.headers.iter() {
+            println!("  {name}: {value}");
+        }
+    }
+}
+
+fn print_default_flags(defaults: &crate::configuration::DefaultsConfig) {
+    println!("Flag defaults:");
+    for flag in DefaultFlag::ALL {
+        println!(
+            "  {:<24}{}",
+            flag.name(),
+            defaults
+                .get(flag)
+                .unwrap_or_else(|| "[NOT SET]".to_string())
+        );
+    }
+}
+
+fn print_network_config(network: &crate::configuration::NetworkConfig) {
+    println!(
+        "Connect timeout: {}s, metadata request timeout: {}s, download idle timeout: {}s.",
+        network.connect_timeout, network.request_timeout, network.idle_timeout,
+    );
+    println!("User agent: {}", network.effective_user_agent());
+    print_extra_headers(network);
+}
+
+/// Validates access keys before they are saved, the key should not be saved on error.
+async fn verify_access_key(action: &WriteableContent) -> anyhow::Result<()> {
+    let key_to_verify = match action {
+        WriteableContent::CivitaiKey {
+            no_verify: false, ..
+        }
+        | WriteableContent::HuggingFaceKey {
+            no_verify: false, ..
+        } => action,
+        _ => return Ok(()),
+    };
+    let config = crate::configuration::current().await;
+    let client = crate::downloader::make_client(&config)
+        .context("Failed to initialize client for validating access key")?;
+    let result = match key_to_verify {
+        WriteableContent::CivitaiKey { key, .. } => {
+            println!("Validating Civitai access key...");
+            crate::civitai::verify_api_key(&client, &config, key)
+                .await
+                .map(|_| println!("Civitai access key is valid."))
+        }
+        WriteableContent::HuggingFaceKey { key, .. } => {
+            println!("Validating HuggingFace access key...");
+            crate::hugging_face::verify_api_key(&client, &config, key)
+                .await
+                .map(|account| println!("HuggingFace access key belongs to {account}."))
+        }
+        _ => Ok(()),
+    };
+    result.context(
+        "Access key validation failed, the key is not saved. Use --no-verify to save it without validation",
+    )
+}
+
+async fn set_config(action: &WriteableContent) -> anyhow::Result<()> {
+    verify_access_key(action).await?;
     let mut configuration = crate::configuration::CONFIGURATION.write().await;
     match action {
-        WriteableContent::CivitaiKey { key } => {
+        WriteableContent::CivitaiKey { key, .. } => {
             configuration
                 .set_civitai_api_key(key.clone())
                 .await
-                .expect("Failed to save Civitai access key.");
+                .context("Failed to save Civitai access key")?;
             println!("Civitai access key has been set.")
         }
-        WriteableContent::HuggingFaceKey { key } => {
+        WriteableContent::CivitaiApiBase { url } => {
+            let url = validate_api_base_url(url)?;
+            configuration
+                .set_civitai_api_base_url(url)
+                .await
+                .context("Failed to save Civitai API base URL")?;
+            println!("Civitai API base URL has been set.")
+        }
+        WriteableContent::DownloadUrlRewrite { from, to } => {
+            for prefix in [from, to] {
+                reqwest::Url::parse(prefix).map_err(|e| {
+                    InvalidInputError(format!("\"{prefix}\" is not a valid URL prefix ({e})"))
+                })?;
+            }
+            configuration
+                .set_download_url_rewrite(from.clone(), to.clone())
+                .await
+                .context("Failed to save download URL rewrite")?;
+            println!("Download URL rewrite has been set.")
+        }
+        WriteableContent::AuthMode { mode } => {
+            configuration
+                .set_auth_mode(*mode)
+                .await
+                .context("Failed to save download authorization mode")?;
+            println!("Download authorization mode has been set.")
+        }
+        WriteableContent::HuggingFaceKey { key, .. } => {
             configuration
                 .set_huggingface_api_key(key.clone())
                 .await
-                .expect("Failed to save HuggingFace access key.");
+                .context("Failed to save HuggingFace access key")?;
             println!("HuggingFace access key has been set.")
         }
         WriteableContent::Proxy {
@@ -146,7 +709,9 @@ async fn set_config(action: &WriteableContent) {
             username,
             password,
         } => {
-            let parsed_url = reqwest::Url::parse(&url).expect("Given proxy URL is invalid.");
+            let parsed_url = reqwest::Url::parse(url).map_err(|e| {
+                InvalidInputError(format!("\"{url}\" is not a valid proxy URL ({e})"))
+            })?;
             configuration
                 .set_proxy(
                     parsed_url.scheme().to_string(),
@@ -156,10 +721,10 @@ async fn set_config(action: &WriteableContent) {
                     password.clone(),
                 )
                 .await
-                .expect("Failed to save proxy server configuration.");
+                .context("Failed to save proxy server configuration")?;
             print!("Proxy server has been set.");
             if configuration.proxy.use_proxy {
-                println!("")
+                println!()
             } else {
                 println!(
                     " Proxy server is not enabled, you need enable it by \"enable-proxy\" command first."
@@ -170,7 +735,7 @@ async fn set_config(action: &WriteableContent) {
             configuration
                 .set_use_proxy(flag.unwrap_or_default())
                 .await
-                .expect("Failed to switch proxy server enable state.");
+                .context("Failed to switch proxy server enable state")?;
             println!("Download through proxy server has been activated.")
         }
         WriteableContent::Retry {
@@ -181,48 +746,391 @@ async fn set_config(action: &WriteableContent) {
             configuration
                 .set_backoff(*interval, *multiplier, *max_retry)
                 .await
-                .expect("Failed to save retry policy.");
+                .context("Failed to save retry policy")?;
             println!("Retry policy has been set.")
         }
+        WriteableContent::OutputDir { path } => {
+            configuration
+                .set_output_dir(path.clone())
+                .await
+                .context("Failed to save default output directory")?;
+            println!("Default output directory has been set.")
+        }
+        WriteableContent::TempDir { path } => {
+            if path.exists() && !path.is_dir() {
+                return Err(
+                    InvalidInputError(format!("{} is not a directory.", path.display())).into(),
+                );
+            }
+            let path = std::path::absolute(path)
+                .with_context(|| format!("Failed to resolve {}", path.display()))?;
+            configuration
+                .set_temp_dir(path)
+                .await
+                .context("Failed to save temporary directory")?;
+            println!("Temporary directory has been set.")
+        }
+        WriteableContent::ComfyUi {
+            model_paths,
+            default_dir,
+        } => {
+            let categories = crate::integrations::comfyui::load_model_paths(model_paths)
+                .map_err(|e| InvalidInputError(format!("{e:#}")))?;
+            let model_paths = model_paths
+                .canonicalize()
+                .with_context(|| format!("Unable to resolve {}", model_paths.display()))?;
+            configuration
+                .set_comfyui(model_paths, default_dir.clone())
+                .await
+                .context("Failed to save ComfyUI model paths")?;
+            println!(
+                "ComfyUI model paths have been set, {} model categories found.",
+                categories.len()
+            )
+        }
+        WriteableContent::Webui { root, default_dir } => {
+            crate::integrations::webui::validate_root(root)?;
+            let root = root
+                .canonicalize()
+                .with_context(|| format!("Unable to resolve {}", root.display()))?;
+            configuration
+                .set_webui(root, default_dir.clone())
+                .await
+                .context("Failed to save Stable Diffusion webui root")?;
+            println!("Stable Diffusion webui root has been set.")
+        }
+        WriteableContent::VideoCover { mode } => {
+            configuration
+                .set_video_cover_mode(*mode)
+                .await
+                .context("Failed to save video cover mode")?;
+            println!("Video cover mode has been set.")
+        }
+        WriteableContent::ExistingCheck { check } => {
+            configuration
+                .set_existing_check(*check)
+                .await
+                .context("Failed to save existing file check")?;
+            println!("Existing file check has been set.")
+        }
+        WriteableContent::SkipDecisionTtl { hours } => {
+            configuration
+                .set_skip_decision_hours(*hours)
+                .await
+                .context("Failed to save skip decision time")?;
+            println!("Skip decision time has been set.")
+        }
+        WriteableContent::ProcessingWait { minutes } => {
+            configuration
+                .set_processing_wait_minutes(*minutes)
+                .await
+                .context("Failed to save processing wait time")?;
+            println!("Processing wait time has been set.")
+        }
+        WriteableContent::FolderPerModel { flag } => {
+            configuration
+                .set_folder_per_model(*flag)
+                .await
+                .context("Failed to save folder per model layout")?;
+            println!("Folder per model layout has been set.")
+        }
+        WriteableContent::TerminalTitle { flag } => {
+            configuration
+                .set_terminal_title(*flag)
+                .await
+                .context("Failed to save terminal title progress")?;
+            println!("Terminal title progress has been set.")
+        }
+        WriteableContent::ReadmeStats { flag } => {
+            configuration
+                .set_readme_stats(*flag)
+                .await
+                .context("Failed to save readme stats")?;
+            println!("Readme stats has been set.")
+        }
+        WriteableContent::CoverWidth { width } => {
+            configuration
+                .set_cover_width(*width)
+                .await
+                .context("Failed to save cover width")?;
+            println!("Cover width has been set.")
+        }
+        WriteableContent::ImagesCacheTtl { hours } => {
+            configuration
+                .set_images_cache_hours(*hours)
+                .await
+                .context("Failed to save community images cache time")?;
+            println!("Community images cache time has been set.")
+        }
+        WriteableContent::UserAgent { user_agent } => {
+            configuration
+                .set_user_agent(user_agent.clone())
+                .await
+                .context("Failed to save user agent")?;
+            println!("User agent has been set.")
+        }
+        WriteableContent::Header { name, value } => {
+            configuration
+                .set_header(name.clone(), value.clone())
+                .await
+                .context("Failed to save extra header")?;
+            println!("Extra header {name} has been set.")
+        }
+        WriteableContent::SelectionRule {
+            base_model,
+            preference,
+            remove,
+        } => {
+            if *remove {
+                let removed = configuration
+                    .remove_selection_rule(base_model)
+                    .await
+                    .context("Failed to remove selection rule")?;
+                if removed {
+                    println!("Selection rule for {base_model} has been removed.")
+                } else {
+                    println!("No selection rule for {base_model}.")
+                }
+            } else {
+                let prefer = preference.clone().unwrap_or_default();
+                prefer.parse::<crate::civitai::FilePreference>()?;
+                configuration
+                    .set_selection_rule(crate::configuration::SelectionRule {
+                        base_model: base_model.clone(),
+                        prefer,
+                    })
+                    .await
+                    .context("Failed to save selection rule")?;
+                println!("Selection rule for {base_model} has been set.")
+            }
+        }
+        WriteableContent::Default { key, value } => {
+            let value = key.validate(value)?;
+            configuration
+                .set_default_flag(*key, &value)
+                .await
+                .context("Failed to save flag default")?;
+            println!("Default of {key} has been set to {value}.")
+        }
+        WriteableContent::Network {
+            connect_timeout,
+            request_timeout,
+            idle_timeout,
+        } => {
+            configuration
+                .set_network(*connect_timeout, *request_timeout, *idle_timeout)
+                .await
+                .context("Failed to save network timeouts")?;
+            println!("Network timeouts have been set.")
+        }
     }
+    Ok(())
 }
 
-async fn clear_config(action: &ReadableContent) {
+async fn clear_config(action: &ReadableContent) -> anyhow::Result<()> {
     let mut configuration = crate::configuration::CONFIGURATION.write().await;
     match action {
         ReadableContent::CivitaiKey => {
             configuration
                 .clear_civitai_api_key()
                 .await
-                .expect("Failed to clear Civitai access key.");
+                .context("Failed to clear Civitai access key")?;
             println!("Civitai access key has been cleared.")
         }
+        ReadableContent::CivitaiApiBase => {
+            configuration
+                .clear_civitai_api_base_url()
+                .await
+                .context("Failed to clear Civitai API base URL")?;
+            println!("Civitai API base URL has been reseted.")
+        }
+        ReadableContent::DownloadUrlRewrite => {
+            configuration
+                .clear_download_url_rewrite()
+                .await
+                .context("Failed to clear download URL rewrite")?;
+            println!("Download URL rewrite has been cleared.")
+        }
+        ReadableContent::AuthMode => {
+            configuration
+                .clear_auth_mode()
+                .await
+                .context("Failed to clear download authorization mode")?;
+            println!("Download authorization mode has been reseted.")
+        }
         ReadableContent::HuggingFaceKey => {
             configuration
                 .clear_huggingface_api_key()
                 .await
-                .expect("Failed to clear HuggingFace access key.");
+                .context("Failed to clear HuggingFace access key")?;
             println!("HuggingFace access key has been cleared.")
         }
         ReadableContent::Proxy => {
             configuration
                 .clear_proxy()
                 .await
-                .expect("Failed to clear proxy server settings.");
+                .context("Failed to clear proxy server settings")?;
             println!("Proxy server settings have been cleared.")
         }
         ReadableContent::Retry => {
             configuration
                 .clear_backoff()
                 .await
-                .expect("Failed to clear retry policy.");
+                .context("Failed to clear retry policy")?;
             println!("Retry policy has been reseted.")
         }
+        ReadableContent::Network => {
+            configuration
+                .clear_network()
+                .await
+                .context("Failed to clear network timeouts")?;
+            println!("Network timeouts have been reseted.")
+        }
+        ReadableContent::OutputDir => {
+            configuration
+                .clear_output_dir()
+                .await
+                .context("Failed to clear default output directory")?;
+            println!("Default output directory has been cleared.")
+        }
+        ReadableContent::TempDir => {
+            configuration
+                .clear_temp_dir()
+                .await
+                .context("Failed to clear temporary directory")?;
+            println!(
+                "Temporary directory has been cleared, partial downloads are written beside the destination."
+            )
+        }
+        ReadableContent::ComfyUi => {
+            configuration
+                .clear_comfyui()
+                .await
+                .context("Failed to clear ComfyUI model paths")?;
+            println!("ComfyUI model paths have been cleared.")
+        }
+        ReadableContent::Webui => {
+            configuration
+                .clear_webui()
+                .await
+                .context("Failed to clear Stable Diffusion webui root")?;
+            println!("Stable Diffusion webui root has been cleared.")
+        }
+        ReadableContent::VideoCover => {
+            configuration
+                .clear_video_cover_mode()
+                .await
+                .context("Failed to clear video cover mode")?;
+            println!("Video cover mode has been reseted.")
+        }
+        ReadableContent::ExistingCheck => {
+            configuration
+                .clear_existing_check()
+                .await
+                .context("Failed to clear existing file check")?;
+            println!("Existing file check has been reseted.")
+        }
+        ReadableContent::SkipDecisionTtl => {
+            configuration
+                .clear_skip_decision_hours()
+                .await
+                .context("Failed to clear skip decision time")?;
+            println!("Skip decision time has been reseted.")
+        }
+        ReadableContent::ProcessingWait => {
+            configuration
+                .clear_processing_wait_minutes()
+                .await
+                .context("Failed to clear processing wait time")?;
+            println!("Processing wait time has been reseted.")
+        }
+        ReadableContent::FolderPerModel => {
+            configuration
+                .clear_folder_per_model()
+                .await
+                .context("Failed to clear folder per model layout")?;
+            println!("Folder per model layout has been reseted.")
+        }
+        ReadableContent::TerminalTitle => {
+            configuration
+                .clear_terminal_title()
+                .await
+                .context("Failed to clear terminal title progress")?;
+            println!("Terminal title progress has been reseted.")
+        }
+        ReadableContent::ReadmeStats => {
+            configuration
+                .clear_readme_stats()
+                .await
+                .context("Failed to clear readme stats")?;
+            println!("Readme stats has been reseted.")
+        }
+        ReadableContent::CoverWidth => {
+            configuration
+                .clear_cover_width()
+                .await
+                .context("Failed to clear cover width")?;
+            println!("Cover width has been reseted.")
+        }
+        ReadableContent::ImagesCacheTtl => {
+            configuration
+                .clear_images_cache_hours()
+                .await
+                .context("Failed to clear community images cache time")?;
+            println!("Community images cache time has been reseted.")
+        }
+        ReadableContent::UserAgent => {
+            configuration
+                .clear_user_agent()
+                .await
+                .context("Failed to clear user agent")?;
+            println!("User agent has been reseted.")
+        }
+        ReadableContent::Headers => {
+            configuration
+                .clear_headers()
+                .await
+                .context("Failed to clear extra headers")?;
+            println!("Extra headers have been cleared.")
+        }
+        ReadableContent::SelectionRules => {
+            configuration
+                .clear_selection_rules()
+                .await
+                .context("Failed to clear selection rules")?;
+            println!("Selection rules have been cleared.")
+        }
+        ReadableContent::Defaults => {
+            configuration
+                .clear_default_flags()
+                .await
+                .context("Failed to clear flag defaults")?;
+            println!("Flag defaults have been cleared.")
+        }
     }
+    Ok(())
 }
 
 async fn show_all_config() {
     let configuration = crate::configuration::CONFIGURATION.read().await;
+    match configuration.active_profile.as_ref() {
+        Some(profile) if configuration.profiles.contains_key(profile) => {
+            println!("Profile: {profile}")
+        }
+        Some(profile) => println!("Profile: {profile} (not configured yet)"),
+        None => println!("Profile: [NONE]"),
+    }
+    if !configuration.profiles.is_empty() {
+        println!(
+            "  Configured profiles: {}",
+            configuration
+                .profiles
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
     println!(
         "Civitai access key: {}",
         configuration
@@ -231,6 +1139,12 @@ async fn show_all_config() {
             .clone()
             .unwrap_or("[NOT SET]".to_string())
     );
+    println!("Civitai API base URL: {}", configuration.civitai.api_base());
+    print_download_url_rewrite(&configuration.civitai);
+    println!(
+        "Download authorization: {}",
+        configuration.civitai.auth_mode
+    );
     println!(
         "Hugging Face access key: {}",
         configuration
@@ -248,10 +1162,38 @@ async fn show_all_config() {
             .unwrap_or("[NOT SET]".to_string())
     );
     println!("Use Proxy: {}", configuration.proxy.use_proxy);
+    print_retry_policy(&configuration.backoff);
+    println!(
+        "Default output directory: {}",
+        configuration
+            .download
+            .output_dir
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or("[NOT SET]".to_string())
+    );
+    print_temp_dir(&configuration.download);
+    println!(
+        "Folder per model layout: {}",
+        configuration.download.folder_per_model
+    );
+    println!(
+        "Existing file check: {}",
+        configuration.download.existing_check
+    );
+    print_skip_decision_ttl(&configuration.download);
+    print_processing_wait(&configuration.download);
     println!(
-        "When action failed, will retry in {} seconds, increase {:.02}x time when continuous failing, and keep retrying in {} times.",
-        configuration.backoff.initial_interval,
-        configuration.backoff.multiplier,
-        configuration.backoff.max_retry,
+        "Terminal title progress: {}",
+        configuration.download.terminal_title
     );
+    println!("Readme stats: {}", configuration.download.readme_stats);
+    println!("Video cover mode: {}", configuration.cover.video);
+    print_cover_width(configuration.cover.width);
+    print_images_cache_ttl(&configuration.civitai);
+    print_network_config(&configuration.network);
+    print_default_flags(&configuration.defaults);
+    print_selection_rules(&configuration.selection_rules);
+    print_comfyui_config(&configuration.comfyui);
+    print_webui_config(&configuration.webui);
 }
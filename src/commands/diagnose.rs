@@ -0,0 +1,276 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Context;
+use clap::Args;
+use serde::Serialize;
+use time::UtcDateTime;
+
+use super::json_output::print_json;
+use crate::{
+    civitai::{FailureCategory, FileTransferStats},
+    configuration::ProxyMode,
+    utils::{ByteUnits, datetime_to_iso_string, format_bytes, format_rate},
+};
+
+/// Failure rate below which downloads are taken as healthy.
+const HEALTHY_FAILURE_RATE: f64 = 0.1;
+
+#[derive(Args, Default)]
+pub struct DiagnoseOptions {
+    #[arg(
+        long,
+        help = "Only consider files attempted in the given number of recent days.",
+        default_value = "30"
+    )]
+    pub days: u32,
+    #[arg(
+        long,
+        short = 'n',
+        help = "Number of the flakiest files to list.",
+        default_value = "10"
+    )]
+    pub limit: usize,
+    #[arg(long, help = "Print the diagnostics as JSON.", default_value = "false")]
+    pub json: bool,
+}
+
+/// Diagnostics printed by `--json`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DiagnoseJson<'a> {
+    days: u32,
+    files: usize,
+    attempts: u64,
+    failures: u64,
+    failure_rate: Option<f64>,
+    retried_bytes: u64,
+    failure_categories: &'a BTreeMap<FailureCategory, u64>,
+    hosts: Vec<HostJson<'a>>,
+    proxy: &'a str,
+    failure_rate_via_proxy: Option<f64>,
+    failure_rate_direct: Option<f64>,
+    flaky_files: Vec<FlakyFileJson<'a>>,
+    advice: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HostJson<'a> {
+    host: &'a str,
+    files: usize,
+    bytes: u64,
+    bytes_per_second: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FlakyFileJson<'a> {
+    file_id: u64,
+    file_name: &'a str,
+    host: Option<&'a str>,
+    attempts: u64,
+    failures: u64,
+    retried_bytes: u64,
+    failure_categories: &'a BTreeMap<FailureCategory, u64>,
+    via_proxy: bool,
+    updated_at: Option<String>,
+}
+
+impl<'a> From<&'a FileTransferStats> for FlakyFileJson<'a> {
+    fn from(file: &'a FileTransferStats) -> Self {
+        Self {
+            file_id: file.file_id,
+            file_name: &file.file_name,
+            host: file.host.as_deref(),
+            attempts: file.attempts,
+            failures: file.failures,
+            retried_bytes: file.retried_bytes,
+            failure_categories: &file.failure_categories,
+            via_proxy: file.via_proxy,
+            updated_at: UtcDateTime::from_unix_timestamp(file.updated_at)
+                .ok()
+                .map(|datetime| datetime_to_iso_string(&datetime)),
+        }
+    }
+}
+
+pub async fn process_diagnose_downloads(options: &DiagnoseOptions) -> anyhow::Result<()> {
+    let since = UtcDateTime::now().unix_timestamp() - i64::from(options.days) * 24 * 3600;
+    let mut stats = crate::cache_db::list_file_transfer_stats()
+        .context("Failed to read download statistics")?
+        .into_iter()
+        .filter(|file| file.updated_at >= since)
+        .collect::<Vec<_>>();
+    stats.sort_by(|a, b| {
+        b.failures
+            .cmp(&a.failures)
+            .then_with(|| b.retried_bytes.cmp(&a.retried_bytes))
+    });
+    let config = crate::configuration::CONFIGURATION.read().await;
+    let proxy_in_use = config.proxy.is_in_use();
+    let proxy_now = match config.proxy.mode() {
+        ProxyMode::Configured(url) => format!("configured proxy {url}"),
+        ProxyMode::Disabled => "direct connection, configured proxy disabled".to_string(),
+        ProxyMode::Environment if proxy_in_use => "proxy from environment variables".to_string(),
+        ProxyMode::Environment => "direct connection".to_string(),
+    };
+    drop(config);
+
+    let attempts = stats.iter().map(|file| file.attempts).sum::<u64>();
+    let failures = stats.iter().map(|file| file.failures).sum::<u64>();
+    let retried_bytes = stats.iter().map(|file| file.retried_bytes).sum::<u64>();
+    let mut categories: BTreeMap<FailureCategory, u64> = BTreeMap::new();
+    for file in stats.iter() {
+        for (category, count) in file.failure_categories.iter() {
+            *categories.entry(*category).or_default() += count;
+        }
+    }
+    let hosts = host_speeds(&stats);
+    let proxy_rates = [true, false].map(|via_proxy| {
+        let files = stats
+            .iter()
+            .filter(|file| file.via_proxy == via_proxy)
+            .collect::<Vec<_>>();
+        let attempts = files.iter().map(|file| file.attempts).sum::<u64>();
+        let failures = files.iter().map(|file| file.failures).sum::<u64>();
+        (files.len(), failure_rate(failures, attempts))
+    });
+    let flaky_files = stats
+        .iter()
+        .filter(|file| file.failures > 0)
+        .take(options.limit)
+        .collect::<Vec<_>>();
+    let advice = advise(failures, attempts, &categories, proxy_in_use);
+
+    if options.json {
+        return print_json(&DiagnoseJson {
+            days: options.days,
+            files: stats.len(),
+            attempts,
+            failures,
+            failure_rate: failure_rate(failures, attempts),
+            retried_bytes,
+            failure_categories: &categories,
+            hosts: hosts
+                .iter()
+                .map(|(host, (files, bytes, secs))| HostJson {
+                    host,
+                    files: *files,
+                    bytes: *bytes,
+                    bytes_per_second: *bytes as f64 / secs.max(0.001),
+                })
+                .collect(),
+            proxy: &proxy_now,
+            failure_rate_via_proxy: proxy_rates[0].1,
+            failure_rate_direct: proxy_rates[1].1,
+            flaky_files: flaky_files.iter().map(|file| (*file).into()).collect(),
+            advice: &advice,
+        });
+    }
+
+    if stats.is_empty() {
+        println!(
+            "No download attempt recorded in the last {} days.",
+            options.days
+        );
+        return Ok(());
+    }
+    println!(
+        "Last {} days: {} files, {attempts} attempts, {failures} failed ({}), {} received before failures.",
+        options.days,
+        stats.len(),
+        format_percent(failure_rate(failures, attempts)),
+        format_bytes(retried_bytes, ByteUnits::Decimal)
+    );
+    if !categories.is_empty() {
+        println!("\nFailures by category:");
+        for (category, count) in categories.iter() {
+            println!("  {:<22}{count:>6}", category.to_string());
+        }
+    }
+    if !hosts.is_empty() {
+        println!("\nAverage speed by host:");
+        for (host, (files, bytes, secs)) in hosts.iter() {
+            println!(
+                "  {host}  {} ({files} files)",
+                format_rate(*bytes, &Duration::from_secs_f64(*secs), ByteUnits::Decimal)
+            );
+        }
+    }
+    println!("\nNetwork now: {proxy_now}.");
+    for ((files, rate), label) in proxy_rates.iter().zip(["through proxy", "direct"]) {
+        if let Some(rate) = rate {
+            println!(
+                "  Failure rate {label}: {} ({files} files)",
+                format_percent(Some(*rate))
+            );
+        }
+    }
+    if !flaky_files.is_empty() {
+        println!("\nFlaky files:");
+        for file in flaky_files.iter() {
+            println!(
+                "  {} ({}): {} of {} attempts failed, {} received before failures, host {}",
+                file.file_name,
+                file.file_id,
+                file.failures,
+                file.attempts,
+                format_bytes(file.retried_bytes, ByteUnits::Decimal),
+                file.host.as_deref().unwrap_or("unknown")
+            );
+        }
+    }
+    println!("\n{advice}");
+    Ok(())
+}
+
+/// Files, bytes and seconds of completed transfers by the host serving them.
+fn host_speeds(stats: &[FileTransferStats]) -> BTreeMap<String, (usize, u64, f64)> {
+    let mut hosts: BTreeMap<String, (usize, u64, f64)> = BTreeMap::new();
+    for file in stats.iter().filter(|file| file.transfer_secs > 0.0) {
+        let Some(host) = file.host.as_ref() else {
+            continue;
+        };
+        let entry = hosts.entry(host.clone()).or_default();
+        entry.0 += 1;
+        entry.1 += file.transferred_bytes;
+        entry.2 += file.transfer_secs;
+    }
+    hosts
+}
+
+fn failure_rate(failures: u64, attempts: u64) -> Option<f64> {
+    (attempts > 0).then(|| failures as f64 / attempts as f64)
+}
+
+fn format_percent(rate: Option<f64>) -> String {
+    rate.map(|rate| format!("{:.0}%", rate * 100.0))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Tells whether the failures point at the local network or at Civitai.
+fn advise(
+    failures: u64,
+    attempts: u64,
+    categories: &BTreeMap<FailureCategory, u64>,
+    via_proxy: bool,
+) -> String {
+    if failure_rate(failures, attempts).is_none_or(|rate| rate < HEALTHY_FAILURE_RATE) {
+        return "Downloads look healthy.".to_string();
+    }
+    let network_side = categories
+        .iter()
+        .filter(|(category, _)| category.is_network_side())
+        .map(|(_, count)| count)
+        .sum::<u64>();
+    if network_side * 2 > failures {
+        if via_proxy {
+            "Most failures happen on the connection, try a different or closer proxy server."
+                .to_string()
+        } else {
+            "Most failures happen on the connection, try downloading through a proxy server (imd config set proxy).".to_string()
+        }
+    } else {
+        "Most failures are answered by Civitai or its CDN, consider reporting them to Civitai with the file ids above.".to_string()
+    }
+}
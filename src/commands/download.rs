@@ -1,17 +1,73 @@
-use std::path::PathBuf;
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use clap::Args;
+use anyhow::{Context, bail};
+use clap::{Args, ValueEnum};
+
+use super::flags::FlagResolver;
+use crate::{
+    artifacts::{Artifact, ArtifactSet},
+    civitai::{CommercialUsePolicy, SizeBudget},
+    configuration::{Configuration, DefaultFlag},
+    downloader::Platform,
+    errors::{
+        CommercialUseNotAllowedError, EarlyAccessOnlyError, IncompleteArtifactsError,
+        InvalidInputError, SizeBudgetExceededError,
+    },
+    events,
+    hugging_face::HuggingFaceTarget,
+    integrations::InstallTarget,
+    progress::ReporterKind,
+};
+
+/// Most models listed from a user page, creators with more have the rest left out.
+const USER_MODELS_CAP: usize = 500;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Progress bars and prompts for human.
+    #[default]
+    Human,
+    /// Newline-delimited JSON events on stdout, without prompts.
+    Json,
+}
 
 #[derive(Args, Default)]
 pub struct DownloadOptions {
-    #[arg(help = "The model detail page URL.")]
-    pub url: String,
+    #[arg(
+        help = "The model detail page URL, a user page URL, the AIR of a model version, or a HuggingFace file URL.",
+        required_unless_present_any = ["resume_session", "list_sessions"]
+    )]
+    pub url: Option<String>,
     #[arg(
         short = 'o',
         long = "output",
-        help = "The directory stores the download files."
+        help = "The directory stores the download files, defaults to the configured output directory. Give - to stream a single file to stdout."
     )]
     pub output_path: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Write partial downloads and cover scratch files in this directory, moving only finished files to the output directory. Overrides the configured temporary directory."
+    )]
+    pub temp_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        help = "Where to place the downloaded files, comfyui and webui place them into the model directories of the UI by model type.",
+        default_value_t = InstallTarget::Directory,
+        conflicts_with = "output_path"
+    )]
+    pub target: InstallTarget,
+    #[arg(
+        long,
+        help = "Root directory of Stable Diffusion webui, overrides the configured one and implies --target webui.",
+        conflicts_with = "output_path"
+    )]
+    pub webui_root: Option<PathBuf>,
     #[arg(
         long = "fix-missing",
         short = 'f',
@@ -23,60 +79,928 @@ pub struct DownloadOptions {
         long,
         short = 'c',
         help = "Skip collecting community images metadata.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub skip_community: Option<bool>,
+    #[arg(
+        long,
+        help = "Skip early access versions, fall back to the newest version available to everyone.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub skip_early_access: Option<bool>,
+    #[arg(
+        long,
+        help = "Download the newest version without prompting.",
+        default_value = "false",
+        conflicts_with = "version_name"
+    )]
+    pub latest: bool,
+    #[arg(
+        long,
+        help = "Download the newest version whose name contains the given text without prompting."
+    )]
+    pub version_name: Option<String>,
+    #[arg(
+        long = "version-id",
+        help = "Download the specified version, can be given multiple times."
+    )]
+    pub version_ids: Vec<u64>,
+    #[arg(
+        long,
+        help = "Download all versions of the model, each into its own subdirectory.",
+        default_value = "false"
+    )]
+    pub all_versions: bool,
+    #[arg(
+        long,
+        help = "Download every model of a user page without prompting.",
+        default_value = "false"
+    )]
+    pub all_models: bool,
+    #[arg(
+        long = "model-type",
+        value_name = "TYPE",
+        help = "Only list models of the given type from a user page, like LORA or Checkpoint, can be given multiple times."
+    )]
+    pub model_types: Vec<String>,
+    #[arg(
+        long,
+        help = "Select multiple versions to download in the prompt.",
         default_value = "false"
     )]
-    pub skip_community: bool,
+    pub multi: bool,
+    #[arg(
+        long,
+        help = "Download files that Civitai's pickle or virus scan marked as dangerous.",
+        default_value = "false"
+    )]
+    pub allow_unsafe: bool,
+    #[arg(
+        long,
+        value_name = "PREFERENCE",
+        help = "Pick the files of each version without prompting, the model file matching most terms plus the files of each +type, like fp16,pruned,safetensor+vae. Overrides the configured selection rules."
+    )]
+    pub prefer: Option<crate::civitai::FilePreference>,
+    #[arg(
+        long,
+        help = "Hardlink or copy files downloaded to another directory before, instead of downloading them again.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub link_existing: Option<bool>,
+    #[arg(
+        long,
+        help = "Hash a file already in the output directory fully before keeping it, instead of the configured check.",
+        default_value = "false"
+    )]
+    pub verify_existing: bool,
+    #[arg(
+        long,
+        help = "Only save the readme, cover and metadata files of the primary file, without downloading the model file.",
+        default_value = "false",
+        conflicts_with_all = ["resume_session", "list_sessions"]
+    )]
+    pub meta_only: bool,
+    #[arg(
+        long,
+        value_name = "LIST",
+        help = "Files to produce for each model, a comma list of model, hash, readme, cover, json, triggers and samples, or all. Defaults to everything but triggers."
+    )]
+    pub artifacts: Option<ArtifactSet>,
+    #[arg(
+        long,
+        value_name = "FILE_NAME",
+        help = "Name of the model file the metadata is saved for with --meta-only, defaults to the primary file name.",
+        requires = "meta_only"
+    )]
+    pub for_file: Option<String>,
+    #[arg(
+        long,
+        value_enum,
+        help = "Output format, json emits newline-delimited events on stdout and never prompts.",
+        default_value_t = OutputFormat::Human
+    )]
+    pub output_format: OutputFormat,
+    #[arg(
+        long,
+        help = "Resolve and print what would be downloaded, without downloading or writing anything.",
+        default_value = "false"
+    )]
+    pub dry_run: bool,
+    #[arg(
+        long,
+        help = "Check whether the access key may download the primary file of the resolved versions, without downloading anything.",
+        default_value = "false",
+        conflicts_with_all = ["dry_run", "meta_only", "resume_session", "list_sessions"]
+    )]
+    pub check_access: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "What to save as cover when a version only has videos, overrides the configured mode."
+    )]
+    pub video_cover: Option<crate::configuration::VideoCoverMode>,
+    #[arg(
+        long,
+        help = "Choose the cover among the images of the version, previewed on terminals showing images inline.",
+        default_value = "false"
+    )]
+    pub pick_cover: bool,
+    #[arg(
+        long,
+        help = "Extract model files that are zip archives into a directory named after the archive.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub extract: Option<bool>,
+    #[arg(
+        long,
+        help = "Delete zip archives once they are extracted by --extract.",
+        default_value = "false"
+    )]
+    pub remove_archive: bool,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "true",
+        help = "Save files into <model name>/<version name>/ under the output directory, overrides the configured layout."
+    )]
+    pub folder_per_model: Option<bool>,
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = crate::utils::parse_size,
+        help = "Stop downloading model files once they add up to the given size, like 500M or 5G, the rest can be resumed later."
+    )]
+    pub max_total_size: Option<u64>,
+    #[arg(
+        long,
+        help = "List the other versions of the model with their descriptions in the readme.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub include_version_history: Option<bool>,
+    #[arg(
+        long,
+        help = "Most other versions listed by --include-version-history, defaults to 10."
+    )]
+    pub history_limit: Option<usize>,
+    #[arg(
+        long,
+        help = "Most community image prompts listed in the readme, images with identical prompts count once, defaults to 15."
+    )]
+    pub max_prompt_sections: Option<usize>,
+    #[arg(
+        long,
+        help = "Cut prompts in the readme longer than this many characters, linking to the image page, 0 keeps them whole, defaults to 1000."
+    )]
+    pub max_prompt_length: Option<usize>,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Show download counts and ratings under the title of the readme, overrides the configured switch."
+    )]
+    pub readme_stats: Option<bool>,
+    #[arg(
+        long,
+        value_name = "ACTION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "abort",
+        help = "Check that models allow commercial use, refusing the ones that do not with abort (the default) or only warning with warn."
+    )]
+    pub require_commercial_use: Option<CommercialUsePolicy>,
+    #[arg(
+        long,
+        value_name = "SESSION",
+        help = "Resume the interrupted download of the given model id, or by the session key listed by --list-sessions when the model has several.",
+        conflicts_with = "url"
+    )]
+    pub resume_session: Option<String>,
+    #[arg(
+        long,
+        help = "List interrupted downloads that can be resumed.",
+        default_value = "false",
+        conflicts_with_all = ["url", "resume_session"]
+    )]
+    pub list_sessions: bool,
+    #[arg(
+        long,
+        help = "Fail when any step fails, like the cover image, community images, Civitai Helper files or hash check.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub strict: Option<bool>,
+    #[arg(
+        long,
+        help = "Print the effective value of flags with configurable defaults, and where it comes from.",
+        default_value = "false"
+    )]
+    pub show_effective_flags: bool,
 }
 
-pub async fn process_download_options(options: &DownloadOptions) {
-    let target_url = reqwest::Url::parse(&options.url).expect("The given url is invalid");
+/// Flags of a download after applying environment and configured defaults.
+struct DownloadFlags {
+    artifacts: ArtifactSet,
+    skip_early_access: bool,
+    link_existing: bool,
+    strict: bool,
+    version_history: Option<usize>,
+    extract: bool,
+}
 
-    if let Some(path) = options.output_path.as_ref() {
-        if !path.exists() && options.fix_missing_dirs {
-            std::fs::create_dir_all(path).expect("Failed to create output directory");
+impl DownloadFlags {
+    fn resolve(options: &DownloadOptions, config: &Configuration) -> anyhow::Result<Self> {
+        let mut flags = FlagResolver::new(&config.defaults);
+        let skip_community = flags.switch(DefaultFlag::SkipCommunity, options.skip_community)?;
+        let mut artifacts = flags
+            .value(
+                DefaultFlag::Artifacts,
+                options.artifacts,
+                ArtifactSet::standard(),
+            )?
+            .skipping_community(skip_community);
+        if options.meta_only {
+            artifacts = artifacts.without(Artifact::Model);
+        }
+        let resolved = Self {
+            artifacts,
+            skip_early_access: flags
+                .switch(DefaultFlag::SkipEarlyAccess, options.skip_early_access)?,
+            link_existing: flags.switch(DefaultFlag::LinkExisting, options.link_existing)?,
+            strict: flags.switch(DefaultFlag::Strict, options.strict)?,
+            version_history: flags
+                .version_history(options.include_version_history, options.history_limit)?,
+            extract: flags.switch(DefaultFlag::Extract, options.extract)?,
+        };
+        if options.show_effective_flags {
+            flags.print();
         }
+        Ok(resolved)
     }
+}
 
-    let target_platform = crate::downloader::detect_platform(&target_url);
+pub async fn process_download_options(options: &DownloadOptions) -> anyhow::Result<()> {
+    let to_stdout = options.output_path.as_deref() == Some(Path::new("-"));
+    if to_stdout {
+        events::reserve_stdout();
+    }
+    if options.output_format == OutputFormat::Json {
+        events::enable();
+    }
+    let mut config = crate::configuration::current().await;
+    config.override_video_cover_mode(options.video_cover);
+    config.override_readme_stats(options.readme_stats);
+    config.override_temp_dir(options.temp_dir.clone());
+    config.override_existing_check(
+        options
+            .verify_existing
+            .then_some(crate::configuration::ExistingCheck::Full),
+    );
+    if events::enabled() && options.pick_cover {
+        return Err(InvalidInputError(
+            "JSON output can not prompt for the cover, leave out --pick-cover.".to_string(),
+        )
+        .into());
+    }
+    if options.list_sessions {
+        return list_sessions();
+    }
+    let flags = DownloadFlags::resolve(options, &config)?;
+    if !options.dry_run {
+        crate::downloader::ensure_online("Downloading models")?;
+    }
+    if to_stdout {
+        return stream_to_stdout(options, &flags, &config).await;
+    }
+    if let Some(selector) = options.resume_session.as_deref() {
+        return resume_session(options, &flags, &config, selector).await;
+    }
+    let download_target = parse_target(options.url.as_deref().unwrap_or_default())?;
+
+    let output_path = match options.output_path.clone() {
+        Some(path) => Some(path),
+        None => config.download.output_dir.clone(),
+    };
+    let install_target = options
+        .target
+        .with_webui_root(options.webui_root.as_deref())?;
+    if options.check_access && matches!(download_target, DownloadTarget::HuggingFace(_)) {
+        return Err(
+            InvalidInputError("Checking access only supports Civitai models.".to_string()).into(),
+        );
+    }
+    if options.meta_only && matches!(download_target, DownloadTarget::HuggingFace(_)) {
+        return Err(InvalidInputError(
+            "Saving only the metadata only supports Civitai models.".to_string(),
+        )
+        .into());
+    }
+    if options.artifacts.is_some() && matches!(download_target, DownloadTarget::HuggingFace(_)) {
+        return Err(InvalidInputError(
+            "Choosing artifacts only supports Civitai models.".to_string(),
+        )
+        .into());
+    }
+    if install_target != InstallTarget::Directory
+        && matches!(download_target, DownloadTarget::HuggingFace(_))
+    {
+        return Err(InvalidInputError(
+            "Installing into a model UI only supports Civitai models.".to_string(),
+        )
+        .into());
+    }
+    let model_dirs = crate::integrations::model_directories(
+        &config,
+        install_target,
+        options.webui_root.as_deref(),
+    )?;
+    // 试运行不写入任何内容，因此也不检查目标目录；模型界面的目录在下载时创建
+    if !options.dry_run && !options.check_access && model_dirs.is_none() {
+        let target_dir = match output_path.clone() {
+            Some(path) => path,
+            None => std::env::current_dir().context("Unable to get current working directory")?,
+        };
+        crate::downloader::validate_output_dir(&target_dir, options.fix_missing_dirs)?;
+    }
 
-    match target_platform {
-        Some(crate::downloader::Platform::Civitai) => {
-            println!("Downloading from Civitai...");
-            if !crate::configuration::check_civitai_key_exists().await {
-                println!("Civitai access key is not set. Please set it first.");
-                return;
+    let civitai_target = match download_target {
+        DownloadTarget::Civitai(target) => target,
+        DownloadTarget::HuggingFace(HuggingFaceTarget::File(file)) => {
+            events::message(format!(
+                "Downloading {} from HuggingFace repository {}...",
+                file.file_name(),
+                file.repo.id
+            ));
+            let target_dir = match output_path {
+                Some(path) => path,
+                None => {
+                    std::env::current_dir().context("Unable to get current working directory")?
+                }
+            };
+            let client =
+                crate::downloader::make_client(&config).context("Failed to initialize client")?;
+            let summary = crate::hugging_face::download_huggingface_file(
+                &client,
+                &config,
+                &file,
+                &target_dir,
+                options.dry_run,
+                reporter_kind(options),
+            )
+            .await
+            .context("Failed to download HuggingFace file")?;
+            summary.ensure_complete(flags.strict)?;
+            if options.dry_run {
+                events::message("Dry run completed, nothing written.");
+            } else {
+                events::message("Download completed.");
             }
+            return Ok(());
+        }
+        DownloadTarget::HuggingFace(HuggingFaceTarget::Repository(repo)) => {
+            return Err(InvalidInputError(format!(
+                "Selecting files of HuggingFace repository {} is not supported yet, give the URL of a single file like https://huggingface.co/{}/resolve/{}/<file path>.",
+                repo.id, repo.id, repo.revision
+            ))
+            .into());
+        }
+    };
+
+    events::message(if options.check_access {
+        "Checking access on Civitai..."
+    } else {
+        "Downloading from Civitai..."
+    });
+    let civitai_client = make_civitai_client(&config)?;
+    let mut version_selection = crate::civitai::VersionSelection {
+        preferred_id: None,
+        skip_early_access: flags.skip_early_access,
+        latest: options.latest,
+        name_pattern: options.version_name.clone(),
+        ids: options.version_ids.clone(),
+        all: options.all_versions,
+        multi: options.multi,
+    };
+    if !matches!(civitai_target, CivitaiTarget::User(_))
+        && (options.all_models || !options.model_types.is_empty())
+    {
+        return Err(InvalidInputError(
+            "--all-models and --model-type only apply to user pages like https://civitai.com/user/<username>.".to_string(),
+        )
+        .into());
+    }
+    let model_id = match civitai_target {
+        CivitaiTarget::User(_) if options.check_access => {
+            return Err(InvalidInputError(
+                "--check-access needs the URL of a model, user pages are checked while downloading them.".to_string(),
+            )
+            .into());
+        }
+        CivitaiTarget::User(username) => {
+            let behavior = crate::civitai::DownloadBehavior {
+                unattended: true,
+                ..download_behavior(options, &flags, &config, model_dirs)
+            };
+            return download_user_models(
+                &civitai_client,
+                &config,
+                options,
+                &flags,
+                &username,
+                output_path.as_ref(),
+                &behavior,
+            )
+            .await;
+        }
+        CivitaiTarget::Image(image_id) => {
             let (model_id, model_version_id) =
-                match crate::civitai::try_parse_civitai_model_url(&target_url) {
-                    Ok(result) => result,
-                    Err(error) => {
-                        panic!("{}", error);
-                    }
-                };
-            let civitai_client = crate::downloader::make_client()
+                crate::civitai::select_image_model_version(&civitai_client, &config, image_id)
+                    .await
+                    .context("Failed to resolve the models used by image")?;
+            version_selection.ids = vec![model_version_id];
+            model_id
+        }
+        CivitaiTarget::Model(model_id, model_version_id) => {
+            version_selection.preferred_id = model_version_id;
+            model_id
+        }
+    };
+    if events::enabled() && !resolves_without_prompt(&mut version_selection) {
+        return Err(InvalidInputError(
+            "JSON output can not prompt for version, use --latest, --version-name, --version-id or --all-versions, or give a URL with modelVersionId.".to_string(),
+        )
+        .into());
+    }
+    if options.check_access {
+        // 只检查主文件，和无人值守的下载一致
+        let behavior = crate::civitai::DownloadBehavior {
+            unattended: true,
+            ..download_behavior(options, &flags, &config, model_dirs)
+        };
+        let inaccessible = crate::civitai::check_model_access(
+            &civitai_client,
+            &config,
+            model_id,
+            &version_selection,
+            output_path.as_ref(),
+            &behavior,
+        )
+        .await
+        .context("Failed to check access to model file(s)")?;
+        if inaccessible > 0 {
+            bail!("{inaccessible} file(s) can not be downloaded with the configured access key.");
+        }
+        events::message("All files can be downloaded with the configured access key.");
+        return Ok(());
+    }
+    let behavior = download_behavior(options, &flags, &config, model_dirs);
+    let summary = crate::civitai::download_from_civitai(
+        &civitai_client,
+        &config,
+        model_id,
+        &version_selection,
+        output_path.as_ref(),
+        &behavior,
+    )
+    .await
+    .context("Failed to download model file(s)")?;
+    summary.ensure_complete(flags.strict)?;
+    if options.dry_run {
+        events::message("Dry run completed, nothing written.");
+    } else {
+        events::message("Download completed.");
+    }
+    Ok(())
+}
+
+/// Streams a single file to stdout for `-o -`, to be piped into another program. Only targets
+/// naming exactly one file are accepted, and nothing but the file content is written.
+async fn stream_to_stdout(
+    options: &DownloadOptions,
+    flags: &DownloadFlags,
+    config: &Configuration,
+) -> anyhow::Result<()> {
+    let rejected = [
+        (
+            options.output_format == OutputFormat::Json,
+            "--output-format json",
+        ),
+        (options.dry_run, "--dry-run"),
+        (options.check_access, "--check-access"),
+        (options.meta_only, "--meta-only"),
+        (options.artifacts.is_some(), "--artifacts"),
+        (options.resume_session.is_some(), "--resume-session"),
+        (options.all_versions, "--all-versions"),
+        (options.multi, "--multi"),
+        (options.all_models, "--all-models"),
+        (!options.model_types.is_empty(), "--model-type"),
+        (options.latest, "--latest"),
+        (options.version_name.is_some(), "--version-name"),
+        (options.version_ids.len() > 1, "several --version-id"),
+        (options.folder_per_model == Some(true), "--folder-per-model"),
+        (options.max_total_size.is_some(), "--max-total-size"),
+        (options.video_cover.is_some(), "--video-cover"),
+        (options.pick_cover, "--pick-cover"),
+        (options.prefer.is_some(), "--prefer"),
+        (options.readme_stats == Some(true), "--readme-stats"),
+        (options.temp_dir.is_some(), "--temp-dir"),
+        (options.extract == Some(true), "--extract"),
+        (options.remove_archive, "--remove-archive"),
+        (
+            options.include_version_history == Some(true),
+            "--include-version-history",
+        ),
+    ];
+    if let Some((_, option)) = rejected.iter().find(|(given, _)| *given) {
+        return Err(InvalidInputError(format!(
+            "{option} can not be used when streaming a file to stdout with -o -"
+        ))
+        .into());
+    }
+    if std::io::stdout().is_terminal() {
+        return Err(InvalidInputError(
+            "Refuse to write the model file to a terminal, pipe it into another program or redirect it to a file.".to_string(),
+        )
+        .into());
+    }
+
+    let summary = match parse_target(options.url.as_deref().unwrap_or_default())? {
+        DownloadTarget::HuggingFace(HuggingFaceTarget::File(file)) => {
+            let client =
+                crate::downloader::make_client(config).context("Failed to initialize client")?;
+            crate::hugging_face::stream_huggingface_file(&client, config, &file)
                 .await
-                .expect("Failed to initialize client");
-            crate::civitai::download_from_civitai(
-                &civitai_client,
-                model_id.parse::<u64>().expect("Failed to parse model id"),
-                model_version_id
-                    .map(|s| s.parse::<u64>().expect("Failed to parse model version id")),
-                options.output_path.as_ref(),
-                options.skip_community,
+                .context("Failed to stream HuggingFace file")?
+        }
+        DownloadTarget::Civitai(CivitaiTarget::Model(model_id, version_id)) => {
+            let version_id = match (version_id, options.version_ids.first()) {
+                (Some(version_id), None) => version_id,
+                (None, Some(version_id)) => *version_id,
+                (Some(version_id), Some(given_id)) if version_id == *given_id => version_id,
+                _ => return Err(single_file_required()),
+            };
+            let client = make_civitai_client(config)?;
+            crate::civitai::stream_from_civitai(
+                &client,
+                config,
+                Some(model_id),
+                version_id,
+                options.allow_unsafe,
             )
             .await
-            .expect("Failed to download model file(s)");
-            println!("Download completed.");
+            .context("Failed to stream model file")?
         }
-        Some(crate::downloader::Platform::HuggingFace) => {
-            if !crate::configuration::check_huggingface_key_exists().await {
-                println!("HuggingFace API key is not set. Please set it first.");
-                return;
+        _ => return Err(single_file_required()),
+    };
+    summary.ensure_complete(flags.strict)?;
+    Ok(())
+}
+
+fn single_file_required() -> anyhow::Error {
+    InvalidInputError(
+        "Streaming to stdout needs a single file, give a Civitai URL with modelVersionId, an AIR with a version, a single --version-id, or a HuggingFace file URL.".to_string(),
+    )
+    .into()
+}
+
+fn download_behavior(
+    options: &DownloadOptions,
+    flags: &DownloadFlags,
+    config: &Configuration,
+    model_dirs: Option<crate::integrations::ModelDirectories>,
+) -> crate::civitai::DownloadBehavior {
+    crate::civitai::DownloadBehavior {
+        artifacts: flags.artifacts,
+        allow_unsafe: options.allow_unsafe,
+        dry_run: options.dry_run,
+        folder_per_model: options
+            .folder_per_model
+            .unwrap_or(config.download.folder_per_model),
+        unattended: false,
+        link_existing: flags.link_existing,
+        file_ids: Vec::new(),
+        model_dirs,
+        size_budget: size_budget(options.max_total_size),
+        reporter: reporter_kind(options),
+        resumed: None,
+        version_history: flags.version_history,
+        meta_file_name: options.for_file.clone(),
+        pick_cover: options.pick_cover,
+        prompt_limits: crate::civitai::PromptLimits::new(
+            options.max_prompt_sections,
+            options.max_prompt_length,
+        ),
+        extract_archives: flags.extract,
+        remove_archive: options.remove_archive,
+        file_preference: options.prefer.clone(),
+        commercial_use_policy: options.require_commercial_use,
+    }
+}
+
+/// Lists the models published by the user and downloads the chosen ones, each with its newest
+/// version and primary file, like a sync does.
+async fn download_user_models(
+    client: &reqwest::Client,
+    config: &Configuration,
+    options: &DownloadOptions,
+    flags: &DownloadFlags,
+    username: &str,
+    output_path: Option<&PathBuf>,
+    behavior: &crate::civitai::DownloadBehavior,
+) -> anyhow::Result<()> {
+    if events::enabled() && !options.all_models {
+        return Err(InvalidInputError(
+            "JSON output can not prompt for models, use --all-models.".to_string(),
+        )
+        .into());
+    }
+    let (models, truncated) =
+        crate::civitai::fetch_user_models(client, config, username, USER_MODELS_CAP)
+            .await
+            .with_context(|| format!("Failed to fetch models of user {username}"))?;
+    if truncated {
+        events::message(format!(
+            "User {username} has more than {USER_MODELS_CAP} models, only the newest {USER_MODELS_CAP} are listed."
+        ));
+    }
+    let models = models
+        .into_iter()
+        .filter(|model| {
+            options.model_types.is_empty()
+                || model.model_type().is_some_and(|model_type| {
+                    options
+                        .model_types
+                        .iter()
+                        .any(|wanted| wanted.eq_ignore_ascii_case(&model_type))
+                })
+        })
+        .collect::<Vec<_>>();
+    if models.is_empty() {
+        events::message(format!("User {username} has no model to download."));
+        return Ok(());
+    }
+    let selected = if options.all_models {
+        events::message(match models.len() {
+            1 => "Downloading 1 model:".to_string(),
+            count => format!("Downloading {count} models:"),
+        });
+        for row in crate::civitai::model_summary_rows(&models) {
+            events::message(format!("  {}", row.trim_end()));
+        }
+        (0..models.len()).collect()
+    } else {
+        crate::civitai::select_models(&models)?
+    };
+
+    let version_selection = crate::civitai::VersionSelection {
+        latest: true,
+        skip_early_access: flags.skip_early_access,
+        ..Default::default()
+    };
+    let mut failed_models = Vec::new();
+    let mut refused_models = Vec::new();
+    let mut warnings = Vec::new();
+    let mut incomplete = 0;
+    for (position, index) in selected.iter().enumerate() {
+        let model = &models[*index];
+        events::message(format!(
+            "\n[{}/{}] Downloading {} ({})...",
+            position + 1,
+            selected.len(),
+            model.name(),
+            model.id()
+        ));
+        // 单个模型失败时继续下载其余模型
+        match crate::civitai::download_from_civitai(
+            client,
+            config,
+            model.id(),
+            &version_selection,
+            output_path,
+            behavior,
+        )
+        .await
+        {
+            Ok(summary) => {
+                incomplete += summary.failed.len();
+                warnings.extend(summary.warnings);
+            }
+            Err(e) if e.downcast_ref::<SizeBudgetExceededError>().is_some() => {
+                events::message(format!("{e}, stop downloading."));
+                break;
             }
-            println!("Downloading from HuggingFace is not supported yet.");
+            Err(e) if e.downcast_ref::<EarlyAccessOnlyError>().is_some() => {
+                events::message(format!("{e}, skip it."));
+            }
+            Err(e) if e.downcast_ref::<CommercialUseNotAllowedError>().is_some() => {
+                events::message(format!("{e}, skip it."));
+                refused_models.push(model.name());
+            }
+            Err(e) => {
+                events::message(format!("Failed to download {}: {e:#}", model.name()));
+                failed_models.push(model.name());
+            }
+        }
+    }
+
+    if !refused_models.is_empty() {
+        events::message(format!(
+            "\nSkipped {} models not allowing commercial use: {}",
+            refused_models.len(),
+            refused_models.join(", ")
+        ));
+    }
+    for warning in warnings.iter() {
+        events::message(format!("WARNING: {warning}."));
+    }
+    if !failed_models.is_empty() {
+        bail!(
+            "{} of {} models failed to download: {}",
+            failed_models.len(),
+            selected.len(),
+            failed_models.join(", ")
+        );
+    }
+    if flags.strict && incomplete > 0 {
+        return Err(IncompleteArtifactsError(incomplete).into());
+    }
+    if options.dry_run {
+        events::message("Dry run completed, nothing written.");
+    } else {
+        events::message("Download completed.");
+    }
+    Ok(())
+}
+
+pub(crate) fn size_budget(max_total_size: Option<u64>) -> Option<Arc<SizeBudget>> {
+    max_total_size.map(|limit| Arc::new(SizeBudget::new(limit)))
+}
+
+fn reporter_kind(options: &DownloadOptions) -> ReporterKind {
+    match options.output_format {
+        OutputFormat::Human => ReporterKind::Bar,
+        OutputFormat::Json => ReporterKind::JsonLines,
+    }
+}
+
+fn make_civitai_client(config: &Configuration) -> anyhow::Result<reqwest::Client> {
+    if config.civitai.api_key.is_none() {
+        bail!(
+            "Civitai access key is not set, set it by \"imd config set civitai <key>\" or run \"imd init\"."
+        );
+    }
+    crate::downloader::make_client(config).context("Failed to initialize client")
+}
+
+fn list_sessions() -> anyhow::Result<()> {
+    let sessions =
+        crate::civitai::DownloadSession::list().context("Failed to read download sessions")?;
+    if sessions.is_empty() {
+        println!("No interrupted download.");
+        return Ok(());
+    }
+    for session in sessions.iter() {
+        session.print();
+    }
+    println!(
+        "Resume one by \"imd download --resume-session <MODEL_ID>\", or by its key when the model has several."
+    );
+    Ok(())
+}
+
+async fn resume_session(
+    options: &DownloadOptions,
+    flags: &DownloadFlags,
+    config: &Configuration,
+    selector: &str,
+) -> anyhow::Result<()> {
+    let mut sessions = crate::civitai::DownloadSession::find(selector)
+        .context("Failed to read download sessions")?;
+    let session = match sessions.len() {
+        0 => {
+            return Err(InvalidInputError(format!(
+                "No interrupted download of {selector}, list them by \"imd download --list-sessions\"."
+            ))
+            .into());
         }
+        1 => sessions.remove(0),
         _ => {
-            println!("Unsupported platform.");
+            let keys = sessions.iter().map(|s| s.key()).collect::<Vec<_>>();
+            return Err(InvalidInputError(format!(
+                "Model {selector} has several interrupted downloads, resume one by its key: {}",
+                keys.join(", ")
+            ))
+            .into());
         }
+    };
+    let model_id = session.model_id;
+    if let Some(destination) = session.destination.as_ref() {
+        crate::downloader::validate_output_dir(destination, options.fix_missing_dirs)?;
+    }
+
+    events::message(format!("Resuming download of {}...", session.model_name));
+    let civitai_client = make_civitai_client(config)?;
+    let version_selection = crate::civitai::VersionSelection {
+        ids: session.version_ids.clone(),
+        ..Default::default()
+    };
+    crate::civitai::download_from_civitai(
+        &civitai_client,
+        config,
+        model_id,
+        &version_selection,
+        session.destination.as_ref(),
+        &crate::civitai::DownloadBehavior {
+            reporter: reporter_kind(options),
+            link_existing: flags.link_existing,
+            size_budget: size_budget(options.max_total_size),
+            version_history: flags.version_history,
+            extract_archives: flags.extract,
+            remove_archive: options.remove_archive,
+            ..session.resume_behavior()
+        },
+    )
+    .await
+    .context("Failed to download model file(s)")?;
+    events::message("Download completed.");
+    Ok(())
+}
+
+/// Resolves the model to download from the URL or AIR before any request.
+fn parse_target(url: &str) -> anyhow::Result<DownloadTarget> {
+    // AIR 也能被解析为 URL，需要先行判断
+    if crate::civitai::is_air(url) {
+        let (model_id, version_id) = url.parse::<crate::civitai::Air>()?.civitai_ids()?;
+        return Ok(DownloadTarget::Civitai(CivitaiTarget::Model(
+            model_id, version_id,
+        )));
+    }
+    let target_url = reqwest::Url::parse(url).map_err(|e| {
+        InvalidInputError(format!(
+            "\"{url}\" is not a valid URL ({e}), expected {}",
+            crate::civitai::CIVITAI_URL_SHAPES
+        ))
+    })?;
+    match crate::downloader::detect_platform(&target_url) {
+        Some(Platform::Civitai) => {
+            if let Some(image_id) = crate::civitai::try_parse_civitai_image_url(&target_url) {
+                return Ok(DownloadTarget::Civitai(CivitaiTarget::Image(image_id)));
+            }
+            if let Some(username) = crate::civitai::try_parse_civitai_user_url(&target_url) {
+                return Ok(DownloadTarget::Civitai(CivitaiTarget::User(username)));
+            }
+            let (model_id, version_id) = crate::civitai::try_parse_civitai_model_url(&target_url)?;
+            Ok(DownloadTarget::Civitai(CivitaiTarget::Model(
+                model_id, version_id,
+            )))
+        }
+        Some(Platform::HuggingFace) => Ok(DownloadTarget::HuggingFace(
+            crate::hugging_face::parse_huggingface_url(&target_url)?,
+        )),
+        None => Err(InvalidInputError(format!(
+            "\"{url}\" is not a Civitai or HuggingFace URL, expected {}",
+            crate::civitai::CIVITAI_URL_SHAPES
+        ))
+        .into()),
+    }
+}
+
+enum DownloadTarget {
+    Civitai(CivitaiTarget),
+    HuggingFace(crate::hugging_face::HuggingFaceTarget),
+}
+
+enum CivitaiTarget {
+    Image(u64),
+    /// Models published by the user.
+    User(String),
+    Model(u64, Option<u64>),
+}
+
+/// Checks whether the version to download can be decided without prompting, a version given
+/// in the URL is taken as the explicit choice.
+fn resolves_without_prompt(selection: &mut crate::civitai::VersionSelection) -> bool {
+    if selection.ids.is_empty()
+        && !selection.all
+        && !selection.latest
+        && selection.name_pattern.is_none()
+        && let Some(preferred_id) = selection.preferred_id
+    {
+        selection.ids = vec![preferred_id];
     }
+    !selection.ids.is_empty()
+        || selection.all
+        || selection.latest
+        || selection.name_pattern.is_some()
 }
@@ -0,0 +1,253 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    configuration::{DefaultFlag, DefaultsConfig},
+    errors::InvalidInputError,
+    events,
+};
+
+/// Most other versions listed in the readme when not configured.
+pub const DEFAULT_HISTORY_LIMIT: usize = 10;
+
+/// Where the effective value of a flag comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagSource {
+    CommandLine,
+    Environment,
+    Config,
+    BuiltIn,
+}
+
+impl Display for FlagSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::CommandLine => "command line",
+            Self::Environment => "environment",
+            Self::Config => "config",
+            Self::BuiltIn => "built-in",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Resolves flags of a command in the order of command line, environment variable, configured
+/// default and built-in default, remembering where each value comes from.
+pub struct FlagResolver<'a, E> {
+    defaults: &'a DefaultsConfig,
+    env: E,
+    resolved: Vec<(DefaultFlag, String, FlagSource)>,
+}
+
+impl<'a> FlagResolver<'a, fn(&str) -> Option<String>> {
+    pub fn new(defaults: &'a DefaultsConfig) -> Self {
+        Self::with_env(defaults, |name| std::env::var(name).ok())
+    }
+}
+
+impl<'a, E: Fn(&str) -> Option<String>> FlagResolver<'a, E> {
+    /// Reads environment variables through the given lookup instead of the process environment.
+    pub fn with_env(defaults: &'a DefaultsConfig, env: E) -> Self {
+        Self {
+            defaults,
+            env,
+            resolved: Vec::new(),
+        }
+    }
+
+    /// Switch flags, off unless turned on.
+    pub fn switch(
+        &mut self,
+        flag: DefaultFlag,
+        cli: Option<bool>,
+    ) -> Result<bool, InvalidInputError> {
+        self.value(flag, cli, false)
+    }
+
+    pub fn value<T>(
+        &mut self,
+        flag: DefaultFlag,
+        cli: Option<T>,
+        built_in: T,
+    ) -> Result<T, InvalidInputError>
+    where
+        T: FromStr + Display,
+    {
+        let (value, source) = match cli {
+            Some(value) => (value, FlagSource::CommandLine),
+            None => match self.lookup(flag)? {
+                Some((value, source)) => (value, source),
+                None => (built_in, FlagSource::BuiltIn),
+            },
+        };
+        self.resolved.push((flag, value.to_string(), source));
+        Ok(value)
+    }
+
+    fn lookup<T: FromStr>(
+        &self,
+        flag: DefaultFlag,
+    ) -> Result<Option<(T, FlagSource)>, InvalidInputError> {
+        let env_var = flag.env_var();
+        let candidates = [
+            (
+                (self.env)(&env_var).filter(|value| !value.trim().is_empty()),
+                FlagSource::Environment,
+            ),
+            (self.defaults.get(flag), FlagSource::Config),
+        ];
+        for (value, source) in candidates {
+            let Some(value) = value else {
+                continue;
+            };
+            // 统一格式后再解析，例如环境变量中的 yes 和 1
+            let parsed = flag
+                .validate(&value)
+                .ok()
+                .and_then(|normalized| normalized.parse::<T>().ok());
+            return match parsed {
+                Some(parsed) => Ok(Some((parsed, source))),
+                None if source == FlagSource::Environment => Err(InvalidInputError(format!(
+                    "Environment variable {env_var} has invalid value \"{value}\""
+                ))),
+                None => Err(InvalidInputError(format!(
+                    "Configured default of {flag} has invalid value \"{value}\""
+                ))),
+            };
+        }
+        Ok(None)
+    }
+
+    /// Number of other versions listed in the readme, `None` when the history is not included.
+    pub fn version_history(
+        &mut self,
+        include: Option<bool>,
+        limit: Option<usize>,
+    ) -> Result<Option<usize>, InvalidInputError> {
+        let include = self.switch(DefaultFlag::IncludeVersionHistory, include)?;
+        let limit = self.value(DefaultFlag::HistoryLimit, limit, DEFAULT_HISTORY_LIMIT)?;
+        Ok(include.then_some(limit))
+    }
+
+    /// Prints the effective value and its source of every flag resolved so far.
+    pub fn print(&self) {
+        events::message("Effective flags:");
+        for (flag, value, source) in self.resolved.iter() {
+            events::message(format!("  {:<24}{value:<7} ({source})", flag.name()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(value: Option<&'static str>) -> impl Fn(&str) -> Option<String> {
+        move |name| {
+            assert_eq!(name, "IMD_HISTORY_LIMIT");
+            value.map(String::from)
+        }
+    }
+
+    fn resolve(
+        cli: Option<usize>,
+        env_value: Option<&'static str>,
+        configured: Option<usize>,
+    ) -> Result<(usize, FlagSource), InvalidInputError> {
+        let defaults = DefaultsConfig {
+            history_limit: configured,
+            ..Default::default()
+        };
+        let mut resolver = FlagResolver::with_env(&defaults, env(env_value));
+        let value = resolver.value(DefaultFlag::HistoryLimit, cli, DEFAULT_HISTORY_LIMIT)?;
+        Ok((value, resolver.resolved[0].2))
+    }
+
+    #[test]
+    fn flags_take_command_line_then_environment_then_config_then_built_in() {
+        assert_eq!(
+            resolve(Some(1), Some("2"), Some(3)).unwrap(),
+            (1, FlagSource::CommandLine)
+        );
+        assert_eq!(
+            resolve(None, Some("2"), Some(3)).unwrap(),
+            (2, FlagSource::Environment)
+        );
+        assert_eq!(
+            resolve(None, None, Some(3)).unwrap(),
+            (3, FlagSource::Config)
+        );
+        assert_eq!(
+            resolve(None, None, None).unwrap(),
+            (DEFAULT_HISTORY_LIMIT, FlagSource::BuiltIn)
+        );
+        // 空的环境变量视为未设置
+        assert_eq!(
+            resolve(None, Some("  "), Some(3)).unwrap(),
+            (3, FlagSource::Config)
+        );
+    }
+
+    #[test]
+    fn invalid_environment_values_are_reported_unless_overridden() {
+        let error = resolve(None, Some("many"), Some(3)).unwrap_err();
+        assert!(error.to_string().contains("IMD_HISTORY_LIMIT"), "{error}");
+        assert_eq!(
+            resolve(Some(1), Some("many"), None).unwrap(),
+            (1, FlagSource::CommandLine)
+        );
+    }
+
+    #[test]
+    fn switches_accept_environment_spellings() {
+        let defaults = DefaultsConfig {
+            strict: Some(true),
+            ..Default::default()
+        };
+        for (value, expected) in [("yes", true), ("1", true), ("OFF", false), ("no", false)] {
+            let mut resolver = FlagResolver::with_env(&defaults, |name| {
+                (name == "IMD_STRICT").then(|| value.to_string())
+            });
+            assert_eq!(
+                resolver.switch(DefaultFlag::Strict, None).unwrap(),
+                expected,
+                "{value}"
+            );
+        }
+        let mut resolver = FlagResolver::with_env(&defaults, |_| None);
+        assert!(
+            resolver
+                .switch(DefaultFlag::Strict, Some(false))
+                .is_ok_and(|v| !v)
+        );
+        assert!(resolver.switch(DefaultFlag::Strict, None).unwrap());
+        assert!(!resolver.switch(DefaultFlag::LinkExisting, None).unwrap());
+        assert_eq!(
+            resolver
+                .resolved
+                .iter()
+                .map(|(_, value, source)| (value.as_str(), *source))
+                .collect::<Vec<_>>(),
+            [
+                ("false", FlagSource::CommandLine),
+                ("true", FlagSource::Config),
+                ("false", FlagSource::BuiltIn),
+            ]
+        );
+    }
+
+    #[test]
+    fn version_history_is_limited_only_when_included() {
+        let defaults = DefaultsConfig {
+            include_version_history: Some(true),
+            history_limit: Some(4),
+            ..Default::default()
+        };
+        let mut resolver = FlagResolver::with_env(&defaults, |_| None);
+        assert_eq!(resolver.version_history(None, None).unwrap(), Some(4));
+        assert_eq!(resolver.version_history(None, Some(2)).unwrap(), Some(2));
+        assert_eq!(
+            resolver.version_history(Some(false), Some(2)).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::hashing::{FileHashes, hash_reader};
+
+/// Files at least this large show a progress bar while hashing.
+const PROGRESS_THRESHOLD: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HashAlgorithm {
+    Blake3,
+    Sha256,
+    Autov2,
+    All,
+}
+
+#[derive(Args)]
+pub struct HashOptions {
+    #[arg(
+        required = true,
+        help = "The files to hash, use \"-\" to read from standard input."
+    )]
+    pub files: Vec<String>,
+    #[arg(long, value_enum, default_value = "all", help = "The hash to compute.")]
+    pub algo: HashAlgorithm,
+    #[arg(
+        long,
+        help = "Save blake3 hash beside the file, as downloaded models do.",
+        default_value = "false"
+    )]
+    pub save: bool,
+}
+
+pub async fn process_hash_files(options: &HashOptions) -> anyhow::Result<()> {
+    let with_blake3 =
+        matches!(options.algo, HashAlgorithm::Blake3 | HashAlgorithm::All) || options.save;
+    let with_sha256 = matches!(
+        options.algo,
+        HashAlgorithm::Sha256 | HashAlgorithm::Autov2 | HashAlgorithm::All
+    );
+
+    for file in options.files.iter() {
+        let source = file.clone();
+        let hashes = tokio::task::spawn_blocking(move || {
+            if source == "-" {
+                Ok(hash_reader(
+                    std::io::stdin().lock(),
+                    None,
+                    with_blake3,
+                    with_sha256,
+                )?)
+            } else {
+                hash_file(Path::new(&source), with_blake3, with_sha256)
+            }
+        })
+        .await
+        .context("Hash calculation is interrupted")?
+        .with_context(|| format!("Failed to hash {file}"))?;
+
+        print_hashes(file, &hashes, options.algo);
+
+        if options.save
+            && let Some(blake3) = hashes.blake3.as_ref()
+        {
+            if file == "-" {
+                eprintln!("Hash of standard input is not saved.");
+                continue;
+            }
+            crate::civitai::save_version_file_hash(PathBuf::from(file), blake3)
+                .await
+                .with_context(|| format!("Failed to save hash of {file}"))?;
+        }
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path, with_blake3: bool, with_sha256: bool) -> anyhow::Result<FileHashes> {
+    let file = File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+    let file_size = file.metadata()?.len();
+    let bar = (file_size >= PROGRESS_THRESHOLD).then(|| {
+        let bar = ProgressBar::new(file_size);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{wide_bar:.cyan/blue}] {decimal_bytes}/{decimal_total_bytes} [{elapsed}] ETA:{eta}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar())
+                .progress_chars("=>-"),
+        );
+        bar
+    });
+    let hashes = hash_reader(file, bar.as_ref(), with_blake3, with_sha256);
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+    Ok(hashes?)
+}
+
+fn print_hashes(file: &str, hashes: &FileHashes, algo: HashAlgorithm) {
+    let show = |wanted: HashAlgorithm| algo == wanted || algo == HashAlgorithm::All;
+    // 标签补齐到相同宽度，便于对齐比较
+    if show(HashAlgorithm::Autov2)
+        && let Some(autov2) = hashes.autov2()
+    {
+        println!("{:<7} {autov2:<64}  {file}", "AutoV2");
+    }
+    if show(HashAlgorithm::Sha256)
+        && let Some(sha256) = hashes.sha256.as_ref()
+    {
+        println!("{:<7} {sha256:<64}  {file}", "SHA256");
+    }
+    if show(HashAlgorithm::Blake3)
+        && let Some(blake3) = hashes.blake3.as_ref()
+    {
+        println!("{:<7} {blake3:<64}  {file}", "BLAKE3");
+    }
+}
@@ -0,0 +1,26 @@
+use anyhow::Context;
+use clap::Args;
+
+#[derive(Args, Default)]
+pub struct HistoryOptions {
+    #[arg(
+        long,
+        short = 'n',
+        help = "Number of the latest downloads to show.",
+        default_value = "20"
+    )]
+    pub limit: usize,
+}
+
+pub async fn process_show_history(options: &HistoryOptions) -> anyhow::Result<()> {
+    let records = crate::civitai::DownloadRecord::list(options.limit)
+        .context("Failed to read download history")?;
+    if records.is_empty() {
+        println!("No download recorded.");
+        return Ok(());
+    }
+    for record in records.iter() {
+        record.print();
+    }
+    Ok(())
+}
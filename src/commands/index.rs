@@ -0,0 +1,253 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::Args;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+use super::collector::collect_model_files;
+use crate::{civitai::IndexEntry, errors::InvalidInputError};
+
+const INDEX_MARKDOWN: &str = "INDEX.md";
+const INDEX_HTML: &str = "index.html";
+/// Width of the cover thumbnails in pixels.
+const THUMBNAIL_WIDTH: u32 = 128;
+const UNKNOWN_TYPE: &str = "Unknown type";
+
+const LINK_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'-')
+    .remove(b'/');
+
+#[derive(Args)]
+pub struct IndexOptions {
+    #[arg(help = "The directory of models, defaults to current directory.")]
+    pub path: Option<PathBuf>,
+    #[arg(
+        long,
+        short = 'r',
+        help = "Also index models in subdirectories.",
+        default_value = "false"
+    )]
+    pub recursive: bool,
+    #[arg(
+        long,
+        help = "Also write an index.html beside INDEX.md.",
+        default_value = "false"
+    )]
+    pub html: bool,
+}
+
+pub async fn process_index_options(options: &IndexOptions) -> anyhow::Result<()> {
+    let directory = match options.path.as_ref() {
+        Some(path) => path.clone(),
+        None => std::env::current_dir().context("Unable to get current working directory")?,
+    };
+    if !directory.is_dir() {
+        return Err(
+            InvalidInputError(format!("\"{}\" is not a directory", directory.display())).into(),
+        );
+    }
+    let model_files = collect_model_files(&directory, options.recursive)
+        .with_context(|| format!("Failed to list model files in {}", directory.display()))?;
+
+    let mut groups: BTreeMap<String, Vec<IndexEntry>> = BTreeMap::new();
+    let mut unidentified = Vec::new();
+    for model_file in model_files.iter() {
+        match crate::civitai::index_entry(model_file) {
+            Ok(Some(entry)) => groups
+                .entry(
+                    entry
+                        .model_type
+                        .clone()
+                        .unwrap_or_else(|| UNKNOWN_TYPE.to_string()),
+                )
+                .or_default()
+                .push(entry),
+            Ok(None) => unidentified.push(model_file.clone()),
+            Err(e) => {
+                println!("Failed to read metadata of {}: {e}", model_file.display());
+                unidentified.push(model_file.clone());
+            }
+        }
+    }
+    for entries in groups.values_mut() {
+        entries.sort_by(|a, b| {
+            a.model_name
+                .to_lowercase()
+                .cmp(&b.model_name.to_lowercase())
+                .then_with(|| a.version_name.cmp(&b.version_name))
+                .then_with(|| a.model_file.cmp(&b.model_file))
+        });
+    }
+
+    let index = Index {
+        base_dir: &directory,
+        groups: &groups,
+        unidentified: &unidentified,
+    };
+    write_if_changed(&directory.join(INDEX_MARKDOWN), &index.markdown())?;
+    if options.html {
+        write_if_changed(&directory.join(INDEX_HTML), &index.html())?;
+    }
+    println!(
+        "Indexed {} models, {} unidentified.",
+        groups.values().map(Vec::len).sum::<usize>(),
+        unidentified.len()
+    );
+    Ok(())
+}
+
+/// Writes the file only when its content changes, so regenerating keeps file times.
+fn write_if_changed(path: &Path, content: &str) -> anyhow::Result<()> {
+    if std::fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        println!("{} is up to date.", path.display());
+        return Ok(());
+    }
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Written {}.", path.display());
+    Ok(())
+}
+
+struct Index<'a> {
+    base_dir: &'a Path,
+    groups: &'a BTreeMap<String, Vec<IndexEntry>>,
+    unidentified: &'a [PathBuf],
+}
+
+impl Index<'_> {
+    /// Path relative to the indexed directory, separated by `/`.
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(self.base_dir)
+            .unwrap_or(path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn link(&self, path: &Path) -> String {
+        utf8_percent_encode(&self.relative(path), LINK_SET).to_string()
+    }
+
+    /// Link of the cover when it is an image, video covers get no thumbnail.
+    fn thumbnail_link(&self, entry: &IndexEntry) -> Option<String> {
+        entry
+            .cover
+            .as_ref()
+            .filter(|cover| {
+                cover
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+            })
+            .map(|cover| self.link(cover))
+    }
+
+    fn markdown(&self) -> String {
+        let mut content = String::from("# Model Index\n");
+        for (model_type, entries) in self.groups.iter() {
+            content.push_str(&format!("\n## {model_type}\n\n"));
+            content.push_str("| Cover | Model | Version | Base Model | Trained Words |\n");
+            content.push_str("| --- | --- | --- | --- | --- |\n");
+            for entry in entries.iter() {
+                let cover = self
+                    .thumbnail_link(entry)
+                    .map(|link| format!("<img src=\"{link}\" width=\"{THUMBNAIL_WIDTH}\">"))
+                    .unwrap_or_default();
+                let name = escape_cell(&entry.model_name);
+                let model = match entry.readme.as_ref() {
+                    Some(readme) => format!("[{name}]({})", self.link(readme)),
+                    None => name,
+                };
+                let trained_words = entry
+                    .trained_words
+                    .iter()
+                    .map(|word| format!("`{}`", escape_cell(word)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                content.push_str(&format!(
+                    "| {cover} | {model} | {}{} | {} | {trained_words} |\n",
+                    escape_cell(&entry.version_name),
+                    removal_note(entry),
+                    escape_cell(entry.base_model.as_deref().unwrap_or("-")),
+                ));
+            }
+        }
+        if !self.unidentified.is_empty() {
+            content.push_str("\n## Unidentified\n\n");
+            for model_file in self.unidentified.iter() {
+                content.push_str(&format!("- {}\n", escape_cell(&self.relative(model_file))));
+            }
+        }
+        content
+    }
+
+    fn html(&self) -> String {
+        let mut content = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Model Index</title>\n</head>\n<body>\n<h1>Model Index</h1>\n",
+        );
+        for (model_type, entries) in self.groups.iter() {
+            content.push_str(&format!("<h2>{}</h2>\n<table>\n", escape_html(model_type)));
+            content.push_str("<tr><th>Cover</th><th>Model</th><th>Version</th><th>Base Model</th><th>Trained Words</th></tr>\n");
+            for entry in entries.iter() {
+                let cover = self
+                    .thumbnail_link(entry)
+                    .map(|link| format!("<img src=\"{link}\" width=\"{THUMBNAIL_WIDTH}\">"))
+                    .unwrap_or_default();
+                let name = escape_html(&entry.model_name);
+                let model = match entry.readme.as_ref() {
+                    Some(readme) => format!("<a href=\"{}\">{name}</a>", self.link(readme)),
+                    None => name,
+                };
+                let trained_words = entry
+                    .trained_words
+                    .iter()
+                    .map(|word| format!("<code>{}</code>", escape_html(word)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                content.push_str(&format!(
+                    "<tr><td>{cover}</td><td>{model}</td><td>{}{}</td><td>{}</td><td>{trained_words}</td></tr>\n",
+                    escape_html(&entry.version_name),
+                    removal_note(entry),
+                    escape_html(entry.base_model.as_deref().unwrap_or("-")),
+                ));
+            }
+            content.push_str("</table>\n");
+        }
+        if !self.unidentified.is_empty() {
+            content.push_str("<h2>Unidentified</h2>\n<ul>\n");
+            for model_file in self.unidentified.iter() {
+                content.push_str(&format!(
+                    "<li>{}</li>\n",
+                    escape_html(&self.relative(model_file))
+                ));
+            }
+            content.push_str("</ul>\n");
+        }
+        content.push_str("</body>\n</html>\n");
+        content
+    }
+}
+
+/// Marks a version gone from Civitai, the local copy may be the only one left.
+fn removal_note(entry: &IndexEntry) -> String {
+    entry
+        .removed_upstream
+        .map(|kind| format!(" ({kind} on Civitai)"))
+        .unwrap_or_default()
+}
+
+/// Escapes text placed in a Markdown table cell.
+fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,317 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use dialoguer::{Confirm, Input, Password, Select};
+
+use crate::configuration::{BackoffConfig, CONFIGURATION};
+
+/// Offers the setup wizard when no configuration has been saved yet.
+pub async fn offer_setup_wizard() {
+    // 超时后向导的输入框不会超时，因此跳过向导
+    let accepted = crate::prompt::interact(
+        || {
+            Confirm::new()
+                .with_prompt("No configuration found, run the setup wizard now?")
+                .default(true)
+                .interact()
+        },
+        false,
+        "skipping the wizard",
+    )
+    .unwrap_or_default();
+    if accepted {
+        if let Err(e) = process_init().await {
+            eprintln!("{e:#}");
+        }
+        return;
+    }
+    // 保存默认配置，避免每次运行都再次询问。
+    if let Err(e) = CONFIGURATION.read().await.persist().await {
+        eprintln!("Failed to save configuration: {e}");
+    }
+    println!("You can run \"imd init\" at any time to setup the downloader.\n");
+}
+
+pub async fn process_init() -> Result<()> {
+    println!("Welcome to IMD setup, leave any input empty to skip the step.\n");
+    run_wizard().await.context("Setup aborted")?;
+    CONFIGURATION
+        .read()
+        .await
+        .persist()
+        .await
+        .context("Failed to save configuration")?;
+    print_summary().await;
+    Ok(())
+}
+
+async fn run_wizard() -> Result<()> {
+    setup_civitai_key().await?;
+    setup_huggingface_key().await?;
+    setup_proxy().await?;
+    setup_output_dir().await?;
+    setup_retry_policy().await?;
+    Ok(())
+}
+
+/// Asks whether an existing value should be replaced, returns `true` when the step should go on.
+fn confirm_replace(name: &str, exists: bool) -> Result<bool> {
+    if !exists {
+        return Ok(true);
+    }
+    Confirm::new()
+        .with_prompt(format!("{name} is already set, replace it?"))
+        .default(false)
+        .interact()
+        .context("Failed to read input")
+}
+
+async fn setup_civitai_key() -> Result<()> {
+    println!("[1/5] Civitai access key");
+    let exists = CONFIGURATION.read().await.civitai.api_key.is_some();
+    if !confirm_replace("Civitai access key", exists)? {
+        return Ok(());
+    }
+    println!("  Create one at https://civitai.com/user/account, in the \"API Keys\" section.");
+    let config = crate::configuration::current().await;
+    let client = crate::downloader::make_client(&config)?;
+    loop {
+        let key = Password::new()
+            .with_prompt("Civitai access key")
+            .allow_empty_password(true)
+            .interact()
+            .context("Failed to read input")?;
+        let key = key.trim().to_string();
+        if key.is_empty() {
+            println!("  Skipped.");
+            return Ok(());
+        }
+
+        println!("  Validating access key...");
+        match crate::civitai::verify_api_key(&client, &config, &key).await {
+            Ok(_) => println!("  Access key is valid."),
+            Err(e) => {
+                println!("  Access key validation failed: {e}");
+                let choice = Select::new()
+                    .with_prompt("What to do next?")
+                    .items(&["Enter again", "Save anyway", "Skip"])
+                    .default(0)
+                    .interact()
+                    .context("Failed to read input")?;
+                match choice {
+                    0 => continue,
+                    1 => {}
+                    _ => return Ok(()),
+                }
+            }
+        }
+        CONFIGURATION.write().await.set_civitai_api_key(key).await?;
+        return Ok(());
+    }
+}
+
+async fn setup_huggingface_key() -> Result<()> {
+    println!("[2/5] HuggingFace access token (optional)");
+    let exists = CONFIGURATION.read().await.huggingface.api_key.is_some();
+    if !confirm_replace("HuggingFace access token", exists)? {
+        return Ok(());
+    }
+    let key = Password::new()
+        .with_prompt("HuggingFace access token")
+        .allow_empty_password(true)
+        .interact()
+        .context("Failed to read input")?;
+    let key = key.trim().to_string();
+    if key.is_empty() {
+        println!("  Skipped.");
+        return Ok(());
+    }
+    println!("  Validating access token...");
+    let config = crate::configuration::current().await;
+    let client = crate::downloader::make_client(&config)?;
+    match crate::hugging_face::verify_api_key(&client, &config, &key).await {
+        Ok(account) => println!("  Access token belongs to {account}."),
+        Err(e) => {
+            println!("  Access token validation failed: {e}");
+            let save_anyway = Confirm::new()
+                .with_prompt("Save it anyway?")
+                .default(false)
+                .interact()
+                .context("Failed to read input")?;
+            if !save_anyway {
+                return Ok(());
+            }
+        }
+    }
+    CONFIGURATION
+        .write()
+        .await
+        .set_huggingface_api_key(key)
+        .await
+}
+
+async fn setup_proxy() -> Result<()> {
+    println!("[3/5] Proxy server");
+    let exists = CONFIGURATION.read().await.proxy.get_proxy_url().is_some();
+    if !confirm_replace("Proxy server", exists)? {
+        return Ok(());
+    }
+    let use_proxy = Confirm::new()
+        .with_prompt("Download through a proxy server?")
+        .default(false)
+        .interact()
+        .context("Failed to read input")?;
+    if !use_proxy {
+        println!("  Skipped.");
+        return Ok(());
+    }
+    let proxy_url = loop {
+        let url: String = Input::new()
+            .with_prompt("Proxy server URL, e.g. http://127.0.0.1:7890")
+            .allow_empty(true)
+            .interact_text()
+            .context("Failed to read input")?;
+        if url.trim().is_empty() {
+            println!("  Skipped.");
+            return Ok(());
+        }
+        match reqwest::Url::parse(url.trim()) {
+            Ok(parsed) if parsed.has_host() => break parsed,
+            _ => println!("  Given proxy URL is invalid, please try again."),
+        }
+    };
+
+    let mut configuration = CONFIGURATION.write().await;
+    configuration
+        .set_proxy(
+            proxy_url.scheme().to_string(),
+            proxy_url.host().map(|h| h.to_string()).unwrap_or_default(),
+            proxy_url.port_or_known_default(),
+            Some(proxy_url.username().to_string()).filter(|u| !u.is_empty()),
+            proxy_url.password().map(ToString::to_string),
+        )
+        .await?;
+    configuration.set_use_proxy(true).await
+}
+
+async fn setup_output_dir() -> Result<()> {
+    println!("[4/5] Default output directory");
+    let exists = CONFIGURATION.read().await.download.output_dir.is_some();
+    if !confirm_replace("Default output directory", exists)? {
+        return Ok(());
+    }
+    let output_dir: String = Input::new()
+        .with_prompt("Directory to store downloaded models")
+        .allow_empty(true)
+        .interact_text()
+        .context("Failed to read input")?;
+    if output_dir.trim().is_empty() {
+        println!("  Skipped, models will be saved into current directory.");
+        return Ok(());
+    }
+    let output_dir = PathBuf::from(output_dir.trim());
+    if !output_dir.exists() {
+        let create = Confirm::new()
+            .with_prompt(format!(
+                "{} does not exist, create it?",
+                output_dir.display()
+            ))
+            .default(true)
+            .interact()
+            .context("Failed to read input")?;
+        if create {
+            std::fs::create_dir_all(&output_dir)
+                .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+        }
+    }
+    CONFIGURATION.write().await.set_output_dir(output_dir).await
+}
+
+async fn setup_retry_policy() -> Result<()> {
+    println!("[5/5] Retry policy");
+    let backoff = CONFIGURATION.read().await.backoff.clone();
+    let customize = Confirm::new()
+        .with_prompt(format!(
+            "Currently retry {} times, starting after {} seconds and {:.02}x longer each time. Change it?",
+            backoff.max_retry, backoff.initial_interval, backoff.multiplier
+        ))
+        .default(false)
+        .interact()
+        .context("Failed to read input")?;
+    if !customize {
+        println!("  Skipped.");
+        return Ok(());
+    }
+    let backoff = loop {
+        let candidate = BackoffConfig {
+            max_retry: Input::new()
+                .with_prompt("Max retry times")
+                .default(backoff.max_retry)
+                .interact_text()
+                .context("Failed to read input")?,
+            initial_interval: Input::new()
+                .with_prompt("Retry interval in seconds")
+                .default(backoff.initial_interval)
+                .interact_text()
+                .context("Failed to read input")?,
+            multiplier: Input::new()
+                .with_prompt("Retry interval increament multiplier")
+                .default(backoff.multiplier)
+                .interact_text()
+                .context("Failed to read input")?,
+        };
+        match candidate.validate() {
+            Ok(()) => break candidate,
+            Err(e) => println!("  {e}, please try again."),
+        }
+    };
+    println!("  Effective schedule: {}.", backoff.describe_schedule());
+    CONFIGURATION
+        .write()
+        .await
+        .set_backoff(
+            Some(backoff.initial_interval),
+            Some(backoff.multiplier),
+            Some(backoff.max_retry),
+        )
+        .await
+}
+
+async fn print_summary() {
+    let configuration = CONFIGURATION.read().await;
+    let set_or_not = |set: bool| if set { "[SET]" } else { "[NOT SET]" };
+    println!("\nSetup completed:");
+    println!(
+        "  Civitai access key: {}",
+        set_or_not(configuration.civitai.api_key.is_some())
+    );
+    println!(
+        "  HuggingFace access token: {}",
+        set_or_not(configuration.huggingface.api_key.is_some())
+    );
+    println!(
+        "  Proxy server: {}",
+        configuration
+            .proxy
+            .get_proxy_url()
+            .filter(|_| configuration.proxy.use_proxy)
+            .map(|url| url.to_string())
+            .unwrap_or("[NOT USED]".to_string())
+    );
+    println!(
+        "  Default output directory: {}",
+        configuration
+            .download
+            .output_dir
+            .as_ref()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or("[CURRENT DIRECTORY]".to_string())
+    );
+    println!(
+        "  Retry: {} times, starting after {} seconds, {:.02}x longer each time.",
+        configuration.backoff.max_retry,
+        configuration.backoff.initial_interval,
+        configuration.backoff.multiplier,
+    );
+    println!("Use \"imd config\" to adjust these settings later.");
+}
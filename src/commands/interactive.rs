@@ -0,0 +1,153 @@
+//! Menu shown when imd tool runs without a command in a terminal.
+
+use anyhow::Result;
+use dialoguer::{Input, Select};
+
+/// Entries of the top-level menu, in the order shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuItem {
+    Download,
+    Scan,
+    List,
+    Configure,
+    Quit,
+}
+
+impl MenuItem {
+    pub const ALL: [MenuItem; 5] = [
+        Self::Download,
+        Self::Scan,
+        Self::List,
+        Self::Configure,
+        Self::Quit,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Download => "Download a model",
+            Self::Scan => "Scan current directory",
+            Self::List => "List models here",
+            Self::Configure => "Configure",
+            Self::Quit => "Quit",
+        }
+    }
+
+    /// Command line arguments running the entry by the existing commands, `None` to quit.
+    pub fn command_args(&self, url: Option<&str>) -> Option<Vec<String>> {
+        let args = match self {
+            Self::Download => vec!["download".to_string(), url?.to_string()],
+            Self::Scan => vec!["scan".to_string()],
+            Self::List => vec!["list".to_string()],
+            Self::Configure => vec!["init".to_string()],
+            Self::Quit => return None,
+        };
+        Some(args)
+    }
+}
+
+/// Asks what to do, returns the command line arguments of the chosen command, or `None` when
+/// the user quits.
+pub fn choose_from_menu() -> Result<Option<Vec<String>>> {
+    let labels = MenuItem::ALL.map(|item| item.label());
+    let choice = crate::prompt::interact(
+        move || {
+            Select::new()
+                .with_prompt("What to do?")
+                .items(&labels)
+                .default(0)
+                .interact_opt()
+        },
+        None,
+        "Quit",
+    )?;
+    let Some(item) = choice.map(|index| MenuItem::ALL[index]) else {
+        return Ok(None);
+    };
+    let url = match item {
+        MenuItem::Download => {
+            let url = crate::prompt::interact(
+                || {
+                    Input::<String>::new()
+                        .with_prompt("Model page URL")
+                        .allow_empty(true)
+                        .interact_text()
+                },
+                String::new(),
+                "no URL",
+            )?;
+            let url = url.trim().to_string();
+            // 未输入地址时直接退出
+            if url.is_empty() {
+                return Ok(None);
+            }
+            Some(url)
+        }
+        _ => None,
+    };
+    Ok(item.command_args(url.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::commands::Commands;
+
+    /// Stands in for the command line of the binary, which parses the chosen arguments again.
+    #[derive(Parser)]
+    struct MenuCli {
+        #[command(subcommand)]
+        command: Commands,
+    }
+
+    fn route(item: MenuItem, url: Option<&str>) -> Option<Commands> {
+        let args = item.command_args(url)?;
+        let command = MenuCli::try_parse_from(std::iter::once("imd".to_string()).chain(args))
+            .unwrap_or_else(|e| panic!("{item:?} routes to invalid arguments: {e}"))
+            .command;
+        Some(command)
+    }
+
+    #[test]
+    fn menu_items_route_to_existing_commands() {
+        let url = "https://civitai.com/models/1";
+        let Some(Commands::Download(options)) = route(MenuItem::Download, Some(url)) else {
+            panic!("Download does not route to the download command");
+        };
+        assert_eq!(options.url.as_deref(), Some(url));
+        assert!(matches!(
+            route(MenuItem::Scan, None),
+            Some(Commands::Scan(_))
+        ));
+        assert!(matches!(
+            route(MenuItem::List, None),
+            Some(Commands::List(_))
+        ));
+        assert!(matches!(
+            route(MenuItem::Configure, None),
+            Some(Commands::Init)
+        ));
+    }
+
+    #[test]
+    fn quit_and_download_without_url_run_nothing() {
+        assert!(MenuItem::Quit.command_args(None).is_none());
+        assert!(MenuItem::Download.command_args(None).is_none());
+        // 地址以外的输入不影响其他菜单项
+        assert_eq!(
+            MenuItem::Scan.command_args(Some("ignored")),
+            Some(vec!["scan".to_string()])
+        );
+    }
+
+    #[test]
+    fn menu_lists_every_item_once() {
+        let labels = MenuItem::ALL.map(|item| item.label());
+        assert_eq!(labels[0], "Download a model");
+        assert_eq!(labels[MenuItem::ALL.len() - 1], "Quit");
+        for (index, label) in labels.iter().enumerate() {
+            assert!(!labels[..index].contains(label), "{label}");
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use serde::Serialize;
+
+/// Version of the documents printed by `--json`, bumped whenever a document changes
+/// incompatibly.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// A top level JSON document, the fields of the body follow `schemaVersion` in their declared
+/// order. Ids are numbers, timestamps RFC 3339 strings and hashes upper case hex.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonDocument<'a, T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    body: &'a T,
+}
+
+/// Renders the document pretty printed.
+pub fn render_json<T: Serialize>(body: &T) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&JsonDocument {
+        schema_version: JSON_SCHEMA_VERSION,
+        body,
+    })
+}
+
+/// Prints the document to stdout, pretty printed.
+pub fn print_json<T: Serialize>(body: &T) -> anyhow::Result<()> {
+    println!("{}", render_json(body)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Body {
+        zebra_count: u64,
+        alpha_name: &'static str,
+    }
+
+    #[test]
+    fn schema_version_leads_the_declared_fields() {
+        let body = Body {
+            zebra_count: 2,
+            alpha_name: "first",
+        };
+        assert_eq!(
+            render_json(&body).unwrap(),
+            r#"{
+  "schemaVersion": 1,
+  "zebraCount": 2,
+  "alphaName": "first"
+}"#
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Args;
+use serde::Serialize;
+
+use super::{collector::collect_model_files, json_output::print_json};
+use crate::{
+    civitai::{ModelStats, RemovalKind, readme_path},
+    integrations::InstallTarget,
+    safetensors,
+    utils::{datetime_to_iso_string, kilobytes_to_human_string},
+};
+
+#[derive(Args, Default)]
+pub struct ListOptions {
+    #[arg(help = "The directory to list models in, defaults to current directory.")]
+    pub directory: Option<PathBuf>,
+    #[arg(
+        long,
+        short = 'r',
+        help = "Also list models in subdirectories.",
+        default_value = "false"
+    )]
+    pub recursive: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "List models of a model UI by model directory instead of a directory.",
+        default_value_t = InstallTarget::Directory,
+        conflicts_with = "directory"
+    )]
+    pub target: InstallTarget,
+    #[arg(
+        long,
+        help = "Root directory of Stable Diffusion webui, overrides the configured one and implies --target webui.",
+        conflicts_with = "directory"
+    )]
+    pub webui_root: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Print the models as JSON for scripting.",
+        default_value = "false"
+    )]
+    pub json: bool,
+}
+
+/// Models listed by `--json`.
+#[derive(Serialize)]
+struct ListJson<'a> {
+    models: &'a [ListedModel],
+}
+
+/// A listed model file, as printed in JSON.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListedModel {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    path: PathBuf,
+    size: u64,
+    base_model: Option<String>,
+    network: Option<String>,
+    readme: bool,
+    /// Page of the model on Civitai, known from its sidecar or cache.
+    page_url: Option<String>,
+    /// How the version left Civitai, found by `imd outdated --check-removed`.
+    removed_upstream: Option<RemovalKind>,
+    /// Counters of the model and version as of the cached metadata.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    civitai_stats: Option<CivitaiStats>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CivitaiStats {
+    model: ModelStats,
+    version: ModelStats,
+    published_at: Option<String>,
+}
+
+pub async fn process_list_models(options: &ListOptions) -> anyhow::Result<()> {
+    let target = options
+        .target
+        .with_webui_root(options.webui_root.as_deref())?;
+    let config = crate::configuration::current().await;
+    match crate::integrations::model_directories(&config, target, options.webui_root.as_deref())? {
+        None => {
+            let directory = match options.directory.as_ref() {
+                Some(dir) => dir.clone(),
+                None => {
+                    std::env::current_dir().context("Unable to get current working directory")?
+                }
+            };
+            let models = list_models_in(&directory, options.recursive, None)?;
+            if options.json {
+                print_json(&ListJson { models: &models })?;
+            } else if models.is_empty() {
+                println!("No model found in {}.", directory.display());
+            } else {
+                print_models(&directory, &models);
+                println!("{} model(s) found.", models.len());
+            }
+        }
+        Some(model_dirs) => {
+            let mut all_models = Vec::new();
+            // 按类别分组列出，类别目录总是递归列出
+            for (category, directory) in model_dirs.categories.iter() {
+                let models = if directory.is_dir() {
+                    list_models_in(directory, true, Some(category))?
+                } else {
+                    Vec::new()
+                };
+                if !options.json {
+                    println!("{category}: {}", directory.display());
+                    if !directory.is_dir() {
+                        println!("Directory does not exist.");
+                    } else if models.is_empty() {
+                        println!("No model found in {}.", directory.display());
+                    } else {
+                        print_models(directory, &models);
+                    }
+                    println!();
+                }
+                all_models.extend(models);
+            }
+            if options.json {
+                print_json(&ListJson {
+                    models: &all_models,
+                })?;
+            } else {
+                println!(
+                    "{} model(s) found in {}.",
+                    all_models.len(),
+                    model_dirs.ui_name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collects the models in the directory.
+fn list_models_in(
+    directory: &Path,
+    recursive: bool,
+    category: Option<&str>,
+) -> anyhow::Result<Vec<ListedModel>> {
+    let model_files = collect_model_files(directory, recursive)
+        .with_context(|| format!("Failed to list model files in {}", directory.display()))?;
+    let models = model_files
+        .into_iter()
+        .map(|file| {
+            let header = safetensors::is_safetensors_file(&file)
+                .then(|| safetensors::read_header(&file).ok())
+                .flatten();
+            ListedModel {
+                category: category.map(String::from),
+                size: std::fs::metadata(&file)
+                    .map(|m| m.len())
+                    .unwrap_or_default(),
+                base_model: header.as_ref().and_then(|h| h.base_model()),
+                network: header.as_ref().and_then(|h| h.network_summary()),
+                readme: readme_path(&file).exists(),
+                page_url: crate::civitai::local_page_url(&file).ok().flatten(),
+                removed_upstream: crate::civitai::local_removal(&file),
+                civitai_stats: crate::civitai::local_metadata(&file).map(|(model, version)| {
+                    CivitaiStats {
+                        model: model.stats(),
+                        version: version.stats(),
+                        published_at: version.published_at().map(|d| datetime_to_iso_string(&d)),
+                    }
+                }),
+                path: file,
+            }
+        })
+        .collect();
+    Ok(models)
+}
+
+/// Prints a table of the models in the directory.
+fn print_models(directory: &Path, models: &[ListedModel]) {
+    let rows = models
+        .iter()
+        .map(|model| {
+            let name = model
+                .path
+                .strip_prefix(directory)
+                .unwrap_or(&model.path)
+                .to_string_lossy()
+                .into_owned();
+            let size = kilobytes_to_human_string(model.size as f64 / 1024.0);
+            let base_model = model.base_model.clone().unwrap_or("-".to_string());
+            let network = model.network.clone().unwrap_or("-".to_string());
+            let readme = if model.readme { "readme" } else { "no readme" };
+            let status = match model.removed_upstream {
+                Some(kind) => format!("{readme}, {kind} on Civitai"),
+                None => readme.to_string(),
+            };
+            [name, size, base_model, network, status]
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = [0usize; 5];
+    for row in rows.iter() {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+    for row in rows.iter() {
+        println!(
+            "{:<w0$}  {:>w1$}  {:<w2$}  {:<w3$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::json_output::render_json;
+
+    #[test]
+    fn listed_models_keep_their_json_shape() {
+        let models = [
+            ListedModel {
+                category: Some("lora".to_string()),
+                path: PathBuf::from("models/Lora/fixture.safetensors"),
+                size: 2048,
+                base_model: Some("SDXL 1.0".to_string()),
+                network: Some("LoRA rank 16".to_string()),
+                readme: true,
+                page_url: Some("https://civitai.com/models/1?modelVersionId=10".to_string()),
+                removed_upstream: Some(RemovalKind::Unpublished),
+                civitai_stats: Some(CivitaiStats {
+                    model: ModelStats {
+                        download_count: Some(1234),
+                        rating: Some(4.8),
+                        ..Default::default()
+                    },
+                    version: ModelStats::default(),
+                    published_at: Some("2024-01-01T00:00:00Z".to_string()),
+                }),
+            },
+            ListedModel {
+                category: None,
+                path: PathBuf::from("untracked.ckpt"),
+                size: 0,
+                base_model: None,
+                network: None,
+                readme: false,
+                page_url: None,
+                removed_upstream: None,
+                civitai_stats: None,
+            },
+        ];
+        let rendered = render_json(&ListJson { models: &models }).unwrap();
+        assert_eq!(
+            rendered,
+            r#"{
+  "schemaVersion": 1,
+  "models": [
+    {
+      "category": "lora",
+      "path": "models/Lora/fixture.safetensors",
+      "size": 2048,
+      "baseModel": "SDXL 1.0",
+      "network": "LoRA rank 16",
+      "readme": true,
+      "pageUrl": "https://civitai.com/models/1?modelVersionId=10",
+      "removedUpstream": "unpublished",
+      "civitaiStats": {
+        "model": {
+          "downloadCount": 1234,
+          "thumbsUpCount": null,
+          "thumbsDownCount": null,
+          "favoriteCount": null,
+          "commentCount": null,
+          "rating": 4.8,
+          "ratingCount": null,
+          "tippedAmountCount": null
+        },
+        "version": {
+          "downloadCount": null,
+          "thumbsUpCount": null,
+          "thumbsDownCount": null,
+          "favoriteCount": null,
+          "commentCount": null,
+          "rating": null,
+          "ratingCount": null,
+          "tippedAmountCount": null
+        },
+        "publishedAt": "2024-01-01T00:00:00Z"
+      }
+    },
+    {
+      "path": "untracked.ckpt",
+      "size": 0,
+      "baseModel": null,
+      "network": null,
+      "readme": false,
+      "pageUrl": null,
+      "removedUpstream": null
+    }
+  ]
+}"#
+        );
+    }
+}
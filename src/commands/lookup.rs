@@ -0,0 +1,367 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use clap::Args;
+use serde::Serialize;
+
+use super::json_output::print_json;
+use crate::{
+    civitai::{LookupResult, ModelPermissions, ModelStats},
+    errors::InvalidInputError,
+    utils::{
+        datetime_to_date_string, datetime_to_iso_string, format_age, kilobytes_to_human_string,
+        open_in_browser,
+    },
+};
+
+/// Lengths of hashes accepted by Civitai: AutoV2 and full BLAKE3 or SHA256.
+const ACCEPTED_HASH_LENGTHS: [usize; 2] = [10, 64];
+
+/// Result of a lookup, as printed in JSON.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LookupJson {
+    found: bool,
+    hash: String,
+    #[serde(flatten)]
+    model: Option<FoundModelJson>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FoundModelJson {
+    model_id: u64,
+    model_name: String,
+    version_id: u64,
+    version_name: String,
+    base_model: Option<String>,
+    air: Option<String>,
+    published_at: Option<String>,
+    updated_at: Option<String>,
+    model_updated_at: Option<String>,
+    page_url: String,
+    file: Option<FoundFileJson>,
+    model_stats: ModelStats,
+    version_stats: ModelStats,
+    permissions: ModelPermissions,
+    /// Every version of the model, newest first.
+    versions: Vec<ModelVersionJson>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelVersionJson {
+    id: u64,
+    name: String,
+    published_at: Option<String>,
+    download_count: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FoundFileJson {
+    id: u64,
+    name: String,
+    #[serde(rename = "sizeKB")]
+    size_kb: f64,
+    primary: Option<bool>,
+    sha256: Option<String>,
+    blake3: Option<String>,
+    /// `None` while Civitai is still processing the file.
+    download_url: Option<String>,
+}
+
+#[derive(Args, Default)]
+pub struct LookupOptions {
+    #[arg(help = "A model file, or its BLAKE3, SHA256 or AutoV2 hash.")]
+    pub target: String,
+    #[arg(
+        long,
+        help = "Open the model page in browser.",
+        default_value = "false"
+    )]
+    pub open: bool,
+    #[arg(
+        long,
+        help = "Print the result as JSON for scripting.",
+        default_value = "false"
+    )]
+    pub json: bool,
+}
+
+pub async fn process_lookup_model(options: &LookupOptions) -> anyhow::Result<()> {
+    let target_path = PathBuf::from(&options.target);
+    let (hash, local_file) = if target_path.is_file() {
+        (local_file_hash(&target_path).await?, Some(target_path))
+    } else if is_hash(&options.target) {
+        (options.target.to_ascii_uppercase(), None)
+    } else {
+        return Err(InvalidInputError(format!(
+            "\"{}\" is neither a file nor a hash, expected a model file or its BLAKE3, SHA256 or AutoV2 hash",
+            options.target
+        ))
+        .into());
+    };
+
+    if !crate::configuration::check_civitai_key_exists().await {
+        bail!(
+            "Civitai access key is not set, set it by \"imd config set civitai <key>\" or run \"imd init\"."
+        );
+    }
+    let config = crate::configuration::current().await;
+    let client = crate::downloader::make_client(&config).context("Failed to initialize client")?;
+    let result = crate::civitai::lookup_by_hash(&client, &config, &hash, local_file.as_deref())
+        .await
+        .with_context(|| format!("Failed to look up hash {hash}"))?;
+
+    let Some(result) = result else {
+        if options.json {
+            print_json(&LookupJson::new(&hash, None))?;
+        } else {
+            println!("No model on Civitai has a file with hash {hash}.");
+        }
+        return Ok(());
+    };
+
+    if options.json {
+        print_json(&LookupJson::new(&hash, Some(&result)))?;
+    } else {
+        print_result(&result);
+    }
+    if options.open {
+        open_in_browser(&result.page_url()).context("Failed to open browser")?;
+    }
+    Ok(())
+}
+
+/// Takes the hash recorded in sidecar or hash file beside the model, and calculates it only when
+/// none is recorded.
+async fn local_file_hash(path: &Path) -> anyhow::Result<String> {
+    if let Ok(Some(sidecar)) = crate::civitai::load_sidecar(path)
+        && let Some(blake3) = sidecar.file.and_then(|file| file.blake3)
+    {
+        return Ok(blake3.to_ascii_uppercase());
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    if let Ok(recorded) = std::fs::read_to_string(path.with_file_name(format!("{stem}.blake3")))
+        && is_hash(recorded.trim())
+    {
+        return Ok(recorded.trim().to_ascii_uppercase());
+    }
+    eprintln!("Calculating hash of {}...", path.display());
+    crate::civitai::blake3_hash(path, None)
+        .await
+        .context("Calculate file hash")
+}
+
+fn is_hash(value: &str) -> bool {
+    ACCEPTED_HASH_LENGTHS.contains(&value.len()) && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn print_result(result: &LookupResult) {
+    println!("Model:   {} ({})", result.model.name(), result.model.id());
+    println!(
+        "Version: {} ({})",
+        result.version.name(),
+        result.version.id()
+    );
+    if let Some(base_model) = result.version.base_model() {
+        println!("Base:    {base_model}");
+    }
+    if let Some(air) = result.version.air() {
+        println!("AIR:     {air}");
+    }
+    for (label, datetime) in [
+        ("Posted:", result.version.published_at()),
+        ("Updated:", result.model.last_updated()),
+    ] {
+        if let Some(datetime) = datetime {
+            println!(
+                "{label:<9}{} ({})",
+                datetime_to_date_string(&datetime),
+                format_age(&datetime)
+            );
+        }
+    }
+    println!("Page:    {}", result.page_url());
+    if let Some(file) = result.file.as_ref() {
+        println!(
+            "File:    {} ({}, {})",
+            file.name(),
+            file.id(),
+            kilobytes_to_human_string(file.size())
+        );
+        if let Some(sha256) = file.sha256_hash() {
+            println!("  SHA256 {sha256}");
+        }
+        if let Some(blake3) = file.blake3_hash() {
+            println!("  BLAKE3 {blake3}");
+        }
+    }
+    println!("Permissions:");
+    for (label, value) in result.model.permissions().lines() {
+        println!("  {label}: {value}");
+    }
+}
+
+impl LookupJson {
+    fn new(hash: &str, result: Option<&LookupResult>) -> Self {
+        Self {
+            found: result.is_some(),
+            hash: hash.to_ascii_uppercase(),
+            model: result.map(|result| FoundModelJson {
+                model_id: result.model.id(),
+                model_name: result.model.name(),
+                version_id: result.version.id(),
+                version_name: result.version.name(),
+                base_model: result.version.base_model(),
+                air: result.version.air(),
+                published_at: result
+                    .version
+                    .published_at()
+                    .map(|d| datetime_to_iso_string(&d)),
+                updated_at: result
+                    .version
+                    .updated_at()
+                    .map(|d| datetime_to_iso_string(&d)),
+                model_updated_at: result
+                    .model
+                    .last_updated()
+                    .map(|d| datetime_to_iso_string(&d)),
+                page_url: result.page_url(),
+                file: result.file.as_ref().map(|file| FoundFileJson {
+                    id: file.id(),
+                    name: file.name(),
+                    size_kb: file.size(),
+                    primary: file.is_primary(),
+                    sha256: file.sha256_hash().map(|hash| hash.to_ascii_uppercase()),
+                    blake3: file.blake3_hash().map(|hash| hash.to_ascii_uppercase()),
+                    download_url: file.download_url(),
+                }),
+                model_stats: result.model.stats(),
+                version_stats: result.version.stats(),
+                permissions: result.model.permissions(),
+                versions: result
+                    .model
+                    .versions()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|version| ModelVersionJson {
+                        id: version.id(),
+                        name: version.name(),
+                        published_at: version.published_at().map(|d| datetime_to_iso_string(&d)),
+                        download_count: version.download_count(),
+                    })
+                    .collect(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        civitai::{Model, ModelVersion},
+        commands::json_output::render_json,
+    };
+
+    fn fixture(text: &str) -> Value {
+        serde_json::from_str(&text.replace("{{server}}", "https://civitai.com")).unwrap()
+    }
+
+    #[test]
+    fn missing_lookup_keeps_its_json_shape() {
+        assert_eq!(
+            render_json(&LookupJson::new("abcdef0123", None)).unwrap(),
+            r#"{
+  "schemaVersion": 1,
+  "found": false,
+  "hash": "ABCDEF0123"
+}"#
+        );
+    }
+
+    #[test]
+    fn found_lookup_keeps_its_json_shape() {
+        let version = ModelVersion::try_from(&fixture(include_str!(
+            "../../tests/fixtures/civitai/version.json"
+        )))
+        .unwrap();
+        let result = LookupResult {
+            model: Model::try_from(&fixture(include_str!(
+                "../../tests/fixtures/civitai/model.json"
+            )))
+            .unwrap(),
+            file: version.files().unwrap().into_iter().next(),
+            version,
+        };
+        let rendered = render_json(&LookupJson::new("07e9639e69", Some(&result))).unwrap();
+        assert_eq!(
+            rendered,
+            r#"{
+  "schemaVersion": 1,
+  "found": true,
+  "hash": "07E9639E69",
+  "modelId": 1,
+  "modelName": "Fixture LoRA",
+  "versionId": 10,
+  "versionName": "v1",
+  "baseModel": "SDXL 1.0",
+  "air": null,
+  "publishedAt": "2024-01-01T00:00:00Z",
+  "updatedAt": "2024-01-01T00:00:00Z",
+  "modelUpdatedAt": "2024-01-01T00:00:00Z",
+  "pageUrl": "https://civitai.com/models/1?modelVersionId=10",
+  "file": {
+    "id": 11,
+    "name": "fixture.safetensors",
+    "sizeKB": 2.0,
+    "primary": true,
+    "sha256": null,
+    "blake3": "07E9639E695A0A52A3D1FEACAC07E455F8DEAD34FA4D2545C734B4B36ED1FE92",
+    "downloadUrl": "https://civitai.com/api/download/models/10"
+  },
+  "modelStats": {
+    "downloadCount": 1234,
+    "thumbsUpCount": 56,
+    "thumbsDownCount": null,
+    "favoriteCount": 7,
+    "commentCount": null,
+    "rating": 4.8,
+    "ratingCount": 20,
+    "tippedAmountCount": null
+  },
+  "versionStats": {
+    "downloadCount": 12,
+    "thumbsUpCount": null,
+    "thumbsDownCount": null,
+    "favoriteCount": null,
+    "commentCount": null,
+    "rating": 0.0,
+    "ratingCount": null,
+    "tippedAmountCount": null
+  },
+  "permissions": {
+    "license": null,
+    "allowNoCredit": true,
+    "allowCommercialUse": [
+      "Image"
+    ],
+    "allowDerivatives": true,
+    "allowDifferentLicense": true
+  },
+  "versions": [
+    {
+      "id": 10,
+      "name": "v1",
+      "publishedAt": "2024-01-01T00:00:00Z",
+      "downloadCount": null
+    }
+  ]
+}"#
+        );
+    }
+}
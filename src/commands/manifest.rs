@@ -0,0 +1,424 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, bail};
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use super::collector::collect_model_files;
+use crate::{
+    artifacts::ArtifactSet,
+    civitai::SizeBudget,
+    errors::{InvalidInputError, SizeBudgetExceededError},
+    progress::{ReporterKind, SkipReason, SkippedItem, describe_skip_counts},
+    utils::sanitize_file_name,
+};
+
+/// Version of the manifest format, increased on incompatible changes.
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Args)]
+pub struct ManifestOptions {
+    #[command(subcommand)]
+    pub action: ManifestAction,
+}
+
+#[derive(Subcommand)]
+pub enum ManifestAction {
+    #[command(about = "Export models downloaded from Civitai in a directory as a manifest.")]
+    Export {
+        #[arg(help = "The manifest file to write.")]
+        manifest: PathBuf,
+        #[arg(
+            short = 'd',
+            long,
+            help = "The directory to export, defaults to current directory."
+        )]
+        directory: Option<PathBuf>,
+    },
+    #[command(about = "Download every model in a manifest, keeping the directory layout.")]
+    Install {
+        #[arg(help = "The manifest file to read.")]
+        manifest: PathBuf,
+        #[arg(
+            short = 'o',
+            long = "output",
+            help = "The directory to install into, defaults to the configured output directory."
+        )]
+        output_path: Option<PathBuf>,
+        #[arg(
+            long = "fix-missing",
+            short = 'f',
+            help = "Fix missing directories.",
+            default_value = "false"
+        )]
+        fix_missing_dirs: bool,
+        #[arg(
+            long,
+            short = 'c',
+            help = "Skip collecting community images metadata.",
+            default_value = "false"
+        )]
+        skip_community: bool,
+        #[arg(
+            long,
+            help = "Only compare local files against the manifest, download nothing.",
+            default_value = "false"
+        )]
+        check: bool,
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = crate::utils::parse_size,
+            help = "Stop downloading model files once they add up to the given size, like 500M or 5G, across all entries."
+        )]
+        max_total_size: Option<u64>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    version: u32,
+    entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestEntry {
+    model_id: u64,
+    version_id: u64,
+    file_id: u64,
+    blake3: Option<String>,
+    /// Path relative to the exported directory, always separated by `/`.
+    relative_path: String,
+}
+
+impl ManifestEntry {
+    /// Local path of the entry, segments leaving the base directory are dropped.
+    fn local_path(&self, base_dir: &Path) -> PathBuf {
+        self.relative_path
+            .split('/')
+            .filter(|segment| !matches!(*segment, "" | "." | ".."))
+            .fold(base_dir.to_path_buf(), |path, segment| {
+                path.join(sanitize_file_name(segment))
+            })
+    }
+}
+
+/// State of a manifest entry on the local disk.
+enum EntryState {
+    Present,
+    Missing,
+    /// The hash recorded beside the local file differs from the manifest.
+    Mismatched(String),
+}
+
+pub async fn process_manifest_options(options: &ManifestOptions) -> anyhow::Result<()> {
+    match &options.action {
+        ManifestAction::Export {
+            manifest,
+            directory,
+        } => export_manifest(manifest, directory.as_deref()),
+        ManifestAction::Install {
+            manifest,
+            output_path,
+            fix_missing_dirs,
+            skip_community,
+            check,
+            max_total_size,
+        } => {
+            let manifest = read_manifest(manifest)?;
+            let output_path = match output_path.clone() {
+                Some(path) => path,
+                None => match crate::configuration::CONFIGURATION
+                    .read()
+                    .await
+                    .download
+                    .output_dir
+                    .clone()
+                {
+                    Some(path) => path,
+                    None => std::env::current_dir()
+                        .context("Unable to get current working directory")?,
+                },
+            };
+            if *check {
+                check_manifest(&manifest, &output_path)
+            } else {
+                crate::downloader::validate_output_dir(&output_path, *fix_missing_dirs)?;
+                let size_budget = super::download::size_budget(*max_total_size);
+                install_manifest(&manifest, &output_path, *skip_community, size_budget).await
+            }
+        }
+    }
+}
+
+fn export_manifest(manifest_path: &Path, directory: Option<&Path>) -> anyhow::Result<()> {
+    let directory = match directory {
+        Some(dir) => dir.to_path_buf(),
+        None => std::env::current_dir().context("Unable to get current working directory")?,
+    };
+    let model_files = collect_model_files(&directory, true)
+        .with_context(|| format!("Failed to list model files in {}", directory.display()))?;
+
+    let mut entries = Vec::new();
+    for model_file in model_files.iter() {
+        let relative_path = model_file
+            .strip_prefix(&directory)
+            .unwrap_or(model_file)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let sidecar = match crate::civitai::load_sidecar(model_file) {
+            Ok(Some(sidecar)) => sidecar,
+            Ok(None) => {
+                println!(
+                    "Skip {relative_path}, no Civitai metadata. Run \"imd renew\" on it first."
+                );
+                continue;
+            }
+            Err(e) => {
+                println!("Skip {relative_path}, its Civitai metadata is unreadable: {e}");
+                continue;
+            }
+        };
+        let Some(file) = sidecar.file else {
+            println!("Skip {relative_path}, its Civitai metadata has no file information.");
+            continue;
+        };
+        entries.push(ManifestEntry {
+            model_id: sidecar.model_id,
+            version_id: sidecar.version_id,
+            file_id: file.id,
+            blake3: file.blake3,
+            relative_path,
+        });
+    }
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        entries,
+    };
+    std::fs::write(manifest_path, serde_json::to_vec_pretty(&manifest)?)
+        .with_context(|| format!("Failed to write {}", manifest_path.display()))?;
+    println!(
+        "Exported {} of {} models to {}.",
+        manifest.entries.len(),
+        model_files.len(),
+        manifest_path.display()
+    );
+    Ok(())
+}
+
+fn read_manifest(manifest_path: &Path) -> anyhow::Result<Manifest> {
+    let content = std::fs::read(manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let manifest: Manifest = serde_json::from_slice(&content).map_err(|e| {
+        InvalidInputError(format!(
+            "\"{}\" is not a valid manifest: {e}",
+            manifest_path.display()
+        ))
+    })?;
+    if manifest.version > MANIFEST_VERSION {
+        return Err(InvalidInputError(format!(
+            "Manifest version {} is newer than supported version {MANIFEST_VERSION}, update imd tool.",
+            manifest.version
+        ))
+        .into());
+    }
+    Ok(manifest)
+}
+
+/// Compares the local file with the entry by the hash recorded beside it, the file is not hashed
+/// again.
+fn entry_state(entry: &ManifestEntry, base_dir: &Path) -> EntryState {
+    let local_path = entry.local_path(base_dir);
+    if !local_path.is_file() {
+        return EntryState::Missing;
+    }
+    let recorded_hash = crate::civitai::load_sidecar(&local_path)
+        .ok()
+        .flatten()
+        .and_then(|sidecar| sidecar.file)
+        .and_then(|file| file.blake3);
+    match (entry.blake3.as_ref(), recorded_hash) {
+        (Some(expected), Some(recorded)) if !expected.eq_ignore_ascii_case(&recorded) => {
+            EntryState::Mismatched(recorded)
+        }
+        _ => EntryState::Present,
+    }
+}
+
+fn check_manifest(manifest: &Manifest, base_dir: &Path) -> anyhow::Result<()> {
+    let mut missing = 0;
+    let mut mismatched = 0;
+    for entry in manifest.entries.iter() {
+        match entry_state(entry, base_dir) {
+            EntryState::Present => {}
+            EntryState::Missing => {
+                missing += 1;
+                println!("missing   {}", entry.relative_path);
+            }
+            EntryState::Mismatched(recorded) => {
+                mismatched += 1;
+                println!(
+                    "different {} (local {recorded}, manifest {})",
+                    entry.relative_path,
+                    entry.blake3.as_deref().unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    let listed = manifest
+        .entries
+        .iter()
+        .map(|entry| entry.local_path(base_dir))
+        .collect::<HashSet<_>>();
+    let mut extra = 0;
+    if base_dir.is_dir() {
+        for model_file in collect_model_files(base_dir, true)? {
+            if !listed.contains(&model_file) {
+                extra += 1;
+                println!(
+                    "extra     {}",
+                    model_file
+                        .strip_prefix(base_dir)
+                        .unwrap_or(&model_file)
+                        .display()
+                );
+            }
+        }
+    }
+
+    println!(
+        "\n{} entries in manifest: {} present, {missing} missing, {mismatched} different. {extra} local models not in manifest.",
+        manifest.entries.len(),
+        manifest.entries.len() - missing - mismatched
+    );
+    Ok(())
+}
+
+async fn install_manifest(
+    manifest: &Manifest,
+    base_dir: &Path,
+    skip_community: bool,
+    size_budget: Option<Arc<SizeBudget>>,
+) -> anyhow::Result<()> {
+    crate::downloader::ensure_online("Installing a manifest")?;
+    if !crate::configuration::check_civitai_key_exists().await {
+        bail!(
+            "Civitai access key is not set, set it by \"imd config set civitai <key>\" or run \"imd init\"."
+        );
+    }
+    let config = crate::configuration::current().await;
+    let client = crate::downloader::make_client(&config).context("Failed to initialize client")?;
+
+    let total = manifest.entries.len();
+    let mut skipped = Vec::new();
+    let mut installed = 0;
+    let mut failed_entries = Vec::new();
+    for (index, entry) in manifest.entries.iter().enumerate() {
+        if matches!(entry_state(entry, base_dir), EntryState::Present) {
+            skipped.push(SkippedItem::new(
+                entry.relative_path.as_str(),
+                SkipReason::AlreadyPresent,
+            ));
+            continue;
+        }
+        println!(
+            "\n[{}/{total}] Installing {}...",
+            index + 1,
+            entry.relative_path
+        );
+        let local_path = entry.local_path(base_dir);
+        let destination = local_path.parent().unwrap_or(base_dir).to_path_buf();
+        let behavior = crate::civitai::DownloadBehavior {
+            artifacts: ArtifactSet::standard().skipping_community(skip_community),
+            allow_unsafe: false,
+            dry_run: false,
+            folder_per_model: false,
+            unattended: true,
+            link_existing: true,
+            file_ids: vec![entry.file_id],
+            model_dirs: None,
+            size_budget: size_budget.clone(),
+            reporter: ReporterKind::Bar,
+            resumed: None,
+            version_history: None,
+            meta_file_name: None,
+            pick_cover: false,
+            prompt_limits: Default::default(),
+            extract_archives: false,
+            remove_archive: false,
+            file_preference: None,
+            commercial_use_policy: None,
+        };
+        let version_selection = crate::civitai::VersionSelection {
+            ids: vec![entry.version_id],
+            ..Default::default()
+        };
+        // 单个条目失败时继续安装其余条目
+        let result = match std::fs::create_dir_all(&destination) {
+            Ok(()) => {
+                crate::civitai::download_from_civitai(
+                    &client,
+                    &config,
+                    entry.model_id,
+                    &version_selection,
+                    Some(&destination),
+                    &behavior,
+                )
+                .await
+            }
+            Err(e) => Err(anyhow::Error::from(e).context(format!(
+                "Failed to create directory {}",
+                destination.display()
+            ))),
+        };
+        match result {
+            Ok(summary) if summary.files.is_empty() => skipped.extend(summary.skipped),
+            Ok(_) => installed += 1,
+            Err(e) if e.downcast_ref::<SizeBudgetExceededError>().is_some() => {
+                println!("{e}, stop installing. Run it again later to install the rest.");
+                skipped.extend(
+                    manifest.entries[index..]
+                        .iter()
+                        .filter(|entry| {
+                            !matches!(entry_state(entry, base_dir), EntryState::Present)
+                        })
+                        .map(|entry| {
+                            SkippedItem::new(entry.relative_path.as_str(), SkipReason::OverBudget)
+                        }),
+                );
+                break;
+            }
+            Err(e) => {
+                eprintln!("Failed to install {}: {e:#}", entry.relative_path);
+                failed_entries.push(entry.relative_path.as_str());
+            }
+        }
+    }
+
+    println!("\nInstalled {installed} of {total} entries.");
+    if !skipped.is_empty() {
+        println!(
+            "Skipped {}: {}.",
+            skipped.len(),
+            describe_skip_counts(&skipped)
+        );
+    }
+    if !failed_entries.is_empty() {
+        bail!(
+            "{} entries failed to install: {}",
+            failed_entries.len(),
+            failed_entries.join(", ")
+        );
+    }
+    Ok(())
+}
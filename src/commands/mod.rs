@@ -1,24 +1,92 @@
 use clap::Subcommand;
 
+mod cache;
+mod clean;
 mod collector;
 mod config;
+mod diagnose;
 mod download;
+mod flags;
+mod hash;
+mod history;
+mod index;
+mod init;
+mod interactive;
+mod json_output;
+mod list;
+mod lookup;
+mod manifest;
+mod open;
+mod outdated;
+mod readme;
+mod remove;
 mod renew;
+mod scan;
+mod sync;
 
+pub use cache::process_cache_options;
+pub use clean::process_clean_options;
 pub use config::process_config_options;
-pub use download::process_download_options;
+pub use diagnose::process_diagnose_downloads;
+pub use download::{OutputFormat, process_download_options};
+pub use hash::process_hash_files;
+pub use history::process_show_history;
+pub use index::process_index_options;
+pub use init::{offer_setup_wizard, process_init};
+pub use interactive::choose_from_menu;
+pub use list::process_list_models;
+pub use lookup::process_lookup_model;
+pub use manifest::process_manifest_options;
+pub use open::process_open_model_page;
+pub use outdated::process_outdated_models;
+pub use readme::process_readme_options;
+pub use remove::process_remove_model;
 pub use renew::process_model_meta_renew;
+pub use scan::process_scan_models;
+pub use sync::process_sync_models;
 
 #[derive(Subcommand)]
 pub enum Commands {
+    #[command(about = "Setup downloader interactively.")]
+    Init,
     #[command(about = "Config downloader.")]
     Config(config::ConfigOptions),
     #[command(about = "Analyze a model URL and download the model.")]
-    Download(download::DownloadOptions),
+    Download(Box<download::DownloadOptions>),
     #[command(about = "Renew locally saved model meta information.")]
     Renew(renew::RenewOptions),
     #[command(about = "Scan all models in current directory, complete model meta information.")]
-    Scan,
+    Scan(scan::ScanOptions),
     #[command(about = "List all models in current directory.")]
-    List,
+    List(list::ListOptions),
+    #[command(about = "Keep a directory in sync with a Civitai collection or user profile.")]
+    Sync(sync::SyncOptions),
+    #[command(about = "Compute hashes of local files without any network access.")]
+    Hash(hash::HashOptions),
+    #[command(about = "Find the Civitai model of a local file or hash.")]
+    Lookup(lookup::LookupOptions),
+    #[command(
+        about = "Check downloaded models for newer versions, and for versions gone from Civitai."
+    )]
+    Outdated(outdated::OutdatedOptions),
+    #[command(about = "Open the Civitai page of a local model file in browser.")]
+    Open(open::OpenOptions),
+    #[command(about = "Remove a model file together with its metadata files and cache records.")]
+    Remove(remove::RemoveOptions),
+    #[command(about = "Export local models as a manifest, or install the models of a manifest.")]
+    Manifest(manifest::ManifestOptions),
+    #[command(about = "Show the latest downloaded files and which accounts downloaded them.")]
+    History(history::HistoryOptions),
+    #[command(
+        about = "Summarize failed download attempts to tell network problems from Civitai ones."
+    )]
+    Diagnose(diagnose::DiagnoseOptions),
+    #[command(about = "Manage readme files of downloaded models.")]
+    Readme(readme::ReadmeOptions),
+    #[command(about = "Write an INDEX.md listing the models in a directory from saved metadata.")]
+    Index(index::IndexOptions),
+    #[command(about = "Delete partial downloads and metadata files left without their model file.")]
+    Clean(clean::CleanOptions),
+    #[command(about = "Manage data remembered in the cache database.")]
+    Cache(cache::CacheOptions),
 }
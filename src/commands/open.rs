@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Args;
+
+use super::collector::is_legal_model_file;
+use crate::{errors::InvalidInputError, utils::open_in_browser};
+
+#[derive(Args, Default)]
+pub struct OpenOptions {
+    #[arg(help = "The model file to open the Civitai page of.")]
+    pub target_file: PathBuf,
+    #[arg(
+        long,
+        help = "Only print the page URL, do not open the browser.",
+        default_value = "false"
+    )]
+    pub print: bool,
+}
+
+pub async fn process_open_model_page(options: &OpenOptions) -> anyhow::Result<()> {
+    if !options.target_file.is_file() || !is_legal_model_file(&options.target_file) {
+        return Err(InvalidInputError(format!(
+            "\"{}\" is not a model file, expected a .safetensors, .ckpt, .pt or .bin file",
+            options.target_file.display()
+        ))
+        .into());
+    }
+    let page_url = crate::civitai::local_page_url(&options.target_file)
+        .with_context(|| format!("Failed to resolve {}", options.target_file.display()))?
+        .ok_or_else(|| {
+            InvalidInputError(format!(
+                "The Civitai model of \"{}\" is unknown, run \"imd lookup\" or \"imd renew\" on it first.",
+                options.target_file.display()
+            ))
+        })?;
+
+    println!("{page_url}");
+    if !options.print {
+        open_in_browser(&page_url).context("Failed to open browser")?;
+    }
+    Ok(())
+}
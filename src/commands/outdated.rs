@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use clap::Args;
+use time::UtcDateTime;
+
+use super::collector::collect_model_files;
+use crate::{
+    civitai::{CivitaiSidecar, Model, RemovalKind, UpstreamState},
+    utils::datetime_to_date_string,
+};
+
+#[derive(Args, Default)]
+pub struct OutdatedOptions {
+    #[arg(help = "The directory to check models in, defaults to current directory.")]
+    pub directory: Option<PathBuf>,
+    #[arg(
+        long,
+        short = 'r',
+        help = "Also check models in subdirectories.",
+        default_value = "false"
+    )]
+    pub recursive: bool,
+    #[arg(
+        long,
+        help = "Also request every downloaded version to find the ones deleted or unpublished on Civitai.",
+        default_value = "false"
+    )]
+    pub check_removed: bool,
+}
+
+pub async fn process_outdated_models(options: &OutdatedOptions) -> anyhow::Result<()> {
+    crate::downloader::ensure_online("Checking models for newer versions")?;
+    let directory = match options.directory.as_ref() {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir().context("Unable to get current working directory")?,
+    };
+    let model_files = collect_model_files(&directory, options.recursive)
+        .with_context(|| format!("Failed to list model files in {}", directory.display()))?;
+    let mut unidentified = 0;
+    let mut local_models = Vec::new();
+    for model_file in model_files {
+        match crate::civitai::load_sidecar(&model_file) {
+            Ok(Some(sidecar)) => local_models.push((model_file, sidecar)),
+            _ => unidentified += 1,
+        }
+    }
+    if local_models.is_empty() {
+        println!(
+            "No model with Civitai metadata found in {}.",
+            directory.display()
+        );
+        return Ok(());
+    }
+
+    let config = crate::configuration::current().await;
+    let client = crate::downloader::make_client(&config).context("Failed to initialize client")?;
+    // 同一模型的多个文件只请求一次
+    let mut models: HashMap<u64, Option<Model>> = HashMap::new();
+    let (mut up_to_date, mut outdated, mut removed, mut failed) = (0, 0, 0, 0);
+    for (model_file, sidecar) in local_models.iter() {
+        let label = format!(
+            "{} ({} {})",
+            model_file.display(),
+            sidecar.model_name,
+            sidecar.version_name
+        );
+        if options.check_removed {
+            match crate::civitai::check_version_upstream(&client, &config, sidecar.version_id).await
+            {
+                Ok(UpstreamState {
+                    removed: Some(kind),
+                    last_seen_at,
+                    ..
+                }) => {
+                    removed += 1;
+                    print_removed(&label, kind, last_seen_at);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    failed += 1;
+                    println!("FAILED    {label}: {e:#}");
+                    continue;
+                }
+            }
+        }
+
+        if let Entry::Vacant(entry) = models.entry(sidecar.model_id) {
+            let model = match crate::civitai::fetch_model_metadata(
+                &client,
+                &config,
+                sidecar.model_id,
+            )
+            .await
+            {
+                Ok(model) => Some(model),
+                Err(e) => {
+                    println!(
+                        "FAILED    {label}: {e:#}{}",
+                        if options.check_removed {
+                            ""
+                        } else {
+                            ", give --check-removed to tell whether it is gone from Civitai"
+                        }
+                    );
+                    None
+                }
+            };
+            entry.insert(model);
+        }
+        match models.get(&sidecar.model_id).and_then(Option::as_ref) {
+            Some(model) => match newer_version(model, sidecar) {
+                Some(newest) => {
+                    outdated += 1;
+                    println!("OUTDATED  {label}: newer version {newest}");
+                }
+                None => up_to_date += 1,
+            },
+            None => failed += 1,
+        }
+    }
+
+    println!(
+        "\n{up_to_date} up to date, {outdated} outdated, {removed} removed from Civitai, {failed} failed, {unidentified} without Civitai metadata."
+    );
+    Ok(())
+}
+
+/// Name and publish date of the newest version with files, when it is newer than the local one.
+fn newer_version(model: &Model, sidecar: &CivitaiSidecar) -> Option<String> {
+    let versions = model.versions().ok()?;
+    // 版本按发布时间从新到旧排列
+    let newest_index = versions.iter().position(|version| version.has_files())?;
+    let local_index = versions
+        .iter()
+        .position(|version| version.id() == sidecar.version_id);
+    if local_index.is_some_and(|local_index| local_index <= newest_index) {
+        return None;
+    }
+    let newest = &versions[newest_index];
+    Some(match newest.released_at() {
+        Some(released_at) => format!(
+            "{} ({})",
+            newest.name(),
+            datetime_to_date_string(&released_at)
+        ),
+        None => newest.name(),
+    })
+}
+
+fn print_removed(label: &str, kind: RemovalKind, last_seen_at: Option<i64>) {
+    let last_seen = last_seen_at
+        .and_then(|timestamp| UtcDateTime::from_unix_timestamp(timestamp).ok())
+        .map(|datetime| format!(", last seen {}", datetime_to_date_string(&datetime)))
+        .unwrap_or_default();
+    println!(
+        "REMOVED   {label}: {kind} on Civitai{last_seen}, the local copy may be the only one left"
+    );
+}
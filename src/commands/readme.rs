@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+
+use super::collector::{collect_model_files, is_legal_model_file};
+use crate::{
+    civitai::{PromptLimits, ReadmeRegeneration},
+    configuration::Configuration,
+    errors::InvalidInputError,
+};
+
+#[derive(Args)]
+pub struct ReadmeOptions {
+    #[command(subcommand)]
+    pub action: ReadmeAction,
+}
+
+#[derive(Subcommand)]
+pub enum ReadmeAction {
+    #[command(about = "Write readme files again from cached metadata, without fetching it.")]
+    Regen {
+        #[arg(help = "A model file or a directory of models, defaults to current directory.")]
+        path: Option<PathBuf>,
+        #[arg(
+            long,
+            short = 'r',
+            help = "Also regenerate models in subdirectories.",
+            default_value = "false"
+        )]
+        recursive: bool,
+        #[arg(
+            long,
+            help = "Fetch metadata missing from cache from Civitai.",
+            default_value = "false"
+        )]
+        fetch_missing: bool,
+        #[arg(
+            long,
+            help = "Most community image prompts listed in the readme, images with identical prompts count once, defaults to 15."
+        )]
+        max_prompt_sections: Option<usize>,
+        #[arg(
+            long,
+            help = "Cut prompts in the readme longer than this many characters, linking to the image page, 0 keeps them whole, defaults to 1000."
+        )]
+        max_prompt_length: Option<usize>,
+        #[arg(
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "true",
+            help = "Show download counts and ratings under the title of the readme, overrides the configured switch."
+        )]
+        readme_stats: Option<bool>,
+    },
+}
+
+pub async fn process_readme_options(options: &ReadmeOptions) -> anyhow::Result<()> {
+    match &options.action {
+        ReadmeAction::Regen {
+            path,
+            recursive,
+            fetch_missing,
+            max_prompt_sections,
+            max_prompt_length,
+            readme_stats,
+        } => {
+            let mut config = crate::configuration::current().await;
+            config.override_readme_stats(*readme_stats);
+            regenerate_readmes(
+                &config,
+                path.as_ref(),
+                *recursive,
+                *fetch_missing,
+                PromptLimits::new(*max_prompt_sections, *max_prompt_length),
+            )
+            .await
+        }
+    }
+}
+
+async fn regenerate_readmes(
+    config: &Configuration,
+    path: Option<&PathBuf>,
+    recursive: bool,
+    fetch_missing: bool,
+    prompt_limits: PromptLimits,
+) -> anyhow::Result<()> {
+    let path = match path {
+        Some(path) => path.clone(),
+        None => std::env::current_dir().context("Unable to get current working directory")?,
+    };
+    let model_files = if path.is_dir() {
+        collect_model_files(&path, recursive)
+            .with_context(|| format!("Failed to list model files in {}", path.display()))?
+    } else if path.is_file() && is_legal_model_file(&path) {
+        vec![path.clone()]
+    } else {
+        return Err(InvalidInputError(format!(
+            "\"{}\" is neither a directory nor a model file",
+            path.display()
+        ))
+        .into());
+    };
+    // 模板有误时每个文件都会失败，提前检查
+    crate::civitai::load_readme_template()?;
+    if let Some(template_path) = crate::civitai::readme_template_path()
+        && template_path.exists()
+    {
+        println!("Using readme template {}.", template_path.display());
+    }
+    // 只有允许补全缺失的元数据时才需要网络
+    let client = if fetch_missing {
+        Some(crate::downloader::make_client(config).context("Failed to initialize client")?)
+    } else {
+        None
+    };
+
+    let mut regenerated = 0;
+    let mut skipped = 0;
+    let mut missing = 0;
+    let mut failed = 0;
+    for model_file in model_files.iter() {
+        let name = model_file
+            .strip_prefix(&path)
+            .unwrap_or(model_file)
+            .display();
+        match crate::civitai::regenerate_readme(model_file, client.as_ref(), config, prompt_limits)
+            .await
+        {
+            Ok(ReadmeRegeneration::Regenerated(readme_path)) => {
+                regenerated += 1;
+                println!("Regenerated {}", readme_path.display());
+            }
+            Ok(ReadmeRegeneration::Unidentified) => {
+                skipped += 1;
+                println!("Skip {name}, no Civitai metadata or hash file.");
+            }
+            Ok(ReadmeRegeneration::MissingMetadata(resource)) => {
+                missing += 1;
+                println!("Skip {name}, {resource} is not cached.");
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("Failed to regenerate readme of {name}: {e:#}");
+            }
+        }
+    }
+
+    println!(
+        "\n{regenerated} regenerated, {skipped} skipped, {missing} missing metadata, {failed} failed."
+    );
+    if missing > 0 && !fetch_missing {
+        println!("Use --fetch-missing to fetch the missing metadata from Civitai.");
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::{io::IsTerminal, path::PathBuf};
+
+use anyhow::{Context, bail};
+use clap::Args;
+use dialoguer::Confirm;
+
+use super::collector::{collect_meta_files, is_legal_model_file};
+use crate::errors::InvalidInputError;
+
+#[derive(Args, Default)]
+pub struct RemoveOptions {
+    #[arg(help = "The model file to remove.")]
+    pub target_file: PathBuf,
+    #[arg(
+        long,
+        short = 'y',
+        help = "Delete without confirmation.",
+        default_value = "false"
+    )]
+    pub yes: bool,
+    #[arg(
+        long,
+        help = "Only delete the model file, keep its readme, cover and other metadata files.",
+        default_value = "false"
+    )]
+    pub keep_meta: bool,
+}
+
+pub async fn process_remove_model(options: &RemoveOptions) -> anyhow::Result<()> {
+    if !options.target_file.is_file() || !is_legal_model_file(&options.target_file) {
+        return Err(InvalidInputError(format!(
+            "\"{}\" is not a model file, expected a .safetensors, .ckpt, .pt or .bin file",
+            options.target_file.display()
+        ))
+        .into());
+    }
+    // 缓存中记录的是完整路径，需要在删除前解析
+    let model_file = options
+        .target_file
+        .canonicalize()
+        .with_context(|| format!("Unable to resolve {}", options.target_file.display()))?;
+    let meta_files = collect_meta_files(&model_file)?;
+
+    let mut deleting_files = vec![model_file.clone()];
+    if !options.keep_meta {
+        deleting_files.extend(meta_files.iter().cloned());
+    }
+    println!("Files to delete:");
+    for file in deleting_files.iter() {
+        println!("  {}", file.display());
+    }
+    if options.keep_meta && !meta_files.is_empty() {
+        println!("Files to keep:");
+        for file in meta_files.iter() {
+            println!("  {}", file.display());
+        }
+    }
+
+    if !options.yes {
+        if !std::io::stdin().is_terminal() {
+            bail!(
+                "Removing requires confirmation, run it in an interactive terminal or give --yes."
+            );
+        }
+        let prompt = format!("Delete these {} files?", deleting_files.len());
+        let confirmed = crate::prompt::interact(
+            move || Confirm::new().with_prompt(prompt).default(false).interact(),
+            false,
+            "no",
+        )?;
+        if !confirmed {
+            println!("Nothing deleted.");
+            return Ok(());
+        }
+    }
+
+    for file in deleting_files.iter() {
+        std::fs::remove_file(file)
+            .with_context(|| format!("Failed to delete {}", file.display()))?;
+        println!("Deleted {}", file.display());
+    }
+    let removed_records = crate::cache_db::remove_civitai_model_file_location(&model_file)
+        .context("Failed to remove the model file from cache")?;
+    if removed_records > 0 {
+        println!("Removed the model file from cache.");
+    }
+    Ok(())
+}
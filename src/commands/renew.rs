@@ -1,52 +1,202 @@
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
+use anyhow::Context;
 use clap::Args;
 
+use super::{collector::is_legal_model_file, flags::FlagResolver};
+use crate::{
+    artifacts::ArtifactSet, civitai::ManualTarget, configuration::DefaultFlag,
+    errors::InvalidInputError, progress::ReporterKind,
+};
+
 #[derive(Args, Default)]
 pub struct RenewOptions {
     #[arg(help = "The model file request to renew metadata.")]
     pub target_file: PathBuf,
+    #[arg(
+        long,
+        help = "Civitai page of the model the file belongs to, instead of looking it up by hash. Without modelVersionId, the version having a file equal to the local one is used.",
+        conflicts_with_all = ["model_id", "version_id"]
+    )]
+    pub model_url: Option<String>,
+    #[arg(
+        long,
+        help = "Id of the model the file belongs to, instead of looking it up by hash."
+    )]
+    pub model_id: Option<u64>,
+    #[arg(
+        long,
+        help = "Id of the model version the file belongs to, instead of looking it up by hash."
+    )]
+    pub version_id: Option<u64>,
+    #[arg(
+        long,
+        help = "Associate the file with the given model or version even when none of its files matches the local file.",
+        default_value = "false"
+    )]
+    pub force: bool,
     #[arg(
         long,
         short = 'c',
         help = "Skip retreive community images metadata.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub skip_community: Option<bool>,
+    #[arg(
+        long,
+        value_name = "LIST",
+        help = "Files to produce beside the model file, a comma list of hash, readme, cover, json, triggers and samples, or all. Defaults to everything but triggers."
+    )]
+    pub artifacts: Option<ArtifactSet>,
+    #[arg(
+        long,
+        help = "Fetch community images metadata again instead of using the cached one.",
         default_value = "false"
     )]
-    pub skip_community: bool,
-}
-
-fn is_legal_model_file<P: AsRef<Path>>(file_path: P) -> bool {
-    let extensions = vec!["ckpt", "safetensors", "pt", "bin"];
-    let file_extension = file_path.as_ref().extension();
-    if file_extension.is_none() {
-        return false;
-    }
-    let file_extension = file_extension.unwrap().to_string_lossy();
-    extensions
-        .iter()
-        .any(|ext| ext.eq_ignore_ascii_case(&file_extension))
+    pub refresh_images: bool,
+    #[arg(
+        long,
+        help = "Download the cover image again when its source changed, instead of keeping the existing one.",
+        default_value = "false"
+    )]
+    pub refresh_cover: bool,
+    #[arg(
+        long,
+        help = "List the other versions of the model with their descriptions in the readme.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub include_version_history: Option<bool>,
+    #[arg(
+        long,
+        help = "Most other versions listed by --include-version-history, defaults to 10."
+    )]
+    pub history_limit: Option<usize>,
+    #[arg(
+        long,
+        help = "Most community image prompts listed in the readme, images with identical prompts count once, defaults to 15."
+    )]
+    pub max_prompt_sections: Option<usize>,
+    #[arg(
+        long,
+        help = "Cut prompts in the readme longer than this many characters, linking to the image page, 0 keeps them whole, defaults to 1000."
+    )]
+    pub max_prompt_length: Option<usize>,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Show download counts and ratings under the title of the readme, overrides the configured switch."
+    )]
+    pub readme_stats: Option<bool>,
+    #[arg(
+        long,
+        help = "Fail when any step fails, like the cover image, community images, Civitai Helper files or hash check.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub strict: Option<bool>,
+    #[arg(
+        long,
+        help = "Print the effective value of flags with configurable defaults, and where it comes from.",
+        default_value = "false"
+    )]
+    pub show_effective_flags: bool,
 }
 
-pub async fn process_model_meta_renew(options: &RenewOptions) {
+pub async fn process_model_meta_renew(options: &RenewOptions) -> anyhow::Result<()> {
     println!("Note: This feature only supports updating models downloaded from Civitai.com.");
+    let mut config = crate::configuration::current().await;
+    config.override_readme_stats(options.readme_stats);
 
     if !options.target_file.is_file() || !is_legal_model_file(&options.target_file) {
-        println!("The target file must be a model file.");
-        return;
+        return Err(InvalidInputError(format!(
+            "\"{}\" is not a model file, expected a .safetensors, .ckpt, .pt or .bin file",
+            options.target_file.display()
+        ))
+        .into());
+    }
+
+    let manual_target = manual_target(options)?;
+    if options.force && manual_target.is_none() {
+        return Err(InvalidInputError(
+            "--force requires --model-url, --model-id or --version-id".to_string(),
+        )
+        .into());
     }
 
-    let civitai_client = crate::downloader::make_client()
-        .await
-        .expect("failed to initialize client");
+    let (artifacts, version_history, strict) = {
+        let mut flags = FlagResolver::new(&config.defaults);
+        let skip_community = flags.switch(DefaultFlag::SkipCommunity, options.skip_community)?;
+        let resolved = (
+            flags
+                .value(
+                    DefaultFlag::Artifacts,
+                    options.artifacts,
+                    ArtifactSet::standard(),
+                )?
+                .skipping_community(skip_community),
+            flags.version_history(options.include_version_history, options.history_limit)?,
+            flags.switch(DefaultFlag::Strict, options.strict)?,
+        );
+        if options.show_effective_flags {
+            flags.print();
+        }
+        resolved
+    };
+
+    let civitai_client =
+        crate::downloader::make_client(&config).context("Failed to initialize client")?;
 
-    if let Err(e) = crate::civitai::complete_file_meta(
+    let summary = crate::civitai::complete_file_meta(
         &civitai_client,
+        &config,
         &options.target_file,
-        options.skip_community,
+        &crate::civitai::CompletionBehavior {
+            artifacts,
+            refresh_images: options.refresh_images,
+            refresh_cover: options.refresh_cover,
+            version_history,
+            manual_target,
+            force_match: options.force,
+            prompt_limits: crate::civitai::PromptLimits::new(
+                options.max_prompt_sections,
+                options.max_prompt_length,
+            ),
+            reporter: ReporterKind::Bar,
+        },
     )
     .await
-    {
-        println!("\nCancel renew metadata for model file: {e}");
-    }
+    .context("Cancel renew metadata for model file")?;
+    summary.ensure_complete(strict)?;
     println!("All Done.");
+    Ok(())
+}
+
+/// Model or version given on command line for the file, `None` to look it up by hash.
+fn manual_target(options: &RenewOptions) -> anyhow::Result<Option<ManualTarget>> {
+    if let Some(url) = options.model_url.as_deref() {
+        let parsed_url = reqwest::Url::parse(url).map_err(|e| {
+            InvalidInputError(format!(
+                "\"{url}\" is not a valid URL ({e}), expected https://civitai.com/models/<model id>"
+            ))
+        })?;
+        let (model_id, version_id) = crate::civitai::try_parse_civitai_model_url(&parsed_url)?;
+        return Ok(Some(ManualTarget {
+            model_id: Some(model_id),
+            version_id,
+        }));
+    }
+    if options.model_id.is_none() && options.version_id.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(ManualTarget {
+        model_id: options.model_id,
+        version_id: options.version_id,
+    }))
 }
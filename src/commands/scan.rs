@@ -0,0 +1,435 @@
+use std::{cmp::Reverse, collections::HashSet, path::PathBuf, time::SystemTime};
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+use futures_util::{StreamExt, stream};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tokio::sync::mpsc;
+
+use super::{collector::collect_model_files, flags::FlagResolver};
+use crate::{
+    artifacts::ArtifactSet,
+    civitai::readme_path,
+    configuration::DefaultFlag,
+    errors::{IncompleteArtifactsError, InvalidInputError},
+    progress::{ReporterKind, SkipReason, SkippedItem, describe_skip_counts},
+};
+
+#[derive(Args, Default)]
+pub struct ScanOptions {
+    #[arg(
+        help = "The directories to scan models in, defaults to current directory. Several directories are scanned in one run, taking turns."
+    )]
+    pub directories: Vec<PathBuf>,
+    #[arg(
+        long,
+        short = 'r',
+        help = "Also scan models in subdirectories.",
+        default_value = "false"
+    )]
+    pub recursive: bool,
+    #[arg(
+        long,
+        short = 'f',
+        help = "Renew models that already have readme file.",
+        default_value = "false"
+    )]
+    pub force: bool,
+    #[arg(
+        long,
+        short = 'c',
+        help = "Skip retreive community images metadata.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub skip_community: Option<bool>,
+    #[arg(
+        long,
+        value_name = "LIST",
+        help = "Files to produce beside the model file, a comma list of hash, readme, cover, json, triggers and samples, or all. Defaults to everything but triggers."
+    )]
+    pub artifacts: Option<ArtifactSet>,
+    #[arg(
+        long,
+        help = "Fetch community images metadata again instead of using the cached one.",
+        default_value = "false"
+    )]
+    pub refresh_images: bool,
+    #[arg(
+        long,
+        help = "Download the cover image again when its source changed, instead of keeping the existing one.",
+        default_value = "false"
+    )]
+    pub refresh_cover: bool,
+    #[arg(
+        long,
+        help = "List the other versions of the model with their descriptions in the readme.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub include_version_history: Option<bool>,
+    #[arg(
+        long,
+        help = "Most other versions listed by --include-version-history, defaults to 10."
+    )]
+    pub history_limit: Option<usize>,
+    #[arg(
+        long,
+        help = "Most community image prompts listed in the readme, images with identical prompts count once, defaults to 15."
+    )]
+    pub max_prompt_sections: Option<usize>,
+    #[arg(
+        long,
+        help = "Cut prompts in the readme longer than this many characters, linking to the image page, 0 keeps them whole, defaults to 1000."
+    )]
+    pub max_prompt_length: Option<usize>,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Show download counts and ratings under the title of the readme, overrides the configured switch."
+    )]
+    pub readme_stats: Option<bool>,
+    #[arg(
+        long,
+        value_enum,
+        help = "Order to complete models in, newest-first starts with the recently modified files.",
+        default_value_t = ScanOrder::Path
+    )]
+    pub order: ScanOrder,
+    #[arg(
+        long,
+        help = "Number of files hashed at the same time.",
+        default_value_t = 2
+    )]
+    pub hash_workers: usize,
+    #[arg(
+        long,
+        help = "Number of Civitai lookups and metadata writes running at the same time.",
+        default_value_t = 2
+    )]
+    pub lookup_concurrency: usize,
+    #[arg(
+        long,
+        help = "Fail when any step fails, like the cover image, community images, Civitai Helper files or hash check.",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub strict: Option<bool>,
+    #[arg(
+        long,
+        help = "Print the effective value of flags with configurable defaults, and where it comes from.",
+        default_value = "false"
+    )]
+    pub show_effective_flags: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ScanOrder {
+    /// By file path.
+    #[default]
+    Path,
+    /// Recently modified files first.
+    NewestFirst,
+}
+
+pub async fn process_scan_models(options: &ScanOptions) -> anyhow::Result<()> {
+    println!("Note: This feature only supports completing models downloaded from Civitai.com.");
+    let mut config = crate::configuration::current().await;
+    config.override_readme_stats(options.readme_stats);
+
+    let (artifacts, version_history, strict) = {
+        let mut flags = FlagResolver::new(&config.defaults);
+        let skip_community = flags.switch(DefaultFlag::SkipCommunity, options.skip_community)?;
+        let resolved = (
+            flags
+                .value(
+                    DefaultFlag::Artifacts,
+                    options.artifacts,
+                    ArtifactSet::standard(),
+                )?
+                .skipping_community(skip_community),
+            flags.version_history(options.include_version_history, options.history_limit)?,
+            flags.switch(DefaultFlag::Strict, options.strict)?,
+        );
+        if options.show_effective_flags {
+            flags.print();
+        }
+        resolved
+    };
+
+    let roots = scan_roots(options)?;
+    let mut tallies = roots
+        .iter()
+        .map(|_| ScanTally::default())
+        .collect::<Vec<_>>();
+    let mut root_queues = Vec::with_capacity(roots.len());
+    for (root_index, root) in roots.iter().enumerate() {
+        let model_files = collect_model_files(root, options.recursive)
+            .with_context(|| format!("Failed to list model files in {}", root.display()))?;
+        if model_files.is_empty() {
+            println!("No model found in {}.", root.display());
+        }
+        let (mut pending_files, skipped_files): (Vec<_>, Vec<_>) = model_files
+            .into_iter()
+            .partition(|model_file| options.force || !readme_path(model_file).exists());
+        if !skipped_files.is_empty() {
+            println!(
+                "{} models in {} already have readme files, skip them.",
+                skipped_files.len(),
+                root.display()
+            );
+        }
+        tallies[root_index].skipped = skipped_files
+            .iter()
+            .map(|model_file| {
+                SkippedItem::new(model_file.display().to_string(), SkipReason::AlreadyPresent)
+            })
+            .collect();
+        if options.order == ScanOrder::NewestFirst {
+            pending_files.sort_by_cached_key(|model_file| {
+                Reverse(
+                    model_file
+                        .metadata()
+                        .and_then(|meta| meta.modified())
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                )
+            });
+        }
+        root_queues.push(pending_files);
+    }
+    let pending_files = interleave(root_queues);
+    if pending_files.is_empty() {
+        print_scan_results(&roots, &tallies);
+        return Ok(());
+    }
+
+    let civitai_client =
+        crate::downloader::make_client(&config).context("Failed to initialize client")?;
+    let behavior = crate::civitai::CompletionBehavior {
+        artifacts,
+        refresh_images: options.refresh_images,
+        refresh_cover: options.refresh_cover,
+        version_history,
+        prompt_limits: crate::civitai::PromptLimits::new(
+            options.max_prompt_sections,
+            options.max_prompt_length,
+        ),
+        // 多个文件同时补全，封面下载不单独显示进度
+        reporter: ReporterKind::Silent,
+        ..Default::default()
+    };
+
+    let total = pending_files.len();
+    let multi = MultiProgress::new();
+    let hashing_bar = multi.add(queue_bar("Hashing   ", total));
+    let lookup_bar = multi.add(queue_bar("Looking up", total));
+
+    // 计算哈希受磁盘限制，查询受网络限制，两者分开并行，哈希完成的文件排队等待查询
+    let (hashed_sender, mut hashed_receiver) = mpsc::unbounded_channel();
+    let hash_workers = options.hash_workers.max(1);
+    let hashing = tokio::spawn({
+        let hashing_bar = hashing_bar.clone();
+        async move {
+            stream::iter(pending_files)
+                .map(|(root_index, model_file)| async move {
+                    let hash = crate::civitai::blake3_hash(&model_file, None).await;
+                    (root_index, model_file, hash)
+                })
+                .buffered(hash_workers)
+                .for_each(|hashed| {
+                    hashing_bar.inc(1);
+                    let _ = hashed_sender.send(hashed);
+                    async {}
+                })
+                .await;
+            hashing_bar.finish();
+        }
+    });
+
+    let mut lookups = stream::poll_fn(|cx| hashed_receiver.poll_recv(cx))
+        .map(
+            |(root_index, model_file, hash): (usize, PathBuf, anyhow::Result<String>)| {
+                let (client, config, behavior, multi) =
+                    (&civitai_client, &config, &behavior, &multi);
+                async move {
+                    let result = match hash {
+                        Ok(hash) => {
+                            crate::civitai::complete_file_meta_with_hash(
+                                client,
+                                config,
+                                &model_file,
+                                &hash,
+                                behavior,
+                                multi,
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e.context("Calculate file hash")),
+                    };
+                    (root_index, model_file, result)
+                }
+            },
+        )
+        .buffer_unordered(options.lookup_concurrency.max(1));
+    while let Some((root_index, model_file, result)) = lookups.next().await {
+        lookup_bar.inc(1);
+        let tally = &mut tallies[root_index];
+        match result {
+            Ok(summary) if !summary.skipped.is_empty() => {
+                for item in summary.skipped.iter() {
+                    multi.suspend(|| println!("Skipped {}: {}", model_file.display(), item.reason));
+                }
+                tally.skipped.extend(summary.skipped);
+            }
+            Ok(summary) if !summary.is_complete() => {
+                tally.incomplete += 1;
+                for artifact in summary.failed.iter() {
+                    multi.suspend(|| {
+                        println!(
+                            "Incomplete {}: {} failed, {}",
+                            model_file.display(),
+                            artifact.kind,
+                            artifact.reason
+                        )
+                    });
+                }
+            }
+            Ok(_) => {
+                tally.completed += 1;
+                multi.suspend(|| println!("Completed {}", model_file.display()));
+            }
+            Err(e) => {
+                tally.failed += 1;
+                multi.suspend(|| {
+                    println!(
+                        "Failed to complete metadata of {}: {e}",
+                        model_file.display()
+                    )
+                });
+            }
+        }
+    }
+    drop(lookups);
+    hashing.await.context("Hashing is interrupted")?;
+    lookup_bar.finish();
+
+    print_scan_results(&roots, &tallies);
+    let unfinished = tallies
+        .iter()
+        .map(|tally| tally.incomplete + tally.failed)
+        .sum::<usize>();
+    if strict && unfinished > 0 {
+        return Err(IncompleteArtifactsError(unfinished).into());
+    }
+    Ok(())
+}
+
+/// Outcome of the models under one scanned directory.
+#[derive(Default)]
+struct ScanTally {
+    completed: usize,
+    incomplete: usize,
+    failed: usize,
+    skipped: Vec<SkippedItem>,
+}
+
+impl ScanTally {
+    fn describe(&self) -> String {
+        let skipped = if self.skipped.is_empty() {
+            "0 skipped".to_string()
+        } else {
+            format!(
+                "{} skipped ({})",
+                self.skipped.len(),
+                describe_skip_counts(&self.skipped)
+            )
+        };
+        format!(
+            "{} completed, {} incomplete, {skipped}, {} failed",
+            self.completed, self.incomplete, self.failed
+        )
+    }
+}
+
+/// Directories to scan, the current directory when none is given. A directory given twice is
+/// scanned once.
+fn scan_roots(options: &ScanOptions) -> anyhow::Result<Vec<PathBuf>> {
+    if options.directories.is_empty() {
+        return Ok(vec![
+            std::env::current_dir().context("Unable to get current working directory")?,
+        ]);
+    }
+    let mut seen = HashSet::new();
+    let mut roots = Vec::new();
+    for directory in options.directories.iter() {
+        if !directory.is_dir() {
+            return Err(InvalidInputError(format!(
+                "\"{}\" is not a directory",
+                directory.display()
+            ))
+            .into());
+        }
+        if seen.insert(directory.canonicalize()?) {
+            roots.push(directory.clone());
+        }
+    }
+    Ok(roots)
+}
+
+/// Takes files from every directory in turn, so that a large directory does not hold up the
+/// others.
+fn interleave(queues: Vec<Vec<PathBuf>>) -> Vec<(usize, PathBuf)> {
+    let mut queues = queues
+        .into_iter()
+        .map(Vec::into_iter)
+        .enumerate()
+        .collect::<Vec<_>>();
+    let mut interleaved = Vec::new();
+    while !queues.is_empty() {
+        queues.retain_mut(|(root_index, files)| match files.next() {
+            Some(file) => {
+                interleaved.push((*root_index, file));
+                true
+            }
+            None => false,
+        });
+    }
+    interleaved
+}
+
+fn print_scan_results(roots: &[PathBuf], tallies: &[ScanTally]) {
+    if let [tally] = tallies {
+        println!("\nScan finished: {}.", tally.describe());
+        return;
+    }
+    println!("\nScan finished:");
+    for (root, tally) in roots.iter().zip(tallies.iter()) {
+        println!("  {}: {}.", root.display(), tally.describe());
+    }
+    let total = ScanTally {
+        completed: tallies.iter().map(|tally| tally.completed).sum(),
+        incomplete: tallies.iter().map(|tally| tally.incomplete).sum(),
+        failed: tallies.iter().map(|tally| tally.failed).sum(),
+        skipped: tallies
+            .iter()
+            .flat_map(|tally| tally.skipped.iter().cloned())
+            .collect(),
+    };
+    println!("  Total: {}.", total.describe());
+}
+
+fn queue_bar(label: &str, total: usize) -> ProgressBar {
+    let bar = ProgressBar::new(total as u64);
+    bar.set_style(
+        ProgressStyle::with_template("{msg} [{wide_bar:.cyan/blue}] {pos}/{len} [{elapsed}]")
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=>-"),
+    );
+    bar.set_message(label.to_string());
+    bar
+}
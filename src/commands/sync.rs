@@ -0,0 +1,455 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use clap::Args;
+use dialoguer::Confirm;
+
+use super::collector::collect_model_files;
+use crate::{
+    artifacts::ArtifactSet,
+    civitai::CommercialUsePolicy,
+    errors::{
+        CommercialUseNotAllowedError, EarlyAccessOnlyError, InvalidInputError,
+        SizeBudgetExceededError,
+    },
+    progress::{ReporterKind, SkipReason, SkippedItem, describe_skip_counts},
+    utils::sanitize_file_name,
+};
+
+#[derive(Args, Default)]
+pub struct SyncOptions {
+    #[arg(help = "The Civitai collection or user profile URL.")]
+    pub url: String,
+    #[arg(
+        short = 'o',
+        long = "output",
+        help = "The directory to keep in sync, defaults to the configured output directory."
+    )]
+    pub output_path: Option<PathBuf>,
+    #[arg(
+        long = "fix-missing",
+        short = 'f',
+        help = "Fix missing directories.",
+        default_value = "false"
+    )]
+    pub fix_missing_dirs: bool,
+    #[arg(
+        long,
+        short = 'c',
+        help = "Skip collecting community images metadata.",
+        default_value = "false"
+    )]
+    pub skip_community: bool,
+    #[arg(
+        long,
+        help = "Skip early access versions, fall back to the newest version available to everyone.",
+        default_value = "false"
+    )]
+    pub skip_early_access: bool,
+    #[arg(
+        long,
+        value_name = "ACTION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "abort",
+        help = "Check that models allow commercial use, refusing the ones that do not with abort (the default) or only warning with warn."
+    )]
+    pub require_commercial_use: Option<CommercialUsePolicy>,
+    #[arg(
+        long,
+        help = "Download files that Civitai's pickle or virus scan marked as dangerous.",
+        default_value = "false"
+    )]
+    pub allow_unsafe: bool,
+    #[arg(
+        long,
+        help = "Hardlink or copy files downloaded to another directory before, instead of downloading them again.",
+        default_value = "false"
+    )]
+    pub link_existing: bool,
+    #[arg(
+        long = "pin",
+        value_name = "MODEL_ID:VERSION_ID",
+        value_parser = parse_pin,
+        help = "Keep the given version of a model instead of the newest one, can be given multiple times."
+    )]
+    pub pins: Vec<(u64, u64)>,
+    #[arg(
+        long,
+        help = "Report local models that are no longer in the collection.",
+        default_value = "false"
+    )]
+    pub report_removed: bool,
+    #[arg(
+        long,
+        help = "Delete the folders of local models that are no longer in the collection, after confirmation.",
+        default_value = "false"
+    )]
+    pub prune: bool,
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = crate::utils::parse_size,
+        help = "Stop downloading model files once they add up to the given size, like 500M or 5G, across all models."
+    )]
+    pub max_total_size: Option<u64>,
+}
+
+fn parse_pin(value: &str) -> Result<(u64, u64), String> {
+    let (model_id, version_id) = value
+        .split_once(':')
+        .ok_or("expected MODEL_ID:VERSION_ID".to_string())?;
+    let model_id = model_id
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid model id: {e}"))?;
+    let version_id = version_id
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid version id: {e}"))?;
+    Ok((model_id, version_id))
+}
+
+pub async fn process_sync_models(options: &SyncOptions) -> anyhow::Result<()> {
+    let source_url = reqwest::Url::parse(&options.url).map_err(|e| {
+        InvalidInputError(format!(
+            "\"{}\" is not a valid URL ({e}), expected {}",
+            options.url,
+            crate::civitai::CIVITAI_SYNC_URL_SHAPES
+        ))
+    })?;
+    if !matches!(
+        crate::downloader::detect_platform(&source_url),
+        Some(crate::downloader::Platform::Civitai)
+    ) {
+        return Err(InvalidInputError(format!(
+            "\"{}\" is not a Civitai URL, expected {}",
+            options.url,
+            crate::civitai::CIVITAI_SYNC_URL_SHAPES
+        ))
+        .into());
+    }
+    let source = crate::civitai::try_parse_civitai_sync_url(&source_url)?;
+
+    let output_path = match options.output_path.clone() {
+        Some(path) => path,
+        None => match crate::configuration::CONFIGURATION
+            .read()
+            .await
+            .download
+            .output_dir
+            .clone()
+        {
+            Some(path) => path,
+            None => std::env::current_dir().context("Unable to get current working directory")?,
+        },
+    };
+    crate::downloader::validate_output_dir(&output_path, options.fix_missing_dirs)?;
+
+    if !crate::configuration::check_civitai_key_exists().await {
+        bail!(
+            "Civitai access key is not set, set it by \"imd config set civitai <key>\" or run \"imd init\"."
+        );
+    }
+    let config = crate::configuration::current().await;
+    let client = crate::downloader::make_client(&config).context("Failed to initialize client")?;
+
+    println!("Fetching models of {source}...");
+    let models = crate::civitai::fetch_source_models(&client, &config, &source)
+        .await
+        .with_context(|| format!("Failed to fetch models of {source}"))?;
+    println!("Found {} models in {source}.", models.len());
+
+    let behavior = crate::civitai::DownloadBehavior {
+        artifacts: ArtifactSet::standard().skipping_community(options.skip_community),
+        allow_unsafe: options.allow_unsafe,
+        dry_run: false,
+        folder_per_model: true,
+        unattended: true,
+        link_existing: options.link_existing,
+        file_ids: Vec::new(),
+        model_dirs: None,
+        size_budget: super::download::size_budget(options.max_total_size),
+        reporter: ReporterKind::Bar,
+        resumed: None,
+        version_history: None,
+        meta_file_name: None,
+        pick_cover: false,
+        prompt_limits: Default::default(),
+        extract_archives: false,
+        remove_archive: false,
+        file_preference: None,
+        commercial_use_policy: options.require_commercial_use,
+    };
+    let mut failed_models = Vec::new();
+    let mut skipped = Vec::new();
+    let mut warnings = Vec::new();
+    for (index, model) in models.iter().enumerate() {
+        println!(
+            "\n[{}/{}] Syncing {} ({})...",
+            index + 1,
+            models.len(),
+            model.name(),
+            model.id()
+        );
+        let pinned_version = options
+            .pins
+            .iter()
+            .find(|(model_id, _)| *model_id == model.id())
+            .map(|(_, version_id)| *version_id);
+        let version_selection = crate::civitai::VersionSelection {
+            ids: pinned_version.into_iter().collect(),
+            latest: pinned_version.is_none(),
+            skip_early_access: options.skip_early_access,
+            ..Default::default()
+        };
+        // 单个模型失败时继续同步其余模型
+        match crate::civitai::download_from_civitai(
+            &client,
+            &config,
+            model.id(),
+            &version_selection,
+            Some(&output_path),
+            &behavior,
+        )
+        .await
+        {
+            Ok(summary) => {
+                skipped.extend(summary.skipped);
+                warnings.extend(summary.warnings);
+            }
+            Err(e) if e.downcast_ref::<SizeBudgetExceededError>().is_some() => {
+                println!("{e}, stop syncing. Run it again later to sync the rest.");
+                skipped.extend(
+                    models[index..]
+                        .iter()
+                        .map(|m| SkippedItem::new(m.name(), SkipReason::OverBudget)),
+                );
+                break;
+            }
+            Err(e) if e.downcast_ref::<EarlyAccessOnlyError>().is_some() => {
+                println!("{e}, skip it.");
+                skipped.push(SkippedItem::new(model.name(), SkipReason::EarlyAccess));
+            }
+            Err(e) if e.downcast_ref::<CommercialUseNotAllowedError>().is_some() => {
+                println!("{e}, skip it.");
+                skipped.push(SkippedItem::new(model.name(), SkipReason::NoCommercialUse));
+            }
+            Err(e) => {
+                eprintln!("Failed to sync {}: {e:#}", model.name());
+                failed_models.push(model.name());
+            }
+        }
+    }
+    if !skipped.is_empty() {
+        println!(
+            "\nSkipped {}: {}.",
+            skipped.len(),
+            describe_skip_counts(&skipped)
+        );
+    }
+    for warning in warnings.iter() {
+        println!("WARNING: {warning}.");
+    }
+
+    if options.report_removed || options.prune {
+        let synced_ids = models.iter().map(|m| m.id()).collect::<HashSet<_>>();
+        report_removed_models(&output_path, &synced_ids, options.prune)?;
+    }
+
+    if !failed_models.is_empty() {
+        bail!(
+            "{} of {} models failed to sync: {}",
+            failed_models.len(),
+            models.len(),
+            failed_models.join(", ")
+        );
+    }
+    println!("Sync completed.");
+    Ok(())
+}
+
+/// Local models whose sidecar refers to a model not in the synced set.
+struct RemovedModels {
+    models: BTreeMap<(String, u64), Vec<PathBuf>>,
+    /// Model folders created by sync for removed models, holding no model file that is kept.
+    prunable_dirs: BTreeSet<PathBuf>,
+}
+
+fn find_removed_models(
+    output_path: &Path,
+    synced_ids: &HashSet<u64>,
+) -> anyhow::Result<RemovedModels> {
+    let model_files = collect_model_files(output_path, true)
+        .with_context(|| format!("Failed to list model files in {}", output_path.display()))?;
+    let mut models = BTreeMap::new();
+    let mut kept_files = Vec::new();
+    for model_file in model_files {
+        match crate::civitai::load_sidecar(&model_file) {
+            Ok(Some(sidecar)) if !synced_ids.contains(&sidecar.model_id) => models
+                .entry((sidecar.model_name, sidecar.model_id))
+                .or_insert_with(Vec::new)
+                .push(model_file),
+            // 仍在同步范围内的、无法识别的模型都要保留
+            _ => kept_files.push(model_file),
+        }
+    }
+
+    // 只删除同步时创建的模型目录，不同模型可能同名，目录里有保留的模型时不删除
+    let prunable_dirs = models
+        .iter()
+        .filter_map(|((model_name, _), files)| {
+            let model_dir = output_path.join(sanitize_file_name(model_name));
+            let own_folder = files.iter().all(|file| file.starts_with(&model_dir));
+            let shared = kept_files.iter().any(|file| file.starts_with(&model_dir));
+            (own_folder && !shared && model_dir.is_dir()).then_some(model_dir)
+        })
+        .collect();
+    Ok(RemovedModels {
+        models,
+        prunable_dirs,
+    })
+}
+
+/// Lists local models whose sidecar refers to a model not in the synced set, and deletes their
+/// model folders when pruning is confirmed.
+fn report_removed_models(
+    output_path: &Path,
+    synced_ids: &HashSet<u64>,
+    prune: bool,
+) -> anyhow::Result<()> {
+    let RemovedModels {
+        models: removed_models,
+        prunable_dirs,
+    } = find_removed_models(output_path, synced_ids)?;
+    if removed_models.is_empty() {
+        println!("\nNo local model is removed from the source.");
+        return Ok(());
+    }
+
+    println!("\nLocal models no longer in the source:");
+    for ((model_name, model_id), files) in removed_models.iter() {
+        println!("  {model_name} ({model_id})");
+        for file in files {
+            println!("    {}", file.display());
+        }
+        let model_dir = output_path.join(sanitize_file_name(model_name));
+        if prune && !prunable_dirs.contains(&model_dir) {
+            println!(
+                "    Not in its own model folder or sharing it with kept models, will be kept."
+            );
+        }
+    }
+    if !prune || prunable_dirs.is_empty() {
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() {
+        bail!("Pruning requires confirmation, run it in an interactive terminal.");
+    }
+    println!("\nFolders to delete:");
+    for dir in prunable_dirs.iter() {
+        println!("  {}", dir.display());
+    }
+    let prompt = format!(
+        "Delete these {} folders and everything in them?",
+        prunable_dirs.len()
+    );
+    let confirmed = crate::prompt::interact(
+        move || Confirm::new().with_prompt(prompt).default(false).interact(),
+        false,
+        "keeping them",
+    )?;
+    if !confirmed {
+        println!("Nothing deleted.");
+        return Ok(());
+    }
+    for dir in prunable_dirs.iter() {
+        std::fs::remove_dir_all(dir)
+            .with_context(|| format!("Failed to delete {}", dir.display()))?;
+        println!("Deleted {}", dir.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::civitai::CivitaiSidecar;
+
+    fn save_model(dir: &Path, file_name: &str, model: Option<(u64, &str)>) {
+        std::fs::create_dir_all(dir).unwrap();
+        let model_file = dir.join(file_name);
+        std::fs::write(&model_file, b"model").unwrap();
+        let Some((model_id, model_name)) = model else {
+            return;
+        };
+        let sidecar = CivitaiSidecar {
+            model_id,
+            model_name: model_name.to_string(),
+            model_type: None,
+            version_id: model_id * 10,
+            version_name: "v1".to_string(),
+            base_model: None,
+            air: None,
+            page_url: None,
+            file: None,
+            model_version: serde_json::Value::Null,
+            cover_source: None,
+            permissions: None,
+        };
+        std::fs::write(
+            model_file.with_extension("civitai.json"),
+            serde_json::to_vec(&sidecar).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn folders_holding_kept_models_are_not_pruned() {
+        let output = tempfile::tempdir().unwrap();
+        let output = output.path();
+        // 同名的两个模型，其中一个仍在同步范围内
+        save_model(
+            &output.join("Shared"),
+            "old.safetensors",
+            Some((1, "Shared")),
+        );
+        save_model(
+            &output.join("Shared"),
+            "new.safetensors",
+            Some((2, "Shared")),
+        );
+        // 目录里有无法识别的模型
+        save_model(&output.join("Manual"), "mine.safetensors", None);
+        save_model(
+            &output.join("Manual"),
+            "gone.safetensors",
+            Some((3, "Manual")),
+        );
+        // 同名的两个模型都已移除
+        save_model(&output.join("Gone"), "a.safetensors", Some((4, "Gone")));
+        save_model(&output.join("Gone"), "b.safetensors", Some((5, "Gone")));
+        // 不在自己的模型目录里
+        save_model(
+            &output.join("Elsewhere"),
+            "c.safetensors",
+            Some((6, "Moved")),
+        );
+
+        let removed = find_removed_models(output, &HashSet::from([2])).unwrap();
+
+        assert_eq!(
+            removed.models.keys().map(|(_, id)| *id).collect::<Vec<_>>(),
+            [4, 5, 3, 6, 1]
+        );
+        assert_eq!(
+            removed.prunable_dirs.into_iter().collect::<Vec<_>>(),
+            [output.join("Gone")]
+        );
+    }
+}
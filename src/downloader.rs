@@ -1,53 +1,413 @@
-use std::time::Duration;
+use std::{
+    io::IsTerminal,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
+use anyhow::{Context, bail};
 use backoff::{ExponentialBackoff, ExponentialBackoffBuilder};
-use reqwest::{Client, ClientBuilder, Url};
+use dialoguer::Confirm;
+use futures_util::StreamExt;
+use reqwest::{Client, ClientBuilder, Proxy, Request, Response, Url};
 
-use crate::configuration;
+use crate::{
+    configuration::{self, BackoffConfig, Configuration, ProxyConfig},
+    errors::{OfflineError, ResponseTooLargeError},
+    events,
+    utils::{ByteUnits, format_bytes},
+};
 
 pub enum Platform {
     Civitai,
     HuggingFace,
 }
 
+const CIVITAI_DOMAINS: [&str; 2] = ["civitai.com", "civitai.green"];
+/// Hosts of the Civitai site the access key is meant for, other subdomains like the image CDN
+/// are not.
+const CIVITAI_SITE_HOSTS: [&str; 4] = [
+    "civitai.com",
+    "www.civitai.com",
+    "civitai.green",
+    "www.civitai.green",
+];
+/// Environment variable enabling offline mode when `--offline` is not given.
+pub const OFFLINE_ENV_VAR: &str = "IMD_OFFLINE";
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+/// Longest time an action is retried, in seconds.
+const MAX_ELAPSED_TIME: u64 = 24 * 3600;
+
+fn is_domain_or_subdomain(host: &str, domain: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
 pub fn detect_platform(url: &Url) -> Option<Platform> {
     match url.host_str() {
-        Some(host) if host.eq_ignore_ascii_case("civitai.com") => Some(Platform::Civitai),
-        Some(host) if host.eq_ignore_ascii_case("huggingface.co") => Some(Platform::HuggingFace),
+        Some(host)
+            if CIVITAI_DOMAINS
+                .iter()
+                .any(|d| is_domain_or_subdomain(host, d)) =>
+        {
+            Some(Platform::Civitai)
+        }
+        Some(host) if is_domain_or_subdomain(host, "huggingface.co") => Some(Platform::HuggingFace),
         _ => None,
     }
 }
 
-pub async fn make_client() -> anyhow::Result<Client> {
-    let config = crate::configuration::CONFIGURATION.read().await;
-    let proxy = config.proxy.get_proxy();
+/// Whether the URL is on the Civitai site or on the same host and port as the API base URL,
+/// the only places the Civitai access key is sent to.
+pub fn is_civitai_key_host(url: &Url, api_base: &str) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if CIVITAI_SITE_HOSTS.contains(&host.as_str()) {
+        return true;
+    }
+    Url::parse(api_base).is_ok_and(|api_base| {
+        api_base
+            .host_str()
+            .is_some_and(|api_host| api_host.eq_ignore_ascii_case(&host))
+            && api_base.port_or_known_default() == url.port_or_known_default()
+    })
+}
+
+/// Makes sure files can be saved into the given directory before anything is fetched. A missing
+/// directory is created when `create_missing` is set, or when user agrees to in a terminal.
+pub fn validate_output_dir(path: &Path, create_missing: bool) -> anyhow::Result<()> {
+    if path.exists() && !path.is_dir() {
+        bail!("Output path {} is a file, not a directory", path.display());
+    }
+    if !path.exists() {
+        let interactive = std::io::stdin().is_terminal() && !events::enabled();
+        let create = create_missing
+            || (interactive && {
+                let prompt = format!(
+                    "Output directory {} does not exist, create it?",
+                    path.display()
+                );
+                crate::prompt::interact(
+                    move || Confirm::new().with_prompt(prompt).default(true).interact(),
+                    true,
+                    "creating it",
+                )
+                .unwrap_or_default()
+            });
+        if !create {
+            bail!(
+                "Output directory {} does not exist, use --fix-missing to create it",
+                path.display()
+            );
+        }
+        std::fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create output directory {}", path.display()))?;
+    }
+
+    // 尝试写入临时文件以确认目录可写
+    let probe_path = path.join(format!(".imd-write-check-{}", std::process::id()));
+    std::fs::File::create(&probe_path)
+        .with_context(|| format!("Output directory {} is not writable", path.display()))?;
+    let _ = std::fs::remove_file(&probe_path);
+    Ok(())
+}
+
+/// Disables every network access for this process, metadata is served from cache only.
+pub fn set_offline() {
+    OFFLINE.store(true, Ordering::Relaxed);
+}
 
-    let client_builder = ClientBuilder::new().user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36").use_rustls_tls();
-    let client_builder = if let Some(proxy) = proxy {
-        client_builder.proxy(proxy)
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Fails fast when the action needs network access in offline mode.
+pub fn ensure_online(action: &str) -> Result<(), OfflineError> {
+    if is_offline() {
+        return Err(OfflineError {
+            action: action.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Builds the client for every request, with the user agent, headers, connect timeout and proxy
+/// of the given settings.
+pub fn make_client(config: &Configuration) -> anyhow::Result<Client> {
+    Ok(client_builder(config)?.build()?)
+}
+
+/// Client handing redirects back instead of following them, for headers only the redirecting
+/// response carries.
+pub fn make_client_without_redirects(config: &Configuration) -> anyhow::Result<Client> {
+    Ok(client_builder(config)?
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?)
+}
+
+fn client_builder(config: &Configuration) -> anyhow::Result<ClientBuilder> {
+    let client_builder = ClientBuilder::new()
+        .user_agent(config.network.effective_user_agent())
+        .default_headers(config.network.header_map()?)
+        .use_rustls_tls()
+        .connect_timeout(config.network.connect_timeout());
+    // 未配置代理时交由reqwest读取环境变量中的代理设置
+    let client_builder = match config.proxy.mode() {
+        configuration::ProxyMode::Configured(url) => {
+            client_builder.proxy(Proxy::all(url).context("Configured proxy is invalid")?)
+        }
+        configuration::ProxyMode::Disabled => client_builder.no_proxy(),
+        configuration::ProxyMode::Environment => client_builder,
+    };
+    Ok(client_builder)
+}
+
+/// Executes the request, and at `-v` logs where it went: the host, the address connected to,
+/// the proxy applied, the TLS backend, the HTTP version and the time to the response headers.
+/// Only hosts are logged, URLs may carry access keys.
+pub async fn execute(
+    client: &Client,
+    proxy: &ProxyConfig,
+    request: Request,
+) -> reqwest::Result<Response> {
+    if !events::is_verbose(1) {
+        return client.execute(request).await;
+    }
+    let method = request.method().clone();
+    let url = request.url().clone();
+    let host = url.host_str().unwrap_or_default().to_string();
+    let route = describe_route(proxy, &url);
+    let tls = if url.scheme() == "https" {
+        "rustls"
     } else {
-        client_builder.no_proxy()
+        "no TLS"
     };
-    let client = client_builder.build()?;
+    let requested_at = std::time::Instant::now();
+    let result = client.execute(request).await;
+    let elapsed = requested_at.elapsed().as_millis();
+    match result.as_ref() {
+        Ok(response) => {
+            let redirected = response
+                .url()
+                .host_str()
+                .filter(|final_host| *final_host != host)
+                .map(|final_host| format!(" -> {final_host}"))
+                .unwrap_or_default();
+            let address = response
+                .remote_addr()
+                .map(|address| address.to_string())
+                .unwrap_or("unknown address".to_string());
+            events::verbose(
+                1,
+                format!(
+                    "{method} {host}{redirected} [{address}] {route}, {tls}, {:?}, {} in {elapsed} ms",
+                    response.version(),
+                    response.status()
+                ),
+            );
+        }
+        Err(e) => {
+            // 错误的描述中带有URL，只记录其原因
+            let cause = std::error::Error::source(e)
+                .map(ToString::to_string)
+                .unwrap_or("request failed".to_string());
+            events::verbose(
+                1,
+                format!("{method} {host} {route}, {tls}, failed after {elapsed} ms: {cause}"),
+            )
+        }
+    }
+    result
+}
+
+/// How a request to the URL is routed, following the same rules as the client.
+fn describe_route(proxy: &ProxyConfig, url: &Url) -> String {
+    match proxy.mode() {
+        configuration::ProxyMode::Configured(proxy) => {
+            format!("via configured proxy {}", proxy_address(&proxy))
+        }
+        configuration::ProxyMode::Disabled => "direct, configured proxy disabled".to_string(),
+        configuration::ProxyMode::Environment => {
+            let host = url.host_str().unwrap_or_default();
+            if let Some(rule) = no_proxy_rule(host) {
+                return format!("direct, excluded by NO_PROXY rule {rule}");
+            }
+            let scheme_vars: &[&str] = if url.scheme() == "https" {
+                &["HTTPS_PROXY", "https_proxy"]
+            } else {
+                &["HTTP_PROXY", "http_proxy"]
+            };
+            scheme_vars
+                .iter()
+                .chain(["ALL_PROXY", "all_proxy"].iter())
+                .find_map(|var| {
+                    std::env::var(var)
+                        .ok()
+                        .filter(|value| !value.is_empty())
+                        .map(|value| (var, value))
+                })
+                .map(|(var, value)| match Url::parse(&value) {
+                    Ok(proxy) => format!("via proxy {} from {var}", proxy_address(&proxy)),
+                    Err(_) => format!("via proxy from {var}"),
+                })
+                .unwrap_or("direct".to_string())
+        }
+    }
+}
+
+/// Proxy server address without its credentials.
+fn proxy_address(proxy: &Url) -> String {
+    format!(
+        "{}://{}{}",
+        proxy.scheme(),
+        proxy.host_str().unwrap_or_default(),
+        proxy
+            .port()
+            .map(|port| format!(":{port}"))
+            .unwrap_or_default()
+    )
+}
+
+/// Entry of `NO_PROXY` matching the host, like `example.com` matching its subdomains too.
+fn no_proxy_rule(host: &str) -> Option<String> {
+    let no_proxy = std::env::var("NO_PROXY")
+        .or_else(|_| std::env::var("no_proxy"))
+        .ok()?;
+    no_proxy
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .find(|rule| *rule == "*" || is_domain_or_subdomain(host, rule.trim_start_matches('.')))
+        .map(String::from)
+}
+
+/// Reads a whole response body, failing once it grows beyond `limit` bytes so that an
+/// unexpectedly large response can not exhaust memory.
+pub async fn read_body_limited(response: Response, limit: usize) -> anyhow::Result<Vec<u8>> {
+    let too_large = || ResponseTooLargeError(format_bytes(limit as u64, ByteUnits::Binary));
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit as u64)
+    {
+        return Err(too_large().into());
+    }
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit {
+            return Err(too_large().into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
 
-    Ok(client)
+/// Reads at most `limit` bytes of a response body and drops the rest, enough to inspect an
+/// error page.
+pub async fn read_body_prefix(response: Response, limit: usize) -> Vec<u8> {
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while body.len() < limit
+        && let Some(Ok(chunk)) = stream.next().await
+    {
+        let taken = chunk.len().min(limit - body.len());
+        body.extend_from_slice(&chunk[..taken]);
+    }
+    body
 }
 
-pub async fn make_backoff_policy(max_timeout_secs: u64) -> ExponentialBackoff {
-    let configuration = configuration::CONFIGURATION.read().await;
-    let initial_interval = configuration.backoff.initial_interval;
-    let multiplier = configuration.backoff.multiplier;
-    let max_retry = configuration.backoff.max_retry;
-    let wait_times = initial_interval as f32 * (1.0 - multiplier.powi(max_retry as i32 - 1))
-        / (1.0 - multiplier);
-    let max_timeouts = max_timeout_secs as f32 * max_retry as f32;
-    let max_elapsed_time = (wait_times + max_timeouts).ceil() as u64;
+/// Retry policy of the given settings, giving up once the waits and `max_timeout_secs` for each
+/// retry add up.
+pub fn make_backoff_policy(backoff: &BackoffConfig, max_timeout_secs: u64) -> ExponentialBackoff {
+    let backoff = backoff.effective();
+    // 随机化可能让每次等待延长20%
+    let wait_times = backoff.schedule().iter().sum::<f64>() * 1.2;
+    let max_timeouts = max_timeout_secs as f64 * backoff.max_retry as f64;
+    let max_elapsed_time = ((wait_times + max_timeouts).ceil() as u64).min(MAX_ELAPSED_TIME);
 
     let mut building = ExponentialBackoffBuilder::default();
     let policy = building
-        .with_initial_interval(Duration::from_secs(initial_interval))
-        .with_multiplier(multiplier as f64)
+        .with_initial_interval(Duration::from_secs(backoff.initial_interval))
+        .with_multiplier(backoff.multiplier as f64)
+        .with_max_interval(Duration::from_secs(configuration::MAX_RETRY_INTERVAL))
         .with_randomization_factor(0.2)
         .with_max_elapsed_time(Some(Duration::from_secs(max_elapsed_time)));
     policy.build()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elapsed_limit(multiplier: f32, max_retry: u32, max_timeout_secs: u64) -> Duration {
+        let backoff = BackoffConfig {
+            initial_interval: 5,
+            multiplier,
+            max_retry,
+        };
+        make_backoff_policy(&backoff, max_timeout_secs)
+            .max_elapsed_time
+            .unwrap()
+    }
+
+    fn platform_of(url: &str) -> Option<Platform> {
+        detect_platform(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn platform_is_detected_from_host() {
+        for url in [
+            "https://civitai.com/models/1",
+            "https://civitai.green/models/1",
+            "https://www.civitai.com/models/1",
+            "https://CIVITAI.COM/models/1",
+            "https://civitai.com./models/1",
+        ] {
+            assert!(matches!(platform_of(url), Some(Platform::Civitai)), "{url}");
+        }
+        assert!(matches!(
+            platform_of("https://huggingface.co/org/model"),
+            Some(Platform::HuggingFace)
+        ));
+        for url in [
+            "https://notcivitai.com/models/1",
+            "https://civitai.com.example.org/models/1",
+            "https://example.org/models/1",
+        ] {
+            assert!(platform_of(url).is_none(), "{url}");
+        }
+    }
+
+    #[test]
+    fn backoff_policy_elapsed_time_is_finite_and_capped() {
+        // 5 × 3 × 1.2 + 30 × 3
+        assert_eq!(elapsed_limit(1.0, 3, 30), Duration::from_secs(108));
+        // max_retry为0时按一次重试计算
+        assert_eq!(elapsed_limit(1.5, 0, 30), Duration::from_secs(36));
+        assert_eq!(elapsed_limit(0.5, 1, 0), Duration::from_secs(6));
+        assert_eq!(
+            elapsed_limit(f32::INFINITY, u32::MAX, u64::MAX),
+            Duration::from_secs(MAX_ELAPSED_TIME)
+        );
+    }
+
+    #[test]
+    fn backoff_policy_uses_effective_values() {
+        let backoff = BackoffConfig {
+            initial_interval: 0,
+            multiplier: 0.5,
+            max_retry: 3,
+        };
+        let policy = make_backoff_policy(&backoff, 30);
+        assert_eq!(policy.initial_interval, Duration::from_secs(1));
+        assert_eq!(policy.multiplier, 1.0);
+        assert_eq!(
+            policy.max_interval,
+            Duration::from_secs(configuration::MAX_RETRY_INTERVAL)
+        );
+    }
+}
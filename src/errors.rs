@@ -1,3 +1,10 @@
+use std::time::Duration;
+
+use reqwest::{
+    StatusCode,
+    header::{CONTENT_TYPE, HeaderMap},
+};
+use serde_json::Value;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -17,3 +24,355 @@ impl From<anyhow::Error> for CivitaiParseError {
         Self::AnyhowError(err)
     }
 }
+
+#[derive(Debug, Error)]
+#[error("Civitai: {message} ({status})")]
+pub struct CivitaiApiError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl CivitaiApiError {
+    /// Checks response status and body for errors reported by Civitai API, `resource`
+    /// describes the requested resource, e.g. "model 12345".
+    pub fn check(status: StatusCode, body: Option<&Value>, resource: &str) -> Result<(), Self> {
+        let body_message = body.and_then(|value| {
+            let error = &value["error"];
+            error
+                .as_str()
+                .or(error["message"].as_str())
+                .or(value["message"].as_str().filter(|_| !status.is_success()))
+                .map(String::from)
+        });
+        if status.is_success() && body_message.is_none() {
+            return Ok(());
+        }
+
+        let message = match status {
+            StatusCode::NOT_FOUND => format!("{resource} not found"),
+            StatusCode::UNAUTHORIZED => {
+                "access key is invalid or missing, check it with `imd config get civitai`"
+                    .to_string()
+            }
+            StatusCode::TOO_MANY_REQUESTS => "too many requests, rate limited".to_string(),
+            _ => body_message.unwrap_or(
+                status
+                    .canonical_reason()
+                    .unwrap_or("unknown error")
+                    .to_string(),
+            ),
+        };
+        Err(Self {
+            status: status.as_u16(),
+            message,
+        })
+    }
+}
+
+/// Text found in Cloudflare challenge pages.
+const CLOUDFLARE_CHALLENGE_MARKERS: [&str; 5] = [
+    "cf-chl",
+    "challenge-platform",
+    "Just a moment...",
+    "Checking your browser",
+    "cf-browser-verification",
+];
+
+/// Cloudflare answered instead of Civitai, with a challenge page or an outage response.
+#[derive(Debug, Error)]
+#[error("Cloudflare blocked the request (HTTP {status}, cf-ray: {})", ray_id.as_deref().unwrap_or("unknown"))]
+pub struct CloudflareChallengeError {
+    pub status: u16,
+    pub ray_id: Option<String>,
+}
+
+impl CloudflareChallengeError {
+    /// Minimum delay before retrying a request blocked by Cloudflare.
+    pub const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+    /// Detects an HTML response of Cloudflare, by a 403 or 503 status with `cf-ray` header or by
+    /// challenge markers in the body.
+    pub fn detect(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Option<Self> {
+        let is_html = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("text/html"));
+        if !is_html {
+            return None;
+        }
+        let ray_id = headers
+            .get("cf-ray")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let blocked_status = matches!(
+            status,
+            StatusCode::FORBIDDEN | StatusCode::SERVICE_UNAVAILABLE
+        ) && ray_id.is_some();
+        let body = String::from_utf8_lossy(body);
+        let challenge_page = CLOUDFLARE_CHALLENGE_MARKERS
+            .iter()
+            .any(|marker| body.contains(marker));
+        (blocked_status || challenge_page).then(|| Self {
+            status: status.as_u16(),
+            ray_id,
+        })
+    }
+
+    /// Adds advice to an error caused by Cloudflare after retries are exhausted.
+    pub fn advise(error: anyhow::Error) -> anyhow::Error {
+        if error.downcast_ref::<Self>().is_some() {
+            error.context(
+                "Civitai is behind a Cloudflare check at the moment, try again later or download through a proxy (imd config set proxy)",
+            )
+        } else {
+            error
+        }
+    }
+}
+
+/// Characters of the response body quoted in [`UnexpectedResponseError`].
+const BODY_SNIPPET_LENGTH: usize = 200;
+
+/// A response that should be JSON but can not be parsed, like a Cloudflare challenge page or a
+/// truncated body.
+#[derive(Debug, Error)]
+#[error(
+    "Unexpected response from {url} (HTTP {status}, {content_type}): {reason}. Body starts with: {snippet}"
+)]
+pub struct UnexpectedResponseError {
+    pub url: String,
+    pub status: u16,
+    pub content_type: String,
+    pub reason: String,
+    pub snippet: String,
+}
+
+impl UnexpectedResponseError {
+    pub fn new<R: ToString>(
+        url: &str,
+        status: StatusCode,
+        content_type: Option<&str>,
+        body: &[u8],
+        reason: R,
+    ) -> Self {
+        let body = String::from_utf8_lossy(body);
+        let mut snippet = body
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(BODY_SNIPPET_LENGTH)
+            .collect::<String>();
+        if snippet.is_empty() {
+            snippet = "[EMPTY]".to_string();
+        }
+        Self {
+            url: url.to_string(),
+            status: status.as_u16(),
+            content_type: content_type.unwrap_or("no content type").to_string(),
+            reason: reason.to_string(),
+            snippet,
+        }
+    }
+}
+
+/// A model file transfer attempt broken off, it is resumed when retried.
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error("Failed to request model file: {0}")]
+    Request(reqwest::Error),
+    #[error("No data received in {0}")]
+    Stalled(String),
+    #[error("Download interrupted: {0}")]
+    Interrupted(reqwest::Error),
+    #[error("Connection closed before download completed")]
+    ClosedEarly,
+}
+
+/// Metadata needed in offline mode has not been cached before.
+#[derive(Debug, Error)]
+#[error("{resource} is not cached, it can not be fetched in offline mode")]
+pub struct OfflineAndUncachedError {
+    pub resource: String,
+}
+
+/// An action requiring network access attempted in offline mode.
+#[derive(Debug, Error)]
+#[error("{action} requires network access, which is disabled by offline mode")]
+pub struct OfflineError {
+    pub action: String,
+}
+
+/// Civitai pointed at a page of a list that was followed before, following it would never end.
+#[derive(Debug, Error)]
+#[error("Pages of {resource} loop back to {page}, stop following them")]
+pub struct PaginationLoopError {
+    pub resource: String,
+    pub page: String,
+}
+
+/// A Civitai response is missing a field imd relies on, or carries it in another type, most
+/// likely because Civitai changed its API.
+#[derive(Debug, Error)]
+#[error(
+    "Response of Civitai endpoint {endpoint} is not in the expected shape, {reason}. Civitai may have changed its API, try updating imd, and report it if the latest version fails too"
+)]
+pub struct SchemaMismatchError {
+    pub endpoint: String,
+    pub reason: String,
+}
+
+/// Steps failed in strict mode, the artifacts of the operation are incomplete.
+#[derive(Debug, Error)]
+#[error("{0} steps failed, the downloaded artifacts are incomplete")]
+pub struct IncompleteArtifactsError(pub usize);
+
+/// A response body grew beyond what is kept in memory.
+#[derive(Debug, Error)]
+#[error("Response is larger than {0}, refuse to read it into memory")]
+pub struct ResponseTooLargeError(pub String);
+
+/// Files left to download do not fit into the remaining size budget, later downloads sharing
+/// the budget are not started.
+#[derive(Debug, Error)]
+#[error("Download size budget of {0} is used up")]
+pub struct SizeBudgetExceededError(pub String);
+
+/// Every version of the model is in early access, while early access versions are skipped.
+#[derive(Debug, Error)]
+#[error("All versions of model {0} are in early access")]
+pub struct EarlyAccessOnlyError(pub u64);
+
+/// The model does not allow commercial use, while it is required.
+#[derive(Debug, Error)]
+#[error("{model_name} ({model_id}) does not allow commercial use")]
+pub struct CommercialUseNotAllowedError {
+    pub model_id: u64,
+    pub model_name: String,
+}
+
+/// A requested file still has no download URL after waiting for Civitai to process it.
+#[derive(Debug, Error)]
+#[error(
+    "File {0} is still being processed by Civitai and can not be downloaded yet, try again later or wait longer by \"imd config set processing-wait <minutes>\""
+)]
+pub struct FileStillProcessingError(pub String);
+
+/// The model has no version with files to download, e.g. it was taken down.
+#[derive(Debug, Error)]
+#[error("Model {0} has no downloadable versions, it may be removed or early access only")]
+pub struct NoDownloadableVersionError(pub u64);
+
+/// The chosen version has no file to download.
+#[derive(Debug, Error)]
+#[error(
+    "Version {version_name} has no downloadable file{}",
+    describe_versions_with_files(.versions_with_files)
+)]
+pub struct VersionWithoutFilesError {
+    pub version_name: String,
+    /// Other versions of the model that have files, as `<name> (<id>)`.
+    pub versions_with_files: Vec<String>,
+}
+
+fn describe_versions_with_files(versions: &[String]) -> String {
+    if versions.is_empty() {
+        String::new()
+    } else {
+        format!(", versions with files: {}", versions.join(", "))
+    }
+}
+
+/// Mistakes in user input, like a malformed URL, reported with [`ExitStatus::InvalidInput`].
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct InvalidInputError(pub String);
+
+/// Exit codes of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExitStatus {
+    Success = 0,
+    Failure = 1,
+    InvalidInput = 2,
+}
+
+impl ExitStatus {
+    pub fn of(error: &anyhow::Error) -> Self {
+        if error.downcast_ref::<InvalidInputError>().is_some() {
+            Self::InvalidInput
+        } else {
+            Self::Failure
+        }
+    }
+}
+
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        Self::from(status as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unexpected_html_response_quotes_status_type_and_body() {
+        let body = format!(
+            "<!DOCTYPE html>\n<html>\n  <head><title>Just a moment...</title></head>\n  <body>{}</body>\n</html>",
+            "x".repeat(500)
+        );
+        let error = UnexpectedResponseError::new(
+            "https://civitai.com/api/v1/models/1",
+            StatusCode::BAD_GATEWAY,
+            Some("text/html; charset=UTF-8"),
+            body.as_bytes(),
+            "expected value at line 1 column 1",
+        );
+
+        assert_eq!(error.status, 502);
+        assert_eq!(error.content_type, "text/html; charset=UTF-8");
+        // 空白被折叠，摘要截断到固定长度
+        assert!(
+            error
+                .snippet
+                .starts_with("<!DOCTYPE html> <html> <head><title>Just a moment...</title></head>")
+        );
+        assert_eq!(error.snippet.chars().count(), BODY_SNIPPET_LENGTH);
+        let message = error.to_string();
+        assert!(message.contains("https://civitai.com/api/v1/models/1"));
+        assert!(message.contains("HTTP 502, text/html; charset=UTF-8"));
+        assert!(message.contains("expected value at line 1 column 1"));
+        assert!(message.contains("Just a moment..."));
+    }
+
+    #[test]
+    fn unexpected_empty_response_is_marked_empty() {
+        let error = UnexpectedResponseError::new(
+            "https://civitai.com/api/v1/models/1",
+            StatusCode::OK,
+            None,
+            b" \n ",
+            "EOF while parsing a value",
+        );
+
+        assert_eq!(error.snippet, "[EMPTY]");
+        assert_eq!(error.content_type, "no content type");
+        assert!(error.to_string().ends_with("Body starts with: [EMPTY]"));
+    }
+
+    #[test]
+    fn unexpected_response_snippet_keeps_multibyte_characters_whole() {
+        let body = "模".repeat(BODY_SNIPPET_LENGTH + 10);
+        let error = UnexpectedResponseError::new(
+            "https://civitai.com/api/v1/models/1",
+            StatusCode::OK,
+            Some("application/json"),
+            body.as_bytes(),
+            "expected value",
+        );
+
+        assert_eq!(error.snippet, "模".repeat(BODY_SNIPPET_LENGTH));
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    progress::{ArtifactKind, DownloadReporter, SkipReason},
+    utils::format_duration,
+};
+
+/// Version of the event format, bumped whenever an event changes incompatibly.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Minimum interval between two byte progress events of the same file.
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
+static EVENTS_ENABLED: AtomicBool = AtomicBool::new(false);
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Switches to machine readable output: events are written to stdout as newline-delimited
+/// JSON, human readable messages go to stderr and interactive prompts are suppressed.
+pub fn enable() {
+    EVENTS_ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    EVENTS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Keeps stdout for the file content streamed to it, human readable messages go to stderr.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileEvent<'a> {
+    pub name: &'a str,
+    pub path: &'a Path,
+    pub size: u64,
+    pub blake3: Option<&'a str>,
+    pub hash_matched: Option<bool>,
+    pub page_url: Option<&'a str>,
+}
+
+/// A file or model left out of an operation.
+#[derive(Debug, Serialize)]
+pub struct SkippedEvent<'a> {
+    pub name: &'a str,
+    pub reason: SkipReason,
+}
+
+/// An artifact a failed step left missing or unverified.
+#[derive(Debug, Serialize)]
+pub struct FailedArtifactEvent<'a> {
+    pub name: &'a str,
+    pub kind: ArtifactKind,
+    pub reason: &'a str,
+}
+
+/// A file a dry run would download.
+#[derive(Debug, Serialize)]
+pub struct PlannedFileEvent<'a> {
+    pub version_id: u64,
+    pub name: &'a str,
+    pub path: &'a Path,
+    pub size: u64,
+    pub blake3: Option<&'a str>,
+    pub sha256: Option<&'a str>,
+    pub exists: bool,
+    pub will_transfer: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    PhaseStarted {
+        step: usize,
+        total_steps: usize,
+        message: &'a str,
+    },
+    PhaseFinished {
+        step: usize,
+        state: &'a str,
+    },
+    Progress {
+        file: &'a str,
+        downloaded: u64,
+        /// 0 when the size is unknown.
+        total: u64,
+    },
+    Retry {
+        file: &'a str,
+        delay_secs: f64,
+        reason: &'a str,
+    },
+    FileCompleted(FileEvent<'a>),
+    PlannedFile(PlannedFileEvent<'a>),
+    Summary {
+        files: Vec<FileEvent<'a>>,
+        readme_files: &'a [PathBuf],
+        skipped: Vec<SkippedEvent<'a>>,
+        failed: Vec<FailedArtifactEvent<'a>>,
+        #[serde(skip_serializing_if = "<[String]>::is_empty")]
+        warnings: &'a [String],
+        /// Kinds of files written, like model, readme or cover.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        artifacts: Vec<&'static str>,
+        /// Whether every step succeeded, false when any artifact is missing or unverified.
+        complete: bool,
+        elapsed_secs: f64,
+    },
+    Error {
+        message: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+struct VersionedEvent<'a> {
+    version: u32,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Writes an event to stdout, does nothing unless machine readable output is enabled.
+pub fn emit(event: &Event) {
+    if !enabled() {
+        return;
+    }
+    let versioned = VersionedEvent {
+        version: EVENT_SCHEMA_VERSION,
+        event,
+    };
+    match serde_json::to_string(&versioned) {
+        Ok(line) => println!("{line}"),
+        Err(e) => eprintln!("Failed to serialize event: {e}"),
+    }
+}
+
+/// Prints a human readable message, to stderr when stdout is occupied by events.
+pub fn message<S: AsRef<str>>(text: S) {
+    if enabled() || STDOUT_RESERVED.load(Ordering::Relaxed) {
+        eprintln!("{}", text.as_ref());
+    } else {
+        println!("{}", text.as_ref());
+    }
+}
+
+/// Sets how many details are printed, given by the count of `-v` flags.
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+/// Whether diagnostic messages of the given level are printed.
+pub fn is_verbose(level: u8) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= level
+}
+
+/// Prints a diagnostic message when verbosity is at least the given level.
+pub fn verbose<S: AsRef<str>>(level: u8, text: S) {
+    if is_verbose(level) {
+        message(text);
+    }
+}
+
+/// Emits byte progress events of a file transfer at a throttled rate.
+pub struct TransferEvents {
+    file: String,
+    total: u64,
+    last_emitted: Option<Instant>,
+}
+
+impl TransferEvents {
+    pub fn new<S: Into<String>>(file: S) -> Self {
+        Self {
+            file: file.into(),
+            total: 0,
+            last_emitted: None,
+        }
+    }
+}
+
+impl DownloadReporter for TransferEvents {
+    fn on_start(&mut self, total: u64) {
+        self.total = total;
+    }
+
+    fn on_progress(&mut self, downloaded: u64) {
+        let due = self
+            .last_emitted
+            .is_none_or(|last| last.elapsed() >= PROGRESS_EVENT_INTERVAL);
+        if due || (self.total > 0 && downloaded >= self.total) {
+            self.last_emitted = Some(Instant::now());
+            emit(&Event::Progress {
+                file: &self.file,
+                downloaded,
+                total: self.total,
+            });
+        }
+    }
+
+    fn on_length_exceeded(&mut self, advertised: u64) {
+        self.total = 0;
+        message(crate::progress::length_exceeded_warning(advertised));
+    }
+
+    fn on_retry(&mut self, delay: Duration, reason: &str) {
+        emit(&Event::Retry {
+            file: &self.file,
+            delay_secs: delay.as_secs_f64(),
+            reason,
+        });
+        message(format!(
+            "{reason}, will resume downloading after {}.",
+            format_duration(&delay)
+        ));
+    }
+
+    fn on_finish(&mut self) {}
+}
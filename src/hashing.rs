@@ -0,0 +1,89 @@
+//! Hashes of model files, computed in one pass over the content.
+
+use std::io::Read;
+
+use indicatif::ProgressBar;
+use sha2::{Digest, Sha256};
+
+/// Large chunks let blake3 spread the work on all cores.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// AutoV2 hash used by Civitai is the leading part of SHA256.
+pub const AUTOV2_LENGTH: usize = 10;
+
+/// Hashes of one file, in upper case hex. Only the requested ones are present.
+#[derive(Debug, Clone, Default)]
+pub struct FileHashes {
+    pub blake3: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl FileHashes {
+    pub fn autov2(&self) -> Option<&str> {
+        self.sha256
+            .as_deref()
+            .map(|sha256| &sha256[..AUTOV2_LENGTH])
+    }
+}
+
+/// The requested hashers, fed with the content piece by piece.
+pub struct Hashers {
+    blake3: Option<blake3::Hasher>,
+    sha256: Option<Sha256>,
+}
+
+impl Hashers {
+    pub fn new(with_blake3: bool, with_sha256: bool) -> Self {
+        Self {
+            blake3: with_blake3.then(blake3::Hasher::new),
+            sha256: with_sha256.then(Sha256::new),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        if let Some(hasher) = self.blake3.as_mut() {
+            hasher.update_rayon(chunk);
+        }
+        if let Some(hasher) = self.sha256.as_mut() {
+            hasher.update(chunk);
+        }
+    }
+
+    pub fn finalize(self) -> FileHashes {
+        FileHashes {
+            blake3: self
+                .blake3
+                .map(|hasher| hasher.finalize().to_hex().to_string().to_uppercase()),
+            sha256: self.sha256.map(|hasher| {
+                hasher
+                    .finalize()
+                    .iter()
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect()
+            }),
+        }
+    }
+}
+
+/// Reads the source once, feeding every requested hasher with the same chunks.
+pub fn hash_reader<R: Read>(
+    mut reader: R,
+    bar: Option<&ProgressBar>,
+    with_blake3: bool,
+    with_sha256: bool,
+) -> std::io::Result<FileHashes> {
+    let mut hashers = Hashers::new(with_blake3, with_sha256);
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+
+    loop {
+        let read_size = reader.read(&mut buffer)?;
+        if read_size == 0 {
+            break;
+        }
+        hashers.update(&buffer[..read_size]);
+        if let Some(bar) = bar {
+            bar.inc(read_size as u64);
+        }
+    }
+
+    Ok(hashers.finalize())
+}
@@ -0,0 +1,424 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use backoff::backoff::Backoff;
+use futures_util::StreamExt;
+use reqwest::{Client, Method, StatusCode, header};
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+use crate::{
+    configuration::Configuration,
+    downloader::make_backoff_policy,
+    progress::{
+        ArtifactKind, DownloadReporter, FileSummary, OperationSummary, ReporterKind, StepProgress,
+    },
+    sink::{DownloadSink, HashingStream},
+    staging,
+    utils::{ByteUnits, format_bytes, format_duration, sanitize_file_name},
+};
+
+use super::target::HuggingFaceFile;
+
+/// Header carrying the SHA256 of a file stored in LFS, on the redirect to the storage.
+const LINKED_ETAG_HEADER: &str = "x-linked-etag";
+/// Header carrying the size of a file stored in LFS, on the redirect to the storage.
+const LINKED_SIZE_HEADER: &str = "x-linked-size";
+
+/// What HuggingFace tells of a file before downloading it.
+struct FileInfo {
+    size: Option<u64>,
+    /// Only files stored in LFS declare their SHA256.
+    sha256: Option<String>,
+}
+
+/// Downloads a single file of a HuggingFace repository into the directory, keeping its name.
+pub async fn download_huggingface_file(
+    client: &Client,
+    config: &Configuration,
+    file: &HuggingFaceFile,
+    target_dir: &Path,
+    dry_run: bool,
+    reporter_kind: ReporterKind,
+) -> Result<OperationSummary> {
+    let token = config.huggingface.api_key.clone();
+    let mut progress = StepProgress::new(if dry_run { 1 } else { 3 });
+    progress.begin("Fetching file information...");
+    let info = progress.track(fetch_file_info(config, file, token.as_deref()).await)?;
+    let file_name = sanitize_file_name(file.file_name());
+    let target_file_path = target_dir.join(&file_name);
+
+    progress.println(format!(
+        "{} from {} at {}: {}, SHA256 {}",
+        file.path,
+        file.repo.id,
+        file.repo.revision,
+        info.size
+            .map(|size| format_bytes(size, ByteUnits::Decimal))
+            .unwrap_or_else(|| "unknown size".to_string()),
+        info.sha256.as_deref().unwrap_or("not declared")
+    ));
+    if dry_run {
+        progress.println(format!("Would save to {}", target_file_path.display()));
+        return Ok(OperationSummary::default());
+    }
+
+    progress.begin(format!("Downloading {file_name}..."));
+    let mut reporter = reporter_kind.create(&file_name, &progress);
+    let staged_path = staging::staging_path(&config.download, &target_file_path).await?;
+    let result = match File::create(&staged_path)
+        .await
+        .with_context(|| format!("Failed to create {}", staged_path.display()))
+    {
+        Ok(mut target_file) => {
+            download_file(
+                client,
+                config,
+                file,
+                token.as_deref(),
+                &mut target_file,
+                reporter.as_mut(),
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        staging::discard(&staged_path, &target_file_path).await;
+    }
+    progress.track(result)?;
+
+    progress.begin("Verifying file...");
+    let received_size = tokio::fs::metadata(&staged_path).await?.len();
+    if let Some(size) = info.size
+        && size != received_size
+    {
+        progress.println(format!(
+            "WARNING: Received {received_size} bytes for file {file_name}, but HuggingFace declares {size} bytes."
+        ));
+    }
+    let hash_matched = match info.sha256.as_ref() {
+        Some(expected) => {
+            let checksum = progress.track(sha256_hash(&staged_path).await)?;
+            let matched = checksum.eq_ignore_ascii_case(expected);
+            if !matched {
+                progress.println(format!(
+                    "File {file_name} sha256 check failed, got {checksum}. Maybe need to redownload."
+                ));
+            }
+            Some(matched)
+        }
+        None => {
+            progress.skip();
+            None
+        }
+    };
+
+    if staging::is_staged(&staged_path, &target_file_path) {
+        progress.println(format!("Moving {file_name} into place..."));
+        staging::finish(&staged_path, &target_file_path).await?;
+    }
+
+    let mut summary = OperationSummary::default();
+    if hash_matched == Some(false) {
+        summary.fail(
+            file_name.clone(),
+            ArtifactKind::HashCheck,
+            "sha256 differs from the hash declared by HuggingFace",
+        );
+    }
+    summary.files.push(FileSummary {
+        name: file_name,
+        path: target_file_path,
+        size: received_size,
+        blake3: None,
+        hash_matched,
+        page_url: None,
+    });
+    summary.print(progress.elapsed());
+    Ok(summary)
+}
+
+/// Streams a single file of a HuggingFace repository to stdout, to be piped into another
+/// program. Its SHA256 is calculated on the streamed content, progress goes to stderr.
+pub async fn stream_huggingface_file(
+    client: &Client,
+    config: &Configuration,
+    file: &HuggingFaceFile,
+) -> Result<OperationSummary> {
+    let token = config.huggingface.api_key.clone();
+    let mut progress = StepProgress::new(2);
+    progress.begin("Fetching file information...");
+    let info = progress.track(fetch_file_info(config, file, token.as_deref()).await)?;
+    let file_name = sanitize_file_name(file.file_name());
+
+    progress.begin(format!("Streaming {file_name}..."));
+    let mut reporter = ReporterKind::Bar.create(&file_name, &progress);
+    let mut stream = HashingStream::new(tokio::io::stdout(), false, true);
+    let result = download_file(
+        client,
+        config,
+        file,
+        token.as_deref(),
+        &mut stream,
+        reporter.as_mut(),
+    )
+    .await;
+    progress.track(result)?;
+
+    let received_size = stream.written();
+    if let Some(size) = info.size
+        && size != received_size
+    {
+        progress.println(format!(
+            "WARNING: Received {received_size} bytes for file {file_name}, but HuggingFace declares {size} bytes."
+        ));
+    }
+    let checksum = stream.finalize().sha256.unwrap_or_default();
+    let hash_matched = info.sha256.as_ref().map(|expected| {
+        let matched = checksum.eq_ignore_ascii_case(expected);
+        if !matched {
+            progress.println(format!(
+                "File {file_name} sha256 check failed, got {checksum}. Maybe need to redownload."
+            ));
+        }
+        matched
+    });
+
+    let mut summary = OperationSummary::default();
+    if hash_matched == Some(false) {
+        summary.fail(
+            file_name.clone(),
+            ArtifactKind::HashCheck,
+            "sha256 differs from the hash declared by HuggingFace",
+        );
+    }
+    summary.files.push(FileSummary {
+        name: file_name,
+        path: PathBuf::from("-"),
+        size: received_size,
+        blake3: None,
+        hash_matched,
+        page_url: None,
+    });
+    summary.print(progress.elapsed());
+    Ok(summary)
+}
+
+/// Reads the size and the LFS hash of the file. The redirect to the storage is not followed,
+/// only it carries the LFS headers.
+async fn fetch_file_info(
+    config: &Configuration,
+    file: &HuggingFaceFile,
+    token: Option<&str>,
+) -> Result<FileInfo> {
+    let client = crate::downloader::make_client_without_redirects(config)
+        .context("Failed to initialize client")?;
+    let mut request = client
+        .request(Method::HEAD, file.resolve_url())
+        .header(header::ACCEPT_ENCODING, "identity");
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to request {}", file.resolve_url()))?;
+    let status = response.status();
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => bail!(
+            "HuggingFace refused to serve {} ({}), the repository may be private or gated. Accept its terms on the repository page and set an access token by \"imd config set huggingface <key>\".",
+            file.path,
+            status.as_u16()
+        ),
+        StatusCode::NOT_FOUND => bail!(
+            "File {} is not found in {} at revision {}",
+            file.path,
+            file.repo.id,
+            file.repo.revision
+        ),
+        _ if !status.is_success() && !status.is_redirection() => bail!(
+            "HuggingFace: {} ({})",
+            status.canonical_reason().unwrap_or("request failed"),
+            status.as_u16()
+        ),
+        _ => {}
+    }
+
+    let headers = response.headers();
+    let header_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().trim_start_matches("W/").trim_matches('"'))
+    };
+    // 非LFS文件的ETag是git对象哈希，不能用于校验
+    let sha256 = header_value(LINKED_ETAG_HEADER)
+        .filter(|etag| etag.len() == 64 && etag.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_lowercase);
+    let size = header_value(LINKED_SIZE_HEADER)
+        .or_else(|| {
+            status
+                .is_success()
+                .then(|| header_value(header::CONTENT_LENGTH.as_str()))
+                .flatten()
+        })
+        .and_then(|size| size.parse::<u64>().ok());
+    Ok(FileInfo { size, sha256 })
+}
+
+/// Downloads the file into the sink, resuming interrupted transfers from where they stopped.
+async fn download_file<S: DownloadSink>(
+    client: &Client,
+    config: &Configuration,
+    file: &HuggingFaceFile,
+    token: Option<&str>,
+    sink: &mut S,
+    reporter: &mut dyn DownloadReporter,
+) -> Result<()> {
+    let idle_timeout = config.network.idle_timeout();
+    let mut downloaded_size: u64 = 0;
+    let mut policy = make_backoff_policy(&config.backoff, idle_timeout.as_secs());
+
+    // 下载中断时，使用Range请求从已下载的位置继续下载
+    loop {
+        let attempt = download_attempt(
+            client,
+            file,
+            token,
+            sink,
+            &mut downloaded_size,
+            reporter,
+            idle_timeout,
+        )
+        .await;
+        match attempt {
+            Ok(()) => break,
+            Err(backoff::Error::Permanent(e)) => return Err(e),
+            Err(backoff::Error::Transient { err, retry_after }) => match policy.next_backoff() {
+                Some(wait) => {
+                    let wait = retry_after.map_or(wait, |delay| delay.max(wait));
+                    reporter.on_retry(wait, &err.to_string());
+                    tokio::time::sleep(wait).await;
+                }
+                None => return Err(err),
+            },
+        }
+    }
+    sink.flush().await?;
+    reporter.on_finish();
+    Ok(())
+}
+
+/// Performs one download request, appending received content to the sink. The token is sent to
+/// HuggingFace only, reqwest drops it on the redirect to the storage host.
+async fn download_attempt<S: DownloadSink>(
+    client: &Client,
+    file: &HuggingFaceFile,
+    token: Option<&str>,
+    sink: &mut S,
+    downloaded_size: &mut u64,
+    reporter: &mut dyn DownloadReporter,
+    idle_timeout: std::time::Duration,
+) -> Result<(), backoff::Error<anyhow::Error>> {
+    let mut request = client
+        .request(Method::GET, file.resolve_url())
+        .header(header::ACCEPT_ENCODING, "identity");
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    if *downloaded_size > 0 {
+        request = request.header(header::RANGE, format!("bytes={downloaded_size}-"));
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| backoff::Error::transient(anyhow!("Failed to request file: {e}")))?;
+    let response = response.error_for_status().map_err(|e| {
+        if e.status().is_some_and(|s| s.is_server_error()) {
+            backoff::Error::transient(anyhow!(e))
+        } else {
+            backoff::Error::permanent(anyhow!(e))
+        }
+    })?;
+
+    if *downloaded_size > 0 && response.status() != StatusCode::PARTIAL_CONTENT {
+        // Server does not support resuming, start over.
+        sink.restart()
+            .await
+            .map_err(|e| backoff::Error::permanent(anyhow!(e)))?;
+        *downloaded_size = 0;
+    }
+    let file_length = response
+        .content_length()
+        .map(|remaining| *downloaded_size + remaining);
+    reporter.on_start(file_length.unwrap_or_default());
+    reporter.on_progress(*downloaded_size);
+
+    let mut length_exceeded = false;
+    let mut download_stream = response.bytes_stream();
+    loop {
+        let chunk = match tokio::time::timeout(idle_timeout, download_stream.next()).await {
+            Err(_) => {
+                return Err(backoff::Error::transient(anyhow!(
+                    "No data received in {}",
+                    format_duration(&idle_timeout)
+                )));
+            }
+            Ok(None) => break,
+            Ok(Some(Err(e))) => {
+                return Err(backoff::Error::transient(anyhow!(
+                    "Download interrupted: {e}"
+                )));
+            }
+            Ok(Some(Ok(chunk))) => chunk,
+        };
+        sink.write_all(&chunk)
+            .await
+            .map_err(|e| backoff::Error::permanent(anyhow!(e)))?;
+        *downloaded_size += chunk.len() as u64;
+        if let Some(length) = file_length
+            && *downloaded_size > length
+            && !length_exceeded
+        {
+            length_exceeded = true;
+            reporter.on_length_exceeded(length);
+        }
+        reporter.on_progress(*downloaded_size);
+    }
+    if file_length.is_some_and(|length| *downloaded_size < length) {
+        return Err(backoff::Error::transient(anyhow!(
+            "Connection closed before download completed"
+        )));
+    }
+    Ok(())
+}
+
+async fn sha256_hash(target_file_path: &Path) -> Result<String> {
+    let target_file_path = target_file_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&target_file_path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 512 * 1024];
+        loop {
+            let read_size = file.read(&mut buffer)?;
+            if read_size == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read_size]);
+        }
+        Ok::<_, std::io::Error>(
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+        )
+    })
+    .await
+    .context("Hash calculation is interrupted")?
+    .context("Failed to calculate sha256 hash")
+}
@@ -0,0 +1,54 @@
+use anyhow::{Context, Result, bail};
+use reqwest::{Client, Method, StatusCode, header};
+use serde_json::Value;
+
+use crate::configuration::Configuration;
+
+mod download;
+mod target;
+
+pub use download::{download_huggingface_file, stream_huggingface_file};
+pub use target::{
+    HUGGINGFACE_URL_SHAPES, HuggingFaceFile, HuggingFaceRepo, HuggingFaceTarget,
+    parse_huggingface_url,
+};
+
+const HUGGINGFACE_WHOAMI_URL: &str = "https://huggingface.co/api/whoami-v2";
+
+/// Checks the given access token against HuggingFace, returns the account name it belongs to.
+pub async fn verify_api_key(
+    client: &Client,
+    config: &Configuration,
+    api_key: &str,
+) -> Result<String> {
+    crate::downloader::ensure_online("Validating access token")?;
+    let request_timeout = config.network.request_timeout();
+    let response = client
+        .request(Method::GET, HUGGINGFACE_WHOAMI_URL)
+        .bearer_auth(api_key)
+        .header(header::ACCEPT, "application/json")
+        .timeout(request_timeout)
+        .send()
+        .await
+        .with_context(|| format!("Failed to request {HUGGINGFACE_WHOAMI_URL}"))?;
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED {
+        bail!("HuggingFace: access token is invalid ({})", status.as_u16());
+    }
+    if !status.is_success() {
+        bail!(
+            "HuggingFace: {} ({})",
+            status.canonical_reason().unwrap_or("request failed"),
+            status.as_u16()
+        );
+    }
+    let account = response
+        .json::<Value>()
+        .await
+        .context("Failed to parse HuggingFace account info")?;
+    Ok(account
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown")
+        .to_string())
+}
@@ -0,0 +1,214 @@
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+
+use crate::errors::InvalidInputError;
+
+const HUGGINGFACE_BASE_URL: &str = "https://huggingface.co/";
+const DEFAULT_REVISION: &str = "main";
+
+/// URL shapes accepted for HuggingFace, shown when the given URL is not recognized.
+pub const HUGGINGFACE_URL_SHAPES: &str = "https://huggingface.co/<owner>/<repo> or https://huggingface.co/<owner>/<repo>/resolve/<revision>/<file path>";
+
+/// What a HuggingFace URL points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HuggingFaceTarget {
+    /// The repository root or one of its folders, files are to be selected.
+    Repository(HuggingFaceRepo),
+    /// A single file in the repository, downloaded directly.
+    File(HuggingFaceFile),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HuggingFaceRepo {
+    /// Repository id like `owner/name`, prefixed by `datasets/` or `spaces/` for those kinds.
+    pub id: String,
+    pub revision: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HuggingFaceFile {
+    pub repo: HuggingFaceRepo,
+    /// Decoded path of the file in the repository, separated by `/`.
+    pub path: String,
+}
+
+impl HuggingFaceFile {
+    /// URL serving the raw file content, `/blob/` URLs point at the web page instead.
+    pub fn resolve_url(&self) -> Url {
+        let mut url = Url::parse(HUGGINGFACE_BASE_URL).unwrap();
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(self.repo.id.split('/'))
+            .push("resolve")
+            // 修订名中的斜杠（如 refs/pr/1）必须编码在同一段中
+            .push(&self.repo.revision)
+            .extend(self.path.split('/'));
+        url
+    }
+
+    /// Name of the file without its folders in the repository.
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Tells a repository URL from a single file URL, percent-encoded segments are decoded.
+pub fn parse_huggingface_url(url: &Url) -> Result<HuggingFaceTarget, InvalidInputError> {
+    let malformed = |reason: &str| {
+        InvalidInputError(format!(
+            "\"{url}\" is not a valid HuggingFace URL, {reason}. Expected {HUGGINGFACE_URL_SHAPES}"
+        ))
+    };
+    let segments = url
+        .path_segments()
+        .map(|segments| {
+            segments
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let (prefix, rest) = match segments.first().map(String::as_str) {
+        Some(kind @ ("datasets" | "spaces")) => (Some(kind), &segments[1..]),
+        _ => (None, &segments[..]),
+    };
+    // 早期的仓库没有所有者，例如 gpt2
+    let name_length = match rest.get(1).map(String::as_str) {
+        Some("resolve" | "blob" | "tree") | None => 1,
+        _ => 2,
+    };
+    if rest.len() < name_length {
+        return Err(malformed("the repository is missing"));
+    }
+    let (name, rest) = rest.split_at(name_length);
+    let id = prefix
+        .into_iter()
+        .chain(name.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join("/");
+
+    match rest {
+        [] => Ok(HuggingFaceTarget::Repository(HuggingFaceRepo {
+            id,
+            revision: DEFAULT_REVISION.to_string(),
+        })),
+        [kind, revision, ..] if kind == "tree" => {
+            Ok(HuggingFaceTarget::Repository(HuggingFaceRepo {
+                id,
+                revision: revision.clone(),
+            }))
+        }
+        [kind, revision, path @ ..]
+            if (kind == "resolve" || kind == "blob") && !path.is_empty() =>
+        {
+            Ok(HuggingFaceTarget::File(HuggingFaceFile {
+                repo: HuggingFaceRepo {
+                    id,
+                    revision: revision.clone(),
+                },
+                path: path.join("/"),
+            }))
+        }
+        [kind, ..] if kind == "resolve" || kind == "blob" => {
+            Err(malformed("the revision and file path are missing"))
+        }
+        _ => Err(malformed("it points at neither a repository nor a file")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(url: &str) -> Result<HuggingFaceTarget, InvalidInputError> {
+        parse_huggingface_url(&Url::parse(url).unwrap())
+    }
+
+    fn repo(id: &str, revision: &str) -> HuggingFaceRepo {
+        HuggingFaceRepo {
+            id: id.to_string(),
+            revision: revision.to_string(),
+        }
+    }
+
+    fn file(id: &str, revision: &str, path: &str) -> HuggingFaceTarget {
+        HuggingFaceTarget::File(HuggingFaceFile {
+            repo: repo(id, revision),
+            path: path.to_string(),
+        })
+    }
+
+    #[test]
+    fn repository_urls_are_recognized() {
+        for (url, id, revision) in [
+            ("https://huggingface.co/owner/repo", "owner/repo", "main"),
+            ("https://huggingface.co/owner/repo/", "owner/repo", "main"),
+            ("https://huggingface.co/gpt2", "gpt2", "main"),
+            (
+                "https://huggingface.co/datasets/owner/set",
+                "datasets/owner/set",
+                "main",
+            ),
+            (
+                "https://huggingface.co/owner/repo/tree/dev",
+                "owner/repo",
+                "dev",
+            ),
+            (
+                "https://huggingface.co/owner/repo/tree/main/unet",
+                "owner/repo",
+                "main",
+            ),
+        ] {
+            assert_eq!(
+                parse(url).unwrap(),
+                HuggingFaceTarget::Repository(repo(id, revision)),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_and_blob_urls_point_at_the_same_file() {
+        let expected = file("owner/repo", "main", "unet/model.safetensors");
+        for url in [
+            "https://huggingface.co/owner/repo/resolve/main/unet/model.safetensors",
+            "https://huggingface.co/owner/repo/blob/main/unet/model.safetensors",
+            "https://huggingface.co/owner/repo/resolve/main/unet/model.safetensors?download=true",
+        ] {
+            assert_eq!(parse(url).unwrap(), expected, "{url}");
+        }
+        assert_eq!(
+            parse("https://huggingface.co/gpt2/blob/main/config.json").unwrap(),
+            file("gpt2", "main", "config.json")
+        );
+    }
+
+    #[test]
+    fn percent_encoded_segments_are_decoded_and_encoded_back() {
+        let url = "https://huggingface.co/owner/repo/resolve/refs%2Fpr%2F1/my%20loras/%E6%A8%A1%E5%9E%8B.safetensors";
+        let HuggingFaceTarget::File(target) = parse(url).unwrap() else {
+            panic!("{url} is not a file URL");
+        };
+        assert_eq!(target.repo.revision, "refs/pr/1");
+        assert_eq!(target.path, "my loras/模型.safetensors");
+        assert_eq!(target.file_name(), "模型.safetensors");
+        assert_eq!(target.resolve_url().as_str(), url);
+    }
+
+    #[test]
+    fn incomplete_urls_are_refused() {
+        for url in [
+            "https://huggingface.co/",
+            "https://huggingface.co/datasets",
+            "https://huggingface.co/owner/repo/resolve/main",
+            "https://huggingface.co/owner/repo/blob",
+            "https://huggingface.co/owner/repo/discussions",
+        ] {
+            let error = parse(url).unwrap_err();
+            assert!(error.to_string().contains(HUGGINGFACE_URL_SHAPES), "{url}");
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde_yaml::Value;
+
+use super::ModelDirectories;
+use crate::configuration::ComfyUiConfig;
+
+/// Maps a Civitai model type to the ComfyUI model category.
+pub fn category_of(model_type: &str) -> Option<&'static str> {
+    match model_type.to_ascii_lowercase().as_str() {
+        "checkpoint" => Some("checkpoints"),
+        "lora" | "locon" | "dora" => Some("loras"),
+        "textualinversion" => Some("embeddings"),
+        "vae" => Some("vae"),
+        "controlnet" => Some("controlnet"),
+        "upscaler" => Some("upscale_models"),
+        "hypernetwork" => Some("hypernetworks"),
+        "motionmodule" => Some("animatediff_models"),
+        _ => None,
+    }
+}
+
+/// Reads the category directories declared in `extra_model_paths.yaml`. Every section may have
+/// a `base_path`, relative paths are resolved against the directory of the YAML file as ComfyUI
+/// does. When a category is declared more than once, the section marked `is_default` wins,
+/// otherwise the first declaration.
+pub fn load_model_paths(yaml_path: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let content = std::fs::read_to_string(yaml_path)
+        .with_context(|| format!("Failed to read {}", yaml_path.display()))?;
+    let document: Value = serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", yaml_path.display()))?;
+    let Some(sections) = document.as_mapping() else {
+        bail!("{} declares no model paths", yaml_path.display());
+    };
+    let yaml_dir = yaml_path.parent().unwrap_or(Path::new("."));
+
+    let mut categories: Vec<(String, PathBuf)> = Vec::new();
+    let mut default_categories: Vec<(String, PathBuf)> = Vec::new();
+    for section in sections.values().filter_map(Value::as_mapping) {
+        let base_path = section
+            .get("base_path")
+            .and_then(Value::as_str)
+            .map(|base| yaml_dir.join(expand_home(base.trim())))
+            .unwrap_or_else(|| yaml_dir.to_path_buf());
+        let is_default = section
+            .get("is_default")
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        for (key, value) in section.iter() {
+            let (Some(category), Some(paths)) = (key.as_str(), value.as_str()) else {
+                continue;
+            };
+            if category == "base_path" {
+                continue;
+            }
+            // 一个类别可以声明多个目录，下载到第一个目录
+            let Some(first_path) = paths.lines().map(str::trim).find(|p| !p.is_empty()) else {
+                continue;
+            };
+            let entry = (
+                category.to_string(),
+                base_path.join(expand_home(first_path)),
+            );
+            if is_default {
+                default_categories.push(entry);
+            } else {
+                categories.push(entry);
+            }
+        }
+    }
+
+    let mut resolved = default_categories;
+    for (category, dir) in categories {
+        if !resolved.iter().any(|(name, _)| *name == category) {
+            resolved.push((category, dir));
+        }
+    }
+    if resolved.is_empty() {
+        bail!("{} declares no model paths", yaml_path.display());
+    }
+    Ok(resolved)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => directories::UserDirs::new()
+            .map(|dirs| dirs.home_dir().join(rest))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Model directories of the ComfyUI configured by `imd config set comfyui`.
+pub fn configured_directories(config: &ComfyUiConfig) -> Result<ModelDirectories> {
+    let config = config.clone();
+    let Some(model_paths) = config.model_paths else {
+        bail!(
+            "ComfyUI model paths are not set, set them by \"imd config set comfyui <path to extra_model_paths.yaml>\"."
+        );
+    };
+    Ok(ModelDirectories {
+        ui_name: "ComfyUI",
+        categories: load_model_paths(&model_paths)?,
+        category_of,
+        fallback: config.default_dir,
+        civitai_helper_files: false,
+    })
+}
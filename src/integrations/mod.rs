@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{configuration::Configuration, errors::InvalidInputError};
+
+pub mod comfyui;
+pub mod webui;
+
+/// Where downloaded models are placed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum InstallTarget {
+    /// The output directory.
+    #[default]
+    Directory,
+    /// Category directories declared in ComfyUI `extra_model_paths.yaml`.
+    Comfyui,
+    /// Model directories of Stable Diffusion webui (AUTOMATIC1111).
+    Webui,
+}
+
+impl InstallTarget {
+    /// The target when a webui root is given, which selects webui.
+    pub fn with_webui_root(self, webui_root: Option<&Path>) -> Result<Self, InvalidInputError> {
+        match (webui_root, self) {
+            (Some(_), InstallTarget::Comfyui) => Err(InvalidInputError(
+                "--webui-root can not be used with --target comfyui".to_string(),
+            )),
+            (Some(_), _) => Ok(InstallTarget::Webui),
+            (None, target) => Ok(target),
+        }
+    }
+}
+
+/// Directories of a model UI, models are placed into them by Civitai model type.
+#[derive(Debug, Clone)]
+pub struct ModelDirectories {
+    /// Name of the UI shown in messages.
+    pub ui_name: &'static str,
+    /// Category name and its directory, in the order they are declared.
+    pub categories: Vec<(String, PathBuf)>,
+    /// Maps a Civitai model type to the category it belongs to.
+    pub category_of: fn(&str) -> Option<&'static str>,
+    /// Directory of models whose type has no category.
+    pub fallback: Option<PathBuf>,
+    /// Also write the `.civitai.info` and `.preview.png` files the Civitai Helper extension reads.
+    pub civitai_helper_files: bool,
+}
+
+impl ModelDirectories {
+    pub fn category_dir(&self, category: &str) -> Option<&Path> {
+        self.categories
+            .iter()
+            .find(|(name, _)| name == category)
+            .map(|(_, dir)| dir.as_path())
+    }
+
+    /// Directory for the Civitai model type, `None` when neither its category nor the fallback
+    /// directory is configured.
+    pub fn directory_for(&self, model_type: Option<&str>) -> Option<&Path> {
+        model_type
+            .and_then(self.category_of)
+            .and_then(|category| self.category_dir(category))
+            .or(self.fallback.as_deref())
+    }
+}
+
+/// Model directories of the install target, `None` for a plain directory. `webui_root`
+/// overrides the configured webui root.
+pub fn model_directories(
+    config: &Configuration,
+    target: InstallTarget,
+    webui_root: Option<&Path>,
+) -> Result<Option<ModelDirectories>> {
+    match target {
+        InstallTarget::Directory => Ok(None),
+        InstallTarget::Comfyui => comfyui::configured_directories(&config.comfyui).map(Some),
+        InstallTarget::Webui => webui::configured_directories(&config.webui, webui_root).map(Some),
+    }
+}
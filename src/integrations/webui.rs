@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::ModelDirectories;
+use crate::{civitai::ModelVersion, configuration::WebuiConfig, errors::InvalidInputError};
+
+/// Files and directories found in the root of a Stable Diffusion webui installation.
+const WEBUI_MARKERS: [&str; 4] = [
+    "webui-user.sh",
+    "webui-user.bat",
+    "webui.py",
+    "models/Stable-diffusion",
+];
+
+/// Maps a Civitai model type to the model directory relative to the webui root.
+pub fn category_of(model_type: &str) -> Option<&'static str> {
+    match model_type.to_ascii_lowercase().as_str() {
+        "checkpoint" => Some("models/Stable-diffusion"),
+        "lora" | "locon" | "dora" => Some("models/Lora"),
+        "textualinversion" => Some("embeddings"),
+        "vae" => Some("models/VAE"),
+        "hypernetwork" => Some("models/hypernetworks"),
+        _ => None,
+    }
+}
+
+/// Checks the directory looks like a webui installation, by its launch scripts or model
+/// directory.
+pub fn validate_root(root: &Path) -> Result<()> {
+    if !root.is_dir() {
+        return Err(InvalidInputError(format!("{} is not a directory", root.display())).into());
+    }
+    if !WEBUI_MARKERS
+        .iter()
+        .any(|marker| root.join(marker).exists())
+    {
+        return Err(InvalidInputError(format!(
+            "{} does not look like a Stable Diffusion webui installation, expected one of {} in it",
+            root.display(),
+            WEBUI_MARKERS.join(", ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Model directories of the webui at the given root, or the root configured by
+/// `imd config set webui`.
+pub fn configured_directories(
+    config: &WebuiConfig,
+    root: Option<&Path>,
+) -> Result<ModelDirectories> {
+    let Some(root) = root.map(Path::to_path_buf).or(config.root.clone()) else {
+        return Err(InvalidInputError(
+            "Stable Diffusion webui root is not set, set it by \"imd config set webui <path to webui>\" or give --webui-root.".to_string(),
+        )
+        .into());
+    };
+    validate_root(&root)?;
+    let categories = [
+        "models/Stable-diffusion",
+        "models/Lora",
+        "embeddings",
+        "models/VAE",
+        "models/hypernetworks",
+    ]
+    .into_iter()
+    .map(|category| (category.to_string(), root.join(category)))
+    .collect();
+    Ok(ModelDirectories {
+        ui_name: "Stable Diffusion webui",
+        categories,
+        category_of,
+        fallback: config.default_dir.clone(),
+        civitai_helper_files: true,
+    })
+}
+
+/// Writes `<stem>.civitai.info` and `<stem>.preview.png` beside the model file, the names the
+/// Civitai Helper extension reads model information and preview from.
+pub fn save_civitai_helper_files(
+    model_file_path: &Path,
+    version: &ModelVersion,
+    cover_path: Option<&Path>,
+) -> Result<()> {
+    let stem = model_file_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let info_path = model_file_path.with_file_name(format!("{stem}.civitai.info"));
+    std::fs::write(&info_path, serde_json::to_vec_pretty(version.as_value())?)
+        .with_context(|| format!("Failed to write {}", info_path.display()))?;
+
+    // 视频封面不能作为预览图
+    if let Some(cover_path) = cover_path.filter(|p| p.extension().is_some_and(|e| e == "png")) {
+        let preview_path = model_file_path.with_file_name(format!("{stem}.preview.png"));
+        std::fs::copy(cover_path, &preview_path)
+            .with_context(|| format!("Failed to write {}", preview_path.display()))?;
+    }
+    Ok(())
+}
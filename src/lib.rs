@@ -0,0 +1,27 @@
+//! Downloads models with their metadata from Civitai.
+//!
+//! The `imd` binary is a thin command line interface over this library. Functions doing network
+//! requests take a [`reqwest::Client`] built by [`downloader::make_client`] and the
+//! [`configuration::Configuration`] to use, loaded from the configuration file by
+//! [`configuration::current`] or built by the embedder.
+
+mod archive;
+pub mod artifacts;
+pub mod cache_db;
+pub mod civitai;
+#[doc(hidden)]
+pub mod commands;
+pub mod configuration;
+pub mod downloader;
+pub mod errors;
+pub mod events;
+mod hashing;
+pub mod hugging_face;
+pub mod integrations;
+pub mod progress;
+#[doc(hidden)]
+pub mod prompt;
+mod safetensors;
+mod sink;
+mod staging;
+mod utils;
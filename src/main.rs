@@ -1,13 +1,7 @@
-use clap::Parser;
+use std::{io::IsTerminal, process::ExitCode, time::Duration};
 
-mod cache_db;
-mod civitai;
-mod commands;
-mod configuration;
-mod downloader;
-mod errors;
-mod hugging_face;
-mod utils;
+use clap::{ArgAction, CommandFactory, Parser};
+use imd::{cache_db, commands, configuration, downloader, errors, events, prompt};
 
 #[derive(Parser)]
 #[command(
@@ -19,13 +13,135 @@ mod utils;
 pub struct Cli {
     #[command(subcommand)]
     command: Option<commands::Commands>,
+    #[arg(long, global = true, help = "Override connect timeout in seconds.")]
+    connect_timeout: Option<u64>,
+    #[arg(
+        long,
+        global = true,
+        help = "Override metadata request timeout in seconds."
+    )]
+    request_timeout: Option<u64>,
+    #[arg(
+        long,
+        global = true,
+        help = "Override download idle timeout in seconds."
+    )]
+    idle_timeout: Option<u64>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECONDS",
+        help = "Take the default choice of any prompt not answered within the given seconds."
+    )]
+    prompt_timeout: Option<u64>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        action = ArgAction::Count,
+        help = "Show more details like the route of every request, -vv also shows metadata response sizes."
+    )]
+    verbose: u8,
+    #[arg(
+        long,
+        global = true,
+        help = "Use the API keys and proxy of the named profile, defaults to IMD_PROFILE environment variable."
+    )]
+    profile: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Never access the network, metadata is read from cache only. Also enabled by IMD_OFFLINE=1.",
+        default_value = "false"
+    )]
+    offline: bool,
 }
 
-#[tokio::main]
-async fn main() {
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: Failed to start async runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let status = runtime.block_on(run());
+    // 超时的提示框仍在等待按键，无法取消，不等它结束
+    runtime.shutdown_background();
+    status
+}
+
+async fn run() -> ExitCode {
+    let mut cli = Cli::parse();
+    configuration::CONFIGURATION.write().await.override_network(
+        cli.connect_timeout,
+        cli.request_timeout,
+        cli.idle_timeout,
+    );
+    if let Some(profile) = cli
+        .profile
+        .clone()
+        .or_else(|| std::env::var(configuration::PROFILE_ENV_VAR).ok())
+        .filter(|name| !name.is_empty())
+    {
+        // 配置命令可以写入尚不存在的配置档案
+        let create = matches!(cli.command, Some(commands::Commands::Config(_)));
+        if let Err(e) = configuration::CONFIGURATION
+            .write()
+            .await
+            .use_profile(&profile, create)
+        {
+            eprintln!("Error: {e}");
+            return errors::ExitStatus::InvalidInput.into();
+        }
+    }
+    let offline_by_env = std::env::var(downloader::OFFLINE_ENV_VAR)
+        .is_ok_and(|value| !matches!(value.trim(), "" | "0" | "false"));
+    if cli.offline || offline_by_env {
+        downloader::set_offline();
+    }
+    events::set_verbosity(cli.verbose);
+    if let Some(seconds) = cli.prompt_timeout {
+        prompt::set_timeout(Duration::from_secs(seconds));
+    }
+
+    if cli.command.is_none() {
+        if !std::io::stdin().is_terminal() {
+            let _ = Cli::command().print_help();
+            return errors::ExitStatus::InvalidInput.into();
+        }
+        // 菜单选择的命令与全局参数一起重新解析
+        match commands::choose_from_menu() {
+            Ok(Some(args)) => cli.command = Cli::parse_from(std::env::args().chain(args)).command,
+            Ok(None) => return errors::ExitStatus::Success.into(),
+            Err(e) => {
+                eprintln!("Error: {e:#}");
+                return errors::ExitStatus::of(&e).into();
+            }
+        }
+    }
 
-    match cli.command {
+    let wizard_skipped = matches!(
+        cli.command,
+        Some(commands::Commands::Init)
+            | Some(commands::Commands::Config(_))
+            | Some(commands::Commands::Hash(_))
+            | Some(commands::Commands::History(_))
+            | Some(commands::Commands::Diagnose(_))
+            | Some(commands::Commands::Index(_))
+            | Some(commands::Commands::Clean(_))
+            | Some(commands::Commands::Cache(_))
+            | Some(commands::Commands::Open(_))
+    ) || matches!(
+        &cli.command,
+        Some(commands::Commands::Download(options)) if options.output_format == commands::OutputFormat::Json
+    );
+    if !wizard_skipped && !configuration::config_file_exists() && std::io::stdin().is_terminal() {
+        commands::offer_setup_wizard().await;
+    }
+
+    let result = match cli.command {
+        Some(commands::Commands::Init) => commands::process_init().await,
         Some(commands::Commands::Config(options)) => {
             commands::process_config_options(&options).await
         }
@@ -35,9 +151,46 @@ async fn main() {
         Some(commands::Commands::Renew(options)) => {
             commands::process_model_meta_renew(&options).await
         }
-        _ => {}
-    }
+        Some(commands::Commands::Scan(options)) => commands::process_scan_models(&options).await,
+        Some(commands::Commands::List(options)) => commands::process_list_models(&options).await,
+        Some(commands::Commands::Sync(options)) => commands::process_sync_models(&options).await,
+        Some(commands::Commands::Hash(options)) => commands::process_hash_files(&options).await,
+        Some(commands::Commands::Lookup(options)) => commands::process_lookup_model(&options).await,
+        Some(commands::Commands::Open(options)) => {
+            commands::process_open_model_page(&options).await
+        }
+        Some(commands::Commands::Outdated(options)) => {
+            commands::process_outdated_models(&options).await
+        }
+        Some(commands::Commands::Remove(options)) => commands::process_remove_model(&options).await,
+        Some(commands::Commands::Manifest(options)) => {
+            commands::process_manifest_options(&options).await
+        }
+        Some(commands::Commands::History(options)) => {
+            commands::process_show_history(&options).await
+        }
+        Some(commands::Commands::Diagnose(options)) => {
+            commands::process_diagnose_downloads(&options).await
+        }
+        Some(commands::Commands::Readme(options)) => {
+            commands::process_readme_options(&options).await
+        }
+        Some(commands::Commands::Index(options)) => commands::process_index_options(&options).await,
+        Some(commands::Commands::Clean(options)) => commands::process_clean_options(&options).await,
+        Some(commands::Commands::Cache(options)) => commands::process_cache_options(&options).await,
+        _ => Ok(()),
+    };
 
     // Gracefully shutdown the cache database to prevent background thread panics
     let _ = cache_db::shutdown_cache_db();
+
+    match result {
+        Ok(()) => errors::ExitStatus::Success.into(),
+        Err(e) => {
+            let message = format!("{e:#}");
+            events::emit(&events::Event::Error { message: &message });
+            eprintln!("Error: {message}");
+            errors::ExitStatus::of(&e).into()
+        }
+    }
 }
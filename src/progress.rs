@@ -0,0 +1,640 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{IsTerminal, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+
+use crate::{
+    artifacts::{Artifact, ArtifactSet},
+    errors::IncompleteArtifactsError,
+    events::{self, Event, FailedArtifactEvent, FileEvent, SkippedEvent},
+    utils::{ByteUnits, format_bytes, format_duration, format_rate, truncate_text},
+};
+
+/// Longest label shown before a download progress bar, in characters.
+const BAR_LABEL_LENGTH: usize = 32;
+/// Least time between updates of the terminal window title.
+const WINDOW_TITLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Receives the progress of a file transfer. A transfer resumed after an interruption starts
+/// again with [`DownloadReporter::on_start`].
+pub trait DownloadReporter: Send {
+    /// Transfer (re)started, `total` is the full size of the file in bytes.
+    fn on_start(&mut self, total: u64);
+    /// Bytes of the file received so far.
+    fn on_progress(&mut self, bytes: u64);
+    /// More bytes arrived than the `total` given at start, the size is unknown from now on.
+    fn on_length_exceeded(&mut self, advertised: u64);
+    /// Transfer interrupted, it will be resumed after the delay.
+    fn on_retry(&mut self, delay: Duration, reason: &str);
+    fn on_finish(&mut self);
+    /// Names the transfer, like by the model and version the file belongs to.
+    fn set_label(&mut self, _label: &str) {}
+    /// Shows the progress of the transfer in the title of the terminal window.
+    fn show_in_window_title(&mut self, _file_name: &str) {}
+    /// Message about the transfer, like a warning about the received file.
+    fn on_message(&mut self, message: &str) {
+        events::message(message);
+    }
+    /// Display other progress bars of the transfer, like the hash spinner, are attached to.
+    fn multi_progress(&self) -> Option<&MultiProgress> {
+        None
+    }
+}
+
+/// Which [`DownloadReporter`] file transfers are reported to, chosen by the command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReporterKind {
+    /// Progress bar attached to the step progress display.
+    #[default]
+    Bar,
+    /// Throttled progress events of machine readable output.
+    JsonLines,
+    Silent,
+}
+
+impl ReporterKind {
+    pub fn create(self, file_name: &str, progress: &StepProgress) -> Box<dyn DownloadReporter> {
+        match self {
+            ReporterKind::Bar => Box::new(BarReporter::new(progress.multi())),
+            ReporterKind::JsonLines => Box::new(events::TransferEvents::new(file_name)),
+            ReporterKind::Silent => Box::new(SilentReporter),
+        }
+    }
+}
+
+pub struct BarReporter {
+    multi: MultiProgress,
+    bar: ProgressBar,
+    window_title: Option<WindowTitle>,
+}
+
+impl BarReporter {
+    pub fn new(multi: &MultiProgress) -> Self {
+        let bar = multi.add(ProgressBar::new(0));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} {prefix}[{wide_bar:.cyan/blue}] {decimal_bytes}/{decimal_total_bytes} [{elapsed}] ETA:{eta}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar())
+                .progress_chars("=>-"),
+        );
+        Self {
+            multi: multi.clone(),
+            bar,
+            window_title: None,
+        }
+    }
+}
+
+/// Progress shown in the terminal window title by OSC escapes, like `imd: 43% foo.safetensors`.
+/// The title in use before is saved on the title stack of the terminal and restored at the end.
+struct WindowTitle {
+    file_name: String,
+    updated_at: Option<Instant>,
+}
+
+impl WindowTitle {
+    fn new(file_name: &str) -> Self {
+        // 保存当前标题，结束时恢复
+        write_escape("\x1b[22;0t");
+        Self {
+            file_name: file_name.to_string(),
+            updated_at: None,
+        }
+    }
+
+    fn update(&mut self, position: u64, total: Option<u64>) {
+        if self
+            .updated_at
+            .is_some_and(|updated_at| updated_at.elapsed() < WINDOW_TITLE_INTERVAL)
+        {
+            return;
+        }
+        self.updated_at = Some(Instant::now());
+        let percent = match total {
+            Some(total) if total > 0 => format!("{}% ", position.min(total) * 100 / total),
+            _ => String::new(),
+        };
+        write_escape(&format!("\x1b]0;imd: {percent}{}\x07", self.file_name));
+    }
+}
+
+impl Drop for WindowTitle {
+    fn drop(&mut self) {
+        write_escape("\x1b[23;0t");
+    }
+}
+
+/// Writes a terminal escape to stderr, where the progress bars are drawn, only when it is a
+/// terminal.
+fn write_escape(escape: &str) {
+    let mut stderr = std::io::stderr();
+    if stderr.is_terminal() {
+        let _ = stderr.write_all(escape.as_bytes());
+        let _ = stderr.flush();
+    }
+}
+
+impl DownloadReporter for BarReporter {
+    fn on_start(&mut self, total: u64) {
+        self.bar.set_length(total);
+    }
+
+    fn on_progress(&mut self, bytes: u64) {
+        self.bar.set_position(bytes);
+        if let Some(window_title) = self.window_title.as_mut() {
+            window_title.update(bytes, self.bar.length());
+        }
+    }
+
+    fn on_length_exceeded(&mut self, advertised: u64) {
+        let _ = self.multi.println(length_exceeded_warning(advertised));
+        // 长度不可信，改为不显示总量的样式
+        self.bar.set_style(
+            ProgressStyle::default_spinner()
+                .template(
+                    "{spinner:.green} {prefix}{decimal_bytes} [{elapsed}] {decimal_bytes_per_sec}",
+                )
+                .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+        );
+        self.bar.unset_length();
+    }
+
+    fn on_retry(&mut self, delay: Duration, reason: &str) {
+        let _ = self.multi.println(format!(
+            "{reason}, will resume downloading after {}.",
+            format_duration(&delay)
+        ));
+    }
+
+    fn on_finish(&mut self) {
+        self.bar.finish_and_clear();
+        self.window_title = None;
+    }
+
+    fn set_label(&mut self, label: &str) {
+        self.bar
+            .set_prefix(format!("{} ", truncate_text(label, BAR_LABEL_LENGTH)));
+    }
+
+    fn show_in_window_title(&mut self, file_name: &str) {
+        if self.window_title.is_none() {
+            self.window_title = Some(WindowTitle::new(file_name));
+        }
+    }
+
+    fn on_message(&mut self, message: &str) {
+        if self.multi.is_hidden() {
+            events::message(message);
+        } else {
+            let _ = self.multi.println(message);
+        }
+    }
+
+    fn multi_progress(&self) -> Option<&MultiProgress> {
+        Some(&self.multi)
+    }
+}
+
+impl Drop for BarReporter {
+    fn drop(&mut self) {
+        // 未开始的传输（例如沿用已有封面）不留下空的进度条
+        if !self.bar.is_finished() {
+            self.bar.finish_and_clear();
+        }
+    }
+}
+
+pub(crate) fn length_exceeded_warning(advertised: u64) -> String {
+    format!(
+        "WARNING: Received more than the {} announced by the server, the file will be verified by its declared size and hash.",
+        format_bytes(advertised, ByteUnits::Decimal)
+    )
+}
+
+pub struct SilentReporter;
+
+impl DownloadReporter for SilentReporter {
+    fn on_start(&mut self, _total: u64) {}
+
+    fn on_progress(&mut self, _bytes: u64) {}
+
+    fn on_length_exceeded(&mut self, _advertised: u64) {}
+
+    fn on_retry(&mut self, _delay: Duration, _reason: &str) {}
+
+    fn on_finish(&mut self) {}
+}
+
+/// Step level progress display for operations consisting of several phases.
+///
+/// Every phase is shown as a spinner labeled with its position, e.g. `[2/5] Fetching version
+/// metadata...`, and is marked as done or failed when it finishes. Other progress bars, like
+/// the model file download bar, can be attached through [`StepProgress::multi`]. When
+/// machine readable output is enabled, nothing is drawn and phase events are emitted instead.
+pub struct StepProgress {
+    multi: MultiProgress,
+    total_steps: usize,
+    current_step: usize,
+    current: Option<(ProgressBar, String)>,
+    started_at: Instant,
+    /// Steps are not drawn, only messages are printed.
+    quiet: bool,
+}
+
+impl StepProgress {
+    pub fn new(total_steps: usize) -> Self {
+        let multi = if events::enabled() {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        };
+        Self {
+            multi,
+            total_steps,
+            current_step: 0,
+            current: None,
+            started_at: Instant::now(),
+            quiet: false,
+        }
+    }
+
+    /// Step progress of an operation running beside others on a shared display. Its steps are
+    /// not drawn, messages are printed above the shared display.
+    pub fn attached(multi: &MultiProgress, total_steps: usize) -> Self {
+        Self {
+            multi: multi.clone(),
+            total_steps,
+            current_step: 0,
+            current: None,
+            started_at: Instant::now(),
+            quiet: true,
+        }
+    }
+
+    pub fn multi(&self) -> &MultiProgress {
+        &self.multi
+    }
+
+    pub fn set_total_steps(&mut self, total_steps: usize) {
+        self.total_steps = total_steps;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Prints a line above the progress display without breaking it.
+    pub fn println<S: AsRef<str>>(&self, message: S) {
+        if events::enabled() {
+            events::message(message);
+        } else {
+            let _ = self.multi.println(message);
+        }
+    }
+
+    /// Starts a new step, a step still running will be marked as done.
+    pub fn begin<S: Into<String>>(&mut self, message: S) {
+        self.done();
+        self.current_step += 1;
+        let message = message.into();
+        let total_steps = self.total_steps.max(self.current_step);
+        events::emit(&Event::PhaseStarted {
+            step: self.current_step,
+            total_steps,
+            message: &message,
+        });
+        let label = format!("[{}/{total_steps}] {message}", self.current_step);
+        let spinner = if self.quiet {
+            ProgressBar::hidden()
+        } else {
+            self.multi.add(ProgressBar::new_spinner())
+        };
+        spinner.set_style(
+            ProgressStyle::with_template("{spinner:.green} {msg} [{elapsed}]")
+                .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+        );
+        spinner.set_message(label.clone());
+        spinner.enable_steady_tick(Duration::from_millis(120));
+        self.current = Some((spinner, label));
+    }
+
+    pub fn done(&mut self) {
+        self.finish_current("done");
+    }
+
+    pub fn skip(&mut self) {
+        self.finish_current("skipped");
+    }
+
+    pub fn fail<S: AsRef<str>>(&mut self, reason: S) {
+        self.finish_current(&format!("failed: {}", reason.as_ref()));
+    }
+
+    /// Marks current step as done or failed according to the given result.
+    pub fn track<T, E: std::fmt::Display>(&mut self, result: Result<T, E>) -> Result<T, E> {
+        match &result {
+            Ok(_) => self.done(),
+            Err(e) => self.fail(e.to_string()),
+        }
+        result
+    }
+
+    fn finish_current(&mut self, state: &str) {
+        if let Some((spinner, label)) = self.current.take() {
+            events::emit(&Event::PhaseFinished {
+                step: self.current_step,
+                state,
+            });
+            spinner.set_style(
+                ProgressStyle::with_template("  {msg}")
+                    .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+            );
+            spinner.finish_with_message(format!("{label} {state}"));
+        }
+    }
+}
+
+impl Drop for StepProgress {
+    fn drop(&mut self) {
+        // Steps left running are interrupted by an error returned early.
+        self.finish_current("interrupted");
+    }
+}
+
+pub struct FileSummary {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub blake3: Option<String>,
+    pub hash_matched: Option<bool>,
+    /// Page of the model version the file belongs to.
+    pub page_url: Option<String>,
+}
+
+impl FileSummary {
+    pub fn as_event(&self) -> FileEvent<'_> {
+        FileEvent {
+            name: &self.name,
+            path: &self.path,
+            size: self.size,
+            blake3: self.blake3.as_deref(),
+            hash_matched: self.hash_matched,
+            page_url: self.page_url.as_deref(),
+        }
+    }
+}
+
+/// Why a file or model was left out of an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Downloaded or completed before.
+    AlreadyPresent,
+    NotFoundOnCivitai,
+    EarlyAccess,
+    /// The access key may not download it, e.g. it needs a membership.
+    NoAccess,
+    OverBudget,
+    /// Civitai marked the file as dangerous.
+    Unsafe,
+    /// User chose to skip it.
+    Declined,
+    /// Commercial use is required but the model does not allow it.
+    NoCommercialUse,
+    /// Civitai is still processing the upload, it has no download URL yet.
+    Processing,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            SkipReason::AlreadyPresent => "already present",
+            SkipReason::NotFoundOnCivitai => "not found on Civitai",
+            SkipReason::EarlyAccess => "in early access",
+            SkipReason::NoAccess => "not accessible with the access key",
+            SkipReason::OverBudget => "over the size budget",
+            SkipReason::Unsafe => "marked as dangerous",
+            SkipReason::Declined => "declined",
+            SkipReason::NoCommercialUse => "commercial use not allowed",
+            SkipReason::Processing => "processing, not yet downloadable",
+        };
+        write!(f, "{description}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkippedItem {
+    pub name: String,
+    pub reason: SkipReason,
+}
+
+impl SkippedItem {
+    pub fn new<S: Into<String>>(name: S, reason: SkipReason) -> Self {
+        Self {
+            name: name.into(),
+            reason,
+        }
+    }
+
+    pub fn as_event(&self) -> SkippedEvent<'_> {
+        SkippedEvent {
+            name: &self.name,
+            reason: self.reason,
+        }
+    }
+}
+
+/// Artifact of a model that a step failed to produce or verify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    Cover,
+    CommunityImages,
+    CivitaiHelperFiles,
+    /// The downloaded file does not match its declared hash.
+    HashCheck,
+    /// The downloaded file is a zip archive that could not be extracted.
+    Extraction,
+    /// The trained words file of the version.
+    TrainedWords,
+}
+
+impl Display for ArtifactKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            ArtifactKind::Cover => "cover image",
+            ArtifactKind::CommunityImages => "community images metadata",
+            ArtifactKind::CivitaiHelperFiles => "Civitai Helper files",
+            ArtifactKind::HashCheck => "hash check",
+            ArtifactKind::Extraction => "archive extraction",
+            ArtifactKind::TrainedWords => "trained words file",
+        };
+        write!(f, "{description}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FailedArtifact {
+    /// The model file the artifact belongs to.
+    pub name: String,
+    pub kind: ArtifactKind,
+    pub reason: String,
+}
+
+impl FailedArtifact {
+    pub fn as_event(&self) -> FailedArtifactEvent<'_> {
+        FailedArtifactEvent {
+            name: &self.name,
+            kind: self.kind,
+            reason: &self.reason,
+        }
+    }
+}
+
+/// Counts of skipped items by reason, like `3 already present, 1 over the size budget`.
+pub fn describe_skip_counts(skipped: &[SkippedItem]) -> String {
+    let mut counts = BTreeMap::new();
+    for item in skipped.iter() {
+        *counts.entry(item.reason).or_insert(0usize) += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(reason, count)| format!("{count} {reason}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Summary printed at the end of an operation.
+#[derive(Default)]
+pub struct OperationSummary {
+    pub files: Vec<FileSummary>,
+    pub readme_files: Vec<PathBuf>,
+    pub skipped: Vec<SkippedItem>,
+    /// Artifacts left missing or unverified by steps that failed without stopping the
+    /// operation.
+    pub failed: Vec<FailedArtifact>,
+    /// Policy decisions that let the operation go on, like a model without commercial use.
+    pub warnings: Vec<String>,
+    /// Kinds of files written, at least once.
+    pub produced: ArtifactSet,
+}
+
+impl OperationSummary {
+    pub fn skip<S: Into<String>>(&mut self, name: S, reason: SkipReason) {
+        self.skipped.push(SkippedItem::new(name, reason));
+    }
+
+    pub fn fail<S: Into<String>, R: Display>(&mut self, name: S, kind: ArtifactKind, reason: R) {
+        self.failed.push(FailedArtifact {
+            name: name.into(),
+            kind,
+            reason: format!("{reason:#}"),
+        });
+    }
+
+    pub fn produce(&mut self, artifact: Artifact) {
+        self.produced.insert(artifact);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Fails in strict mode when any step failed, lenient mode only reports it in the summary.
+    pub fn ensure_complete(&self, strict: bool) -> Result<(), IncompleteArtifactsError> {
+        if strict && !self.is_complete() {
+            return Err(IncompleteArtifactsError(self.failed.len()));
+        }
+        Ok(())
+    }
+
+    pub fn print(&self, elapsed: Duration) {
+        events::emit(&Event::Summary {
+            files: self.files.iter().map(FileSummary::as_event).collect(),
+            readme_files: &self.readme_files,
+            skipped: self.skipped.iter().map(SkippedItem::as_event).collect(),
+            failed: self.failed.iter().map(FailedArtifact::as_event).collect(),
+            warnings: &self.warnings,
+            artifacts: self
+                .produced
+                .iter()
+                .map(|artifact| artifact.name())
+                .collect(),
+            complete: self.is_complete(),
+            elapsed_secs: elapsed.as_secs_f64(),
+        });
+        events::message("\nSummary:");
+        if self.files.is_empty() {
+            events::message("  No file downloaded.");
+        }
+        for file in self.files.iter() {
+            let hash_state = match file.hash_matched {
+                Some(true) => "hash matched",
+                Some(false) => "HASH MISMATCHED",
+                None => "hash not checked",
+            };
+            events::message(format!(
+                "  {} ({}, {hash_state})",
+                file.name,
+                format_bytes(file.size, ByteUnits::Binary)
+            ));
+            if let Some(page_url) = file.page_url.as_ref() {
+                events::message(format!("    {page_url}"));
+            }
+        }
+        for readme in self.readme_files.iter() {
+            events::message(format!("  Readme: {}", readme.display()));
+        }
+        if !self.produced.is_empty() {
+            events::message(format!("  Artifacts: {}", self.produced));
+        }
+        for item in self.skipped.iter() {
+            events::message(format!("  Skipped {}: {}", item.name, item.reason));
+        }
+        for artifact in self.failed.iter() {
+            events::message(format!(
+                "  INCOMPLETE {}: {} failed, {}",
+                artifact.name, artifact.kind, artifact.reason
+            ));
+        }
+        for warning in self.warnings.iter() {
+            events::message(format!("  WARNING: {warning}"));
+        }
+        let downloaded_size = self.files.iter().map(|file| file.size).sum::<u64>();
+        if downloaded_size > 0 {
+            events::message(format!(
+                "  {} in {} ({})",
+                format_bytes(downloaded_size, ByteUnits::Binary),
+                format_duration(&elapsed),
+                format_rate(downloaded_size, &elapsed, ByteUnits::Binary)
+            ));
+        } else {
+            events::message(format!("  Elapsed: {}", format_duration(&elapsed)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bar_drops_its_length_when_exceeded() {
+        let multi = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let mut reporter = BarReporter::new(&multi);
+        reporter.on_start(1024);
+        reporter.on_progress(1024);
+        assert_eq!(reporter.bar.length(), Some(1024));
+
+        reporter.on_length_exceeded(1024);
+        reporter.on_progress(2048);
+        assert_eq!(reporter.bar.length(), None);
+        assert_eq!(reporter.bar.position(), 2048);
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use dialoguer::console::Term;
+use tokio::task::AbortHandle;
+
+static PROMPT_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+/// Prompt that timed out and still waits for a key. Its blocking read can not be cancelled, so
+/// nothing else may read the terminal until it ends, or the two would race for the input.
+static ABANDONED_PROMPT: Mutex<Option<AbortHandle>> = Mutex::new(None);
+
+/// Makes every prompt take its default choice when no input arrives within the timeout.
+pub fn set_timeout(timeout: Duration) {
+    let _ = PROMPT_TIMEOUT.set(timeout);
+}
+
+/// Whether prompts take their default after a timeout.
+pub fn has_timeout() -> bool {
+    PROMPT_TIMEOUT.get().is_some()
+}
+
+/// Whether a prompt that timed out is still reading the terminal.
+fn is_abandoned_prompt_waiting() -> bool {
+    ABANDONED_PROMPT
+        .lock()
+        .ok()
+        .and_then(|abandoned| abandoned.as_ref().map(|handle| !handle.is_finished()))
+        .unwrap_or(false)
+}
+
+/// Runs a blocking prompt. With a prompt timeout set, the prompt runs as a blocking task raced
+/// with the timeout, and the default is taken when it is not answered in time. Until the
+/// abandoned prompt gets its key, later prompts take their defaults at once instead of reading
+/// the terminal. The timeout needs the multi-thread runtime.
+pub fn interact<T, F>(prompt: F, default: T, default_label: &str) -> anyhow::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> dialoguer::Result<T> + Send + 'static,
+{
+    Ok(answer(prompt, default_label)?.unwrap_or(default))
+}
+
+/// Runs a blocking prompt like [`interact`], giving `None` when the default is taken because no
+/// answer can arrive in time, so that callers can tell a person's answer from the default.
+pub fn answer<T, F>(prompt: F, default_label: &str) -> anyhow::Result<Option<T>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> dialoguer::Result<T> + Send + 'static,
+{
+    let Some(timeout) = PROMPT_TIMEOUT.get() else {
+        return prompt().map(Some).context("Failed to read input");
+    };
+    if is_abandoned_prompt_waiting() {
+        eprintln!("Auto selected {default_label}.");
+        return Ok(None);
+    }
+
+    let terminal_state = terminal::save();
+    let runtime = tokio::runtime::Handle::current();
+    let task = runtime.spawn_blocking(prompt);
+    let abort_handle = task.abort_handle();
+    let answered =
+        tokio::task::block_in_place(|| runtime.block_on(tokio::time::timeout(*timeout, task)));
+    match answered {
+        Ok(Ok(result)) => result.map(Some).context("Failed to read input"),
+        Ok(Err(_)) => bail!("Prompt was interrupted"),
+        Err(_) => {
+            if let Ok(mut abandoned) = ABANDONED_PROMPT.lock() {
+                *abandoned = Some(abort_handle);
+            }
+            // 恢复被提示框修改的终端状态
+            terminal::restore(terminal_state);
+            let _ = Term::stderr().show_cursor();
+            eprintln!(
+                "\nNo input in {} seconds, auto selected {default_label}.",
+                timeout.as_secs()
+            );
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::io::IsTerminal;
+
+    use rustix::termios::{OptionalActions, Termios, tcgetattr, tcsetattr};
+
+    pub fn save() -> Option<Termios> {
+        let stdin = std::io::stdin();
+        if !stdin.is_terminal() {
+            return None;
+        }
+        tcgetattr(&stdin).ok()
+    }
+
+    pub fn restore(state: Option<Termios>) {
+        if let Some(termios) = state {
+            let _ = tcsetattr(std::io::stdin(), OptionalActions::Now, &termios);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod terminal {
+    pub fn save() -> Option<()> {
+        None
+    }
+
+    pub fn restore(_state: Option<()>) {}
+}
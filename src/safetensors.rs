@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+
+/// Headers larger than this are considered corrupted, real world headers are far smaller.
+const MAX_HEADER_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Metadata embedded in the JSON header of a `.safetensors` file.
+pub struct SafetensorsHeader {
+    metadata: BTreeMap<String, String>,
+}
+
+pub fn is_safetensors_file<P: AsRef<Path>>(file_path: P) -> bool {
+    file_path
+        .as_ref()
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("safetensors"))
+        .unwrap_or_default()
+}
+
+/// Reads the header of a `.safetensors` file without reading the tensors.
+pub fn read_header<P: AsRef<Path>>(file_path: P) -> Result<SafetensorsHeader> {
+    let file_path = file_path.as_ref();
+    let file =
+        File::open(file_path).with_context(|| format!("Failed to open {}", file_path.display()))?;
+    let file_length = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut length_bytes = [0u8; 8];
+    reader
+        .read_exact(&mut length_bytes)
+        .context("File is too short to be a safetensors file")?;
+    let header_length = u64::from_le_bytes(length_bytes);
+    if header_length > MAX_HEADER_SIZE || header_length > file_length.saturating_sub(8) {
+        bail!("Safetensors header length {header_length} is invalid");
+    }
+
+    let mut header_bytes = vec![0u8; header_length as usize];
+    reader
+        .read_exact(&mut header_bytes)
+        .context("Failed to read safetensors header")?;
+    let header: Value =
+        serde_json::from_slice(&header_bytes).context("Safetensors header is not valid JSON")?;
+    let Some(header) = header.as_object() else {
+        bail!("Safetensors header is not a JSON object");
+    };
+
+    let metadata = header
+        .get("__metadata__")
+        .and_then(Value::as_object)
+        .map(|metadata| {
+            metadata
+                .iter()
+                .filter_map(|(key, value)| value.as_str().map(|v| (key.clone(), v.to_string())))
+                .collect::<BTreeMap<_, _>>()
+        })
+        .unwrap_or_default();
+
+    Ok(SafetensorsHeader { metadata })
+}
+
+impl SafetensorsHeader {
+    fn get(&self, key: &str) -> Option<String> {
+        self.metadata
+            .get(key)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty() && value != "None")
+    }
+
+    pub fn title(&self) -> Option<String> {
+        self.get("modelspec.title").or(self.get("ss_output_name"))
+    }
+
+    pub fn base_model(&self) -> Option<String> {
+        self.get("ss_base_model_version")
+            .or(self.get("modelspec.architecture"))
+            .or(self.get("ss_sd_model_name"))
+    }
+
+    pub fn network_module(&self) -> Option<String> {
+        self.get("ss_network_module")
+    }
+
+    pub fn network_dim(&self) -> Option<String> {
+        self.get("ss_network_dim")
+    }
+
+    pub fn network_alpha(&self) -> Option<String> {
+        self.get("ss_network_alpha")
+    }
+
+    /// Network type and dimension in a short form, e.g. `networks.lora (dim 32)`.
+    pub fn network_summary(&self) -> Option<String> {
+        match (self.network_module(), self.network_dim()) {
+            (Some(module), Some(dim)) => Some(format!("{module} (dim {dim})")),
+            (Some(module), None) => Some(module),
+            (None, Some(dim)) => Some(format!("dim {dim}")),
+            (None, None) => None,
+        }
+    }
+
+    pub fn training_comment(&self) -> Option<String> {
+        self.get("ss_training_comment")
+            .or(self.get("modelspec.description"))
+    }
+
+    pub fn trigger_phrase(&self) -> Option<String> {
+        self.get("modelspec.trigger_phrase")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Writes a file with the given header length, header and tensor data.
+    fn write_file(directory: &Path, header_length: u64, header: &[u8], data: &[u8]) -> PathBuf {
+        let file_path = directory.join("model.safetensors");
+        let mut content = header_length.to_le_bytes().to_vec();
+        content.extend_from_slice(header);
+        content.extend_from_slice(data);
+        std::fs::write(&file_path, content).unwrap();
+        file_path
+    }
+
+    fn read_error(file_path: &Path) -> String {
+        match read_header(file_path) {
+            Ok(_) => panic!("header of {} should be rejected", file_path.display()),
+            Err(e) => format!("{e:#}"),
+        }
+    }
+
+    #[test]
+    fn metadata_is_read_from_valid_header() {
+        let directory = tempfile::tempdir().unwrap();
+        let header = br#"{"__metadata__":{"ss_network_module":"networks.lora","ss_network_dim":"32","ss_base_model_version":"sdxl_base_v1-0","modelspec.title":" None "},"w":{"dtype":"F16","shape":[1],"data_offsets":[0,2]}}"#;
+        let file_path = write_file(directory.path(), header.len() as u64, header, &[0, 0]);
+
+        let header = read_header(&file_path).unwrap();
+        assert_eq!(
+            header.network_summary().as_deref(),
+            Some("networks.lora (dim 32)")
+        );
+        assert_eq!(header.base_model().as_deref(), Some("sdxl_base_v1-0"));
+        // 空值和"None"视为没有
+        assert_eq!(header.title(), None);
+    }
+
+    #[test]
+    fn header_longer_than_file_is_rejected() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_path = write_file(directory.path(), 1024, b"{}", &[]);
+        assert_eq!(
+            read_error(&file_path),
+            "Safetensors header length 1024 is invalid"
+        );
+    }
+
+    #[test]
+    fn file_shorter_than_length_prefix_is_rejected() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_path = directory.path().join("short.safetensors");
+        std::fs::write(&file_path, [1, 2, 3]).unwrap();
+        assert!(
+            read_error(&file_path).starts_with("File is too short to be a safetensors file"),
+            "{}",
+            read_error(&file_path)
+        );
+    }
+
+    #[test]
+    fn header_must_be_json_object() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_path = write_file(directory.path(), 2, b"[]", &[]);
+        assert_eq!(
+            read_error(&file_path),
+            "Safetensors header is not a JSON object"
+        );
+    }
+}
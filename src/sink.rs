@@ -0,0 +1,86 @@
+//! Destinations a download is written to, a file on disk or a stream like stdout.
+
+use std::{
+    io::SeekFrom,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWrite},
+};
+
+use crate::hashing::{FileHashes, Hashers};
+
+/// Where the content of a download is written, resumed transfers keep appending to it.
+pub trait DownloadSink: AsyncWrite + Unpin + Send {
+    /// Drops the content written so far when the server can not resume the transfer. Streams
+    /// can not take back what they wrote, so they fail.
+    fn restart(&mut self) -> impl Future<Output = std::io::Result<()>> + Send;
+}
+
+impl DownloadSink for File {
+    async fn restart(&mut self) -> std::io::Result<()> {
+        self.set_len(0).await?;
+        self.seek(SeekFrom::Start(0)).await?;
+        Ok(())
+    }
+}
+
+/// Writes to a stream and hashes the content on the way, as there is no file to read again.
+pub struct HashingStream<W> {
+    inner: W,
+    hashers: Hashers,
+    written: u64,
+}
+
+impl<W> HashingStream<W> {
+    pub fn new(inner: W, with_blake3: bool, with_sha256: bool) -> Self {
+        Self {
+            inner,
+            hashers: Hashers::new(with_blake3, with_sha256),
+            written: 0,
+        }
+    }
+
+    /// Bytes written to the stream so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn finalize(self) -> FileHashes {
+        self.hashers.finalize()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingStream<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        // 只有真正写出的部分计入哈希
+        this.hashers.update(&buf[..written]);
+        this.written += written as u64;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send> DownloadSink for HashingStream<W> {
+    fn restart(&mut self) -> impl Future<Output = std::io::Result<()>> + Send {
+        std::future::ready(Err(std::io::Error::other(
+            "the server can not resume the download, and the content already streamed can not be taken back",
+        )))
+    }
+}
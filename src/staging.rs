@@ -0,0 +1,110 @@
+//! Files written in the temporary directory while they are incomplete, like partial downloads
+//! and cover scratch files, moved to their destination once finished.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tokio::fs::{self, File};
+
+use crate::configuration::DownloadConfig;
+
+/// Where the file for the target is written until it is finished. The target itself when no
+/// temporary directory is configured.
+pub async fn staging_path(download: &DownloadConfig, target: &Path) -> anyhow::Result<PathBuf> {
+    let Some(temp_dir) = download.temp_dir.as_ref() else {
+        return Ok(target.to_path_buf());
+    };
+    fs::create_dir_all(temp_dir).await.with_context(|| {
+        format!(
+            "Failed to create temporary directory {}",
+            temp_dir.display()
+        )
+    })?;
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // 不同目标目录中的同名文件使用不同的临时文件
+    let prefix = &blake3::hash(target.to_string_lossy().as_bytes()).to_hex()[..8];
+    let staged_name = if file_name.ends_with(".part") {
+        format!("{prefix}-{file_name}")
+    } else {
+        format!("{prefix}-{file_name}.part")
+    };
+    Ok(temp_dir.join(staged_name))
+}
+
+/// Whether the file is written somewhere else than its target.
+pub fn is_staged(staged: &Path, target: &Path) -> bool {
+    staged != target
+}
+
+/// Moves the finished file to its target. Files on the same file system are renamed, others are
+/// copied beside the target, synced and renamed over it, so the target is never left half
+/// written. The partial copy is removed on failure, while the staged file is kept.
+pub async fn finish(staged: &Path, target: &Path) -> anyhow::Result<()> {
+    if !is_staged(staged, target) {
+        return Ok(());
+    }
+    match fs::rename(staged, target).await {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {}
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!(
+                    "Failed to move {} to {}",
+                    staged.display(),
+                    target.display()
+                )
+            });
+        }
+    }
+
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let copy_path = target.with_file_name(format!("{file_name}.part"));
+    if let Err(e) = copy_synced(staged, &copy_path).await {
+        let _ = fs::remove_file(&copy_path).await;
+        return Err(e).with_context(|| {
+            format!(
+                "Failed to copy {} to {}, the finished file is kept at {}",
+                file_name,
+                target.display(),
+                staged.display()
+            )
+        });
+    }
+    if let Err(e) = fs::rename(&copy_path, target).await {
+        let _ = fs::remove_file(&copy_path).await;
+        return Err(e).with_context(|| {
+            format!(
+                "Failed to move the copy into {}, the finished file is kept at {}",
+                target.display(),
+                staged.display()
+            )
+        });
+    }
+    let _ = fs::remove_file(staged).await;
+    Ok(())
+}
+
+/// Removes the staged file of a failed transfer. Files written at the target are left alone,
+/// like before there was a temporary directory.
+pub async fn discard(staged: &Path, target: &Path) {
+    if is_staged(staged, target) {
+        let _ = fs::remove_file(staged).await;
+    }
+}
+
+async fn copy_synced(source: &Path, target: &Path) -> std::io::Result<()> {
+    let mut source = File::open(source).await?;
+    let mut copy = File::create(target).await?;
+    tokio::io::copy(&mut source, &mut copy).await?;
+    copy.sync_all().await?;
+    Ok(())
+}
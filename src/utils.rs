@@ -1,6 +1,482 @@
-use std::time::Duration;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-pub fn duration_to_sec_string(duration: &Duration) -> String {
-    let sec = duration.as_secs();
-    format!("{sec}s")
+use time::{UtcDateTime, format_description::well_known::Rfc3339, macros::format_description};
+
+/// Renders a duration like `9m 42s` or `1h 5m`, durations under 10 seconds keep one decimal.
+pub fn format_duration(duration: &Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 10.0 {
+        let seconds = (seconds * 10.0).round() / 10.0;
+        return format!("{seconds}s");
+    }
+    let total = seconds.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total % 3600 / 60, total % 60);
+    [(hours, "h"), (minutes, "m"), (seconds, "s")]
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{value}{unit}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Units a byte size is rendered in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteUnits {
+    /// Powers of 1000: KB, MB, GB, TB.
+    Decimal,
+    /// Powers of 1024: KiB, MiB, GiB, TiB.
+    Binary,
+}
+
+/// Renders a byte size like `512 B`, `12.6 GB` or `12.6 GiB`.
+pub fn format_bytes(size: u64, units: ByteUnits) -> String {
+    let (base, labels) = match units {
+        ByteUnits::Decimal => (1000.0, ["KB", "MB", "GB", "TB"]),
+        ByteUnits::Binary => (1024.0, ["KiB", "MiB", "GiB", "TiB"]),
+    };
+    if (size as f64) < base {
+        return format!("{size} B");
+    }
+    let mut value = size as f64 / base;
+    let mut label = labels[0];
+    for next_label in labels.iter().skip(1) {
+        // 四舍五入后达到下一级单位时也进位，避免出现 1024.0 MiB
+        if (value * 10.0).round() / 10.0 < base {
+            break;
+        }
+        value /= base;
+        label = next_label;
+    }
+    format!("{value:.1} {label}")
+}
+
+/// Renders the average speed of transferring `size` bytes in `elapsed`, like `22.1 MB/s`.
+pub fn format_rate(size: u64, elapsed: &Duration, units: ByteUnits) -> String {
+    // 极短的耗时按1毫秒计算，避免除以零
+    let seconds = elapsed.as_secs_f64().max(0.001);
+    format!(
+        "{}/s",
+        format_bytes((size as f64 / seconds).round() as u64, units)
+    )
+}
+
+/// Renders how long ago the time was, like `today`, `3 weeks ago` or `2 years ago`.
+pub fn format_age(datetime: &UtcDateTime) -> String {
+    let days = (UtcDateTime::now() - *datetime).whole_days();
+    let (count, unit) = match days {
+        ..=0 => return "today".to_string(),
+        1 => return "yesterday".to_string(),
+        2..14 => (days, "day"),
+        14..60 => (days / 7, "week"),
+        60..365 => (days / 30, "month"),
+        _ => (days / 365, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural} ago")
+}
+
+pub fn datetime_to_date_string(datetime: &UtcDateTime) -> String {
+    datetime
+        .format(format_description!("[year]-[month]-[day]"))
+        .unwrap_or_default()
+}
+
+/// Renders the time in RFC 3339, like `2024-05-01T08:30:00Z`.
+pub fn datetime_to_iso_string(datetime: &UtcDateTime) -> String {
+    datetime.format(&Rfc3339).unwrap_or_default()
+}
+
+pub fn kilobytes_to_human_string(size_kb: f64) -> String {
+    let units = ["KB", "MB", "GB", "TB"];
+    let mut size = size_kb;
+    let mut unit_index = 0;
+    while size >= 1024.0 && unit_index < units.len() - 1 {
+        size /= 1024.0;
+        unit_index += 1;
+    }
+    format!("{size:.2} {}", units[unit_index])
+}
+
+pub fn bytes_to_human_string(size: u64) -> String {
+    kilobytes_to_human_string(size as f64 / 1024.0)
+}
+
+/// Parses a size like `500M`, `5G` or `1.5GB` into bytes, units are powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let unit_start = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("\"{value}\" is not a size, expected e.g. 500M or 5G"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let multiplier: u64 = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "unknown size unit \"{unit}\", expected K, M, G or T"
+            ));
+        }
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses an age like `30m`, `12h`, `7d` or `2w`, a number without unit is in days.
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("\"{value}\" is not an age, expected e.g. 12h or 7d"))?;
+    let seconds: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "" | "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        unit => {
+            return Err(format!(
+                "unknown age unit \"{unit}\", expected s, m, h, d or w"
+            ));
+        }
+    };
+    Ok(Duration::from_secs(number.saturating_mul(seconds)))
+}
+
+const RESERVED_FILE_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Longest file name accepted by common file systems, in bytes.
+pub const MAX_FILE_NAME_LENGTH: usize = 255;
+/// Longest suffix of the files written beside a model file, replacing its extension.
+pub const LONGEST_COMPANION_SUFFIX: &str = ".civitai.json";
+/// Longest stem of a sanitized file name, leaving room for the companion suffixes.
+const MAX_FILE_STEM_LENGTH: usize = MAX_FILE_NAME_LENGTH - LONGEST_COMPANION_SUFFIX.len();
+
+/// Longest path accepted by the platform, Windows allows longer paths only with the
+/// extended-length prefix.
+#[cfg(windows)]
+pub const MAX_PATH_LENGTH: usize = 260;
+#[cfg(not(windows))]
+pub const MAX_PATH_LENGTH: usize = 4096;
+
+/// Makes a file name legal on all major platforms, replaces reserved characters, trims
+/// trailing dots and spaces, avoids Windows reserved device names and limits the length. The
+/// stem is kept short enough for companion files like `{stem}.civitai.json` to stay legal too.
+pub fn sanitize_file_name(name: &str) -> String {
+    let sanitized = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let sanitized = shorten_file_name(
+        sanitized.trim().trim_end_matches(['.', ' ']),
+        MAX_FILE_NAME_LENGTH,
+        MAX_FILE_STEM_LENGTH,
+    );
+    if sanitized.is_empty() {
+        return "_".to_string();
+    }
+    let device_name = sanitized.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_FILE_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(device_name))
+    {
+        return format!("_{sanitized}");
+    }
+    sanitized
+}
+
+/// Truncates the stem of a file name to at most `max_stem_length` bytes and the whole name to
+/// `max_length` bytes, the extension is kept.
+fn shorten_file_name(name: &str, max_length: usize, max_stem_length: usize) -> String {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+    if name.len() <= max_length && stem.len() <= max_stem_length {
+        return name.to_string();
+    }
+    let mut stem_length = max_length
+        .saturating_sub(extension.len())
+        .min(max_stem_length)
+        .min(stem.len());
+    while !stem.is_char_boundary(stem_length) {
+        stem_length -= 1;
+    }
+    let stem = stem[..stem_length].trim_end_matches(['.', ' ']);
+    format!("{stem}{extension}")
+}
+
+/// Cuts the text to at most the given number of characters, marking the cut with an ellipsis.
+pub fn truncate_text(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let kept = text
+        .chars()
+        .take(max_chars.saturating_sub(1))
+        .collect::<String>();
+    format!("{}…", kept.trim_end())
+}
+
+/// Length of the path as counted by the platform limits.
+pub fn path_length(path: &Path) -> usize {
+    path.to_string_lossy().chars().count()
+}
+
+/// Adds the extended-length prefix to an absolute path on Windows, so that it can exceed
+/// `MAX_PATH_LENGTH`.
+#[cfg(windows)]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    if path.to_string_lossy().starts_with(r"\\?\") {
+        return path.to_path_buf();
+    }
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let absolute_str = absolute.to_string_lossy();
+    // 网络路径需要使用UNC形式的前缀
+    match absolute_str.strip_prefix(r"\\") {
+        Some(share) => PathBuf::from(format!(r"\\?\UNC\{share}")),
+        None => PathBuf::from(format!(r"\\?\{absolute_str}")),
+    }
+}
+
+/// Paths on other platforms have no length limit worth a prefix, returned as is.
+#[cfg(not(windows))]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Opens the URL in the default browser of the system.
+pub fn open_in_browser(url: &str) -> std::io::Result<()> {
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    };
+    #[cfg(target_os = "macos")]
+    let mut command = std::process::Command::new("open");
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let mut command = std::process::Command::new("xdg-open");
+
+    command
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+/// Matches the text against a glob pattern case insensitively, `*` matches any run of
+/// characters and `?` a single one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+    let text = text.to_lowercase().chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // 最近一个星号的位置，以及它当前匹配到的文本位置
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Explains a permission error on one of the directories of imd, with the access it needs and
+/// how to give it back, such directories are usually left behind by a run with sudo.
+pub fn permission_advice(path: &Path) -> String {
+    let advice = format!(
+        "imd needs to read and write {}, and to list and create files in it when it is a directory.",
+        path.display()
+    );
+    // 路径尚不存在时，需要修复的是其最近的已存在的上级目录
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(path);
+    #[cfg(unix)]
+    let fix = format!(
+        "It may have been created by running imd with sudo, give it back to your user with:\n  sudo chown -R \"$(id -un)\" \"{0}\" && chmod -R u+rwX \"{0}\"",
+        existing.display()
+    );
+    #[cfg(not(unix))]
+    let fix = format!(
+        "Check that your user account has full control of {} in the security settings of its properties.",
+        existing.display()
+    );
+    format!("{advice}\n{fix}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_formatted_at_boundaries() {
+        for (seconds, expected) in [
+            (0.0, "0s"),
+            (0.04, "0s"),
+            (0.05, "0.1s"),
+            (9.94, "9.9s"),
+            (9.96, "10s"),
+            (10.0, "10s"),
+            (59.4, "59s"),
+            (59.6, "1m"),
+            (60.0, "1m"),
+            (61.0, "1m 1s"),
+            (3599.0, "59m 59s"),
+            (3600.0, "1h"),
+            (3661.0, "1h 1m 1s"),
+            (90000.0, "25h"),
+        ] {
+            assert_eq!(
+                format_duration(&Duration::from_secs_f64(seconds)),
+                expected,
+                "{seconds}"
+            );
+        }
+    }
+
+    #[test]
+    fn bytes_are_formatted_at_unit_boundaries() {
+        for (size, units, expected) in [
+            (0, ByteUnits::Decimal, "0 B"),
+            (999, ByteUnits::Decimal, "999 B"),
+            (1000, ByteUnits::Decimal, "1.0 KB"),
+            (1023, ByteUnits::Binary, "1023 B"),
+            (1024, ByteUnits::Binary, "1.0 KiB"),
+            (999_949, ByteUnits::Decimal, "999.9 KB"),
+            // 四舍五入到下一级单位时进位
+            (999_950, ByteUnits::Decimal, "1.0 MB"),
+            (1024 * 1024 - 1, ByteUnits::Binary, "1.0 MiB"),
+            (12_600_000_000, ByteUnits::Decimal, "12.6 GB"),
+            (1024u64.pow(4), ByteUnits::Binary, "1.0 TiB"),
+            (1000u64.pow(5), ByteUnits::Decimal, "1000.0 TB"),
+            (u64::MAX, ByteUnits::Binary, "16777216.0 TiB"),
+        ] {
+            assert_eq!(format_bytes(size, units), expected, "{size}");
+        }
+    }
+
+    #[test]
+    fn rates_are_formatted_without_dividing_by_zero() {
+        assert_eq!(format_rate(0, &Duration::ZERO, ByteUnits::Decimal), "0 B/s");
+        assert_eq!(
+            format_rate(1000, &Duration::ZERO, ByteUnits::Decimal),
+            "1.0 MB/s"
+        );
+        assert_eq!(
+            format_rate(22_100_000, &Duration::from_secs(1), ByteUnits::Decimal),
+            "22.1 MB/s"
+        );
+        assert_eq!(
+            format_rate(1536, &Duration::from_secs(2), ByteUnits::Binary),
+            "768 B/s"
+        );
+        assert_eq!(
+            format_rate(u64::MAX, &Duration::from_nanos(1), ByteUnits::Binary),
+            "16777216.0 TiB/s"
+        );
+    }
+
+    #[test]
+    fn problematic_file_names_are_sanitized() {
+        for (name, expected) in [
+            ("Detail Tweaker LoRA (LoRA)", "Detail Tweaker LoRA (LoRA)"),
+            ("SDXL: Turbo", "SDXL_ Turbo"),
+            ("a/b\\c", "a_b_c"),
+            ("what?<*>|\"", "what______"),
+            ("tab\there\nnewline", "tab_here_newline"),
+            ("  padded  ", "padded"),
+            ("trailing dots...", "trailing dots"),
+            ("trailing. . .", "trailing"),
+            ("...", "_"),
+            ("", "_"),
+            ("CON", "_CON"),
+            ("con.safetensors", "_con.safetensors"),
+            ("LPT9 .txt", "_LPT9 .txt"),
+            ("COM10", "COM10"),
+            ("CONSOLE", "CONSOLE"),
+            ("模型：风格", "模型：风格"),
+        ] {
+            assert_eq!(sanitize_file_name(name), expected, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn long_file_names_leave_room_for_companions() {
+        for name in [
+            format!("{}.safetensors", "a".repeat(300)),
+            format!("{}.safetensors", "模".repeat(100)),
+            "b".repeat(300),
+        ] {
+            let sanitized = sanitize_file_name(&name);
+            assert!(sanitized.len() <= MAX_FILE_NAME_LENGTH, "{sanitized}");
+            let stem = Path::new(&sanitized).file_stem().unwrap().to_string_lossy();
+            // 伴随文件以清理后的主干加后缀命名
+            assert_eq!(sanitize_file_name(&stem), stem);
+            let companion = format!("{stem}{LONGEST_COMPANION_SUFFIX}");
+            assert!(companion.len() <= MAX_FILE_NAME_LENGTH, "{companion}");
+        }
+        assert!(
+            sanitize_file_name(&format!("{}.safetensors", "a".repeat(300)))
+                .ends_with(".safetensors")
+        );
+    }
+
+    #[test]
+    fn emoji_in_file_names_are_kept() {
+        assert_eq!(
+            sanitize_file_name("🔥 Fire | Style ✨.safetensors"),
+            "🔥 Fire _ Style ✨.safetensors"
+        );
+        assert_eq!(sanitize_file_name("👍🏽"), "👍🏽");
+        // 表情符号占4字节，截断时不能拆开
+        let sanitized = sanitize_file_name(&format!("{}.safetensors", "😀".repeat(100)));
+        let stem = Path::new(&sanitized).file_stem().unwrap().to_string_lossy();
+        assert!(stem.len() <= MAX_FILE_STEM_LENGTH, "{sanitized}");
+        assert!(stem.chars().all(|c| c == '😀'), "{sanitized}");
+        assert!(sanitized.ends_with(".safetensors"));
+    }
+
+    #[test]
+    fn shortened_names_keep_the_extension_and_char_boundaries() {
+        assert_eq!(shorten_file_name("short.pt", 20, 20), "short.pt");
+        assert_eq!(shorten_file_name("abcdefghij.pt", 8, 8), "abcde.pt");
+        assert_eq!(shorten_file_name("abcd .pt", 7, 7), "abcd.pt");
+        assert_eq!(shorten_file_name("模型模型.pt", 10, 10), "模型.pt");
+        assert_eq!(shorten_file_name(".hidden-file", 7, 7), ".hidden");
+        assert_eq!(shorten_file_name("abcdefghij.pt", 255, 4), "abcd.pt");
+    }
 }
@@ -0,0 +1,94 @@
+//! Downloads a model from the mock Civitai into a temporary directory.
+
+mod common;
+
+use imd::{
+    artifacts::ArtifactSet,
+    civitai::{DownloadBehavior, VersionSelection},
+};
+
+#[tokio::test]
+async fn download_writes_model_and_metadata() {
+    let _home = common::isolated_home();
+    let server = common::civitai_server().await;
+    let config = common::configuration(&server);
+    let client = imd::downloader::make_client(&config).unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let output_path = output.path().to_path_buf();
+
+    let summary = imd::civitai::download_from_civitai(
+        &client,
+        &config,
+        1,
+        &VersionSelection {
+            ids: vec![10],
+            ..Default::default()
+        },
+        Some(&output_path),
+        &DownloadBehavior {
+            artifacts: ArtifactSet::standard(),
+            unattended: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+
+    let model_path = output_path.join("fixture.safetensors");
+    assert_eq!(std::fs::read(&model_path).unwrap(), common::MODEL_CONTENT);
+    let hash = std::fs::read_to_string(output_path.join("fixture.blake3")).unwrap();
+    assert_eq!(hash.trim(), common::MODEL_BLAKE3);
+    assert!(output_path.join("fixture.cover.png").is_file());
+    assert!(output_path.join("fixture.civitai.json").is_file());
+
+    let readme = std::fs::read_to_string(output_path.join("fixture.md")).unwrap();
+    let headings = readme
+        .lines()
+        .filter(|line| line.starts_with('#'))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        headings,
+        [
+            "# Fixture LoRA",
+            "## Version: v1",
+            "## Trained Words",
+            "## Files",
+            "## License / Permissions",
+            "## Cover image prompts",
+            "## Community image prompts",
+        ]
+    );
+    assert!(readme.contains("![](./fixture.cover.png)"));
+    assert!(readme.contains("fixture style"));
+    assert!(readme.contains("a fixture landscape"));
+
+    let model = imd::cache_db::retreive_civitai_model(1).unwrap().unwrap();
+    assert_eq!(model.name(), "Fixture LoRA");
+    let locations = imd::cache_db::retreive_civitai_model_locations_by_blake3(common::MODEL_BLAKE3)
+        .unwrap()
+        .unwrap();
+    assert_eq!(locations, vec![model_path]);
+    let downloads = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.url.path() == "/api/download/models/10")
+        .collect::<Vec<_>>();
+    // 无人值守时先探测一个字节确认可以下载，然后才传输整个文件
+    let transfers = downloads
+        .iter()
+        .filter(|request| !request.headers.contains_key("range"))
+        .count();
+    assert_eq!((downloads.len(), transfers), (2, 1));
+    for request in downloads.iter() {
+        let authorization = request.headers.get("authorization").unwrap();
+        assert_eq!(
+            authorization.to_str().unwrap(),
+            format!("Bearer {}", common::ACCESS_KEY)
+        );
+    }
+    // 下载完成后不再保留会话
+    assert!(imd::civitai::DownloadSession::find("1").unwrap().is_empty());
+}
@@ -0,0 +1,49 @@
+//! Completes the metadata of a local model file, found on the mock Civitai by its hash.
+
+mod common;
+
+use imd::{artifacts::ArtifactSet, civitai::CompletionBehavior};
+
+#[tokio::test]
+async fn renew_finds_version_by_hash() {
+    let _home = common::isolated_home();
+    let server = common::civitai_server().await;
+    let config = common::configuration(&server);
+    let client = imd::downloader::make_client(&config).unwrap();
+    let models = tempfile::tempdir().unwrap();
+    let model_path = models.path().join("renamed.safetensors");
+    std::fs::write(&model_path, common::MODEL_CONTENT).unwrap();
+
+    let summary = imd::civitai::complete_file_meta(
+        &client,
+        &config,
+        &model_path,
+        &CompletionBehavior {
+            artifacts: ArtifactSet::standard(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+
+    // 本地文件名保持不变，元数据以它命名
+    let hash = std::fs::read_to_string(models.path().join("renamed.blake3")).unwrap();
+    assert_eq!(hash.trim(), common::MODEL_BLAKE3);
+    let readme = std::fs::read_to_string(models.path().join("renamed.md")).unwrap();
+    assert!(readme.starts_with("# Fixture LoRA\n"));
+    assert!(models.path().join("renamed.cover.png").is_file());
+    let sidecar = imd::civitai::load_sidecar(&model_path).unwrap().unwrap();
+    assert_eq!(sidecar.version_id, 10);
+
+    let lookups = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.url.path().contains("/by-hash/"))
+        .count();
+    assert_eq!(lookups, 1);
+    let version = imd::cache_db::retreive_civitai_model_version(1, 10).unwrap();
+    assert!(version.is_some());
+}
@@ -0,0 +1,85 @@
+//! Models taken down from the mock Civitai, still listed but without anything to download.
+
+mod common;
+
+use imd::{
+    civitai::{DownloadBehavior, VersionSelection},
+    errors::{NoDownloadableVersionError, VersionWithoutFilesError},
+};
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+#[tokio::test]
+async fn models_without_files_fail_with_friendly_errors() {
+    let _home = common::isolated_home();
+    let server = common::civitai_server().await;
+    for (endpoint, fixture) in [
+        ("/api/v1/models/2", "model_without_versions.json"),
+        ("/api/v1/models/3", "model_with_empty_version.json"),
+        ("/api/v1/model-versions/30", "version_without_files.json"),
+    ] {
+        Mock::given(method("GET"))
+            .and(path(endpoint))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(common::fixture(&server, fixture)),
+            )
+            .mount(&server)
+            .await;
+    }
+    let config = common::configuration(&server);
+    let client = imd::downloader::make_client(&config).unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let output_path = output.path().to_path_buf();
+    let behavior = DownloadBehavior {
+        unattended: true,
+        ..Default::default()
+    };
+
+    let Err(error) = imd::civitai::download_from_civitai(
+        &client,
+        &config,
+        2,
+        &VersionSelection::default(),
+        Some(&output_path),
+        &behavior,
+    )
+    .await
+    else {
+        panic!("a model without versions should fail");
+    };
+    assert!(
+        error
+            .chain()
+            .any(|cause| cause.downcast_ref::<NoDownloadableVersionError>().is_some()),
+        "{error:#}"
+    );
+    assert!(
+        format!("{error:#}").contains("Model 2 has no downloadable versions"),
+        "{error:#}"
+    );
+
+    let Err(error) = imd::civitai::download_from_civitai(
+        &client,
+        &config,
+        3,
+        &VersionSelection {
+            ids: vec![30],
+            ..Default::default()
+        },
+        Some(&output_path),
+        &behavior,
+    )
+    .await
+    else {
+        panic!("a version without files should fail");
+    };
+    let without_files = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<VersionWithoutFilesError>())
+        .unwrap_or_else(|| panic!("{error:#}"));
+    assert_eq!(without_files.version_name, "v2");
+    assert_eq!(without_files.versions_with_files, ["v1 (31)"]);
+    assert_eq!(std::fs::read_dir(&output_path).unwrap().count(), 0);
+}
@@ -0,0 +1,108 @@
+//! Civitai served by a mock server from the fixtures in `tests/fixtures/civitai`.
+
+use std::path::Path;
+
+use imd::configuration::{CivitaiConfig, Configuration, DownloadAuthMode};
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path, path_regex, query_param},
+};
+
+pub const ACCESS_KEY: &str = "fixture-key";
+/// Content of the model file, its BLAKE3 is declared in `version.json`.
+pub const MODEL_CONTENT: [u8; 2048] = [b'x'; 2048];
+pub const MODEL_BLAKE3: &str = "07E9639E695A0A52A3D1FEACAC07E455F8DEAD34FA4D2545C734B4B36ED1FE92";
+
+/// Points the home directory to a new temporary directory, so the configuration and the cache
+/// database of the test are kept apart from the user's. Must be called before anything else
+/// runs in the test binary.
+pub fn isolated_home() -> tempfile::TempDir {
+    let home = tempfile::tempdir().unwrap();
+    // SAFETY: called first in the only test of the binary, no other thread reads the environment.
+    unsafe { std::env::set_var("HOME", home.path()) };
+    home
+}
+
+/// Fixture with the mock server address filled in.
+pub fn fixture(server: &MockServer, name: &str) -> serde_json::Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/civitai")
+        .join(name);
+    let text = std::fs::read_to_string(path).unwrap();
+    serde_json::from_str(&text.replace("{{server}}", &server.uri())).unwrap()
+}
+
+/// A small PNG, the cover and community image of the fixtures.
+fn png() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    image::RgbImage::from_pixel(32, 32, image::Rgb([200, 30, 30]))
+        .write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    bytes
+}
+
+/// Starts the mock server answering the model, version, by-hash lookup, images page and file
+/// download of the fixtures.
+pub async fn civitai_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/models/1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fixture(&server, "model.json")))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/model-versions/10"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fixture(&server, "version.json")))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/api/v1/model-versions/by-hash/{MODEL_BLAKE3}"
+        )))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(fixture(&server, "version_by_hash.json")),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/images"))
+        .and(query_param("modelId", "1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fixture(&server, "images.json")))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex("^/img/"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Type", "image/png")
+                .set_body_bytes(png()),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/download/models/10"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Type", "application/octet-stream")
+                .set_body_bytes(MODEL_CONTENT.to_vec()),
+        )
+        .mount(&server)
+        .await;
+    server
+}
+
+/// Settings using the mock server as Civitai.
+pub fn configuration(server: &MockServer) -> Configuration {
+    let mut config = Configuration::default();
+    config.civitai = CivitaiConfig {
+        api_key: Some(ACCESS_KEY.to_string()),
+        api_base_url: Some(format!("{}/api/v1", server.uri())),
+        // 模拟服务器不是Civitai的主机，明确使用请求头发送密钥
+        auth_mode: DownloadAuthMode::Header,
+        ..Default::default()
+    };
+    config
+}
@@ -0,0 +1,95 @@
+//! Offline mode serves metadata from cache and fails fast without opening any socket.
+
+mod common;
+
+use std::time::Duration;
+
+use imd::{
+    civitai::{DownloadBehavior, Model, VersionSelection},
+    configuration::Configuration,
+    errors::{OfflineAndUncachedError, OfflineError},
+};
+use serde_json::json;
+
+/// An address in a private range no host answers, connecting to it hangs until the timeout.
+const UNROUTABLE_API_BASE: &str = "http://10.255.255.1/api/v1";
+
+fn unroutable_configuration(config: &Configuration) -> Configuration {
+    let mut config = config.clone();
+    config.civitai.api_base_url = Some(UNROUTABLE_API_BASE.to_string());
+    config.network.connect_timeout = 10;
+    config
+}
+
+/// Error of the action, which must fail well before a connection attempt could time out.
+async fn fail_fast<T>(action: impl Future<Output = anyhow::Result<T>>) -> anyhow::Error {
+    // 尝试连接不可达的地址会一直等到连接超时
+    match tokio::time::timeout(Duration::from_secs(2), action).await {
+        Ok(Err(error)) => error,
+        Ok(Ok(_)) => panic!("the action should fail offline"),
+        Err(_) => panic!("the action waited for the network"),
+    }
+}
+
+#[tokio::test]
+async fn offline_mode_opens_no_socket() {
+    let _home = common::isolated_home();
+    imd::downloader::set_offline();
+    let server = common::civitai_server().await;
+    let served_config = common::configuration(&server);
+
+    for config in [unroutable_configuration(&served_config), served_config] {
+        let client = imd::downloader::make_client(&config).unwrap();
+        let error = fail_fast(imd::civitai::fetch_model_metadata(&client, &config, 1)).await;
+        assert!(
+            error.downcast_ref::<OfflineAndUncachedError>().is_some(),
+            "{error:#}"
+        );
+        let error = fail_fast(imd::civitai::fetch_model_version_meta(&client, &config, 10)).await;
+        assert!(
+            error.downcast_ref::<OfflineAndUncachedError>().is_some(),
+            "{error:#}"
+        );
+        let error = fail_fast(imd::civitai::verify_api_key(
+            &client,
+            &config,
+            common::ACCESS_KEY,
+        ))
+        .await;
+        assert!(error.downcast_ref::<OfflineError>().is_some(), "{error:#}");
+        let output = tempfile::tempdir().unwrap();
+        let error = fail_fast(imd::civitai::download_from_civitai(
+            &client,
+            &config,
+            1,
+            &VersionSelection::default(),
+            Some(&output.path().to_path_buf()),
+            &DownloadBehavior {
+                unattended: true,
+                ..Default::default()
+            },
+        ))
+        .await;
+        assert!(error.downcast_ref::<OfflineError>().is_some(), "{error:#}");
+    }
+    assert!(
+        server.received_requests().await.unwrap().is_empty(),
+        "offline mode requested the API"
+    );
+
+    // 缓存的元数据仍然可用
+    let model = Model::try_from(&json!({
+        "id": 1,
+        "name": "Cached LoRA",
+        "description": "",
+        "modelVersions": [],
+    }))
+    .unwrap();
+    imd::cache_db::store_civitai_model(&model).unwrap();
+    let config = unroutable_configuration(&common::configuration(&server));
+    let client = imd::downloader::make_client(&config).unwrap();
+    let cached = imd::civitai::fetch_model_metadata(&client, &config, 1)
+        .await
+        .unwrap();
+    assert_eq!(cached.name(), "Cached LoRA");
+}
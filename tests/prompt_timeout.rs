@@ -0,0 +1,31 @@
+//! Prompts taking their defaults after the prompt timeout, in a binary of their own since the
+//! timeout is set once for the whole process.
+
+use std::time::{Duration, Instant};
+
+use imd::prompt;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn abandoned_prompt_keeps_later_prompts_off_the_terminal() {
+    prompt::set_timeout(Duration::from_millis(100));
+    let started = Instant::now();
+    let answer = prompt::interact(
+        || {
+            std::thread::sleep(Duration::from_millis(800));
+            Ok(1)
+        },
+        0,
+        "zero",
+    )
+    .unwrap();
+    assert_eq!(answer, 0);
+    assert!(started.elapsed() < Duration::from_millis(800));
+
+    // 前一个提示框仍在等待按键
+    let answer = prompt::interact(|| Ok(2), 0, "zero").unwrap();
+    assert_eq!(answer, 0);
+
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let answer = prompt::interact(|| Ok(3), 0, "zero").unwrap();
+    assert_eq!(answer, 3);
+}